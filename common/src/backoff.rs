@@ -0,0 +1,142 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Tracks consecutive failures per object, producing exponentially
+/// increasing, jittered retry delays.
+pub struct Backoff {
+    /// Delay before retrying after the first failure.
+    base: Duration,
+    /// Upper bound for the delay between retries.
+    max: Duration,
+    /// Delay before re-checking objects which cannot succeed without
+    /// user intervention, such as ones with an invalid spec.
+    misconfigured: Duration,
+    /// Consecutive failures per object, along with when it last failed.
+    failures: Mutex<HashMap<String, (u32, Instant)>>,
+}
+
+impl Backoff {
+    /// Back off exponentially from `base` up to `max`. Misconfigured objects
+    /// are re-checked at `max` as well, unless [`Backoff::with_misconfigured`]
+    /// says otherwise.
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Backoff {
+            base,
+            max,
+            misconfigured: max,
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Re-check misconfigured objects after `delay`, rather than at the max delay.
+    pub fn with_misconfigured(self, delay: Duration) -> Self {
+        Backoff {
+            misconfigured: delay,
+            ..self
+        }
+    }
+
+    /// Register a failure for the object identified by `key`, returning
+    /// the delay until it should be retried.
+    pub fn failure(&self, key: &str) -> Duration {
+        let now = Instant::now();
+        let mut failures = self.failures.lock().unwrap();
+
+        // Objects which are failing are retried within the max delay, so
+        // those which haven't failed for twice as long have either recovered
+        // or been deleted, neither of which reaches the error policy.
+        let expiry = self.max.saturating_mul(2);
+        failures.retain(|_, (_, last)| now.duration_since(*last) <= expiry);
+
+        let (attempts, last) = failures.entry(key.to_string()).or_insert((0, now));
+        *attempts = attempts.saturating_add(1);
+        *last = now;
+
+        // Cap the exponent, so the multiplication can't overflow before
+        // being capped by the max delay.
+        let delay = self
+            .base
+            .saturating_mul(1 << (*attempts - 1).min(16))
+            .min(self.max);
+
+        jitter(delay)
+    }
+
+    /// Delay until an object which failed because of its configuration
+    /// should be re-checked. This does not count as a failure, since
+    /// retrying sooner would not help.
+    pub fn misconfigured(&self) -> Duration {
+        jitter(self.misconfigured)
+    }
+
+    /// Forget previous failures for the object, after it has been reconciled
+    /// successfully or deleted.
+    pub fn reset(&self, key: &str) {
+        self.failures.lock().unwrap().remove(key);
+    }
+}
+
+/// Pick a random delay between half and all of `delay`, so objects
+/// which failed at the same time don't all retry at the same time.
+fn jitter(delay: Duration) -> Duration {
+    // RandomState is seeded randomly, which is plenty random
+    // for spreading out retries, without pulling in a dependency.
+    let random = RandomState::new().build_hasher().finish();
+
+    let half = delay / 2;
+    half + Duration::from_millis(random % (half.as_millis() as u64 + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Backoff;
+
+    // Jitter picks a delay between half and all of the nominal delay.
+    fn within(delay: Duration, nominal: Duration) -> bool {
+        delay >= nominal / 2 && delay <= nominal
+    }
+
+    #[test]
+    fn exponential_backoff_per_object() {
+        let backoff = Backoff::new(Duration::from_secs(4), Duration::from_secs(30))
+            .with_misconfigured(Duration::from_secs(300));
+
+        let secs = Duration::from_secs;
+        assert!(within(backoff.failure("default/a"), secs(4)));
+        assert!(within(backoff.failure("default/a"), secs(8)));
+        assert!(within(backoff.failure("default/a"), secs(16)));
+        assert!(within(backoff.failure("default/a"), secs(30)));
+
+        // Failures are tracked per object.
+        assert!(within(backoff.failure("default/b"), secs(4)));
+
+        // Misconfigurations always use the fixed delay.
+        assert!(within(backoff.misconfigured(), secs(300)));
+
+        backoff.reset("default/a");
+        assert!(within(backoff.failure("default/a"), secs(4)));
+    }
+
+    #[test]
+    fn stale_failures_are_evicted() {
+        let backoff = Backoff::new(Duration::from_millis(10), Duration::from_millis(20));
+
+        backoff.failure("default/deleted");
+        backoff.failure("default/deleted");
+        std::thread::sleep(Duration::from_millis(50));
+
+        // Failures of other objects prune those which stopped failing.
+        backoff.failure("default/other");
+        assert_eq!(backoff.failures.lock().unwrap().len(), 1);
+        assert!(within(
+            backoff.failure("default/deleted"),
+            Duration::from_millis(10)
+        ));
+    }
+}
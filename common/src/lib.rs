@@ -0,0 +1,11 @@
+//! Plumbing shared by the kubizone, zonefile and provider operators, such as
+//! retry [`Backoff`], reconciliation [`Metrics`] and the [`Scope`] limiting
+//! the resources they operate on.
+
+mod backoff;
+pub mod metrics;
+pub mod scope;
+
+pub use backoff::Backoff;
+pub use metrics::Metrics;
+pub use scope::{Scope, ScopeArgs};
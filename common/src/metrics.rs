@@ -0,0 +1,91 @@
+use std::{collections::BTreeMap, net::SocketAddr, sync::Mutex, time::Duration};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+use tracing::*;
+
+/// Time to wait before accepting connections again after failing to, such as
+/// when running out of file descriptors.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Counts reconciliation outcomes per controller, exposed in the Prometheus text format.
+#[derive(Default)]
+pub struct Metrics {
+    /// Keyed by (controller, result), where result is either `Success` or the
+    /// category of the error the reconciliation failed with.
+    reconciliations: Mutex<BTreeMap<(&'static str, &'static str), u64>>,
+}
+
+impl Metrics {
+    pub fn reconciled(&self, controller: &'static str, result: &'static str) {
+        *self
+            .reconciliations
+            .lock()
+            .unwrap()
+            .entry((controller, result))
+            .or_default() += 1;
+    }
+
+    pub fn render(&self) -> String {
+        let mut output = String::from(
+            "# HELP kubizone_reconciliations_total Reconciliations by controller and result.\n\
+             # TYPE kubizone_reconciliations_total counter\n",
+        );
+
+        for ((controller, result), count) in self.reconciliations.lock().unwrap().iter() {
+            output.push_str(&format!(
+                "kubizone_reconciliations_total{{controller=\"{controller}\",result=\"{result}\"}} {count}\n"
+            ));
+        }
+
+        output
+    }
+}
+
+/// Serve the metrics over plain HTTP on `address`.
+///
+/// Every request is answered with the metrics regardless of method or path,
+/// since this is only ever meant to be scraped.
+pub async fn serve(address: SocketAddr, metrics: std::sync::Arc<Metrics>) {
+    let listener = match TcpListener::bind(address).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            // Losing metrics is no reason to stop reconciling.
+            error!("failed to bind metrics listener to {address}: {err}");
+            return std::future::pending().await;
+        }
+    };
+
+    info!("serving metrics on {address}");
+
+    loop {
+        let mut stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                warn!("failed to accept metrics connection: {err}");
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+                continue;
+            }
+        };
+
+        let body = metrics.render();
+        tokio::spawn(async move {
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request).await;
+
+            let response = format!(
+                "HTTP/1.1 200 OK\r\n\
+                 content-type: text/plain; version=0.0.4\r\n\
+                 content-length: {}\r\n\
+                 connection: close\r\n\r\n{body}",
+                body.len()
+            );
+
+            if let Err(err) = stream.write_all(response.as_bytes()).await {
+                debug!("failed to write metrics response: {err}");
+            }
+        });
+    }
+}
@@ -0,0 +1,242 @@
+use std::fmt::Debug;
+
+use futures::{channel::mpsc, stream::BoxStream, StreamExt};
+use k8s_openapi::serde::de::DeserializeOwned;
+use kube::{
+    api::ListParams,
+    core::NamespaceResourceScope,
+    runtime::{reflector::ObjectRef, watcher, WatchStreamExt},
+    Api, Client, Resource,
+};
+use tracing::log::*;
+
+/// Limits the resources the controllers operate on to a set of namespaces
+/// and/or resources matching a label selector.
+///
+/// This allows running the operator without cluster-wide permissions, or running
+/// multiple independent instances of it within the same cluster.
+#[derive(Debug, Clone, Default)]
+pub struct Scope {
+    /// Namespaces to operate within. Empty means all namespaces.
+    pub namespaces: Vec<String>,
+
+    /// Label selector which resources must match, such as `environment=production`.
+    pub selector: Option<String>,
+}
+
+/// Command line flags for the [`Scope`] of a controller, shared by all binaries.
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct ScopeArgs {
+    /// Only operate on resources within this namespace. Can be specified
+    /// multiple times. Defaults to all namespaces.
+    #[arg(long = "namespace", short = 'n', global = true)]
+    pub namespaces: Vec<String>,
+
+    /// Only operate on resources matching this label selector, such as
+    /// `kubi.zone/instance=production`.
+    #[arg(long, short = 'l', global = true)]
+    pub selector: Option<String>,
+}
+
+impl ScopeArgs {
+    pub fn into_scope(self) -> Scope {
+        Scope {
+            namespaces: self.namespaces,
+            selector: self.selector,
+        }
+    }
+}
+
+impl Scope {
+    /// Produce one [`Api`] per namespace in scope, or a single cluster-wide
+    /// [`Api`] if the scope is not limited to any namespaces.
+    pub fn apis<K>(&self, client: &Client) -> Vec<Api<K>>
+    where
+        K: Resource<Scope = NamespaceResourceScope>,
+        <K as Resource>::DynamicType: Default,
+    {
+        if self.namespaces.is_empty() {
+            vec![Api::all(client.clone())]
+        } else {
+            self.namespaces
+                .iter()
+                .map(|namespace| Api::namespaced(client.clone(), namespace))
+                .collect()
+        }
+    }
+
+    /// Namespace of each of the [`Scope::apis`], in the same order, with
+    /// `None` standing in for all namespaces.
+    pub fn api_namespaces(&self) -> Vec<Option<String>> {
+        if self.namespaces.is_empty() {
+            vec![None]
+        } else {
+            self.namespaces.iter().cloned().map(Some).collect()
+        }
+    }
+
+    pub fn contains_namespace(&self, namespace: &str) -> bool {
+        self.namespaces.is_empty() || self.namespaces.iter().any(|scoped| scoped == namespace)
+    }
+
+    pub fn watcher_config(&self) -> watcher::Config {
+        match &self.selector {
+            Some(selector) => watcher::Config::default().labels(selector),
+            None => watcher::Config::default(),
+        }
+    }
+
+    /// Restrict `params` to resources matching the label selector of the scope,
+    /// in addition to any label selector already present.
+    pub fn list_params(&self, params: ListParams) -> ListParams {
+        match (&self.selector, &params.label_selector) {
+            (Some(selector), Some(existing)) if !existing.is_empty() => {
+                let combined = format!("{existing},{selector}");
+                params.labels(&combined)
+            }
+            (Some(selector), _) => params.labels(selector),
+            (None, _) => params,
+        }
+    }
+
+    /// List resources across all namespaces in scope.
+    pub async fn list<K>(&self, client: &Client, params: &ListParams) -> Result<Vec<K>, kube::Error>
+    where
+        K: Resource<Scope = NamespaceResourceScope> + Clone + DeserializeOwned + Debug,
+        <K as Resource>::DynamicType: Default,
+    {
+        let params = self.list_params(params.clone());
+
+        let mut resources = Vec::new();
+        for api in self.apis::<K>(client) {
+            resources.extend(api.list(&params).await?.items);
+        }
+
+        Ok(resources)
+    }
+
+    /// List resources within `namespace`, treating namespaces outside of the
+    /// scope as empty.
+    pub async fn list_in<K>(
+        &self,
+        client: &Client,
+        namespace: &str,
+        params: &ListParams,
+    ) -> Result<Vec<K>, kube::Error>
+    where
+        K: Resource<Scope = NamespaceResourceScope> + Clone + DeserializeOwned + Debug,
+        <K as Resource>::DynamicType: Default,
+    {
+        if !self.contains_namespace(namespace) {
+            return Ok(Vec::new());
+        }
+
+        Ok(Api::<K>::namespaced(client.clone(), namespace)
+            .list(&self.list_params(params.clone()))
+            .await?
+            .items)
+    }
+
+    /// Watch resources across all namespaces in scope with a single watcher
+    /// per namespace, handing every change to each of the `consumers` streams.
+    ///
+    /// This lets the per-namespace controllers share the watches of the
+    /// resources they are triggered by, rather than each of them watching
+    /// every namespace in scope.
+    pub fn shared_watch<K>(
+        &self,
+        client: &Client,
+        consumers: usize,
+    ) -> Vec<BoxStream<'static, Result<K, watcher::Error>>>
+    where
+        K: Resource<Scope = NamespaceResourceScope>
+            + Clone
+            + DeserializeOwned
+            + Debug
+            + Send
+            + Sync
+            + 'static,
+        <K as Resource>::DynamicType: Default,
+    {
+        let watchers = self.apis::<K>(client).into_iter().map(|api| {
+            watcher(api, self.watcher_config())
+                .default_backoff()
+                .touched_objects()
+                .boxed()
+        });
+
+        let mut changes = futures::stream::select_all(watchers);
+        let (senders, receivers): (Vec<_>, Vec<_>) =
+            (0..consumers).map(|_| mpsc::unbounded()).unzip();
+
+        tokio::spawn(async move {
+            while let Some(change) = changes.next().await {
+                match change {
+                    Ok(object) => {
+                        for sender in &senders {
+                            // Consumers only go away when shutting down.
+                            let _ = sender.unbounded_send(object.clone());
+                        }
+                    }
+                    Err(err) => warn!("shared watch failed: {err}"),
+                }
+            }
+        });
+
+        receivers
+            .into_iter()
+            .map(|receiver| receiver.map(Ok).boxed())
+            .collect()
+    }
+
+    /// Fetch a single resource, treating resources outside of the scope as missing.
+    pub async fn get_opt<K>(
+        &self,
+        client: &Client,
+        namespace: &str,
+        name: &str,
+    ) -> Result<Option<K>, kube::Error>
+    where
+        K: Resource<Scope = NamespaceResourceScope> + Clone + DeserializeOwned + Debug,
+        <K as Resource>::DynamicType: Default,
+    {
+        if !self.contains_namespace(namespace) {
+            return Ok(None);
+        }
+
+        let api = Api::<K>::namespaced(client.clone(), namespace);
+
+        match &self.selector {
+            None => api.get_opt(name).await,
+            // Let the API server evaluate the selector for us, rather than
+            // trying to match it against the resource's labels ourselves.
+            Some(selector) => Ok(api
+                .list(
+                    &ListParams::default()
+                        .labels(selector)
+                        .fields(&format!("metadata.name={name}")),
+                )
+                .await?
+                .items
+                .into_iter()
+                .next()),
+        }
+    }
+}
+
+/// Narrow `mapper` down to references to objects within `namespace`, so the
+/// per-namespace controller of `namespace` among the [`Scope::apis`] is not
+/// triggered for objects it doesn't manage. `None` allows all namespaces.
+pub fn within<K, Parent>(
+    namespace: Option<String>,
+    mapper: impl Fn(K) -> Option<ObjectRef<Parent>>,
+) -> impl Fn(K) -> Option<ObjectRef<Parent>>
+where
+    Parent: Resource,
+{
+    move |object| {
+        mapper(object).filter(|reference| {
+            namespace.is_none() || reference.namespace.as_ref() == namespace.as_ref()
+        })
+    }
+}
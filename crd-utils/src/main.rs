@@ -10,7 +10,7 @@ use kube::{
     },
     Api, Client, CustomResourceExt, Resource,
 };
-use kubizone_crds::v1alpha1::{Record, Zone};
+use kubizone_crds::v1alpha1::{Record, Zone, ZoneRevision};
 use zonefile_crds::ZoneFile;
 
 #[derive(Debug, Parser)]
@@ -39,6 +39,7 @@ async fn main() {
         Command::Dump => {
             write_to_path::<Zone>().unwrap();
             write_to_path::<Record>().unwrap();
+            write_to_path::<ZoneRevision>().unwrap();
             write_to_path::<ZoneFile>().unwrap();
         }
         Command::Recreate {
@@ -46,7 +47,7 @@ async fn main() {
         } => {
             if !yes_im_sure_i_want_to_delete_all_resources {
                 eprintln!("Recreating all custom resource definitions will automatically delete");
-                eprintln!("**ALL** zones, records and zonefiles across the ENTIRE CLUSTER!\n");
+                eprintln!("**ALL** zones, records, zone revisions and zonefiles across the ENTIRE CLUSTER!\n");
                 eprintln!("If you are ABSOLUTELY SURE THAT IS WHAT YOU WANT TO DO, then you");
                 eprintln!("must set the --yes-im-sure-i-want-to-delete-all-resources flag");
                 exit(1)
@@ -57,6 +58,7 @@ async fn main() {
 
             recreate_crd_destructively::<Zone>(api.clone()).await;
             recreate_crd_destructively::<Record>(api.clone()).await;
+            recreate_crd_destructively::<ZoneRevision>(api.clone()).await;
             recreate_crd_destructively::<ZoneFile>(api.clone()).await;
         }
     }
@@ -4,20 +4,65 @@ pub mod v1alpha1;
 
 use kube::{runtime::reflector::ObjectRef, Resource, ResourceExt};
 use serde::de::DeserializeOwned;
+use v1alpha1::ZoneRef;
 
+/// Label attached to [`Zone`](v1alpha1::Zone)s and [`Record`](v1alpha1::Record)s
+/// which have been adopted by a parent zone.
+///
+/// The value is a fixed-length hash of the parent reference as produced by
+/// [`ZoneRef::as_label`], which allows looking up children of a zone using
+/// label selectors, regardless of the length or contents of the parent's name.
+/// The full reference is kept in the [`PARENT_ZONE_ANNOTATION`].
 pub const PARENT_ZONE_LABEL: &str = "kubi.zone/parent-zone";
 
-pub fn watch_reference<Parent, K>(label: &'static str) -> impl Fn(K) -> Option<ObjectRef<Parent>>
+/// Annotation holding the full `namespace/name` reference to the parent zone
+/// of a [`Zone`](v1alpha1::Zone) or [`Record`](v1alpha1::Record).
+pub const PARENT_ZONE_ANNOTATION: &str = "kubi.zone/parent-zone";
+
+/// Label attached to [`ZoneRevision`](v1alpha1::ZoneRevision)s, identifying the
+/// zone they are a revision of by the hash produced by [`ZoneRef::as_label`].
+pub const ZONE_REVISION_LABEL: &str = "kubi.zone/revision-of";
+
+/// Annotation on [`Zone`](v1alpha1::Zone)s which, when set to `"true"`, lets
+/// provider operators take over record sets at the provider which have not
+/// been marked as owned by anyone yet.
+pub const PROVIDER_TAKEOVER_ANNOTATION: &str = "kubi.zone/provider-takeover";
+
+/// Read the reference stored under `key`, preferring the annotation and falling
+/// back to the legacy `name.namespace` label format written by earlier versions
+/// of the controllers, so objects which have not been migrated yet are still
+/// understood.
+pub fn get_reference<K: ResourceExt>(object: &K, key: &str) -> Option<ZoneRef> {
+    if let Some(annotation) = object.annotations().get(key) {
+        return ZoneRef::from_annotation(annotation);
+    }
+
+    object
+        .labels()
+        .get(key)
+        .and_then(|label| ZoneRef::from_legacy_label(label))
+}
+
+/// Checks whether the reference stored under `key` on the object points to `target`.
+///
+/// Used to weed out any hash collisions after listing objects by label.
+pub fn references<K: ResourceExt>(object: &K, key: &str, target: &ZoneRef) -> bool {
+    get_reference(object, key).as_ref() == Some(target)
+}
+
+pub fn watch_reference<Parent, K>(key: &'static str) -> impl Fn(K) -> Option<ObjectRef<Parent>>
 where
     K: ResourceExt,
     Parent: Clone + Resource + DeserializeOwned + Debug + Send + 'static,
     Parent::DynamicType: Default + Debug + Clone + Eq + Hash,
 {
-    |object| {
-        let parent = object.labels().get(label)?;
-
-        let (name, namespace) = parent.split_once('.')?;
+    move |object| {
+        let parent = get_reference(&object, key)?;
 
-        Some(ObjectRef::new(name).within(namespace))
+        let object_ref = ObjectRef::new(&parent.name);
+        Some(match parent.namespace {
+            Some(namespace) => object_ref.within(&namespace),
+            None => object_ref,
+        })
     }
 }
@@ -0,0 +1,107 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Describes an aspect of the current state of a resource, modelled after
+/// the conditions used by built-in Kubernetes resources.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Condition {
+    /// Type of the condition, such as `ParentResolved`.
+    #[serde(rename = "type")]
+    pub type_: String,
+
+    /// Status of the condition, one of `True`, `False` or `Unknown`.
+    pub status: String,
+
+    /// Machine-readable, CamelCase reason for the condition's last transition.
+    pub reason: String,
+
+    /// Human-readable explanation of the condition.
+    #[serde(default)]
+    pub message: String,
+
+    /// RFC 3339 timestamp of the last time the status of the condition changed.
+    pub last_transition_time: String,
+}
+
+impl Condition {
+    pub fn new(
+        type_: &str,
+        status: bool,
+        reason: &str,
+        message: impl Into<String>,
+        now: impl Into<String>,
+    ) -> Self {
+        Condition {
+            type_: type_.to_string(),
+            status: if status { "True" } else { "False" }.to_string(),
+            reason: reason.to_string(),
+            message: message.into(),
+            last_transition_time: now.into(),
+        }
+    }
+}
+
+/// Insert `condition` into the list, replacing any existing condition of the same type.
+///
+/// The last transition time of the existing condition is kept if its status did not
+/// change. Returns whether the list of conditions was modified.
+pub fn set_condition(conditions: &mut Vec<Condition>, mut condition: Condition) -> bool {
+    let Some(existing) = conditions
+        .iter_mut()
+        .find(|existing| existing.type_ == condition.type_)
+    else {
+        conditions.push(condition);
+        return true;
+    };
+
+    if existing.status == condition.status {
+        condition
+            .last_transition_time
+            .clone_from(&existing.last_transition_time);
+    }
+
+    if *existing == condition {
+        return false;
+    }
+
+    *existing = condition;
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{set_condition, Condition};
+
+    #[test]
+    fn transition_time_only_changes_with_status() {
+        let mut conditions = Vec::new();
+
+        assert!(set_condition(
+            &mut conditions,
+            Condition::new("ParentResolved", false, "ZoneRefCycle", "a -> b -> a", "t0")
+        ));
+
+        // Same status, same everything: no change.
+        assert!(!set_condition(
+            &mut conditions,
+            Condition::new("ParentResolved", false, "ZoneRefCycle", "a -> b -> a", "t1")
+        ));
+        assert_eq!(conditions[0].last_transition_time, "t0");
+
+        // Changed message, but same status: transition time is kept.
+        assert!(set_condition(
+            &mut conditions,
+            Condition::new("ParentResolved", false, "ZoneRefCycle", "a -> c -> a", "t2")
+        ));
+        assert_eq!(conditions[0].last_transition_time, "t0");
+
+        // Changed status: transition time is updated.
+        assert!(set_condition(
+            &mut conditions,
+            Condition::new("ParentResolved", true, "Resolved", "", "t3")
+        ));
+        assert_eq!(conditions.len(), 1);
+        assert_eq!(conditions[0].last_transition_time, "t3");
+    }
+}
@@ -4,7 +4,7 @@ use kube::{CustomResource, ResourceExt};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use super::ZoneRef;
+use super::{Condition, ZoneRef};
 
 #[derive(
     CustomResource,
@@ -28,7 +28,7 @@ use super::ZoneRef;
 #[kube(printcolumn = r#"{"name":"data", "jsonPath": ".spec.rdata", "type": "string"}"#)]
 #[kube(printcolumn = r#"{"name":"fqdn", "jsonPath": ".status.fqdn", "type": "string"}"#)]
 #[kube(
-    printcolumn = r#"{"name":"parent", "jsonPath": ".metadata.labels.kubi\\.zone/parent-zone", "type": "string"}"#
+    printcolumn = r#"{"name":"parent", "jsonPath": ".metadata.annotations.kubi\\.zone/parent-zone", "type": "string"}"#
 )]
 #[serde(rename_all = "camelCase")]
 pub struct RecordSpec {
@@ -42,9 +42,14 @@ pub struct RecordSpec {
     pub rdata: String,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[derive(Default, Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub struct RecordStatus {
     pub fqdn: Option<String>,
+
+    /// Observations of the record's state made by the controller, such as
+    /// the outcome of the latest reconciliation.
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
 }
 
 impl Record {
@@ -1,12 +1,16 @@
+mod condition;
 mod dnsrecord;
 mod zone;
+mod zonerevision;
 
 use std::fmt::Display;
 
+pub use condition::*;
 pub use dnsrecord::*;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 pub use zone::*;
+pub use zonerevision::*;
 
 #[derive(
     Serialize, Deserialize, Clone, Debug, JsonSchema, Hash, PartialEq, Eq, PartialOrd, Ord,
@@ -17,12 +21,78 @@ pub struct ZoneRef {
 }
 
 impl ZoneRef {
+    /// Produce a label-safe representation of this reference.
+    ///
+    /// Label values are limited to 63 characters, which both names and
+    /// namespaces can exceed on their own, so the reference is hashed into
+    /// a fixed-length hexadecimal string instead. The hash is not reversible,
+    /// use [`ZoneRef::as_annotation`] where the reference needs to be read back.
     pub fn as_label(&self) -> String {
-        if let Some(namespace) = &self.namespace {
-            format!("{}.{namespace}", self.name)
-        } else {
-            self.name.clone()
+        // 64-bit FNV-1a. Unlike the std hashers, this is guaranteed to
+        // be stable across compiler versions and platforms, which matters
+        // since the label values outlive any single controller version.
+        let hash = self
+            .as_annotation()
+            .bytes()
+            .fold(0xcbf29ce484222325u64, |hash, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+            });
+
+        format!("{hash:016x}")
+    }
+
+    /// Produce an unambiguous `namespace/name` representation of this reference,
+    /// which can be parsed back using [`ZoneRef::from_annotation`].
+    pub fn as_annotation(&self) -> String {
+        self.to_string()
+    }
+
+    /// Parse a reference produced by [`ZoneRef::as_annotation`].
+    pub fn from_annotation(annotation: &str) -> Option<Self> {
+        if annotation.is_empty() {
+            return None;
+        }
+
+        // Neither names nor namespaces can contain slashes.
+        Some(match annotation.split_once('/') {
+            Some((namespace, name)) => ZoneRef {
+                name: name.to_string(),
+                namespace: Some(namespace.to_string()),
+            },
+            None => ZoneRef {
+                name: annotation.to_string(),
+                namespace: None,
+            },
+        })
+    }
+
+    /// Produce the `name.namespace` label value which earlier versions of the
+    /// controllers used for this reference, if it was representable as one.
+    ///
+    /// Used for finding objects which have not been migrated to [`ZoneRef::as_label`] yet.
+    pub fn as_legacy_label(&self) -> Option<String> {
+        let label = format!("{}.{}", self.name, self.namespace.as_ref()?);
+
+        // Label values are at most 63 characters, and resource names can only
+        // contain lowercase alphanumerics, dashes and dots.
+        (label.len() <= 63).then_some(label)
+    }
+
+    /// Parse the `name.namespace` label format used by earlier versions of the controllers.
+    ///
+    /// Names may contain dots, but namespaces can not, so the namespace is everything
+    /// after the *last* dot. Labels without any dot are ambiguous and cannot be parsed.
+    pub fn from_legacy_label(label: &str) -> Option<Self> {
+        let (name, namespace) = label.rsplit_once('.')?;
+
+        if name.is_empty() || namespace.is_empty() {
+            return None;
         }
+
+        Some(ZoneRef {
+            name: name.to_string(),
+            namespace: Some(namespace.to_string()),
+        })
     }
 }
 
@@ -71,7 +141,60 @@ pub mod defaults {
 
 #[cfg(test)]
 mod tests {
-    use crate::v1alpha1::domain_matches_pattern;
+    use crate::v1alpha1::{domain_matches_pattern, ZoneRef};
+
+    #[test]
+    fn zone_ref_encoding() {
+        let dotted = ZoneRef {
+            name: String::from("example.org"),
+            namespace: Some(String::from("default")),
+        };
+
+        // Annotations must survive names containing dots.
+        assert_eq!(dotted.as_annotation(), "default/example.org");
+        assert_eq!(
+            ZoneRef::from_annotation(&dotted.as_annotation()),
+            Some(dotted.clone())
+        );
+
+        // Labels must be valid label values, regardless of input length.
+        let long = ZoneRef {
+            name: "a".repeat(253),
+            namespace: Some("b".repeat(63)),
+        };
+
+        for zone_ref in [&dotted, &long] {
+            let label = zone_ref.as_label();
+            assert_eq!(label.len(), 16);
+            assert!(label.chars().all(|c| c.is_ascii_hexdigit()));
+        }
+
+        assert_ne!(dotted.as_label(), long.as_label());
+
+        // References without a namespace must still be parseable.
+        let bare = ZoneRef {
+            name: String::from("example-org"),
+            namespace: None,
+        };
+        assert_eq!(
+            ZoneRef::from_annotation(&bare.as_annotation()),
+            Some(bare.clone())
+        );
+
+        // Legacy labels split on the last dot, since namespaces cannot contain dots.
+        assert_eq!(
+            ZoneRef::from_legacy_label("example.org.default"),
+            Some(dotted.clone())
+        );
+        assert_eq!(ZoneRef::from_legacy_label("example-org"), None);
+
+        assert_eq!(
+            dotted.as_legacy_label().as_deref(),
+            Some("example.org.default")
+        );
+        assert_eq!(long.as_legacy_label(), None);
+        assert_eq!(bare.as_legacy_label(), None);
+    }
 
     #[test]
     fn pattern_matching() {
@@ -1,11 +1,11 @@
-use std::fmt::Display;
+use std::{collections::BTreeMap, fmt::Display};
 
 use kube::{core::object::HasSpec, CustomResource, ResourceExt};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use tracing::*;
 
-use super::{domain_matches_pattern, Record, ZoneRef};
+use super::{domain_matches_pattern, Condition, Record, ZoneRef};
 
 pub mod defaults {
 
@@ -49,6 +49,17 @@ pub mod defaults {
     pub(super) const fn negative_response_cache() -> u32 {
         NEGATIVE_RESPONSE_CACHE
     }
+
+    /// Enough to go back a few changes, without cluttering the namespace.
+    pub const REVISION_HISTORY_LIMIT: u32 = 10;
+
+    pub(super) const fn revision_history_limit() -> u32 {
+        REVISION_HISTORY_LIMIT
+    }
+
+    pub(super) fn secret_key() -> String {
+        String::from("secret")
+    }
 }
 
 #[derive(
@@ -72,7 +83,7 @@ pub mod defaults {
 #[kube(printcolumn = r#"{"name":"hash", "jsonPath": ".status.hash", "type": "string"}"#)]
 #[kube(printcolumn = r#"{"name":"serial", "jsonPath": ".status.serial", "type": "string"}"#)]
 #[kube(
-    printcolumn = r#"{"name":"parent", "jsonPath": ".metadata.labels.kubi\\.zone/parent-zone", "type": "string"}"#
+    printcolumn = r#"{"name":"parent", "jsonPath": ".metadata.annotations.kubi\\.zone/parent-zone", "type": "string"}"#
 )]
 #[serde(rename_all = "camelCase")]
 pub struct ZoneSpec {
@@ -137,6 +148,166 @@ pub struct ZoneSpec {
     /// [^1]: <https://www.ripe.net/publications/docs/ripe-203>
     #[serde(default = "defaults::negative_response_cache")]
     pub negative_response_cache: u32,
+
+    /// Secondary name servers to send DNS NOTIFY messages to whenever the
+    /// serial of the zone changes, as described in
+    /// [RFC 1996](https://datatracker.ietf.org/doc/html/rfc1996), so they
+    /// transfer the zone right away, rather than at the next refresh.
+    ///
+    /// Each target is an IP address or hostname, optionally followed by a
+    /// port, such as `192.0.2.1`, `[2001:db8::1]:5353` or `ns2.example.org:53`.
+    /// The port defaults to 53.
+    #[serde(default)]
+    pub notify: Vec<String>,
+
+    /// Push the entries of the zone into an existing authoritative name server
+    /// using DNS UPDATE, as described in
+    /// [RFC 2136](https://datatracker.ietf.org/doc/html/rfc2136).
+    #[serde(default)]
+    pub dynamic_update: Option<DynamicUpdate>,
+
+    /// Allow secondary name servers to transfer the zone from `kubizone serve`
+    /// using AXFR and IXFR. Transfers are refused if unset.
+    #[serde(default)]
+    pub transfer: Option<ZoneTransfer>,
+
+    /// Transfer the entries of the zone from an existing primary name server
+    /// using AXFR and IXFR, instead of computing them from its records and
+    /// sub-zones.
+    ///
+    /// The zone is checked for changes every `.spec.refresh` seconds, retried
+    /// every `.spec.retry` seconds while the primary cannot be reached, and
+    /// its entries are removed once the primary has been unreachable for
+    /// `.spec.expire` seconds.
+    #[serde(default)]
+    pub secondary: Option<Secondary>,
+
+    /// Number of [`ZoneRevision`](super::ZoneRevision)s of the zone to keep,
+    /// including the one for its current serial. Older revisions are deleted,
+    /// except for the `.spec.pinnedRevision`. Zero disables revisions.
+    #[serde(default = "defaults::revision_history_limit")]
+    pub revision_history_limit: u32,
+
+    /// Serial of a kept [`ZoneRevision`](super::ZoneRevision) to roll the zone
+    /// back to. While set, the entries of the zone are those of the revision
+    /// rather than computed from its records and sub-zones, under a new serial
+    /// so secondaries pick up the change.
+    #[serde(default)]
+    pub pinned_revision: Option<u32>,
+}
+
+/// Primary name server to transfer the entries of a secondary [`Zone`] from.
+#[derive(
+    Serialize, Deserialize, Clone, Debug, JsonSchema, Hash, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "camelCase")]
+pub struct Secondary {
+    /// Primary name server of the zone, as an IP address or hostname, optionally
+    /// followed by a port, such as `192.0.2.1` or `ns1.example.org:5353`.
+    pub primary: String,
+
+    /// Key to sign transfer requests with, as described in
+    /// [RFC 8945](https://datatracker.ietf.org/doc/html/rfc8945).
+    #[serde(default)]
+    pub tsig: Option<TsigKey>,
+}
+
+/// Secondary name servers allowed to transfer a [`Zone`].
+///
+/// At least one address or key must be listed, otherwise all transfers are refused.
+#[derive(
+    Serialize, Deserialize, Clone, Debug, JsonSchema, Hash, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "camelCase")]
+pub struct ZoneTransfer {
+    /// Addresses or CIDR blocks transfers are allowed from, such as
+    /// `192.0.2.1` or `2001:db8::/32`. Empty allows any address, provided
+    /// requests are signed with one of the `keys`.
+    #[serde(default)]
+    pub allow: Vec<String>,
+
+    /// Keys transfer requests must be signed with one of, as described in
+    /// [RFC 8945](https://datatracker.ietf.org/doc/html/rfc8945).
+    /// Empty allows unsigned requests.
+    #[serde(default)]
+    pub keys: Vec<TsigKey>,
+}
+
+/// Name server to apply the entries of a [`Zone`] to using DNS UPDATE.
+#[derive(
+    Serialize, Deserialize, Clone, Debug, JsonSchema, Hash, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "camelCase")]
+pub struct DynamicUpdate {
+    /// Primary name server of the zone, as an IP address or hostname, optionally
+    /// followed by a port, such as `192.0.2.1` or `ns1.example.org:5353`.
+    pub server: String,
+
+    /// Key to sign updates with, as described in
+    /// [RFC 8945](https://datatracker.ietf.org/doc/html/rfc8945).
+    #[serde(default)]
+    pub tsig: Option<TsigKey>,
+}
+
+#[derive(
+    Serialize, Deserialize, Clone, Debug, JsonSchema, Hash, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "camelCase")]
+pub struct TsigKey {
+    /// Name of the key, as configured on the name server.
+    pub name: String,
+
+    #[serde(default)]
+    pub algorithm: TsigAlgorithm,
+
+    /// Secret in the namespace of the zone holding the base64 encoded key,
+    /// as produced by `tsig-keygen`.
+    pub secret_ref: SecretKeyRef,
+}
+
+#[derive(
+    Default,
+    Serialize,
+    Deserialize,
+    Clone,
+    Copy,
+    Debug,
+    JsonSchema,
+    Hash,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum TsigAlgorithm {
+    HmacSha1,
+    #[default]
+    HmacSha256,
+    HmacSha512,
+}
+
+impl TsigAlgorithm {
+    /// Name of the algorithm, as used within TSIG records.
+    pub fn name(&self) -> &'static str {
+        match self {
+            TsigAlgorithm::HmacSha1 => "hmac-sha1.",
+            TsigAlgorithm::HmacSha256 => "hmac-sha256.",
+            TsigAlgorithm::HmacSha512 => "hmac-sha512.",
+        }
+    }
+}
+
+/// Reference to a key within a `Secret`.
+#[derive(
+    Serialize, Deserialize, Clone, Debug, JsonSchema, Hash, PartialEq, Eq, PartialOrd, Ord,
+)]
+pub struct SecretKeyRef {
+    pub name: String,
+
+    /// Key within the `Secret`. Defaults to `secret`.
+    #[serde(default = "defaults::secret_key")]
+    pub key: String,
 }
 
 impl Zone {
@@ -269,9 +440,142 @@ pub struct ZoneStatus {
     /// [RFC 1912](https://datatracker.ietf.org/doc/html/rfc1912#section-2.2)
     #[serde(default)]
     pub serial: Option<u32>,
+
+    /// Observations of the zone's state made by the controller, such as
+    /// whether or not the zone's parent could be resolved.
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+
+    /// Outcome of notifying each of the `.spec.notify` targets of the current serial.
+    #[serde(default)]
+    pub notifications: Vec<Notification>,
+
+    /// Entries applied to the name server configured in `.spec.dynamicUpdate`.
+    #[serde(default)]
+    pub dynamic_update: Option<DynamicUpdateStatus>,
+
+    /// Outcome of transferring the zone from the primary configured in `.spec.secondary`.
+    #[serde(default)]
+    pub secondary: Option<SecondaryStatus>,
+
+    /// Outcome of synchronizing the zone with each DNS provider, keyed by
+    /// the name of the provider.
+    #[serde(default)]
+    pub providers: BTreeMap<String, ProviderStatus>,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone, Debug, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderStatus {
+    /// Serial of the zone whose entries the provider was last brought in sync with, if any.
+    #[serde(default)]
+    pub serial: Option<u32>,
+
+    /// Whether the provider matched the `.status.entries` after the last check.
+    #[serde(default)]
+    pub in_sync: bool,
+
+    /// Whether changes are only planned, and never applied to the provider.
+    #[serde(default)]
+    pub dry_run: bool,
+
+    /// Changes needed to bring the provider in line with the `.status.entries`,
+    /// as found during the last check. These have been applied, unless
+    /// `dryRun` is set or the check failed.
+    #[serde(default)]
+    pub drift: Vec<String>,
+
+    /// Record sets of the zone which exist at the provider, but are not owned
+    /// by kubizone and are therefore left alone.
+    #[serde(default)]
+    pub conflicts: Vec<String>,
+
+    /// Time of the last check of the provider which changed its status.
+    /// Checks finding the same outcome as before are not recorded, so they
+    /// don't trigger another reconciliation of the zone.
+    #[serde(default)]
+    pub last_check: Option<String>,
+
+    /// Reason the last check failed, if it did.
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SecondaryStatus {
+    /// Primary name server the entries were transferred from.
+    pub primary: String,
+
+    /// Time the entries were last confirmed to be up to date with the primary.
+    #[serde(default)]
+    pub last_refresh: Option<String>,
+
+    /// Time of the last attempt at checking the primary for changes.
+    #[serde(default)]
+    pub last_attempt: Option<String>,
+
+    /// Whether the entries have been removed, after the primary could not be
+    /// reached for longer than `.spec.expire` seconds.
+    #[serde(default)]
+    pub expired: bool,
+
+    /// Reason the last attempt failed, if it did.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DynamicUpdateStatus {
+    /// Name server the entries were applied to.
+    pub server: String,
+
+    /// Serial of the zone whose entries have all been applied, if any.
+    #[serde(default)]
+    pub serial: Option<u32>,
+
+    /// Entries known to have been applied to the name server, which are
+    /// compared against the `.status.entries` to compute the next update.
+    #[serde(default)]
+    pub applied: Vec<ZoneEntry>,
+
+    /// Time of the last attempt at updating the name server.
+    #[serde(default)]
+    pub last_attempt: Option<String>,
+
+    /// Reason the last update failed, if it did.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema, Hash)]
+/// Outcome of sending a DNS NOTIFY message to a secondary name server.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Notification {
+    /// Secondary name server, as listed in `.spec.notify`.
+    pub target: String,
+
+    /// Serial the secondary was notified of.
+    pub serial: u32,
+
+    /// Whether the secondary acknowledged the notification.
+    pub acknowledged: bool,
+
+    /// Time of the last attempt at notifying the secondary.
+    pub last_attempt: String,
+
+    /// Time after which the secondary is notified again, if it did not
+    /// acknowledge the notification.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_attempt: Option<String>,
+
+    /// Reason the notification was not acknowledged, if it wasn't.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema, Hash, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct ZoneEntry {
     pub fqdn: String,
@@ -299,12 +603,12 @@ impl RecordDelegation {
     pub fn validate(&self, zone_fqdn: &str, record_type: &str, domain: &str) -> bool {
         let record_type = record_type.to_uppercase();
 
-        return domain_matches_pattern(&self.pattern.replace('@', zone_fqdn), domain)
+        domain_matches_pattern(&self.pattern.replace('@', zone_fqdn), domain)
             && (self.types.is_empty()
                 || self
                     .types
                     .iter()
-                    .any(|delegated_type| delegated_type.to_uppercase() == record_type));
+                    .any(|delegated_type| delegated_type.to_uppercase() == record_type))
     }
 }
 
@@ -414,7 +718,8 @@ mod tests {
                 rdata: String::from("192.168.0.1")
             },
             status: Some(RecordStatus {
-                fqdn: Some(String::from("www.example.org."))
+                fqdn: Some(String::from("www.example.org.")),
+                ..Default::default()
             })
         }));
 
@@ -492,7 +797,8 @@ mod tests {
                 rdata: String::from("10 mail1.example.org.")
             },
             status: Some(RecordStatus {
-                fqdn: Some(String::from("example.org."))
+                fqdn: Some(String::from("example.org.")),
+                ..Default::default()
             })
         }));
 
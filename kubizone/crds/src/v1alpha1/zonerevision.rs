@@ -0,0 +1,108 @@
+use kube::{CustomResource, ResourceExt};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::{Zone, ZoneEntry};
+
+/// Snapshot of the entries of a [`Zone`] at one of its serials, written by the
+/// controller whenever the serial changes.
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema, PartialEq, Eq)]
+#[kube(
+    group = "kubi.zone",
+    version = "v1alpha1",
+    kind = "ZoneRevision",
+    namespaced
+)]
+#[kube(printcolumn = r#"{"name":"zone", "jsonPath": ".spec.zone", "type": "string"}"#)]
+#[kube(printcolumn = r#"{"name":"fqdn", "jsonPath": ".spec.fqdn", "type": "string"}"#)]
+#[kube(printcolumn = r#"{"name":"serial", "jsonPath": ".spec.serial", "type": "string"}"#)]
+#[kube(
+    printcolumn = r#"{"name":"age", "jsonPath": ".metadata.creationTimestamp", "type": "date"}"#
+)]
+#[serde(rename_all = "camelCase")]
+pub struct ZoneRevisionSpec {
+    /// Name of the zone in the same namespace this is a revision of.
+    pub zone: String,
+
+    /// Fully qualified domain name of the zone at the time.
+    pub fqdn: String,
+
+    pub serial: u32,
+
+    pub hash: String,
+
+    /// Serial of the revision this one was compared against, if any was kept.
+    #[serde(default)]
+    pub previous_serial: Option<u32>,
+
+    /// Entries of the zone at this serial.
+    #[serde(default)]
+    pub entries: Vec<ZoneEntry>,
+
+    /// Entries which were not part of the previous revision.
+    #[serde(default)]
+    pub added: Vec<ZoneEntry>,
+
+    /// Entries of the previous revision which are no longer part of the zone.
+    #[serde(default)]
+    pub removed: Vec<ZoneEntry>,
+}
+
+/// Longest name Kubernetes allows for objects.
+const MAX_NAME_LENGTH: usize = 253;
+
+impl ZoneRevision {
+    /// Name of the revision of `zone` at `serial`, such as `example-org-2023120101`.
+    ///
+    /// Names of zones which are too long to fit the serial are shortened,
+    /// and made unique again by the hash of the zone's [`ZoneRef::as_label`](super::ZoneRef::as_label).
+    pub fn name_for(zone: &Zone, serial: u32) -> String {
+        let name = zone.name_any();
+        let full = format!("{name}-{serial}");
+        if full.len() <= MAX_NAME_LENGTH {
+            return full;
+        }
+
+        // Names are ASCII, and must not end in a separator before the suffix.
+        let suffix = format!("-{}-{serial}", zone.zone_ref().as_label());
+        let prefix = name[..MAX_NAME_LENGTH - suffix.len()].trim_end_matches(['-', '.']);
+
+        format!("{prefix}{suffix}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kube::core::ObjectMeta;
+
+    use super::{Zone, ZoneRevision};
+
+    fn zone(name: &str) -> Zone {
+        Zone {
+            metadata: ObjectMeta {
+                name: Some(name.to_string()),
+                namespace: Some(String::from("default")),
+                ..Default::default()
+            },
+            spec: Default::default(),
+            status: None,
+        }
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(
+            ZoneRevision::name_for(&zone("example-org"), 2023120101),
+            "example-org-2023120101"
+        );
+
+        let long = format!("{}.example-org", "a".repeat(250));
+        let name = ZoneRevision::name_for(&zone(&long), 2023120101);
+        assert_eq!(name.len(), 253);
+        assert!(name.ends_with("-2023120101"));
+
+        // Zones sharing the same prefix still get distinct names.
+        let other = format!("{}.example-net", "a".repeat(250));
+        assert_ne!(ZoneRevision::name_for(&zone(&other), 2023120101), name);
+    }
+}
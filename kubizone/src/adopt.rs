@@ -0,0 +1,544 @@
+use std::collections::BTreeSet;
+
+use kube::{
+    api::{ListParams, ObjectMeta, PostParams},
+    Api, Client, ResourceExt,
+};
+use kubizone_crds::v1alpha1::{
+    Record, RecordSpec, RecordStatus, SecretKeyRef, TsigAlgorithm, TsigKey, Zone, ZoneEntry,
+};
+use kubizone_provider::{parse_secret_ref, DnsProvider, Owner, ProviderArgs};
+
+use crate::{record::longest_parent_zone, secondary, tsig};
+
+const FIELD_MANAGER: &str = "kubi.zone/adopt";
+
+/// Where to read the live entries of the adopted zone from.
+#[derive(Debug, clap::Subcommand)]
+pub enum Source {
+    /// Transfer the zone from a name server using AXFR.
+    Axfr {
+        /// Name server to transfer the zone from, given as an address or host
+        /// name, optionally followed by a port, such as `ns1.example.org:53`.
+        #[arg(long)]
+        primary: String,
+
+        /// Name of the TSIG key to authenticate the transfer with.
+        #[arg(long, requires = "tsig_secret")]
+        tsig_key: Option<String>,
+
+        /// Algorithm of the TSIG key.
+        #[arg(long, value_parser = parse_algorithm, default_value = "hmac-sha256")]
+        tsig_algorithm: TsigAlgorithm,
+
+        /// Secret holding the base64 encoded TSIG key in its `secret` key,
+        /// given as `namespace/name`.
+        #[arg(long, value_parser = parse_secret_ref, requires = "tsig_key")]
+        tsig_secret: Option<(String, String)>,
+    },
+    /// Read the record sets of the zone from a DNS provider.
+    #[command(flatten)]
+    Provider(ProviderArgs),
+}
+
+fn parse_algorithm(value: &str) -> Result<TsigAlgorithm, String> {
+    k8s_openapi::serde_json::from_value(k8s_openapi::serde_json::json!(value))
+        .map_err(|_| format!("unknown algorithm {value}"))
+}
+
+/// Live entry which could not be turned into a record, and why.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Unplaced {
+    pub entry: String,
+    pub reason: String,
+}
+
+/// Outcome of matching the live entries of a zone against the zones in the cluster.
+#[derive(Debug, Default)]
+pub struct Adoption {
+    /// Records to create, one for each entry not served by a zone yet.
+    pub records: Vec<Record>,
+    /// Entries which are already served by the zone they belong to, along
+    /// with that zone.
+    pub existing: Vec<(ZoneEntry, String)>,
+    /// Entries for which a record exists in the namespace already, such as
+    /// from a previous adoption, along with that record.
+    pub adopted: Vec<(ZoneEntry, String)>,
+    pub unplaced: Vec<Unplaced>,
+}
+
+fn describe(entry: &ZoneEntry) -> String {
+    format!(
+        "{} {} {} {} {}",
+        entry.fqdn, entry.ttl, entry.class, entry.type_, entry.rdata
+    )
+}
+
+/// Produce a resource name from a domain name, such as `example-org` for `example.org.`.
+fn resource_name(domain: &str) -> String {
+    let mut name = String::with_capacity(domain.len());
+    for c in domain.to_lowercase().chars() {
+        match c {
+            'a'..='z' | '0'..='9' => name.push(c),
+            '*' => name.push_str("wildcard"),
+            _ if !name.is_empty() && !name.ends_with('-') => name.push('-'),
+            _ => (),
+        }
+    }
+
+    // Resource names are limited to 253 characters, and must end in an alphanumeric.
+    name.truncate(253);
+    name.trim_end_matches('-').to_string()
+}
+
+/// Match the live `entries` of the zone `origin` against the `zones` in the
+/// cluster, producing a record in the `namespace` for each entry which the
+/// delegations of the most specific zone containing it allow.
+///
+/// Entries for which one of the `existing` records in the namespace exists
+/// already are skipped, so adopting a zone again does not duplicate them.
+///
+/// Records are named after the zone they are placed in, their name within it,
+/// and their type, avoiding the names of existing records. SOA records are
+/// skipped, since zones produce their own.
+pub fn place(
+    origin: &str,
+    namespace: &str,
+    entries: &[ZoneEntry],
+    zones: &[Zone],
+    existing: &[Record],
+) -> Adoption {
+    let mut adoption = Adoption::default();
+    let mut names: BTreeSet<_> = existing.iter().map(ResourceExt::name_any).collect();
+
+    for entry in entries.iter().filter(|entry| entry.type_ != "SOA") {
+        let fqdn = entry.fqdn.to_lowercase();
+        if fqdn != origin && !fqdn.ends_with(&format!(".{origin}")) {
+            adoption.unplaced.push(Unplaced {
+                entry: describe(entry),
+                reason: format!("outside of zone {origin}"),
+            });
+            continue;
+        }
+
+        // Records which have not been reconciled yet only have their domain
+        // name, which is the fqdn for records created by adoption.
+        let adopted = existing.iter().find(|record| {
+            record
+                .fqdn()
+                .unwrap_or(&record.spec.domain_name)
+                .eq_ignore_ascii_case(&fqdn)
+                && record.spec.type_ == entry.type_
+                && record.spec.class == entry.class
+                && record.spec.rdata == entry.rdata
+        });
+
+        if let Some(record) = adopted {
+            adoption.adopted.push((entry.clone(), record.to_string()));
+            continue;
+        }
+
+        let mut record = Record {
+            metadata: ObjectMeta {
+                namespace: Some(namespace.to_string()),
+                ..Default::default()
+            },
+            spec: RecordSpec {
+                domain_name: fqdn.clone(),
+                zone_ref: None,
+                type_: entry.type_.clone(),
+                class: entry.class.clone(),
+                ttl: Some(entry.ttl),
+                rdata: entry.rdata.clone(),
+            },
+            // The fqdn is what the record controller would determine, and
+            // is needed to find the zones which can adopt the record.
+            status: Some(RecordStatus {
+                fqdn: Some(fqdn.clone()),
+                ..Default::default()
+            }),
+        };
+
+        let Some(zone) = longest_parent_zone(&record, zones) else {
+            adoption.unplaced.push(Unplaced {
+                entry: describe(entry),
+                reason: format!(
+                    "no zone delegates {} records for {fqdn} to namespace {namespace}",
+                    entry.type_
+                ),
+            });
+            continue;
+        };
+
+        let served = zone.status.as_ref().is_some_and(|status| {
+            status.entries.iter().any(|existing| {
+                existing.fqdn.eq_ignore_ascii_case(&fqdn)
+                    && existing.type_ == entry.type_
+                    && existing.class == entry.class
+                    && existing.rdata == entry.rdata
+            })
+        });
+
+        if served {
+            adoption.existing.push((entry.clone(), zone.to_string()));
+            continue;
+        }
+
+        // Unwrap safety: zones without an fqdn are never picked as parents.
+        let zone_fqdn = zone.fqdn().unwrap();
+        let base = match fqdn.strip_suffix(zone_fqdn) {
+            Some(relative) if !relative.is_empty() => format!(
+                "{}-{}-{}",
+                zone.name_any(),
+                resource_name(relative),
+                entry.type_.to_lowercase()
+            ),
+            _ => format!("{}-{}", zone.name_any(), entry.type_.to_lowercase()),
+        };
+
+        let base = resource_name(&base);
+        let mut name = base.clone();
+        let mut count = 1;
+        while names.contains(&name) {
+            count += 1;
+            name = resource_name(&format!("{base}-{count}"));
+        }
+
+        names.insert(name.clone());
+        record.metadata.name = Some(name);
+        record.status = None;
+        adoption.records.push(record);
+    }
+
+    adoption
+}
+
+/// Read the live entries of the zone `origin` from a DNS provider.
+///
+/// Record sets reserved for the provider, and the ownership markers written
+/// by `kubizone-provider`, are not part of the zone. Record sets which the
+/// provider would not let kubizone manage cannot be adopted.
+async fn provider_entries(
+    provider: &dyn DnsProvider,
+    origin: &str,
+) -> Result<(Vec<ZoneEntry>, Vec<Unplaced>), String> {
+    let mut entries = Vec::new();
+    let mut unplaced = Vec::new();
+
+    let sets = provider.list(origin).await.map_err(|err| err.to_string())?;
+
+    for set in sets {
+        if set.reserved(origin) || Owner::is_marker(&set) {
+            continue;
+        }
+
+        if !provider.owns(origin, &set) {
+            unplaced.push(Unplaced {
+                entry: set.to_string(),
+                reason: format!("cannot be represented outside of {}", provider.name()),
+            });
+            continue;
+        }
+
+        entries.extend(set.records.iter().map(|rdata| ZoneEntry {
+            fqdn: set.name.clone(),
+            type_: set.type_.clone(),
+            class: String::from("IN"),
+            ttl: set.ttl,
+            rdata: rdata.clone(),
+        }));
+    }
+
+    Ok((entries, unplaced))
+}
+
+/// Read the live entries of the zone `origin` from the `source`, and create
+/// a Record in the `namespace` for each of them which is not served by a zone
+/// in the cluster yet. With `dry_run`, the records are printed as manifests
+/// instead of being created.
+///
+/// Entries which cannot be placed are reported, and make the adoption fail
+/// once all other records have been created.
+pub async fn adopt(
+    client: Client,
+    origin: &str,
+    namespace: &str,
+    source: &Source,
+    dry_run: bool,
+) -> Result<(), String> {
+    let (entries, mut unplaced) = match source {
+        Source::Axfr {
+            primary,
+            tsig_key,
+            tsig_algorithm,
+            tsig_secret,
+        } => {
+            let key = match (tsig_key, tsig_secret) {
+                (Some(name), Some((secret_namespace, secret_name))) => Some(
+                    tsig::Key::from_secret(
+                        client.clone(),
+                        secret_namespace,
+                        &TsigKey {
+                            name: name.clone(),
+                            algorithm: *tsig_algorithm,
+                            secret_ref: SecretKeyRef {
+                                name: secret_name.clone(),
+                                key: String::from("secret"),
+                            },
+                        },
+                    )
+                    .await
+                    .map_err(|err| err.to_string())?,
+                ),
+                _ => None,
+            };
+
+            let (_, entries) = secondary::transfer(primary, origin, key.as_ref()).await?;
+            (entries, Vec::new())
+        }
+        Source::Provider(args) => {
+            let provider = args.connect(&client).await.map_err(|err| err.to_string())?;
+            provider_entries(provider.as_ref(), origin).await?
+        }
+    };
+
+    let zones = Api::<Zone>::all(client.clone())
+        .list(&ListParams::default())
+        .await
+        .map_err(|err| err.to_string())?;
+
+    let records = Api::<Record>::namespaced(client.clone(), namespace);
+    let existing = records
+        .list(&ListParams::default())
+        .await
+        .map_err(|err| err.to_string())?;
+
+    let mut adoption = place(origin, namespace, &entries, &zones.items, &existing.items);
+    unplaced.append(&mut adoption.unplaced);
+
+    for (entry, zone) in &adoption.existing {
+        eprintln!("skipped {}: already served by zone {zone}", describe(entry));
+    }
+
+    for (entry, record) in &adoption.adopted {
+        eprintln!(
+            "skipped {}: already adopted as record {record}",
+            describe(entry)
+        );
+    }
+
+    for record in &adoption.records {
+        if dry_run {
+            print!(
+                "---\n{}",
+                // Unwrap safety: records always serialize.
+                serde_yaml::to_string(record).unwrap()
+            );
+            continue;
+        }
+
+        let params = PostParams {
+            field_manager: Some(FIELD_MANAGER.to_string()),
+            ..Default::default()
+        };
+
+        records
+            .create(&params, record)
+            .await
+            .map_err(|err| format!("failed to create record {record}: {err}"))?;
+
+        println!(
+            "created record {record} for {} {} {}",
+            record.spec.domain_name, record.spec.type_, record.spec.rdata
+        );
+    }
+
+    for Unplaced { entry, reason } in &unplaced {
+        eprintln!("could not place {entry}: {reason}");
+    }
+
+    if unplaced.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} of the entries of zone {origin} could not be placed",
+            unplaced.len()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kube::core::ObjectMeta;
+    use kubizone_crds::v1alpha1::{
+        Delegation, Record, RecordDelegation, RecordSpec, Zone, ZoneEntry, ZoneSpec, ZoneStatus,
+    };
+
+    use super::{place, Unplaced};
+
+    fn zone(name: &str, fqdn: &str, delegations: Vec<Delegation>, entries: Vec<ZoneEntry>) -> Zone {
+        Zone {
+            metadata: ObjectMeta {
+                name: Some(name.to_string()),
+                namespace: Some(String::from("dns")),
+                ..Default::default()
+            },
+            spec: ZoneSpec {
+                domain_name: fqdn.to_string(),
+                delegations,
+                ..Default::default()
+            },
+            status: Some(ZoneStatus {
+                fqdn: Some(fqdn.to_string()),
+                entries,
+                ..Default::default()
+            }),
+        }
+    }
+
+    fn delegation(namespace: &str, pattern: &str, types: &[&str]) -> Delegation {
+        Delegation {
+            namespaces: vec![namespace.to_string()],
+            zones: vec![],
+            records: vec![RecordDelegation {
+                pattern: pattern.to_string(),
+                types: types.iter().map(ToString::to_string).collect(),
+            }],
+        }
+    }
+
+    fn entry(fqdn: &str, type_: &str, rdata: &str) -> ZoneEntry {
+        ZoneEntry {
+            fqdn: fqdn.to_string(),
+            type_: type_.to_string(),
+            class: String::from("IN"),
+            ttl: 300,
+            rdata: rdata.to_string(),
+        }
+    }
+
+    #[test]
+    fn placement() {
+        let zones = vec![
+            zone(
+                "example-org",
+                "example.org.",
+                vec![delegation("adopted", "*.example.org.", &["A", "MX"])],
+                vec![entry("www.example.org.", "A", "192.0.2.1")],
+            ),
+            zone(
+                "dev",
+                "dev.example.org.",
+                vec![delegation("adopted", "*.dev.example.org.", &["A", "AAAA"])],
+                vec![],
+            ),
+        ];
+
+        let entries = [
+            entry(
+                "example.org.",
+                "SOA",
+                "ns1.example.org. hostmaster.example.org. 1 7200 3600 86400 300",
+            ),
+            entry("example.org.", "MX", "10 mail.example.org."),
+            entry("www.example.org.", "A", "192.0.2.1"),
+            entry("Mail.example.org.", "A", "192.0.2.2"),
+            entry("mail.example.org.", "A", "192.0.2.3"),
+            entry("api.dev.example.org.", "A", "192.0.2.4"),
+            entry("api.dev.example.org.", "TXT", "\"v=1\""),
+            entry("www.example.com.", "A", "192.0.2.5"),
+        ];
+
+        // Records named like the ones being adopted, but for other entries.
+        let taken = vec![Record {
+            metadata: ObjectMeta {
+                name: Some(String::from("example-org-mx")),
+                namespace: Some(String::from("adopted")),
+                ..Default::default()
+            },
+            spec: RecordSpec {
+                domain_name: String::from("example.org."),
+                zone_ref: None,
+                type_: String::from("MX"),
+                class: String::from("IN"),
+                ttl: None,
+                rdata: String::from("20 backup.example.org."),
+            },
+            status: None,
+        }];
+        let adoption = place("example.org.", "adopted", &entries, &zones, &taken);
+
+        let names: Vec<_> = adoption
+            .records
+            .iter()
+            .map(|record| {
+                (
+                    record.metadata.name.as_deref().unwrap(),
+                    record.spec.domain_name.as_str(),
+                )
+            })
+            .collect();
+
+        // Records go into the most specific zone which accepts them, and are
+        // named after it, without colliding with existing records.
+        assert_eq!(
+            names,
+            [
+                ("example-org-mx-2", "example.org."),
+                ("example-org-mail-a", "mail.example.org."),
+                ("example-org-mail-a-2", "mail.example.org."),
+                ("dev-api-a", "api.dev.example.org."),
+            ]
+        );
+
+        assert!(adoption.records.iter().all(|record| {
+            record.metadata.namespace.as_deref() == Some("adopted")
+                && record.spec.ttl == Some(300)
+                && record.status.is_none()
+        }));
+
+        // Entries which the zone serves already are left alone.
+        assert_eq!(
+            adoption.existing,
+            [(
+                entry("www.example.org.", "A", "192.0.2.1"),
+                String::from("dns/example-org")
+            )]
+        );
+
+        assert_eq!(
+            adoption.unplaced,
+            [
+                Unplaced {
+                    entry: String::from("api.dev.example.org. 300 IN TXT \"v=1\""),
+                    reason: String::from(
+                        "no zone delegates TXT records for api.dev.example.org. to namespace adopted"
+                    ),
+                },
+                Unplaced {
+                    entry: String::from("www.example.com. 300 IN A 192.0.2.5"),
+                    reason: String::from("outside of zone example.org."),
+                },
+            ]
+        );
+
+        // Adopting the zone again skips the records created the first time.
+        let mut existing = taken.clone();
+        existing.extend(adoption.records);
+        let again = place("example.org.", "adopted", &entries, &zones, &existing);
+        assert!(again.records.is_empty());
+        assert_eq!(again.adopted.len(), 4);
+        assert_eq!(
+            again.adopted[0],
+            (
+                entry("example.org.", "MX", "10 mail.example.org."),
+                String::from("adopted/example-org-mx-2")
+            )
+        );
+
+        // Nothing can be placed in namespaces the zones do not delegate to.
+        let adoption = place("example.org.", "other", &entries, &zones, &taken);
+        assert!(adoption.records.is_empty());
+        assert_eq!(adoption.unplaced.len(), 7);
+    }
+}
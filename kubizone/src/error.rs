@@ -0,0 +1,115 @@
+use std::fmt::Display;
+
+use kubizone_crds::v1alpha1::ZoneRef;
+
+/// Condition reporting the outcome of the most recent reconciliation
+/// of a [`Zone`](kubizone_crds::v1alpha1::Zone) or
+/// [`Record`](kubizone_crds::v1alpha1::Record).
+///
+/// On failure, the reason of the condition is the [`Error::category`].
+pub const RECONCILED_CONDITION: &str = "Reconciled";
+
+/// Reason used for the [`RECONCILED_CONDITION`] and metrics when reconciliation succeeds.
+pub const SUCCESS: &str = "Success";
+
+/// Errors encountered while reconciling zones and records.
+#[derive(Debug)]
+pub enum Error {
+    /// Communication with the Kubernetes API failed. Usually transient.
+    Kube(kube::Error),
+    /// The `zoneRef` points to a zone which does not exist (yet).
+    MissingParent(ZoneRef),
+    /// The parent zone exists, but has not had its fully qualified domain name
+    /// determined yet.
+    ParentNotReady(ZoneRef),
+    /// The parent zone exists, but its delegations do not allow adoption.
+    DelegationDenied { parent: String, fqdn: String },
+    /// The resource has both or neither of a `zoneRef` and a fully qualified domain name.
+    InvalidSpec(String),
+    /// The chain of `zoneRef`s loops back on itself.
+    ZoneRefCycle(String),
+    /// The chain of `zoneRef`s is excessively long.
+    ZoneRefTooDeep(String),
+    /// The Secret holding a TSIG key does not exist, or does not contain a valid key.
+    InvalidKey(String),
+    /// Applying the entries of a zone to its name server using DNS UPDATE failed.
+    DynamicUpdate(String),
+    /// Transferring the entries of a secondary zone from its primary failed.
+    Transfer(String),
+    /// The zone is pinned to a revision which does not exist (anymore).
+    MissingRevision(String),
+}
+
+impl Error {
+    /// Short, CamelCase name of the kind of error, used as the reason in the
+    /// [`RECONCILED_CONDITION`] and as a label on metrics.
+    pub fn category(&self) -> &'static str {
+        match self {
+            Error::Kube(_) => "KubeApi",
+            Error::MissingParent(_) => "MissingParent",
+            Error::ParentNotReady(_) => "ParentNotReady",
+            Error::DelegationDenied { .. } => "DelegationDenied",
+            Error::InvalidSpec(_) => "InvalidSpec",
+            Error::ZoneRefCycle(_) => "ZoneRefCycle",
+            Error::ZoneRefTooDeep(_) => "ZoneRefTooDeep",
+            Error::InvalidKey(_) => "InvalidKey",
+            Error::DynamicUpdate(_) => "DynamicUpdate",
+            Error::Transfer(_) => "Transfer",
+            Error::MissingRevision(_) => "MissingRevision",
+        }
+    }
+
+    /// Misconfigurations cannot be resolved without user intervention, so
+    /// there is no point in retrying them with increasing urgency.
+    pub fn is_misconfiguration(&self) -> bool {
+        matches!(
+            self,
+            Error::DelegationDenied { .. }
+                | Error::InvalidSpec(_)
+                | Error::ZoneRefCycle(_)
+                | Error::ZoneRefTooDeep(_)
+                | Error::InvalidKey(_)
+                | Error::MissingRevision(_)
+        )
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Kube(error) => write!(f, "kubernetes api error: {error}"),
+            Error::MissingParent(zone_ref) => write!(f, "parent zone {zone_ref} does not exist"),
+            Error::ParentNotReady(zone_ref) => {
+                write!(f, "parent zone {zone_ref} has no fqdn yet")
+            }
+            Error::DelegationDenied { parent, fqdn } => write!(
+                f,
+                "delegations of parent zone {parent} do not allow adoption of {fqdn}"
+            ),
+            Error::InvalidSpec(reason) => write!(f, "invalid spec: {reason}"),
+            Error::ZoneRefCycle(path) => write!(f, "zoneRef chain forms a cycle: {path}"),
+            Error::ZoneRefTooDeep(path) => {
+                write!(f, "zoneRef chain exceeds the maximum depth: {path}")
+            }
+            Error::InvalidKey(reason) => write!(f, "invalid tsig key: {reason}"),
+            Error::DynamicUpdate(reason) => write!(f, "dynamic update failed: {reason}"),
+            Error::Transfer(reason) => write!(f, "zone transfer failed: {reason}"),
+            Error::MissingRevision(name) => write!(f, "pinned revision {name} does not exist"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Kube(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<kube::Error> for Error {
+    fn from(error: kube::Error) -> Self {
+        Error::Kube(error)
+    }
+}
@@ -1,9 +1,25 @@
-use clap::{command, Parser, Subcommand};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use clap::{Parser, Subcommand};
 use kube::Client;
 
+mod adopt;
+mod error;
+mod notify;
+mod plan;
+mod rdata;
 mod record;
+mod revision;
+mod secondary;
+mod serve;
+mod transfer;
+mod tsig;
+mod update;
+mod wire;
 mod zone;
 
+use kubizone_common::{metrics, Backoff, Metrics, ScopeArgs};
+
 #[derive(Debug, Parser)]
 #[command(author, version, about, long_about = None)]
 struct Args {
@@ -13,21 +29,203 @@ struct Args {
 
 #[derive(Debug, Subcommand)]
 enum Command {
-    Reconcile,
+    Reconcile(ReconcileArgs),
+    /// Print the changes the controllers would make to the zones and records
+    /// currently in the cluster, without applying them.
+    Plan(PlanArgs),
+    /// Answer DNS queries over UDP and TCP directly from the zones in the cluster.
+    Serve(ServeArgs),
+    /// Create Records for the live records of an existing zone, read from a
+    /// name server or DNS provider, placing them in the zones which delegate them.
+    Adopt(AdoptArgs),
+}
+
+#[derive(Debug, clap::Args)]
+struct ReconcileArgs {
+    #[command(flatten)]
+    scope: ScopeArgs,
+
+    /// Seconds between periodic reconciliations of healthy zones.
+    #[arg(long, default_value_t = 300)]
+    zone_requeue: u64,
+
+    /// Seconds between periodic reconciliations of healthy records.
+    #[arg(long, default_value_t = 30)]
+    record_requeue: u64,
+
+    /// Seconds before retrying a failed reconciliation for the first time.
+    /// The delay doubles with every consecutive failure.
+    #[arg(long, default_value_t = 5)]
+    backoff_base: u64,
+
+    /// Upper limit in seconds for the delay between retries of failed reconciliations.
+    #[arg(long, default_value_t = 300)]
+    backoff_max: u64,
+
+    /// Seconds before re-checking resources which cannot be reconciled without
+    /// user intervention, such as ones with invalid specs or denied delegations.
+    #[arg(long, default_value_t = 300)]
+    misconfigured_requeue: u64,
+
+    /// Address on which to serve Prometheus metrics, such as `0.0.0.0:9090`.
+    #[arg(long)]
+    metrics_address: Option<SocketAddr>,
+}
+
+#[derive(Debug, clap::Args)]
+struct PlanArgs {
+    #[command(flatten)]
+    scope: ScopeArgs,
+}
+
+#[derive(Debug, clap::Args)]
+struct ServeArgs {
+    #[command(flatten)]
+    scope: ScopeArgs,
+
+    /// Address on which to answer DNS queries, over both UDP and TCP.
+    #[arg(long, default_value = "0.0.0.0:53")]
+    address: SocketAddr,
+
+    /// Number of previous versions of each zone kept in memory, for answering
+    /// IXFR requests with the differences since a secondary's version.
+    #[arg(long, default_value_t = 16)]
+    ixfr_history: usize,
+}
+
+#[derive(Debug, clap::Args)]
+struct AdoptArgs {
+    /// Fully qualified domain name of the zone to adopt.
+    #[arg(long)]
+    origin: String,
+
+    /// Namespace to create the Records in.
+    #[arg(long, short = 'n', default_value = "default")]
+    namespace: String,
+
+    /// Print the Records as manifests, instead of creating them.
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    #[command(subcommand)]
+    source: adopt::Source,
+}
+
+impl ReconcileArgs {
+    fn backoff(&self) -> Backoff {
+        Backoff::new(
+            Duration::from_secs(self.backoff_base),
+            Duration::from_secs(self.backoff_max),
+        )
+        .with_misconfigured(Duration::from_secs(self.misconfigured_requeue))
+    }
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
-    tracing_subscriber::fmt::init();
     let args = Args::parse();
 
     match args.command {
-        Command::Reconcile => {
+        Command::Reconcile(args) => {
+            tracing_subscriber::fmt::init();
             let client = Client::try_default().await.unwrap();
+            let metrics = Arc::new(Metrics::default());
+            let scope = args.scope.clone().into_scope();
+
+            let metrics_server = {
+                let metrics = metrics.clone();
+                async move {
+                    match args.metrics_address {
+                        Some(address) => metrics::serve(address, metrics).await,
+                        None => std::future::pending().await,
+                    }
+                }
+            };
 
             tokio::select! {
-                _ = zone::controller(client.clone()) => (),
-                _ = record::controller(client) => ()
+                _ = zone::controller(
+                    client.clone(),
+                    scope.clone(),
+                    args.backoff(),
+                    metrics.clone(),
+                    Duration::from_secs(args.zone_requeue),
+                ) => (),
+                _ = update::controller(
+                    client.clone(),
+                    scope.clone(),
+                    args.backoff(),
+                    metrics.clone(),
+                    Duration::from_secs(args.zone_requeue),
+                ) => (),
+                _ = revision::controller(
+                    client.clone(),
+                    scope.clone(),
+                    args.backoff(),
+                    metrics.clone(),
+                    Duration::from_secs(args.zone_requeue),
+                ) => (),
+                _ = notify::controller(
+                    client.clone(),
+                    scope.clone(),
+                    args.backoff(),
+                    metrics.clone(),
+                ) => (),
+                _ = secondary::controller(
+                    client.clone(),
+                    scope.clone(),
+                    args.backoff(),
+                    metrics.clone(),
+                ) => (),
+                _ = record::controller(
+                    client,
+                    scope,
+                    args.backoff(),
+                    metrics,
+                    Duration::from_secs(args.record_requeue),
+                ) => (),
+                _ = metrics_server => (),
+            }
+        }
+        Command::Plan(args) => {
+            // Keep stdout clean for the plan itself.
+            tracing_subscriber::fmt()
+                .with_writer(std::io::stderr)
+                .init();
+
+            let client = Client::try_default().await.unwrap();
+            if let Err(err) = plan::plan(client, args.scope.into_scope()).await {
+                eprintln!("failed to compute plan: {err}");
+                std::process::exit(1);
+            }
+        }
+        Command::Serve(args) => {
+            tracing_subscriber::fmt::init();
+            let client = Client::try_default().await.unwrap();
+            if let Err(err) = serve::run(
+                client,
+                args.scope.into_scope(),
+                args.address,
+                args.ixfr_history,
+            )
+            .await
+            {
+                eprintln!("failed to serve zones on {}: {err}", args.address);
+                std::process::exit(1);
+            }
+        }
+        Command::Adopt(args) => {
+            // Keep stdout clean for the records, in case of a dry run.
+            tracing_subscriber::fmt()
+                .with_writer(std::io::stderr)
+                .init();
+
+            let origin = format!("{}.", args.origin.trim_end_matches('.')).to_lowercase();
+            let client = Client::try_default().await.unwrap();
+            if let Err(err) =
+                adopt::adopt(client, &origin, &args.namespace, &args.source, args.dry_run).await
+            {
+                eprintln!("failed to adopt zone {origin}: {err}");
+                std::process::exit(1);
             }
         }
     }
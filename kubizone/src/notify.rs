@@ -0,0 +1,383 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use futures::StreamExt;
+use k8s_openapi::serde_json::json;
+use kube::{
+    api::{Patch, PatchParams},
+    runtime::{controller::Action, Controller},
+    Api, Client, ResourceExt,
+};
+use kubizone_common::{Backoff, Metrics, Scope};
+use kubizone_crds::v1alpha1::{Notification, Zone};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tokio::net::{lookup_host, UdpSocket};
+use tracing::log::*;
+
+use crate::{
+    error::{Error, SUCCESS},
+    wire::{opcode, rcode, types, Header, Message, Question, CLASS_IN},
+    zone::{now, parse_time},
+};
+
+const CONTROLLER_NAME: &str = "kubi.zone/notify";
+
+/// Number of times a NOTIFY message is sent before giving up on the secondary.
+pub const ATTEMPTS: u32 = 3;
+
+/// Time to wait for a response to each attempt.
+pub const TIMEOUT: Duration = Duration::from_secs(2);
+
+const DEFAULT_PORT: u16 = 53;
+
+/// Resolve a name server address, such as `192.0.2.1`,
+/// `[2001:db8::1]:5353` or `ns2.example.org`, into a socket address.
+pub async fn resolve(target: &str) -> Result<SocketAddr, String> {
+    if let Ok(address) = target.parse::<SocketAddr>() {
+        return Ok(address);
+    }
+
+    if let Ok(address) = target.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(address, DEFAULT_PORT));
+    }
+
+    let host = match target.rsplit_once(':') {
+        Some((_, port)) if port.parse::<u16>().is_ok() => target.to_string(),
+        _ => format!("{target}:{DEFAULT_PORT}"),
+    };
+
+    let mut addresses = lookup_host(host)
+        .await
+        .map_err(|err| format!("failed to resolve {target}: {err}"))?;
+
+    addresses
+        .next()
+        .ok_or_else(|| format!("{target} does not resolve to any address"))
+}
+
+/// Message ids only need to be hard to guess for off-path attackers spoofing
+/// responses, which is prevented by TSIG where it matters, so the clock will do.
+pub fn message_id() -> u16 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos() as u16
+}
+
+/// Send a NOTIFY message for the zone `origin` to the secondary at `target`,
+/// as described in [RFC 1996](https://datatracker.ietf.org/doc/html/rfc1996),
+/// retrying up to `attempts` times until it is acknowledged.
+pub async fn notify(
+    target: &str,
+    origin: &str,
+    attempts: u32,
+    timeout: Duration,
+) -> Result<(), String> {
+    let address = resolve(target).await?;
+
+    let request = Message {
+        header: Header {
+            id: message_id(),
+            opcode: opcode::NOTIFY,
+            authoritative: true,
+            ..Default::default()
+        },
+        questions: vec![Question {
+            name: origin.to_string(),
+            type_: types::SOA,
+            class: CLASS_IN,
+        }],
+        ..Default::default()
+    };
+
+    let encoded = request.encode().map_err(|err| err.to_string())?;
+
+    let local: SocketAddr = match address {
+        SocketAddr::V4(_) => "0.0.0.0:0",
+        SocketAddr::V6(_) => "[::]:0",
+    }
+    // Unwrap safety: both addresses are valid.
+    .parse()
+    .unwrap();
+
+    let socket = UdpSocket::bind(local)
+        .await
+        .map_err(|err| err.to_string())?;
+
+    // Only accept responses from the secondary itself.
+    socket
+        .connect(address)
+        .await
+        .map_err(|err| err.to_string())?;
+
+    let mut buffer = [0u8; 512];
+    for _ in 0..attempts {
+        socket
+            .send(&encoded)
+            .await
+            .map_err(|err| format!("failed to send to {address}: {err}"))?;
+
+        let deadline = tokio::time::Instant::now() + timeout;
+        while let Ok(received) = tokio::time::timeout_at(deadline, socket.recv(&mut buffer)).await {
+            let length =
+                received.map_err(|err| format!("failed to receive from {address}: {err}"))?;
+
+            // Anything but the response to our request is ignored, rather
+            // than treated as a failure, since it could be a stray packet.
+            let Ok(response) = Message::decode(&buffer[..length]) else {
+                continue;
+            };
+
+            if !response.header.response
+                || response.header.id != request.header.id
+                || response.header.opcode != opcode::NOTIFY
+            {
+                continue;
+            }
+
+            return match response.header.rcode {
+                rcode::NOERROR => Ok(()),
+                code => Err(format!("{address} responded with {}", rcode::name(code))),
+            };
+        }
+    }
+
+    Err(format!(
+        "{address} did not respond after {attempts} attempt(s)"
+    ))
+}
+
+struct Data {
+    client: Client,
+    backoff: Backoff,
+    metrics: Arc<Metrics>,
+}
+
+/// Notify the secondaries listed in the zone's `.spec.notify` of its serial,
+/// unless they have acknowledged it already, and record the outcome in the
+/// zone's status.
+///
+/// Secondaries which don't acknowledge the serial are notified again once
+/// their backoff expires, until they do.
+async fn notify_zone(zone: &Zone, ctx: &Data) -> Result<Action, Error> {
+    let (Some(origin), Some(serial)) = (zone.fqdn(), zone.serial()) else {
+        return Ok(Action::await_change());
+    };
+
+    let started = OffsetDateTime::now_utc();
+    let previous = zone
+        .status
+        .as_ref()
+        .map(|status| status.notifications.as_slice())
+        .unwrap_or_default();
+
+    let notifications: Vec<_> =
+        futures::future::join_all(zone.spec.notify.iter().map(|target| async move {
+            let key = format!("{zone} {target}");
+
+            if let Some(existing) = previous
+                .iter()
+                .find(|notification| &notification.target == target && notification.serial == serial)
+            {
+                // Leaving notifications which are not due as they are keeps
+                // the status, and with it the zone, from changing.
+                let pending = parse_time(existing.next_attempt.as_deref())
+                    .is_some_and(|next_attempt| next_attempt > started);
+
+                if existing.acknowledged || pending {
+                    return existing.clone();
+                }
+            }
+
+            let result = notify(target, origin, ATTEMPTS, TIMEOUT).await;
+            let next_attempt = match &result {
+                Ok(()) => {
+                    info!("secondary {target} acknowledged serial {serial} of zone {zone}");
+                    ctx.backoff.reset(&key);
+                    None
+                }
+                Err(err) => {
+                    let delay = ctx.backoff.failure(&key);
+                    warn!(
+                        "failed to notify {target} of serial {serial} of zone {zone}: {err}, retrying in {}s",
+                        delay.as_secs()
+                    );
+
+                    (OffsetDateTime::now_utc() + delay).format(&Rfc3339).ok()
+                }
+            };
+
+            Notification {
+                target: target.clone(),
+                serial,
+                acknowledged: result.is_ok(),
+                last_attempt: now(),
+                next_attempt,
+                message: result.err(),
+            }
+        }))
+        .await;
+
+    if notifications != previous {
+        Api::<Zone>::namespaced(ctx.client.clone(), zone.namespace().as_ref().unwrap())
+            .patch_status(
+                &zone.name_any(),
+                &PatchParams::apply(CONTROLLER_NAME),
+                &Patch::Merge(json!({
+                    "status": {
+                        "notifications": notifications,
+                    },
+                })),
+            )
+            .await?;
+    }
+
+    // Come back once the earliest of the unacknowledged notifications is due.
+    let retry = notifications
+        .iter()
+        .filter_map(|notification| parse_time(notification.next_attempt.as_deref()))
+        .min()
+        .map(|next_attempt| {
+            (next_attempt - OffsetDateTime::now_utc())
+                .max(time::Duration::ZERO)
+                .unsigned_abs()
+        });
+
+    Ok(retry.map_or_else(Action::await_change, Action::requeue))
+}
+
+async fn reconcile(zone: Arc<Zone>, ctx: Arc<Data>) -> Result<Action, Error> {
+    let result = notify_zone(&zone, &ctx).await;
+
+    ctx.metrics.reconciled(
+        "notify",
+        result
+            .as_ref()
+            .err()
+            .map(Error::category)
+            .unwrap_or(SUCCESS),
+    );
+
+    if result.is_ok() {
+        ctx.backoff.reset(&zone.to_string());
+    }
+
+    result
+}
+
+fn error_policy(zone: Arc<Zone>, error: &Error, ctx: Arc<Data>) -> Action {
+    let delay = if error.is_misconfiguration() {
+        ctx.backoff.misconfigured()
+    } else {
+        ctx.backoff.failure(&zone.to_string())
+    };
+
+    error!(
+        "notifying secondaries of zone {} encountered {} error: {error}, retrying in {}s",
+        zone.name_any(),
+        error.category(),
+        delay.as_secs()
+    );
+    Action::requeue(delay)
+}
+
+/// Send NOTIFY messages for the serial of each zone to the secondaries listed
+/// in its `.spec.notify`.
+///
+/// This runs separately from the zone controller, so waiting for secondaries
+/// which don't respond never holds up the zones themselves.
+pub async fn controller(client: Client, scope: Scope, backoff: Backoff, metrics: Arc<Metrics>) {
+    let data = Arc::new(Data {
+        client: client.clone(),
+        backoff,
+        metrics,
+    });
+
+    let controllers = scope.apis::<Zone>(&client).into_iter().map(|zones| {
+        Controller::new(zones, scope.watcher_config())
+            .shutdown_on_signal()
+            .run(reconcile, error_policy, data.clone())
+            .for_each(|res| async move {
+                match res {
+                    Ok(o) => debug!("notified {:?}", o),
+                    Err(e) => warn!("notifying secondaries failed: {}", e),
+                }
+            })
+    });
+
+    futures::future::join_all(controllers).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::net::UdpSocket;
+
+    use crate::wire::{opcode, rcode, Message};
+
+    use super::notify;
+
+    /// Secondary which ignores the first NOTIFY it receives, and responds with
+    /// `rcode` to the second.
+    async fn secondary(rcode: u8) -> (String, tokio::task::JoinHandle<Message>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap().to_string();
+
+        let handle = tokio::spawn(async move {
+            let mut buffer = [0u8; 512];
+            socket.recv_from(&mut buffer).await.unwrap();
+
+            let (length, peer) = socket.recv_from(&mut buffer).await.unwrap();
+            let request = Message::decode(&buffer[..length]).unwrap();
+
+            let mut response = request.clone();
+            response.header.response = true;
+            response.header.rcode = rcode;
+
+            socket
+                .send_to(&response.encode().unwrap(), peer)
+                .await
+                .unwrap();
+
+            request
+        });
+
+        (address, handle)
+    }
+
+    #[tokio::test]
+    async fn retries_until_acknowledged() {
+        let (address, secondary) = secondary(rcode::NOERROR).await;
+
+        notify(&address, "example.org.", 3, Duration::from_millis(100))
+            .await
+            .unwrap();
+
+        let request = secondary.await.unwrap();
+        assert_eq!(request.header.opcode, opcode::NOTIFY);
+        assert!(request.header.authoritative);
+        assert_eq!(request.questions[0].name, "example.org.");
+    }
+
+    #[tokio::test]
+    async fn failures() {
+        let (address, _) = secondary(rcode::REFUSED).await;
+
+        assert_eq!(
+            notify(&address, "example.org.", 3, Duration::from_millis(100)).await,
+            Err(format!("{address} responded with REFUSED"))
+        );
+
+        // A single attempt is ignored by the secondary.
+        let (address, _) = secondary(rcode::NOERROR).await;
+        assert!(
+            notify(&address, "example.org.", 1, Duration::from_millis(100))
+                .await
+                .is_err()
+        );
+    }
+}
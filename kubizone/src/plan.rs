@@ -0,0 +1,449 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use kube::{api::ListParams, Client, ResourceExt};
+use kubizone_common::Scope;
+use kubizone_crds::{
+    get_reference,
+    v1alpha1::{Record, Zone, ZoneEntry, ZoneRef},
+    PARENT_ZONE_ANNOTATION, PARENT_ZONE_LABEL,
+};
+
+use crate::{
+    error::Error,
+    record,
+    zone::{self, ZoneRefChain, MAX_ZONE_REF_DEPTH},
+};
+
+/// Compute what the zone and record controllers would do to the resources
+/// currently in the cluster, and print the difference without applying any of it.
+pub async fn plan(client: Client, scope: Scope) -> Result<(), kube::Error> {
+    let zones = scope.list::<Zone>(&client, &ListParams::default()).await?;
+    let records = scope
+        .list::<Record>(&client, &ListParams::default())
+        .await?;
+
+    print!(
+        "{}",
+        render_plan(&zones, &records, time::OffsetDateTime::now_utc())
+    );
+
+    Ok(())
+}
+
+/// State of all zones and records after running the controllers to completion.
+struct Simulation {
+    zones: Vec<Zone>,
+    records: Vec<Record>,
+    /// Errors which the controllers would report, keyed by resource.
+    errors: BTreeMap<String, Error>,
+}
+
+/// Repeatedly resolve the fqdns and parents of all `zones` and `records` in memory,
+/// the same way the controllers would, until nothing changes anymore.
+fn simulate(mut zones: Vec<Zone>, mut records: Vec<Record>) -> Simulation {
+    let mut errors = BTreeMap::new();
+
+    // Every pass resolves at least one more level of zoneRefs, so chains
+    // within the depth limit settle within this many passes.
+    for _ in 0..=MAX_ZONE_REF_DEPTH + 1 {
+        errors.clear();
+        let mut changed = false;
+
+        for index in 0..zones.len() {
+            match resolve_zone(&zones[index], &zones) {
+                Ok((fqdn, parent)) => {
+                    let zone = &mut zones[index];
+                    let status = zone.status.get_or_insert_with(Default::default);
+                    changed |= status.fqdn.as_ref() != Some(&fqdn);
+                    status.fqdn = Some(fqdn);
+
+                    if let Some(parent) = parent {
+                        changed |= set_parent(zone, &parent);
+                    }
+                }
+                Err(err) => {
+                    errors.insert(format!("zone {}", zones[index]), err);
+                }
+            }
+        }
+
+        for record in records.iter_mut() {
+            match resolve_record(record, &zones) {
+                Ok((fqdn, parent)) => {
+                    let status = record.status.get_or_insert_with(Default::default);
+                    changed |= status.fqdn.as_ref() != Some(&fqdn);
+                    status.fqdn = Some(fqdn);
+
+                    if let Some(parent) = parent {
+                        changed |= set_parent(record, &parent);
+                    }
+                }
+                Err(err) => {
+                    errors.insert(format!("record {record}"), err);
+                }
+            }
+        }
+
+        if !changed {
+            break;
+        }
+    }
+
+    Simulation {
+        zones,
+        records,
+        errors,
+    }
+}
+
+/// In-memory equivalent of the zone controller's resolution of a zone's fqdn and parent.
+fn resolve_zone(zone: &Zone, zones: &[Zone]) -> Result<(String, Option<ZoneRef>), Error> {
+    let Some(parent_ref) = zone::zone_parent_ref(zone)? else {
+        return Ok((
+            zone.spec.domain_name.clone(),
+            zone::longest_parent_zone(zone, zones).map(Zone::zone_ref),
+        ));
+    };
+
+    match zone::walk_zone_ref_chain(zone, zones) {
+        ZoneRefChain::Terminated => (),
+        ZoneRefChain::Cycle(cycle) => {
+            return Err(Error::ZoneRefCycle(zone::format_chain(
+                cycle.iter().chain(cycle.first()),
+            )))
+        }
+        ZoneRefChain::TooDeep(chain) => {
+            return Err(Error::ZoneRefTooDeep(zone::format_chain(&chain)))
+        }
+    }
+
+    let parent_zone = zones
+        .iter()
+        .find(|candidate| candidate.zone_ref() == parent_ref)
+        .ok_or(Error::MissingParent(parent_ref))?;
+
+    Ok((
+        zone::adopt_zone(zone, parent_zone)?,
+        Some(parent_zone.zone_ref()),
+    ))
+}
+
+/// In-memory equivalent of the record controller's resolution of a record's fqdn and parent.
+fn resolve_record(record: &Record, zones: &[Zone]) -> Result<(String, Option<ZoneRef>), Error> {
+    let Some(parent_ref) = record::record_parent_ref(record)? else {
+        return Ok((
+            record.spec.domain_name.clone(),
+            record::longest_parent_zone(record, zones).map(Zone::zone_ref),
+        ));
+    };
+
+    let parent_zone = zones
+        .iter()
+        .find(|candidate| candidate.zone_ref() == parent_ref)
+        .ok_or(Error::MissingParent(parent_ref))?;
+
+    Ok((
+        record::adopt_record(record, parent_zone)?,
+        Some(parent_zone.zone_ref()),
+    ))
+}
+
+/// Set the parent label and annotation of `object`, returning whether either changed.
+fn set_parent<K: ResourceExt>(object: &mut K, parent: &ZoneRef) -> bool {
+    let label = parent.as_label();
+    let annotation = parent.as_annotation();
+
+    if object.labels().get(PARENT_ZONE_LABEL) == Some(&label)
+        && object.annotations().get(PARENT_ZONE_ANNOTATION) == Some(&annotation)
+    {
+        return false;
+    }
+
+    object
+        .labels_mut()
+        .insert(PARENT_ZONE_LABEL.to_string(), label);
+    object
+        .annotations_mut()
+        .insert(PARENT_ZONE_ANNOTATION.to_string(), annotation);
+    true
+}
+
+/// Describe how the parent reference of `object` would change, if at all.
+fn parent_change<K: ResourceExt>(current: &K, planned: &K) -> Option<String> {
+    let before = get_reference(current, PARENT_ZONE_ANNOTATION);
+    let after = get_reference(planned, PARENT_ZONE_ANNOTATION);
+
+    if before != after {
+        return Some(format!(
+            "parent: {} -> {}",
+            display_or_none(before.as_ref()),
+            display_or_none(after.as_ref())
+        ));
+    }
+
+    // Same parent, but still carrying a label written by an earlier version of the controller.
+    if current.labels().get(PARENT_ZONE_LABEL) != planned.labels().get(PARENT_ZONE_LABEL) {
+        return Some(format!(
+            "parent: {} (migrating legacy label)",
+            display_or_none(after.as_ref())
+        ));
+    }
+
+    None
+}
+
+fn display_or_none(value: Option<&impl std::fmt::Display>) -> String {
+    value
+        .map(ToString::to_string)
+        .unwrap_or_else(|| String::from("<none>"))
+}
+
+fn render_entry(entry: &ZoneEntry) -> String {
+    format!(
+        "{} {} {} {} {}",
+        entry.fqdn, entry.ttl, entry.class, entry.type_, entry.rdata
+    )
+}
+
+/// Lines of `before` missing from `after` prefixed with `-`, followed by
+/// lines of `after` missing from `before` prefixed with `+`.
+fn diff_lines(before: Vec<String>, after: Vec<String>) -> Vec<String> {
+    let mut added = after;
+    let mut removed = Vec::new();
+
+    for line in before {
+        match added.iter().position(|candidate| *candidate == line) {
+            Some(position) => {
+                added.remove(position);
+            }
+            None => removed.push(line),
+        }
+    }
+
+    removed
+        .into_iter()
+        .map(|line| format!("- {line}"))
+        .chain(added.into_iter().map(|line| format!("+ {line}")))
+        .collect()
+}
+
+/// Simulate reconciliation of `zones` and `records` and render the resulting
+/// changes, as well as the errors the controllers would run into.
+fn render_plan(zones: &[Zone], records: &[Record], now: time::OffsetDateTime) -> String {
+    let simulation = simulate(zones.to_vec(), records.to_vec());
+
+    let mut output = String::new();
+    let mut changed_zones = 0;
+    let mut changed_records = 0;
+
+    for (current, planned) in zones.iter().zip(&simulation.zones) {
+        let key = format!("zone {current}");
+        let mut changes = Vec::new();
+
+        if current.fqdn() != planned.fqdn() {
+            changes.push(format!(
+                "fqdn: {} -> {}",
+                display_or_none(current.fqdn().as_ref()),
+                display_or_none(planned.fqdn().as_ref())
+            ));
+        }
+
+        changes.extend(parent_change(current, planned));
+
+        // Zones which fail to reconcile never have their entries updated, the
+        // entries of secondary zones are transferred from their primary, and
+        // those of pinned zones come from their revision.
+        if !simulation.errors.contains_key(&key)
+            && planned.spec.secondary.is_none()
+            && planned.spec.pinned_revision.is_none()
+        {
+            if let Some(contents) =
+                zone::zone_contents(planned, &simulation.zones, &simulation.records, now)
+            {
+                if current.hash() != Some(contents.hash.as_str()) {
+                    changes.push(format!(
+                        "hash: {} -> {}",
+                        display_or_none(current.hash().as_ref()),
+                        contents.hash
+                    ));
+                }
+
+                if current.serial() != Some(contents.serial) {
+                    changes.push(format!(
+                        "serial: {} -> {}",
+                        display_or_none(current.serial().as_ref()),
+                        contents.serial
+                    ));
+                }
+
+                let current_entries = current
+                    .status
+                    .as_ref()
+                    .map(|status| status.entries.iter().map(render_entry).collect())
+                    .unwrap_or_default();
+
+                changes.extend(diff_lines(
+                    current_entries,
+                    contents.entries.iter().map(render_entry).collect(),
+                ));
+            }
+        }
+
+        if !changes.is_empty() {
+            changed_zones += 1;
+        }
+
+        render_resource(&mut output, &key, changes, simulation.errors.get(&key));
+    }
+
+    for (current, planned) in records.iter().zip(&simulation.records) {
+        let key = format!("record {current}");
+        let mut changes = Vec::new();
+
+        if current.fqdn() != planned.fqdn() {
+            changes.push(format!(
+                "fqdn: {} -> {}",
+                display_or_none(current.fqdn().as_ref()),
+                display_or_none(planned.fqdn().as_ref())
+            ));
+        }
+
+        changes.extend(parent_change(current, planned));
+
+        if !changes.is_empty() {
+            changed_records += 1;
+        }
+
+        render_resource(&mut output, &key, changes, simulation.errors.get(&key));
+    }
+
+    if changed_zones == 0 && changed_records == 0 && simulation.errors.is_empty() {
+        output.push_str("No changes.\n");
+    } else {
+        let _ = writeln!(
+            output,
+            "{changed_zones} zone(s) and {changed_records} record(s) would change, {} would fail to reconcile.",
+            simulation.errors.len()
+        );
+    }
+
+    output
+}
+
+fn render_resource(output: &mut String, key: &str, changes: Vec<String>, error: Option<&Error>) {
+    if changes.is_empty() && error.is_none() {
+        return;
+    }
+
+    let _ = writeln!(output, "{key}");
+
+    for change in changes {
+        let _ = writeln!(output, "    {change}");
+    }
+
+    if let Some(error) = error {
+        let _ = writeln!(output, "    error ({}): {error}", error.category());
+    }
+
+    output.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use kube::core::ObjectMeta;
+    use kubizone_crds::v1alpha1::{
+        Delegation, Record, RecordDelegation, RecordSpec, Zone, ZoneRef, ZoneSpec,
+    };
+
+    use super::render_plan;
+
+    fn meta(name: &str) -> ObjectMeta {
+        ObjectMeta {
+            name: Some(name.to_string()),
+            namespace: Some(String::from("default")),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn plan_resolves_zone_ref_chains() {
+        let zones = vec![
+            Zone {
+                metadata: meta("sub"),
+                spec: ZoneSpec {
+                    domain_name: String::from("sub"),
+                    zone_ref: Some(ZoneRef {
+                        name: String::from("example"),
+                        namespace: None,
+                    }),
+                    delegations: vec![Delegation {
+                        namespaces: vec![],
+                        zones: vec![],
+                        records: vec![RecordDelegation {
+                            pattern: String::from("*.sub.example.org."),
+                            types: vec![],
+                        }],
+                    }],
+                    ..Default::default()
+                },
+                status: None,
+            },
+            Zone {
+                metadata: meta("example"),
+                spec: ZoneSpec {
+                    domain_name: String::from("example.org."),
+                    delegations: vec![Delegation {
+                        namespaces: vec![],
+                        zones: vec![String::from("*.example.org.")],
+                        records: vec![],
+                    }],
+                    ..Default::default()
+                },
+                status: None,
+            },
+            Zone {
+                metadata: meta("broken"),
+                spec: ZoneSpec {
+                    domain_name: String::from("broken"),
+                    ..Default::default()
+                },
+                status: None,
+            },
+        ];
+
+        let records = vec![Record {
+            metadata: meta("www"),
+            spec: RecordSpec {
+                domain_name: String::from("www"),
+                zone_ref: Some(ZoneRef {
+                    name: String::from("sub"),
+                    namespace: None,
+                }),
+                type_: String::from("A"),
+                class: String::from("IN"),
+                ttl: Some(300),
+                rdata: String::from("192.168.0.1"),
+            },
+            status: None,
+        }];
+
+        // 2023-12-01T12:00:00Z
+        let now = time::OffsetDateTime::from_unix_timestamp(1701432000).unwrap();
+        let plan = render_plan(&zones, &records, now);
+
+        // The chain of zoneRefs is resolved within a single plan, even though
+        // the sub-zone is listed before its parent.
+        assert!(plan.contains("zone default/sub\n    fqdn: <none> -> sub.example.org.\n"));
+        assert!(plan.contains("    parent: <none> -> default/example\n"));
+        assert!(plan.contains("    serial: <none> -> 2023120100\n"));
+
+        // Records end up as entries of the zone they're adopted by.
+        assert!(plan.contains("record default/www\n    fqdn: <none> -> www.sub.example.org.\n"));
+        assert!(plan.contains("+ www 300 IN A 192.168.0.1\n"));
+
+        assert!(plan.contains("zone default/broken\n    error (InvalidSpec)"));
+        assert!(
+            plan.ends_with("2 zone(s) and 1 record(s) would change, 1 would fail to reconcile.\n")
+        );
+    }
+}
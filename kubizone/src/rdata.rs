@@ -0,0 +1,410 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::wire::{encode_name, types, Reader, WireError};
+
+/// Qualify `name` relative to `origin`, like owner names in zone files.
+pub fn absolute(name: &str, origin: &str) -> String {
+    if name == "@" {
+        origin.to_string()
+    } else if name.ends_with('.') {
+        name.to_string()
+    } else {
+        format!("{name}.{origin}")
+    }
+}
+
+/// Split `rdata` into its fields, decoding quoted strings and escapes,
+/// and dropping the parentheses records such as SOA are often wrapped in.
+fn fields(rdata: &str) -> Result<Vec<Vec<u8>>, String> {
+    let bytes = rdata.as_bytes();
+    let mut fields = Vec::new();
+    let mut position = 0;
+
+    while position < bytes.len() {
+        match bytes[position] {
+            b' ' | b'\t' | b'\n' | b'\r' | b'(' | b')' => {
+                position += 1;
+                continue;
+            }
+            _ => (),
+        }
+
+        let quoted = bytes[position] == b'"';
+        if quoted {
+            position += 1;
+        }
+
+        let mut field = Vec::new();
+        loop {
+            let Some(&byte) = bytes.get(position) else {
+                if quoted {
+                    return Err(format!("unterminated string in {rdata:?}"));
+                }
+                break;
+            };
+
+            match byte {
+                b'"' if quoted => {
+                    position += 1;
+                    break;
+                }
+                b' ' | b'\t' | b'\n' | b'\r' | b'(' | b')' if !quoted => break,
+                b'\\' => {
+                    let digits = bytes.get(position + 1..position + 4);
+                    match digits.filter(|digits| digits.iter().all(u8::is_ascii_digit)) {
+                        // Unwrap safety: three ascii digits are valid utf-8 and a valid number.
+                        Some(digits) => {
+                            let value: u16 = std::str::from_utf8(digits).unwrap().parse().unwrap();
+                            field.push(
+                                u8::try_from(value)
+                                    .map_err(|_| format!("invalid escape in {rdata:?}"))?,
+                            );
+                            position += 4;
+                        }
+                        None => {
+                            let escaped = bytes
+                                .get(position + 1)
+                                .ok_or_else(|| format!("dangling escape in {rdata:?}"))?;
+                            field.push(*escaped);
+                            position += 2;
+                        }
+                    }
+                }
+                byte => {
+                    field.push(byte);
+                    position += 1;
+                }
+            }
+        }
+
+        fields.push(field);
+    }
+
+    Ok(fields)
+}
+
+/// Encode a domain name field of record data, qualifying it relative to `origin`.
+fn name(field: &[u8], origin: &str, output: &mut Vec<u8>) -> Result<(), String> {
+    let name = std::str::from_utf8(field).map_err(|err| err.to_string())?;
+    encode_name(&absolute(name, origin), output).map_err(|err| err.to_string())
+}
+
+fn number<T: std::str::FromStr>(field: &[u8]) -> Result<T, String> {
+    std::str::from_utf8(field)
+        .ok()
+        .and_then(|field| field.parse().ok())
+        .ok_or_else(|| format!("invalid number {:?}", String::from_utf8_lossy(field)))
+}
+
+fn address<T: std::str::FromStr>(field: &[u8]) -> Result<T, String> {
+    std::str::from_utf8(field)
+        .ok()
+        .and_then(|field| field.parse().ok())
+        .ok_or_else(|| format!("invalid address {:?}", String::from_utf8_lossy(field)))
+}
+
+fn character_string(field: &[u8], output: &mut Vec<u8>) -> Result<(), String> {
+    let length = u8::try_from(field.len())
+        .map_err(|_| String::from("character strings are limited to 255 bytes"))?;
+
+    output.push(length);
+    output.extend_from_slice(field);
+    Ok(())
+}
+
+/// Decode hexadecimal data, which may have been split across fields.
+fn hex_data(hex: &[u8], output: &mut Vec<u8>) -> Result<(), ()> {
+    let pairs = hex.chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return Err(());
+    }
+
+    for pair in pairs {
+        let pair = std::str::from_utf8(pair).map_err(|_| ())?;
+        output.push(u8::from_str_radix(pair, 16).map_err(|_| ())?);
+    }
+
+    Ok(())
+}
+
+/// Encode the presentation format `rdata` of a record of the given `type_`
+/// into its wire format, qualifying domain names relative to `origin`.
+///
+/// Types without explicit support can be given in the generic
+/// `\# length hex` format described in [RFC 3597](https://datatracker.ietf.org/doc/html/rfc3597).
+pub fn encode(type_: u16, rdata: &str, origin: &str) -> Result<Vec<u8>, String> {
+    let fields = fields(rdata)?;
+    let fields: Vec<&[u8]> = fields.iter().map(Vec::as_slice).collect();
+    let mut output = Vec::new();
+
+    match (type_, &fields[..]) {
+        // The escape has been decoded along with the others, so the generic
+        // format is recognized by the original data.
+        (_, [b"#", length, hex @ ..]) if rdata.trim_start().starts_with("\\#") => {
+            hex_data(&hex.concat(), &mut output)
+                .map_err(|_| format!("invalid hex data in {rdata:?}"))?;
+
+            if output.len() != number::<usize>(length)? {
+                return Err(format!("length of {rdata:?} does not match its data"));
+            }
+        }
+        (types::A, [ip]) => output.extend_from_slice(&address::<Ipv4Addr>(ip)?.octets()),
+        (types::AAAA, [ip]) => output.extend_from_slice(&address::<Ipv6Addr>(ip)?.octets()),
+        (types::NS | types::CNAME | types::PTR | types::DNAME, [target]) => {
+            name(target, origin, &mut output)?
+        }
+        (types::MX, [preference, exchange]) => {
+            output.extend_from_slice(&number::<u16>(preference)?.to_be_bytes());
+            name(exchange, origin, &mut output)?;
+        }
+        (types::SRV, [priority, weight, port, target]) => {
+            for field in [priority, weight, port] {
+                output.extend_from_slice(&number::<u16>(field)?.to_be_bytes());
+            }
+            name(target, origin, &mut output)?;
+        }
+        (types::TXT | types::SPF, strings) if !strings.is_empty() => {
+            for string in strings {
+                character_string(string, &mut output)?;
+            }
+        }
+        (types::DS, [key_tag, algorithm, digest_type, digest @ ..]) if !digest.is_empty() => {
+            output.extend_from_slice(&number::<u16>(key_tag)?.to_be_bytes());
+            output.push(number::<u8>(algorithm)?);
+            output.push(number::<u8>(digest_type)?);
+            hex_data(&digest.concat(), &mut output)
+                .map_err(|_| format!("invalid digest in {rdata:?}"))?;
+        }
+        (types::CAA, [flags, tag, value]) => {
+            output.push(number::<u8>(flags)?);
+            character_string(tag, &mut output)?;
+            output.extend_from_slice(value);
+        }
+        (types::SOA, [mname, rname, timers @ ..]) if timers.len() == 5 => {
+            name(mname, origin, &mut output)?;
+            name(rname, origin, &mut output)?;
+            for timer in timers {
+                output.extend_from_slice(&number::<u32>(timer)?.to_be_bytes());
+            }
+        }
+        _ => {
+            return Err(format!(
+                "unsupported record data {rdata:?} for type {type_}"
+            ))
+        }
+    }
+
+    Ok(output)
+}
+
+/// Present a character string, quoting it and escaping anything
+/// that isn't printable ascii.
+fn quoted(string: &[u8]) -> String {
+    let mut output = String::from('"');
+    for &byte in string {
+        match byte {
+            b'"' | b'\\' => {
+                output.push('\\');
+                output.push(byte as char);
+            }
+            0x20..=0x7e => output.push(byte as char),
+            byte => output.push_str(&format!("\\{byte:03}")),
+        }
+    }
+    output.push('"');
+    output
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{byte:02X}")).collect()
+}
+
+/// Decode the `length` bytes of record data of the given `type_` at the
+/// position of `reader` into their presentation format, with fully
+/// qualified domain names.
+///
+/// The reader spans the entire message, since names within the data may
+/// be compressed. Types without explicit support, and data which doesn't
+/// fit its type, are presented in the generic `\# length hex` format.
+pub fn decode(type_: u16, reader: &mut Reader, length: usize) -> Result<String, WireError> {
+    let start = reader.position();
+    let end = start + length;
+
+    let decoded = decode_known(type_, reader, length)
+        .ok()
+        .flatten()
+        .filter(|_| reader.position() == end);
+
+    reader.seek(start);
+    let data = reader.bytes(length)?;
+
+    Ok(decoded.unwrap_or_else(|| {
+        if data.is_empty() {
+            String::from("\\# 0")
+        } else {
+            format!("\\# {length} {}", hex(data))
+        }
+    }))
+}
+
+/// Decode data of the types with explicit support, or `None` for other types.
+fn decode_known(
+    type_: u16,
+    reader: &mut Reader,
+    length: usize,
+) -> Result<Option<String>, WireError> {
+    let end = reader.position() + length;
+
+    Ok(Some(match type_ {
+        types::A => match <[u8; 4]>::try_from(reader.bytes(length)?) {
+            Ok(octets) => Ipv4Addr::from(octets).to_string(),
+            Err(_) => return Ok(None),
+        },
+        types::AAAA => match <[u8; 16]>::try_from(reader.bytes(length)?) {
+            Ok(octets) => Ipv6Addr::from(octets).to_string(),
+            Err(_) => return Ok(None),
+        },
+        types::NS | types::CNAME | types::PTR | types::DNAME => reader.name()?,
+        types::MX => format!("{} {}", reader.u16()?, reader.name()?),
+        types::SRV => format!(
+            "{} {} {} {}",
+            reader.u16()?,
+            reader.u16()?,
+            reader.u16()?,
+            reader.name()?
+        ),
+        types::TXT | types::SPF if length > 0 => {
+            let mut strings = Vec::new();
+            while reader.position() < end {
+                let size = reader.bytes(1)?[0] as usize;
+                strings.push(quoted(reader.bytes(size)?));
+            }
+            strings.join(" ")
+        }
+        types::DS if length > 4 => format!(
+            "{} {} {} {}",
+            reader.u16()?,
+            reader.bytes(1)?[0],
+            reader.bytes(1)?[0],
+            hex(reader.bytes(length - 4)?)
+        ),
+        types::CAA => {
+            let flags = reader.bytes(1)?[0];
+            let size = reader.bytes(1)?[0] as usize;
+            let tag = String::from_utf8_lossy(reader.bytes(size)?).to_string();
+            let value = reader.bytes(end.saturating_sub(reader.position()))?;
+            format!("{flags} {tag} {}", quoted(value))
+        }
+        types::SOA => format!(
+            "{} {} {} {} {} {} {}",
+            reader.name()?,
+            reader.name()?,
+            reader.u32()?,
+            reader.u32()?,
+            reader.u32()?,
+            reader.u32()?,
+            reader.u32()?
+        ),
+        _ => return Ok(None),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::wire::{types, Reader};
+
+    use super::{decode, encode};
+
+    #[test]
+    fn encoding() {
+        let origin = "example.org.";
+
+        assert_eq!(
+            encode(types::A, "192.168.0.1", origin).unwrap(),
+            [192, 168, 0, 1]
+        );
+        assert_eq!(
+            encode(types::MX, "10 mail", origin).unwrap(),
+            b"\x00\x0a\x04mail\x07example\x03org\x00"
+        );
+        assert_eq!(
+            encode(types::TXT, r#""v=spf1 -all" "say \"hi\"" \065"#, origin).unwrap(),
+            b"\x0bv=spf1 -all\x08say \"hi\"\x01A"
+        );
+        assert_eq!(
+            encode(types::CAA, r#"0 issue "letsencrypt.org""#, origin).unwrap(),
+            b"\x00\x05issueletsencrypt.org"
+        );
+        assert_eq!(
+            encode(
+                types::SOA,
+                "ns.example.org. noc (2023120100 86400 7200 3600000 360)",
+                origin
+            )
+            .unwrap()
+            .len(),
+            16 + 17 + 20
+        );
+        assert_eq!(
+            encode(
+                types::DS,
+                "60485 5 1 2BB183AF5F225 88179A53B0A98631FAD1A292118",
+                origin
+            )
+            .unwrap()
+            .len(),
+            4 + 20
+        );
+        assert_eq!(
+            encode(99, r"\# 3 abcd ef", origin).unwrap(),
+            [0xab, 0xcd, 0xef]
+        );
+
+        assert!(encode(types::A, "192.168.0", origin).is_err());
+        assert!(encode(types::TXT, r#""unterminated"#, origin).is_err());
+        assert!(encode(types::TXT, &"a".repeat(256), origin).is_err());
+        assert!(encode(1234, "anything", origin).is_err());
+    }
+
+    #[test]
+    fn decoding() {
+        let origin = "example.org.";
+
+        for (type_, rdata) in [
+            (types::A, "192.168.0.1"),
+            (types::AAAA, "2001:db8::1"),
+            (types::CNAME, "www.example.org."),
+            (types::MX, "10 mail.example.org."),
+            (types::SRV, "0 5 5060 sip.example.org."),
+            (types::TXT, r#""v=spf1 -all" "say \"hi\"" "\009""#),
+            (types::CAA, r#"0 issue "letsencrypt.org""#),
+            (
+                types::DS,
+                "60485 5 1 2BB183AF5F22588179A53B0A98631FAD1A292118",
+            ),
+            (
+                types::SOA,
+                "ns.example.org. noc.example.org. 2023120100 86400 7200 3600000 360",
+            ),
+            (99, r"\# 3 ABCDEF"),
+        ] {
+            let data = encode(type_, rdata, origin).unwrap();
+            let mut reader = Reader::new(&data);
+            assert_eq!(decode(type_, &mut reader, data.len()).unwrap(), rdata);
+            assert_eq!(reader.position(), data.len());
+        }
+
+        // Names may be compressed, pointing elsewhere in the message.
+        let message = b"\x07example\x03org\x00\x00\x0a\x04mail\xc0\x00";
+        let mut reader = Reader::new(message);
+        reader.seek(13);
+        assert_eq!(
+            decode(types::MX, &mut reader, 9).unwrap(),
+            "10 mail.example.org."
+        );
+
+        // Data that doesn't fit its type falls back to the generic format.
+        let mut reader = Reader::new(&[10, 0, 0]);
+        assert_eq!(decode(types::A, &mut reader, 3).unwrap(), r"\# 3 0A0000");
+    }
+}
@@ -4,46 +4,105 @@ use std::{sync::Arc, time::Duration};
 
 use kube::{
     api::{ListParams, Patch, PatchParams},
-    runtime::{controller::Action, watcher, Controller},
+    runtime::{controller::Action, Controller},
     Api, Client, ResourceExt,
 };
+use kubizone_common::{scope, Backoff, Metrics, Scope};
 use kubizone_crds::{
-    v1alpha1::{Record, Zone, ZoneRef},
-    PARENT_ZONE_LABEL,
+    v1alpha1::{set_condition, Condition, Record, Zone, ZoneRef},
+    PARENT_ZONE_ANNOTATION, PARENT_ZONE_LABEL,
 };
 use tracing::*;
 
+use crate::{
+    error::{Error, RECONCILED_CONDITION, SUCCESS},
+    zone::now,
+};
+
 const CONTROLLER_NAME: &str = "kubi.zone/record-resolver";
 
-pub async fn controller(client: Client) {
-    let records = Api::<Record>::all(client.clone());
+pub async fn controller(
+    client: Client,
+    scope: Scope,
+    backoff: Backoff,
+    metrics: Arc<Metrics>,
+    requeue: Duration,
+) {
+    let data = Arc::new(Data {
+        client: client.clone(),
+        scope: scope.clone(),
+        backoff,
+        metrics,
+        requeue,
+    });
 
-    let record_controller = Controller::new(records, watcher::Config::default())
-        .watches(
-            Api::<Zone>::all(client.clone()),
-            watcher::Config::default(),
-            kubizone_crds::watch_reference(PARENT_ZONE_LABEL),
-        )
-        .shutdown_on_signal()
-        .run(
-            reconcile_records,
-            record_error_policy,
-            Arc::new(Data {
-                client: client.clone(),
-            }),
-        )
-        .for_each(|res| async move {
-            match res {
-                Ok(o) => info!("reconciled {:?}", o),
-                Err(e) => warn!("reconcile failed: {}", e),
-            }
+    // See zone::controller for why there's one controller per namespace.
+    let namespaces = scope.api_namespaces();
+    let zone_changes = scope.shared_watch::<Zone>(&client, namespaces.len());
+
+    let record_controllers = scope
+        .apis::<Record>(&client)
+        .into_iter()
+        .zip(namespaces)
+        .zip(zone_changes)
+        .map(|((records, namespace), zone_changes)| {
+            Controller::new(records, scope.watcher_config())
+                .watches_stream(
+                    zone_changes,
+                    scope::within(
+                        namespace,
+                        kubizone_crds::watch_reference(PARENT_ZONE_ANNOTATION),
+                    ),
+                )
+                .shutdown_on_signal()
+                .run(reconcile_records, record_error_policy, data.clone())
+                .for_each(|res| async move {
+                    match res {
+                        Ok(o) => info!("reconciled {:?}", o),
+                        Err(e) => warn!("reconcile failed: {}", e),
+                    }
+                })
         });
 
-    record_controller.await;
+    futures::future::join_all(record_controllers).await;
 }
 
 struct Data {
     client: Client,
+    scope: Scope,
+    backoff: Backoff,
+    metrics: Arc<Metrics>,
+    /// Interval at which successfully reconciled records are re-checked.
+    requeue: Duration,
+}
+
+async fn set_record_condition(
+    client: Client,
+    record: &Record,
+    condition: Condition,
+) -> Result<(), kube::Error> {
+    let mut conditions = record
+        .status
+        .as_ref()
+        .map(|status| status.conditions.clone())
+        .unwrap_or_default();
+
+    if set_condition(&mut conditions, condition) {
+        debug!("updating conditions of record {record}");
+        Api::<Record>::namespaced(client, record.namespace().as_ref().unwrap())
+            .patch_status(
+                &record.name_any(),
+                &PatchParams::apply(CONTROLLER_NAME),
+                &Patch::Merge(json!({
+                    "status": {
+                        "conditions": conditions,
+                    }
+                })),
+            )
+            .await?;
+    }
+
+    Ok(())
 }
 
 async fn set_record_fqdn(client: Client, record: &Record, fqdn: &str) -> Result<(), kube::Error> {
@@ -76,7 +135,12 @@ async fn set_record_parent_ref(
     record: &Arc<Record>,
     parent_ref: &ZoneRef,
 ) -> Result<(), kube::Error> {
-    if record.labels().get(PARENT_ZONE_LABEL) != Some(&parent_ref.as_label()) {
+    // Objects labelled by earlier versions of the controller carry a `name.namespace`
+    // label and no annotation. These are migrated transparently here, since the
+    // label will not match the hashed representation.
+    if record.labels().get(PARENT_ZONE_LABEL) != Some(&parent_ref.as_label())
+        || record.annotations().get(PARENT_ZONE_ANNOTATION) != Some(&parent_ref.as_annotation())
+    {
         info!(
             "updating record {}'s {PARENT_ZONE_LABEL} to {parent_ref}",
             record.name_any()
@@ -90,6 +154,9 @@ async fn set_record_parent_ref(
                         "labels": {
                             PARENT_ZONE_LABEL: parent_ref.as_label()
                         },
+                        "annotations": {
+                            PARENT_ZONE_ANNOTATION: parent_ref.as_annotation()
+                        },
                     }
                 })),
             )
@@ -102,72 +169,78 @@ async fn set_record_parent_ref(
     Ok(())
 }
 
-async fn reconcile_records(record: Arc<Record>, ctx: Arc<Data>) -> Result<Action, kube::Error> {
+async fn reconcile_records(record: Arc<Record>, ctx: Arc<Data>) -> Result<Action, Error> {
+    let result = reconcile_record(&record, &ctx).await;
+
+    let condition = match &result {
+        Ok(_) => {
+            ctx.backoff.reset(&record.to_string());
+            Condition::new(RECONCILED_CONDITION, true, SUCCESS, "", now())
+        }
+        Err(err) => Condition::new(
+            RECONCILED_CONDITION,
+            false,
+            err.category(),
+            err.to_string(),
+            now(),
+        ),
+    };
+
+    ctx.metrics.reconciled(
+        "record",
+        result
+            .as_ref()
+            .err()
+            .map(Error::category)
+            .unwrap_or(SUCCESS),
+    );
+
+    // Unlike zones, nothing else modifies the record's conditions during
+    // reconciliation, so the record we were handed is recent enough.
+    if let Err(err) = set_record_condition(ctx.client.clone(), &record, condition).await {
+        warn!("failed to report reconciliation outcome for record {record}: {err}");
+    }
+
+    result
+}
+
+async fn reconcile_record(record: &Arc<Record>, ctx: &Data) -> Result<Action, Error> {
     // Determine the fqdn of the record
 
-    match (
-        record.spec.zone_ref.as_ref(),
-        record.spec.domain_name.ends_with('.'),
-    ) {
-        (Some(zone_ref), false) => {
+    match record_parent_ref(record)? {
+        Some(parent_ref) => {
             // Follow the zoneRef to the supposed parent zone, if it exists
-            // or requeue later if it does not.
-            let Some(parent_zone) = Api::<Zone>::namespaced(
-                ctx.client.clone(),
-                &zone_ref
-                    .namespace
-                    .as_ref()
-                    .or(record.namespace().as_ref())
-                    .cloned()
-                    .unwrap(),
-            )
-            .get_opt(&zone_ref.name)
-            .await?
+            // or retry later if it does not.
+            let Some(parent_zone) = ctx
+                .scope
+                .get_opt::<Zone>(
+                    &ctx.client,
+                    parent_ref.namespace.as_ref().unwrap(),
+                    &parent_ref.name,
+                )
+                .await?
             else {
-                warn!("record {record} references unknown zone {zone_ref}");
-                return Ok(Action::requeue(Duration::from_secs(30)));
-            };
-
-            // If the parent does not have a fully qualified domain name defined
-            // yet, we can't check if the delegations provided by it are valid.
-            // Postpone the reconcilliation until a later time, when the fqdn
-            // has (hopefully) been determined.
-            let Some(parent_fqdn) = parent_zone.fqdn() else {
-                info!("parent zone {parent_zone} missing fqdn, requeuing.",);
-                return Ok(Action::requeue(Duration::from_secs(5)));
+                warn!("record {record} references unknown zone {parent_ref}");
+                return Err(Error::MissingParent(parent_ref));
             };
 
-            // This is only "alleged", since we don't know yet if the referenced
-            // zone's delegations allow the adoption.
-            let alleged_fqdn = format!("{}.{}", record.spec.domain_name, parent_fqdn);
+            let fqdn = adopt_record(record, &parent_zone)?;
 
-            if parent_zone.spec.delegations.iter().any(|delegation| {
-                delegation.covers_namespace(record.namespace().as_deref().unwrap())
-                    && delegation.validate_record(parent_fqdn, &record.spec.type_, &alleged_fqdn)
-            }) {
-                set_record_fqdn(ctx.client.clone(), &record, &alleged_fqdn).await?;
-                set_record_parent_ref(ctx.client.clone(), &record, &parent_zone.zone_ref()).await?;
-            } else {
-                warn!("parent zone {parent_zone} was found, but its delegations does not allow adoption of {record} with {alleged_fqdn} and type {}", record.spec.type_);
-                return Ok(Action::requeue(Duration::from_secs(300)));
-            }
+            set_record_fqdn(ctx.client.clone(), record, &fqdn).await?;
+            set_record_parent_ref(ctx.client.clone(), record, &parent_zone.zone_ref()).await?;
         }
-        (None, true) => {
-            set_record_fqdn(ctx.client.clone(), &record, &record.spec.domain_name).await?;
-
-            // Fetch all zones from across the cluster and then filter down results to only parent
-            // zones which are valid parent zones for this one.
-            //
-            // This means filtering out parent zones without fqdns, as well as ones which do not
-            // have appropriate delegations for our `zone`'s namespace and suffix.
-            if let Some(longest_parent_zone) = Api::<Zone>::all(ctx.client.clone())
-                .list(&ListParams::default())
-                .await?
-                .into_iter()
-                .filter(|parent| parent.validate_record(&record))
-                .max_by_key(|parent| parent.fqdn().unwrap().len())
-            {
-                set_record_parent_ref(ctx.client.clone(), &record, &longest_parent_zone.zone_ref())
+        None => {
+            set_record_fqdn(ctx.client.clone(), record, &record.spec.domain_name).await?;
+
+            // Fetch all zones from across the cluster and then pick the most specific
+            // one which is a valid parent for this record.
+            let zones = ctx
+                .scope
+                .list::<Zone>(&ctx.client, &ListParams::default())
+                .await?;
+
+            if let Some(longest_parent_zone) = longest_parent_zone(record, &zones) {
+                set_record_parent_ref(ctx.client.clone(), record, &longest_parent_zone.zone_ref())
                     .await?;
             } else {
                 warn!(
@@ -176,23 +249,93 @@ async fn reconcile_records(record: Arc<Record>, ctx: Arc<Data>) -> Result<Action
                 );
             };
         }
+    }
+
+    Ok(Action::requeue(ctx.requeue))
+}
+
+/// Determine the parent referenced by the record's `zoneRef`, if it has one.
+///
+/// Records must have *either* a `zoneRef` or a fully qualified domain name,
+/// anything else is an invalid spec.
+pub(crate) fn record_parent_ref(record: &Record) -> Result<Option<ZoneRef>, Error> {
+    match (
+        record.spec.zone_ref.as_ref(),
+        record.spec.domain_name.ends_with('.'),
+    ) {
+        (Some(zone_ref), false) => Ok(Some(ZoneRef {
+            name: zone_ref.name.clone(),
+            namespace: zone_ref.namespace.clone().or(record.namespace()),
+        })),
+        (None, true) => Ok(None),
         (Some(zone_ref), true) => {
             warn!("record {record} has both a fully qualified domain_name ({}) and a zoneRef({zone_ref}). It cannot have both.", record.spec.domain_name);
-            return Ok(Action::requeue(Duration::from_secs(300)));
+            Err(Error::InvalidSpec(String::from(
+                "record cannot have both a zoneRef and a fully qualified domainName",
+            )))
         }
         (None, false) => {
             warn!("{record} has neither zoneRef nor a fully qualified domainName, making it impossible to deduce its parent zone.");
-            return Ok(Action::requeue(Duration::from_secs(300)));
+            Err(Error::InvalidSpec(String::from(
+                "record must have either a zoneRef or a fully qualified domainName",
+            )))
         }
     }
+}
+
+/// Check if `parent_zone`'s delegations allow it to adopt `record`, producing
+/// the fully qualified domain name `record` would have within it.
+pub(crate) fn adopt_record(record: &Record, parent_zone: &Zone) -> Result<String, Error> {
+    // If the parent does not have a fully qualified domain name defined
+    // yet, we can't check if the delegations provided by it are valid.
+    // Postpone the reconcilliation until a later time, when the fqdn
+    // has (hopefully) been determined.
+    let Some(parent_fqdn) = parent_zone.fqdn() else {
+        info!("parent zone {parent_zone} missing fqdn, requeuing.",);
+        return Err(Error::ParentNotReady(parent_zone.zone_ref()));
+    };
+
+    // This is only "alleged", since we don't know yet if the referenced
+    // zone's delegations allow the adoption.
+    let alleged_fqdn = format!("{}.{}", record.spec.domain_name, parent_fqdn);
+
+    if parent_zone.spec.delegations.iter().any(|delegation| {
+        delegation.covers_namespace(record.namespace().as_deref().unwrap())
+            && delegation.validate_record(parent_fqdn, &record.spec.type_, &alleged_fqdn)
+    }) {
+        Ok(alleged_fqdn)
+    } else {
+        warn!("parent zone {parent_zone} was found, but its delegations does not allow adoption of {record} with {alleged_fqdn} and type {}", record.spec.type_);
+        Err(Error::DelegationDenied {
+            parent: parent_zone.to_string(),
+            fqdn: alleged_fqdn,
+        })
+    }
+}
 
-    Ok(Action::requeue(Duration::from_secs(30)))
+/// Pick the most specific of `zones` which is a valid parent zone for `record`.
+///
+/// This means filtering out parent zones without fqdns, as well as ones which do not
+/// have appropriate delegations for our `record`'s namespace and suffix.
+pub(crate) fn longest_parent_zone<'a>(record: &Record, zones: &'a [Zone]) -> Option<&'a Zone> {
+    zones
+        .iter()
+        .filter(|parent| parent.validate_record(record))
+        .max_by_key(|parent| parent.fqdn().unwrap().len())
 }
 
-fn record_error_policy(record: Arc<Record>, error: &kube::Error, _ctx: Arc<Data>) -> Action {
+fn record_error_policy(record: Arc<Record>, error: &Error, ctx: Arc<Data>) -> Action {
+    let delay = if error.is_misconfiguration() {
+        ctx.backoff.misconfigured()
+    } else {
+        ctx.backoff.failure(&record.to_string())
+    };
+
     error!(
-        "record {} reconciliation encountered error: {error}",
-        record.name_any()
+        "record {} reconciliation encountered {} error: {error}, retrying in {}s",
+        record.name_any(),
+        error.category(),
+        delay.as_secs()
     );
-    Action::requeue(Duration::from_secs(60))
+    Action::requeue(delay)
 }
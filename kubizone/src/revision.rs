@@ -0,0 +1,340 @@
+use std::{sync::Arc, time::Duration};
+
+use futures::StreamExt;
+use k8s_openapi::serde_json;
+use kube::{
+    api::{DeleteParams, ListParams, PostParams},
+    core::ObjectMeta,
+    runtime::{controller::Action, Controller},
+    Api, Client, Resource, ResourceExt,
+};
+use kubizone_common::{Backoff, Metrics, Scope};
+use kubizone_crds::{
+    v1alpha1::{Zone, ZoneEntry, ZoneRevision, ZoneRevisionSpec},
+    ZONE_REVISION_LABEL,
+};
+use tracing::log::*;
+
+use crate::error::{Error, SUCCESS};
+
+struct Data {
+    client: Client,
+    backoff: Backoff,
+    metrics: Arc<Metrics>,
+    /// Interval at which zones are re-checked.
+    requeue: Duration,
+}
+
+/// Entries of `current` which are not in `previous`, and vice versa,
+/// counting duplicates.
+fn diff(previous: &[ZoneEntry], current: &[ZoneEntry]) -> (Vec<ZoneEntry>, Vec<ZoneEntry>) {
+    let difference = |from: &[ZoneEntry], other: &[ZoneEntry]| {
+        let mut other = other.to_vec();
+        from.iter()
+            .filter(
+                |entry| match other.iter().position(|candidate| candidate == *entry) {
+                    Some(position) => {
+                        other.remove(position);
+                        false
+                    }
+                    None => true,
+                },
+            )
+            .cloned()
+            .collect()
+    };
+
+    (difference(current, previous), difference(previous, current))
+}
+
+/// Revisions which fall outside of the `limit` most recent ones, other than the
+/// `pinned` one, which is kept for as long as the zone is pinned to it.
+fn expired(mut revisions: Vec<ZoneRevision>, limit: u32, pinned: Option<u32>) -> Vec<ZoneRevision> {
+    revisions.sort_by_key(|revision| std::cmp::Reverse(revision.spec.serial));
+
+    revisions
+        .into_iter()
+        .skip(limit as usize)
+        .filter(|revision| Some(revision.spec.serial) != pinned)
+        .collect()
+}
+
+/// Largest serialized size of the spec of a revision. Objects are stored in
+/// etcd, which by default rejects anything over 1.5MiB.
+const MAX_REVISION_SIZE: usize = 1024 * 1024;
+
+/// Make `revision` fit within `max` bytes. The differences can always be
+/// recomputed from the previous revision, so they are left out of revisions
+/// which would be too large otherwise, but the entries cannot.
+fn fit(mut revision: ZoneRevision, max: usize) -> Option<ZoneRevision> {
+    let size =
+        |spec: &ZoneRevisionSpec| serde_json::to_vec(spec).map_or(usize::MAX, |json| json.len());
+
+    if size(&revision.spec) > max {
+        revision.spec.added.clear();
+        revision.spec.removed.clear();
+    }
+
+    (size(&revision.spec) <= max).then_some(revision)
+}
+
+/// Record a [`ZoneRevision`] of the `entries` of `zone` at `serial`, unless one
+/// has been recorded already, or the zone keeps no revisions.
+///
+/// This is called by the controllers changing the serial of a zone, before
+/// they do, so no serial goes unrecorded.
+pub(crate) async fn record(
+    client: &Client,
+    zone: &Zone,
+    serial: u32,
+    hash: &str,
+    entries: &[ZoneEntry],
+) -> Result<(), kube::Error> {
+    let Some(fqdn) = zone.fqdn() else {
+        return Ok(());
+    };
+
+    if zone.spec.revision_history_limit == 0 {
+        return Ok(());
+    }
+
+    let api = Api::<ZoneRevision>::namespaced(client.clone(), zone.namespace().as_ref().unwrap());
+    let label = format!("{ZONE_REVISION_LABEL}={}", zone.zone_ref().as_label());
+    let revisions = api.list(&ListParams::default().labels(&label)).await?.items;
+
+    if revisions
+        .iter()
+        .any(|revision| revision.spec.serial == serial)
+    {
+        return Ok(());
+    }
+
+    let previous = revisions.iter().max_by_key(|revision| revision.spec.serial);
+    let (added, removed) = diff(
+        previous
+            .map(|previous| previous.spec.entries.as_slice())
+            .unwrap_or_default(),
+        entries,
+    );
+
+    let revision = ZoneRevision {
+        metadata: ObjectMeta {
+            name: Some(ZoneRevision::name_for(zone, serial)),
+            namespace: zone.namespace(),
+            labels: Some([(ZONE_REVISION_LABEL.to_string(), zone.zone_ref().as_label())].into()),
+            // Revisions are deleted along with their zone.
+            owner_references: zone.controller_owner_ref(&()).map(|owner| vec![owner]),
+            ..Default::default()
+        },
+        spec: ZoneRevisionSpec {
+            zone: zone.name_any(),
+            fqdn: fqdn.to_string(),
+            serial,
+            hash: hash.to_string(),
+            previous_serial: previous.map(|previous| previous.spec.serial),
+            entries: entries.to_vec(),
+            added,
+            removed,
+        },
+    };
+
+    let Some(revision) = fit(revision, MAX_REVISION_SIZE) else {
+        warn!("not recording serial {serial} of zone {zone}, since its entries exceed {MAX_REVISION_SIZE} bytes");
+        return Ok(());
+    };
+
+    info!("recording revision {} of zone {zone}", revision.name_any());
+    match api.create(&PostParams::default(), &revision).await {
+        // Recorded by an earlier attempt, which failed to change the serial.
+        Err(kube::Error::Api(response)) if response.code == 409 => Ok(()),
+        result => result.map(|_| ()),
+    }
+}
+
+/// Delete the revisions of `zone` exceeding its `.spec.revisionHistoryLimit`.
+async fn prune_revisions(zone: &Zone, ctx: &Data) -> Result<(), Error> {
+    let api =
+        Api::<ZoneRevision>::namespaced(ctx.client.clone(), zone.namespace().as_ref().unwrap());
+    let label = format!("{ZONE_REVISION_LABEL}={}", zone.zone_ref().as_label());
+    let revisions = api.list(&ListParams::default().labels(&label)).await?.items;
+
+    let limit = zone.spec.revision_history_limit;
+    for revision in expired(revisions, limit, zone.spec.pinned_revision) {
+        info!(
+            "deleting revision {} of zone {zone}, which exceeds its history limit of {limit}",
+            revision.name_any()
+        );
+        api.delete(&revision.name_any(), &DeleteParams::default())
+            .await?;
+    }
+
+    Ok(())
+}
+
+async fn reconcile(zone: Arc<Zone>, ctx: Arc<Data>) -> Result<Action, Error> {
+    let result = prune_revisions(&zone, &ctx).await;
+
+    ctx.metrics.reconciled(
+        "revision",
+        result
+            .as_ref()
+            .err()
+            .map(Error::category)
+            .unwrap_or(SUCCESS),
+    );
+
+    if result.is_ok() {
+        ctx.backoff.reset(&zone.to_string());
+    }
+
+    result.map(|()| Action::requeue(ctx.requeue))
+}
+
+fn error_policy(zone: Arc<Zone>, error: &Error, ctx: Arc<Data>) -> Action {
+    let delay = if error.is_misconfiguration() {
+        ctx.backoff.misconfigured()
+    } else {
+        ctx.backoff.failure(&zone.to_string())
+    };
+
+    error!(
+        "pruning revisions of zone {} encountered {} error: {error}, retrying in {}s",
+        zone.name_any(),
+        error.category(),
+        delay.as_secs()
+    );
+    Action::requeue(delay)
+}
+
+/// Prune the [`ZoneRevision`]s of each zone exceeding the zone's
+/// `.spec.revisionHistoryLimit`, as new ones are recorded.
+pub async fn controller(
+    client: Client,
+    scope: Scope,
+    backoff: Backoff,
+    metrics: Arc<Metrics>,
+    requeue: Duration,
+) {
+    let data = Arc::new(Data {
+        client: client.clone(),
+        backoff,
+        metrics,
+        requeue,
+    });
+
+    let controllers = scope.apis::<Zone>(&client).into_iter().map(|zones| {
+        Controller::new(zones, scope.watcher_config())
+            .shutdown_on_signal()
+            .run(reconcile, error_policy, data.clone())
+            .for_each(|res| async move {
+                match res {
+                    Ok(o) => debug!("pruned revisions of {:?}", o),
+                    Err(e) => warn!("pruning revisions failed: {}", e),
+                }
+            })
+    });
+
+    futures::future::join_all(controllers).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::serde_json;
+    use kube::api::ObjectMeta;
+    use kubizone_crds::v1alpha1::{ZoneEntry, ZoneRevision, ZoneRevisionSpec};
+
+    use super::{diff, expired, fit};
+
+    fn entry(rdata: &str) -> ZoneEntry {
+        ZoneEntry {
+            fqdn: String::from("www.example.org."),
+            type_: String::from("A"),
+            class: String::from("IN"),
+            ttl: 300,
+            rdata: rdata.to_string(),
+        }
+    }
+
+    fn revision(serial: u32) -> ZoneRevision {
+        ZoneRevision {
+            metadata: ObjectMeta {
+                name: Some(format!("example-org-{serial}")),
+                ..Default::default()
+            },
+            spec: ZoneRevisionSpec {
+                zone: String::from("example-org"),
+                fqdn: String::from("example.org."),
+                serial,
+                hash: serial.to_string(),
+                previous_serial: None,
+                entries: Vec::new(),
+                added: Vec::new(),
+                removed: Vec::new(),
+            },
+        }
+    }
+
+    #[test]
+    fn differences() {
+        let previous = [entry("192.0.2.1"), entry("192.0.2.2"), entry("192.0.2.2")];
+        let current = [entry("192.0.2.2"), entry("192.0.2.3")];
+
+        assert_eq!(
+            diff(&previous, &current),
+            (
+                vec![entry("192.0.2.3")],
+                vec![entry("192.0.2.1"), entry("192.0.2.2")]
+            )
+        );
+        assert_eq!(diff(&[], &current), (current.to_vec(), Vec::new()));
+        assert_eq!(diff(&current, &current), (Vec::new(), Vec::new()));
+    }
+
+    #[test]
+    fn retention() {
+        let revisions = vec![
+            revision(2),
+            revision(5),
+            revision(1),
+            revision(4),
+            revision(3),
+        ];
+        let serials = |revisions: Vec<ZoneRevision>| -> Vec<u32> {
+            revisions
+                .into_iter()
+                .map(|revision| revision.spec.serial)
+                .collect()
+        };
+
+        assert_eq!(serials(expired(revisions.clone(), 3, None)), [2, 1]);
+        assert_eq!(serials(expired(revisions.clone(), 3, Some(1))), [2]);
+        assert_eq!(
+            serials(expired(revisions.clone(), 0, Some(4))),
+            [5, 3, 2, 1]
+        );
+        assert!(expired(revisions, 10, None).is_empty());
+    }
+
+    #[test]
+    fn size_limit() {
+        let mut large = revision(2);
+        large.spec.entries = (0..10)
+            .map(|host| entry(&format!("192.0.2.{host}")))
+            .collect();
+        large.spec.added = large.spec.entries.clone();
+
+        // Revisions which fit are left alone.
+        let unchanged = fit(large.clone(), usize::MAX).unwrap();
+        assert_eq!(unchanged.spec, large.spec);
+
+        // Differences are dropped first, but the entries are required.
+        let mut smaller = large.clone();
+        smaller.spec.added.clear();
+        let size = serde_json::to_vec(&smaller.spec).unwrap().len();
+
+        let entries_only = fit(large.clone(), size).unwrap();
+        assert_eq!(entries_only.spec, smaller.spec);
+
+        assert!(fit(large, size - 1).is_none());
+    }
+}
@@ -0,0 +1,783 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use futures::StreamExt;
+use k8s_openapi::serde_json::json;
+use kube::{
+    api::{Patch, PatchParams},
+    runtime::{controller::Action, Controller},
+    Api, Client, ResourceExt,
+};
+use kubizone_common::{Backoff, Metrics, Scope};
+use kubizone_crds::v1alpha1::{SecondaryStatus, Zone, ZoneEntry};
+use time::OffsetDateTime;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use tracing::log::*;
+
+use crate::{
+    error::{Error, SUCCESS},
+    notify, rdata, revision, tsig,
+    wire::{
+        opcode, rcode, types, Header, Message, Question, Reader, ResourceRecord, WireError,
+        CLASS_IN,
+    },
+    zone::{now, parse_time},
+};
+
+const CONTROLLER_NAME: &str = "kubi.zone/secondary";
+
+/// Time to wait for the primary to accept the connection, and for each
+/// message of a transfer.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Size of the fixed header preceding the sections of a message.
+const HEADER_SIZE: usize = 12;
+
+struct Data {
+    client: Client,
+    backoff: Backoff,
+    metrics: Arc<Metrics>,
+}
+
+/// Entries removed and added by one of the differences of an incremental
+/// transfer, including the SOA records of the old and new version.
+type Difference = (Vec<ZoneEntry>, Vec<ZoneEntry>);
+
+/// Response of the primary to a transfer request.
+#[derive(Debug, PartialEq, Eq)]
+enum Transfer {
+    /// The serial of the primary is the one requested from.
+    UpToDate,
+    /// All entries of the zone, starting with its SOA record.
+    Full(Vec<ZoneEntry>),
+    /// Differences leading from the requested serial to the current one.
+    Incremental(Vec<Difference>),
+}
+
+fn is_soa(entry: &ZoneEntry) -> bool {
+    entry.type_ == "SOA"
+}
+
+/// Serial of an SOA entry, as presented by [`rdata::decode`].
+fn serial(entry: &ZoneEntry) -> Option<u32> {
+    entry.rdata.split_whitespace().nth(2)?.parse().ok()
+}
+
+/// Whether `a` and `b` are the same record, regardless of their TTL.
+fn same(a: &ZoneEntry, b: &ZoneEntry) -> bool {
+    a.fqdn.eq_ignore_ascii_case(&b.fqdn)
+        && a.type_ == b.type_
+        && a.class == b.class
+        && a.rdata == b.rdata
+}
+
+/// Decode the answers of a transfer response into entries. Names within the
+/// record data may be compressed, so they are decoded in place.
+fn answers(message: &[u8], decoded: &Message) -> Result<Vec<ZoneEntry>, WireError> {
+    let mut reader = Reader::new(message);
+    reader.seek(HEADER_SIZE);
+    for _ in &decoded.questions {
+        reader.question()?;
+    }
+
+    decoded
+        .answers
+        .iter()
+        .map(|_| {
+            let fqdn = reader.name()?;
+            let type_ = reader.u16()?;
+            let class = reader.u16()?;
+            let ttl = reader.u32()?;
+            let length = reader.u16()? as usize;
+
+            Ok(ZoneEntry {
+                fqdn,
+                type_: types::mnemonic(type_),
+                class: match class {
+                    CLASS_IN => String::from("IN"),
+                    class => format!("CLASS{class}"),
+                },
+                ttl,
+                rdata: rdata::decode(type_, &mut reader, length)?,
+            })
+        })
+        .collect()
+}
+
+/// Interpret the `records` received so far in response to a transfer request
+/// from the `current` serial, if any, as described in
+/// [RFC 1995](https://datatracker.ietf.org/doc/html/rfc1995) and
+/// [RFC 5936](https://datatracker.ietf.org/doc/html/rfc5936).
+///
+/// Returns the serial of the primary along with the transfer once it is
+/// complete, or `None` if more records are yet to come.
+fn parse(records: &[ZoneEntry], current: Option<u32>) -> Result<Option<(u32, Transfer)>, String> {
+    let Some(first) = records.first() else {
+        return Ok(None);
+    };
+
+    let serial_of = |entry: &ZoneEntry| {
+        serial(entry).ok_or_else(|| format!("invalid SOA record {}", entry.rdata))
+    };
+
+    if !is_soa(first) {
+        return Err(String::from("transfer does not start with an SOA record"));
+    }
+    let latest = serial_of(first)?;
+
+    // Primaries respond to incremental requests with just their SOA record
+    // if there is nothing newer to transfer.
+    if records.len() == 1 {
+        return Ok(current.map(|_| (latest, Transfer::UpToDate)));
+    }
+
+    // Full transfers are enclosed in the SOA record, and are what an
+    // incremental request is answered with if the primary has no history.
+    if !is_soa(&records[1]) || serial_of(&records[1])? == latest {
+        // Unwrap safety: there are at least two records.
+        let last = records.last().unwrap();
+        if !is_soa(last) {
+            return Ok(None);
+        }
+
+        return Ok(Some((
+            latest,
+            Transfer::Full(records[..records.len() - 1].to_vec()),
+        )));
+    }
+
+    // Each difference consists of the SOA record of the old version, the
+    // removed records, the SOA record of the new version and the added
+    // records. The SOA record of the latest version ends the transfer.
+    let mut differences = Vec::new();
+    let mut rest = &records[1..];
+    loop {
+        let Some(removed) = rest[1..].iter().position(is_soa).map(|end| end + 2) else {
+            return Ok(None);
+        };
+
+        let Some(added) = rest[removed..]
+            .iter()
+            .position(is_soa)
+            .map(|end| end + removed)
+        else {
+            return Ok(None);
+        };
+
+        let from = serial_of(&rest[0])?;
+        if differences.is_empty() && Some(from) != current {
+            return Err(format!(
+                "primary sent differences from serial {from}, which was not requested"
+            ));
+        }
+
+        differences.push((
+            rest[..removed - 1].to_vec(),
+            rest[removed - 1..added].to_vec(),
+        ));
+        rest = &rest[added..];
+
+        if serial_of(&rest[0])? == latest {
+            if rest.len() > 1 {
+                return Err(String::from("records follow the end of the transfer"));
+            }
+
+            return Ok(Some((latest, Transfer::Incremental(differences))));
+        }
+    }
+}
+
+/// Apply the `differences` of an incremental transfer to `entries`, or `None`
+/// if they remove entries which aren't there.
+fn apply(entries: &[ZoneEntry], differences: Vec<Difference>) -> Option<Vec<ZoneEntry>> {
+    let mut entries = entries.to_vec();
+
+    for (removed, added) in differences {
+        for entry in removed {
+            let position = entries.iter().position(|existing| same(existing, &entry))?;
+            entries.remove(position);
+        }
+
+        entries.extend(added);
+    }
+
+    // The SOA record comes first, like in the entries of other zones.
+    entries.sort_by_key(|entry| !is_soa(entry));
+    Some(entries)
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Request a transfer of the zone `origin` from the primary at `address`,
+/// incremental from the `current` SOA record if any, authenticating the
+/// exchange with the `key` if any.
+async fn exchange(
+    address: SocketAddr,
+    origin: &str,
+    key: Option<&tsig::Key>,
+    current: Option<&ZoneEntry>,
+) -> Result<(u32, Transfer), String> {
+    let mut request = Message {
+        header: Header {
+            id: notify::message_id(),
+            opcode: opcode::QUERY,
+            ..Default::default()
+        },
+        questions: vec![Question {
+            name: origin.to_string(),
+            type_: if current.is_some() {
+                types::IXFR
+            } else {
+                types::AXFR
+            },
+            class: CLASS_IN,
+        }],
+        ..Default::default()
+    };
+
+    if let Some(soa) = current {
+        request.authorities.push(ResourceRecord {
+            name: origin.to_string(),
+            type_: types::SOA,
+            class: CLASS_IN,
+            ttl: soa.ttl,
+            rdata: rdata::encode(types::SOA, &soa.rdata, origin)?,
+        });
+    }
+
+    let (encoded, mut mac) = match key {
+        Some(key) => key.sign(&request, &[], unix_time()),
+        None => request.encode().map(|encoded| (encoded, Vec::new())),
+    }
+    .map_err(|err| err.to_string())?;
+
+    let mut stream = tokio::time::timeout(TIMEOUT, TcpStream::connect(address))
+        .await
+        .map_err(|_| format!("{address} did not respond within {}s", TIMEOUT.as_secs()))?
+        .map_err(|err| format!("failed to connect to {address}: {err}"))?;
+
+    let mut message = (encoded.len() as u16).to_be_bytes().to_vec();
+    message.extend(encoded);
+    stream
+        .write_all(&message)
+        .await
+        .map_err(|err| format!("failed to send request to {address}: {err}"))?;
+
+    let mut records = Vec::new();
+    let mut first = true;
+    loop {
+        let response = tokio::time::timeout(TIMEOUT, async {
+            let mut length = [0u8; 2];
+            stream.read_exact(&mut length).await?;
+            let mut response = vec![0u8; u16::from_be_bytes(length) as usize];
+            stream.read_exact(&mut response).await?;
+            Ok::<_, std::io::Error>(response)
+        })
+        .await
+        .map_err(|_| format!("{address} did not respond within {}s", TIMEOUT.as_secs()))?
+        .map_err(|err| format!("failed to receive transfer from {address}: {err}"))?;
+
+        let decoded = Message::decode(&response)
+            .map_err(|err| format!("invalid response from {address}: {err}"))?;
+
+        if !decoded.header.response || decoded.header.id != request.header.id {
+            return Err(format!("{address} responded to a different request"));
+        }
+
+        if decoded.header.rcode != rcode::NOERROR {
+            return Err(format!(
+                "{address} responded with {}",
+                rcode::name(decoded.header.rcode)
+            ));
+        }
+
+        if let Some(key) = key {
+            let now = unix_time();
+            mac = if first {
+                key.verify(&response, &mac, now)
+            } else {
+                key.verify_subsequent(&response, &mac, now)
+            }
+            .map_err(|err| format!("failed to authenticate response from {address}: {err}"))?;
+        }
+        first = false;
+
+        records.extend(
+            answers(&response, &decoded)
+                .map_err(|err| format!("invalid response from {address}: {err}"))?,
+        );
+
+        if let Some(transfer) = parse(&records, current.and_then(serial))? {
+            return Ok(transfer);
+        }
+    }
+}
+
+/// Bring the `entries` of the zone `origin` up to date with the `primary`,
+/// transferring only the differences where possible.
+///
+/// Returns the serial and entries of the primary, or `None` if the entries
+/// are up to date already.
+async fn refresh(
+    primary: &str,
+    origin: &str,
+    key: Option<&tsig::Key>,
+    entries: &[ZoneEntry],
+) -> Result<Option<(u32, Vec<ZoneEntry>)>, String> {
+    let address = notify::resolve(primary).await?;
+
+    let soa = entries
+        .first()
+        .filter(|entry| is_soa(entry) && serial(entry).is_some());
+
+    if let Some(soa) = soa {
+        match exchange(address, origin, key, Some(soa)).await {
+            Ok((_, Transfer::UpToDate)) => return Ok(None),
+            Ok((serial, Transfer::Full(entries))) => return Ok(Some((serial, entries))),
+            Ok((serial, Transfer::Incremental(differences))) => match apply(entries, differences) {
+                Some(entries) => return Ok(Some((serial, entries))),
+                None => warn!(
+                    "differences of zone {origin} from {primary} do not apply, \
+                        falling back to a full transfer"
+                ),
+            },
+            Err(err) => warn!(
+                "incremental transfer of zone {origin} from {primary} failed, \
+                falling back to a full transfer: {err}"
+            ),
+        }
+    }
+
+    transfer(primary, origin, key).await.map(Some)
+}
+
+/// Transfer all entries of the zone `origin` from the `primary` using AXFR,
+/// returning its serial along with the entries, starting with its SOA record.
+pub(crate) async fn transfer(
+    primary: &str,
+    origin: &str,
+    key: Option<&tsig::Key>,
+) -> Result<(u32, Vec<ZoneEntry>), String> {
+    let address = notify::resolve(primary).await?;
+
+    match exchange(address, origin, key, None).await? {
+        (serial, Transfer::Full(entries)) => Ok((serial, entries)),
+        _ => Err(format!("{primary} did not respond with a full transfer")),
+    }
+}
+
+fn hash(entries: &[ZoneEntry]) -> String {
+    let mut hasher = DefaultHasher::new();
+    entries.hash(&mut hasher);
+    hasher.finish().to_string()
+}
+
+async fn refresh_zone(zone: &Zone, ctx: &Data) -> Result<Action, Error> {
+    let Some(secondary) = &zone.spec.secondary else {
+        return Ok(Action::await_change());
+    };
+
+    let Some(origin) = zone.fqdn() else {
+        debug!("zone {zone} has not computed its fqdn yet");
+        return Ok(Action::await_change());
+    };
+
+    // Transfers from a different primary tell us nothing about this one.
+    let status = zone
+        .status
+        .as_ref()
+        .and_then(|status| status.secondary.as_ref())
+        .filter(|status| status.primary == secondary.primary);
+
+    // Check the primary again once the refresh timer expires, or the retry
+    // timer if the last attempt failed.
+    let started = OffsetDateTime::now_utc();
+    if let Some(status) = status {
+        let interval = match status.message {
+            None => zone.spec.refresh,
+            Some(_) => zone.spec.retry,
+        };
+
+        let due = parse_time(status.last_attempt.as_deref())
+            .map(|last_attempt| last_attempt + time::Duration::seconds(interval.into()));
+
+        if let Some(due) = due.filter(|due| *due > started) {
+            return Ok(Action::requeue((due - started).unsigned_abs()));
+        }
+    }
+
+    let key = match &secondary.tsig {
+        Some(tsig) => Some(
+            tsig::Key::from_secret(ctx.client.clone(), zone.namespace().as_ref().unwrap(), tsig)
+                .await?,
+        ),
+        None => None,
+    };
+
+    let entries = status
+        .filter(|status| !status.expired)
+        .and(zone.status.as_ref())
+        .map(|status| status.entries.as_slice())
+        .unwrap_or_default();
+
+    let result = refresh(&secondary.primary, origin, key.as_ref(), entries).await;
+    let attempt = now();
+
+    // Record the revision before storing the serial, so none go unrecorded.
+    if let Ok(Some((serial, entries))) = &result {
+        revision::record(&ctx.client, zone, *serial, &hash(entries), entries).await?;
+    }
+
+    let mut patch = match &result {
+        Ok(Some((serial, entries))) => {
+            info!(
+                "transferred serial {serial} of zone {zone} from {}",
+                secondary.primary
+            );
+
+            json!({
+                "entries": entries,
+                "serial": serial,
+                "hash": hash(entries),
+            })
+        }
+        Ok(None) => {
+            debug!("zone {zone} is up to date with {}", secondary.primary);
+            json!({})
+        }
+        Err(err) => {
+            warn!(
+                "failed to refresh zone {zone} from {}: {err}",
+                secondary.primary
+            );
+            json!({})
+        }
+    };
+
+    let last_refresh = match &result {
+        Ok(_) => Some(attempt.clone()),
+        Err(_) => status.and_then(|status| status.last_refresh.clone()),
+    };
+
+    // Secondaries stop answering for a zone once its primary has been
+    // unreachable for longer than the expire timer.
+    let was_expired = status.is_some_and(|status| status.expired);
+    let expired = result.is_err()
+        && (was_expired
+            || parse_time(last_refresh.as_deref()).is_some_and(|last_refresh| {
+                last_refresh + time::Duration::seconds(zone.spec.expire.into()) <= started
+            }));
+
+    if expired && !was_expired {
+        warn!(
+            "zone {zone} expired, since {} could not be reached for {}s, removing its entries",
+            secondary.primary, zone.spec.expire
+        );
+
+        patch = json!({
+            "entries": [],
+            "serial": null,
+            "hash": null,
+        });
+    }
+
+    patch["secondary"] = json!(SecondaryStatus {
+        primary: secondary.primary.clone(),
+        last_refresh,
+        last_attempt: Some(attempt),
+        expired,
+        message: result.as_ref().err().cloned(),
+    });
+
+    Api::<Zone>::namespaced(ctx.client.clone(), zone.namespace().as_ref().unwrap())
+        .patch_status(
+            &zone.name_any(),
+            &PatchParams::apply(CONTROLLER_NAME),
+            &Patch::Merge(json!({
+                "status": patch,
+            })),
+        )
+        .await?;
+
+    match result {
+        Ok(_) => Ok(Action::requeue(Duration::from_secs(
+            zone.spec.refresh.into(),
+        ))),
+        Err(err) => Err(Error::Transfer(err)),
+    }
+}
+
+async fn reconcile(zone: Arc<Zone>, ctx: Arc<Data>) -> Result<Action, Error> {
+    let result = refresh_zone(&zone, &ctx).await;
+
+    ctx.metrics.reconciled(
+        "secondary",
+        result
+            .as_ref()
+            .err()
+            .map(Error::category)
+            .unwrap_or(SUCCESS),
+    );
+
+    if result.is_ok() {
+        ctx.backoff.reset(&zone.to_string());
+    }
+
+    result
+}
+
+fn error_policy(zone: Arc<Zone>, error: &Error, ctx: Arc<Data>) -> Action {
+    let delay = if error.is_misconfiguration() {
+        ctx.backoff.misconfigured()
+    } else {
+        ctx.backoff.failure(&zone.to_string())
+    };
+
+    error!(
+        "refreshing secondary zone {} encountered {} error: {error}, retrying in {}s",
+        zone.name_any(),
+        error.category(),
+        delay.as_secs()
+    );
+    Action::requeue(delay)
+}
+
+/// Transfer the entries of zones with a `.spec.secondary` from their primary,
+/// following the refresh, retry and expire timers of the zone.
+pub async fn controller(client: Client, scope: Scope, backoff: Backoff, metrics: Arc<Metrics>) {
+    let data = Arc::new(Data {
+        client: client.clone(),
+        backoff,
+        metrics,
+    });
+
+    let controllers = scope.apis::<Zone>(&client).into_iter().map(|zones| {
+        Controller::new(zones, scope.watcher_config())
+            .shutdown_on_signal()
+            .run(reconcile, error_policy, data.clone())
+            .for_each(|res| async move {
+                match res {
+                    Ok(o) => debug!("refreshed {:?}", o),
+                    Err(e) => warn!("secondary refresh failed: {}", e),
+                }
+            })
+    });
+
+    futures::future::join_all(controllers).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use kube::api::ObjectMeta;
+    use kubizone_crds::v1alpha1::{
+        SecretKeyRef, TsigAlgorithm, TsigKey, Zone, ZoneEntry, ZoneSpec, ZoneStatus, ZoneTransfer,
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use crate::{serve::Server, tsig::Key};
+
+    use super::{apply, parse, refresh, Transfer};
+
+    fn entry(fqdn: &str, type_: &str, rdata: &str) -> ZoneEntry {
+        ZoneEntry {
+            fqdn: fqdn.to_string(),
+            type_: type_.to_string(),
+            class: String::from("IN"),
+            ttl: 300,
+            rdata: rdata.to_string(),
+        }
+    }
+
+    fn soa(serial: u32) -> ZoneEntry {
+        entry(
+            "example.org.",
+            "SOA",
+            &format!("ns.example.org. noc.example.org. {serial} 86400 7200 3600000 60"),
+        )
+    }
+
+    fn key() -> Key {
+        Key {
+            name: String::from("kubizone."),
+            algorithm: TsigAlgorithm::HmacSha256,
+            secret: b"secret".to_vec(),
+        }
+    }
+
+    fn zone(serial: u32, addresses: &[&str], keys: Vec<TsigKey>) -> Zone {
+        let mut entries = vec![soa(serial), entry("alias.example.org.", "CNAME", "www")];
+        entries.extend(
+            addresses
+                .iter()
+                .map(|address| entry("www.example.org.", "A", address)),
+        );
+
+        Zone {
+            metadata: ObjectMeta {
+                name: Some(String::from("example-org")),
+                namespace: Some(String::from("default")),
+                ..Default::default()
+            },
+            spec: ZoneSpec {
+                domain_name: String::from("example.org."),
+                transfer: Some(ZoneTransfer {
+                    allow: vec![String::from("127.0.0.1")],
+                    keys,
+                }),
+                ..Default::default()
+            },
+            status: Some(ZoneStatus {
+                fqdn: Some(String::from("example.org.")),
+                hash: Some(format!("{serial}")),
+                serial: Some(serial),
+                entries,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Stub primary answering transfer requests with `server`.
+    async fn primary(server: Server<Vec<Arc<Zone>>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        tokio::spawn(async move {
+            while let Ok((mut stream, peer)) = listener.accept().await {
+                let mut length = [0u8; 2];
+                stream.read_exact(&mut length).await.unwrap();
+                let mut request = vec![0u8; u16::from_be_bytes(length) as usize];
+                stream.read_exact(&mut request).await.unwrap();
+
+                for response in server.respond(&request, peer.ip(), false) {
+                    stream
+                        .write_all(&(response.len() as u16).to_be_bytes())
+                        .await
+                        .unwrap();
+                    stream.write_all(&response).await.unwrap();
+                }
+            }
+        });
+
+        address
+    }
+
+    #[test]
+    fn transfers() {
+        let a = entry("www.example.org.", "A", "192.0.2.1");
+        let b = entry("www.example.org.", "A", "192.0.2.2");
+
+        // Transfers are only complete once the closing SOA record arrives.
+        let full = [soa(2), a.clone(), b.clone(), soa(2)];
+        for incomplete in 0..full.len() {
+            assert_eq!(parse(&full[..incomplete], None), Ok(None));
+        }
+        assert_eq!(
+            parse(&full, None),
+            Ok(Some((2, Transfer::Full(full[..3].to_vec()))))
+        );
+
+        assert_eq!(parse(&[soa(2)], Some(2)), Ok(Some((2, Transfer::UpToDate))));
+        assert!(parse(&[a.clone(), soa(2)], None).is_err());
+
+        let incremental = [
+            soa(3),
+            soa(1),
+            a.clone(),
+            soa(2),
+            soa(2),
+            soa(3),
+            b.clone(),
+            soa(3),
+        ];
+        for incomplete in 2..incremental.len() {
+            assert_eq!(parse(&incremental[..incomplete], Some(1)), Ok(None));
+        }
+
+        let Ok(Some((3, Transfer::Incremental(differences)))) = parse(&incremental, Some(1)) else {
+            panic!("incremental transfer was not recognized");
+        };
+        assert_eq!(differences.len(), 2);
+
+        // Differences must start from the serial requested.
+        assert!(parse(&incremental, Some(2)).is_err());
+
+        assert_eq!(
+            apply(&[soa(1), a.clone()], differences.clone()),
+            Some(vec![soa(3), b.clone()])
+        );
+        assert_eq!(apply(&[soa(1), b.clone()], differences), None);
+    }
+
+    #[tokio::test]
+    async fn stub_primary() {
+        let origin = "example.org.";
+        let v1 = zone(1, &["192.0.2.1", "192.0.2.2"], Vec::new());
+        let v2 = zone(2, &["192.0.2.2", "192.0.2.3"], Vec::new());
+
+        let address = primary(Server::new(vec![Arc::new(v1.clone())], 16)).await;
+        let (serial, v1_entries) = refresh(&address, origin, None, &[]).await.unwrap().unwrap();
+        assert_eq!(serial, 1);
+        assert_eq!(v1_entries.len(), 4);
+        assert_eq!(v1_entries[1].rdata, "www.example.org.");
+
+        let server = Server::new(vec![Arc::new(v2.clone())], 16);
+        server.observe(&v1);
+        server.observe(&v2);
+        let address = primary(server).await;
+
+        // The differences since the first version are applied to its entries.
+        let (serial, v2_entries) = refresh(&address, origin, None, &v1_entries)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(serial, 2);
+
+        let (_, full) = refresh(&address, origin, None, &[]).await.unwrap().unwrap();
+        assert_eq!(v2_entries[0], full[0]);
+        assert_eq!(v2_entries.len(), full.len());
+        assert!(full.iter().all(|entry| v2_entries.contains(entry)));
+
+        assert_eq!(refresh(&address, origin, None, &v2_entries).await, Ok(None));
+
+        // Zones with keys only transfer to secondaries signing with one of them.
+        let signed = zone(
+            1,
+            &["192.0.2.1"],
+            vec![TsigKey {
+                name: String::from("kubizone."),
+                algorithm: TsigAlgorithm::HmacSha256,
+                secret_ref: SecretKeyRef {
+                    name: String::from("tsig"),
+                    key: String::from("secret"),
+                },
+            }],
+        );
+        let server = Server::new(vec![Arc::new(signed)], 16);
+        server.set_keys(origin, vec![key()]);
+        let address = primary(server).await;
+
+        assert!(refresh(&address, origin, Some(&key()), &[])
+            .await
+            .unwrap()
+            .is_some());
+        assert!(refresh(&address, origin, None, &[]).await.is_err());
+    }
+}
@@ -0,0 +1,930 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::StreamExt;
+use kube::{
+    runtime::{reflector, watcher, WatchStreamExt},
+    Client, ResourceExt,
+};
+use kubizone_common::Scope;
+use kubizone_crds::v1alpha1::{Zone, ZoneTransfer};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream, UdpSocket},
+};
+use tracing::log::*;
+
+use crate::{
+    rdata, transfer, tsig,
+    wire::{opcode, rcode, types, Message, Reader, ResourceRecord, CLASS_ANY, CLASS_IN},
+};
+
+/// Size of UDP responses to clients which don't advertise a larger one using EDNS.
+const MAX_UDP_SIZE: usize = 512;
+
+/// Largest UDP response we send to clients using EDNS, which avoids fragmentation.
+const MAX_EDNS_UDP_SIZE: u16 = 1232;
+
+/// Maximum number of CNAME records followed within a zone while answering a query.
+const MAX_CNAME_CHAIN: usize = 8;
+
+/// Time a TCP connection is kept open while waiting for the next query.
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time to wait before accepting connections or receiving datagrams again after
+/// failing to, such as when running out of file descriptors.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Interval at which the TSIG keys zones may be transferred with are re-read
+/// from their Secrets, in addition to whenever the zone changes.
+const KEY_REFRESH: Duration = Duration::from_secs(300);
+
+/// Source of the zones queries are answered from.
+pub trait Zones: Send + Sync + 'static {
+    fn zones(&self) -> Vec<Arc<Zone>>;
+}
+
+impl Zones for Vec<reflector::Store<Zone>> {
+    fn zones(&self) -> Vec<Arc<Zone>> {
+        self.iter().flat_map(|store| store.state()).collect()
+    }
+}
+
+impl Zones for Vec<Arc<Zone>> {
+    fn zones(&self) -> Vec<Arc<Zone>> {
+        self.clone()
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) struct Record {
+    pub type_: u16,
+    pub ttl: u32,
+    pub rdata: Vec<u8>,
+}
+
+impl Record {
+    pub fn with_owner(&self, name: &str) -> ResourceRecord {
+        ResourceRecord {
+            name: name.to_string(),
+            type_: self.type_,
+            class: CLASS_IN,
+            ttl: self.ttl,
+            rdata: self.rdata.clone(),
+        }
+    }
+
+    /// Domain name an NS or CNAME record points to.
+    fn target(&self) -> Option<String> {
+        Reader::new(&self.rdata).name().ok()
+    }
+}
+
+/// Records of a single version of a zone, indexed for answering queries.
+pub(crate) struct Authority {
+    /// Lowercased fully qualified domain name of the zone.
+    pub origin: String,
+    pub serial: u32,
+    /// TTL of negative responses.
+    negative_ttl: u32,
+    /// Records by lowercased owner name.
+    pub records: BTreeMap<String, Vec<Record>>,
+    pub transfer: Option<ZoneTransfer>,
+}
+
+/// Lowercased ancestors of `name` within the zone `origin`, from the
+/// closest one to the origin itself, excluding `name`.
+fn ancestors<'a>(name: &'a str, origin: &'a str) -> impl Iterator<Item = &'a str> {
+    let mut current = name;
+    std::iter::from_fn(move || {
+        if current == origin {
+            return None;
+        }
+
+        current = current.split_once('.').map(|(_, parent)| parent)?;
+        Some(if current.is_empty() { "." } else { current })
+    })
+}
+
+fn within(name: &str, origin: &str) -> bool {
+    origin == "." || name == origin || name.ends_with(&format!(".{origin}"))
+}
+
+impl Authority {
+    fn new(zone: &Zone, origin: &str) -> Self {
+        let origin = origin.to_lowercase();
+        let mut records: BTreeMap<String, Vec<Record>> = BTreeMap::new();
+
+        let entries = zone
+            .status
+            .as_ref()
+            .map(|status| status.entries.as_slice())
+            .unwrap_or_default();
+
+        for entry in entries {
+            let name = rdata::absolute(&entry.fqdn, &origin).to_lowercase();
+            if !within(&name, &origin) || !entry.class.eq_ignore_ascii_case("IN") {
+                continue;
+            }
+
+            let Some(type_) = types::from_mnemonic(&entry.type_) else {
+                warn!(
+                    "zone {zone} contains entry {} of unknown type {}",
+                    entry.fqdn, entry.type_
+                );
+                continue;
+            };
+
+            match rdata::encode(type_, &entry.rdata, &origin) {
+                Ok(rdata) => records.entry(name).or_default().push(Record {
+                    type_,
+                    ttl: entry.ttl,
+                    rdata,
+                }),
+                Err(err) => warn!("zone {zone} contains invalid entry {}: {err}", entry.fqdn),
+            }
+        }
+
+        Authority {
+            origin,
+            serial: zone.serial().unwrap_or_default(),
+            negative_ttl: zone.spec.negative_response_cache,
+            records,
+            transfer: zone.spec.transfer.clone(),
+        }
+    }
+
+    /// Records of `type_` owned by `name`.
+    pub fn rrset<'a>(&'a self, name: &str, type_: u16) -> impl Iterator<Item = &'a Record> {
+        self.records
+            .get(name)
+            .into_iter()
+            .flatten()
+            .filter(move |record| record.type_ == type_)
+    }
+
+    /// Whether `name` owns records, or is an empty non-terminal with descendants which do.
+    fn exists(&self, name: &str) -> bool {
+        let suffix = format!(".{name}");
+        self.records
+            .keys()
+            .any(|owner| owner == name || owner.ends_with(&suffix))
+    }
+
+    /// Highest zone cut above or at `name`, below which the zone delegates
+    /// authority to the name servers of a child zone.
+    fn delegation(&self, name: &str, type_: u16) -> Option<String> {
+        let mut candidates: Vec<&str> = ancestors(name, &self.origin).collect();
+        candidates.pop();
+        candidates.reverse();
+        if name != self.origin && type_ != types::DS {
+            // DS records at the cut itself belong to the parent.
+            candidates.push(name);
+        }
+
+        candidates
+            .into_iter()
+            .find(|candidate| self.rrset(candidate, types::NS).next().is_some())
+            .map(str::to_string)
+    }
+
+    /// Records of the wildcard matching the non-existent `name`, if any.
+    fn wildcard(&self, name: &str) -> Option<&Vec<Record>> {
+        // Only the closest existing ancestor is considered for wildcards.
+        let closest_encloser =
+            ancestors(name, &self.origin).find(|ancestor| self.exists(ancestor))?;
+        self.records.get(&format!("*.{closest_encloser}"))
+    }
+
+    /// Add the SOA record to the authority section of a negative response.
+    fn negative(&self, response: &mut Message) {
+        response
+            .authorities
+            .extend(
+                self.rrset(&self.origin, types::SOA)
+                    .map(|soa| ResourceRecord {
+                        ttl: soa.ttl.min(self.negative_ttl),
+                        ..soa.with_owner(&self.origin)
+                    }),
+            );
+    }
+
+    /// Answer the query for `type_` records of the lowercased `name`, as
+    /// described in [RFC 1034 section 4.3.2](https://datatracker.ietf.org/doc/html/rfc1034#section-4.3.2).
+    fn answer(&self, name: &str, type_: u16, response: &mut Message) {
+        response.header.authoritative = true;
+        let mut name = name.to_string();
+
+        for _ in 0..MAX_CNAME_CHAIN {
+            if let Some(cut) = self.delegation(&name, type_) {
+                // Referrals are only authoritative for the CNAMEs leading up to them.
+                response.header.authoritative = !response.answers.is_empty();
+
+                for ns in self.rrset(&cut, types::NS) {
+                    response.authorities.push(ns.with_owner(&cut));
+
+                    let Some(target) = ns.target().map(|target| target.to_lowercase()) else {
+                        continue;
+                    };
+
+                    for glue in self
+                        .rrset(&target, types::A)
+                        .chain(self.rrset(&target, types::AAAA))
+                    {
+                        response.additionals.push(glue.with_owner(&target));
+                    }
+                }
+
+                return;
+            }
+
+            let records = match self.records.get(&name) {
+                Some(records) => records,
+                None if self.exists(&name) => return self.negative(response),
+                None => match self.wildcard(&name) {
+                    Some(records) => records,
+                    None => {
+                        response.header.rcode = rcode::NXDOMAIN;
+                        return self.negative(response);
+                    }
+                },
+            };
+
+            let matching: Vec<_> = records
+                .iter()
+                .filter(|record| type_ == types::ANY || record.type_ == type_)
+                .collect();
+
+            if !matching.is_empty() {
+                response
+                    .answers
+                    .extend(matching.iter().map(|record| record.with_owner(&name)));
+                return;
+            }
+
+            let Some(cname) = records.iter().find(|record| record.type_ == types::CNAME) else {
+                return self.negative(response);
+            };
+
+            response.answers.push(cname.with_owner(&name));
+
+            // Targets outside of the zone are left for the client to resolve.
+            match cname.target().map(|target| target.to_lowercase()) {
+                Some(target) if within(&target, &self.origin) => name = target,
+                _ => return,
+            }
+        }
+    }
+}
+
+/// Current and previous versions of a zone.
+pub(crate) struct Versions {
+    /// Hash of the zone the current version was built from.
+    hash: String,
+    pub current: Arc<Authority>,
+    /// Previous versions with distinct serials, most recent first.
+    pub previous: VecDeque<Arc<Authority>>,
+}
+
+/// Answers DNS queries from the entries of the zones.
+pub struct Server<Z> {
+    zones: Z,
+    /// Number of previous versions of each zone kept for IXFR.
+    history: usize,
+    /// Versions by lowercased origin, updated whenever the hash of the zone changes.
+    versions: Mutex<HashMap<String, Versions>>,
+    /// Keys transfers of each zone may be signed with, by lowercased origin.
+    keys: Mutex<HashMap<String, Vec<Arc<tsig::Key>>>>,
+}
+
+impl<Z: Zones> Server<Z> {
+    pub fn new(zones: Z, history: usize) -> Self {
+        Server {
+            zones,
+            history,
+            versions: Mutex::default(),
+            keys: Mutex::default(),
+        }
+    }
+
+    /// Record the current version of `zone`, keeping the one it replaces for IXFR.
+    pub(crate) fn observe(&self, zone: &Zone) -> Option<Arc<Authority>> {
+        let origin = zone.fqdn()?.to_lowercase();
+        let hash = zone.hash().unwrap_or_default();
+
+        // Unwrap safety: the lock is never held across anything that could panic.
+        let mut versions = self.versions.lock().unwrap();
+        if let Some(existing) = versions.get(&origin) {
+            if existing.hash == hash {
+                return Some(existing.current.clone());
+            }
+        }
+
+        let current = Arc::new(Authority::new(zone, &origin));
+        let previous = match versions.remove(&origin) {
+            Some(mut existing) => {
+                if existing.current.serial != current.serial {
+                    existing.previous.push_front(existing.current);
+                    existing.previous.truncate(self.history);
+                }
+                existing.previous
+            }
+            None => VecDeque::new(),
+        };
+
+        versions.insert(
+            origin,
+            Versions {
+                hash: hash.to_string(),
+                current: current.clone(),
+                previous,
+            },
+        );
+
+        Some(current)
+    }
+
+    /// Authority for the zone closest to the lowercased `name`, if any.
+    ///
+    /// Secondary zones which have expired are not answered for at all.
+    fn authority(&self, name: &str) -> Option<Arc<Authority>> {
+        let zones = self.zones.zones();
+        let zone = zones
+            .iter()
+            .filter(|zone| {
+                zone.fqdn()
+                    .is_some_and(|origin| within(name, &origin.to_lowercase()))
+            })
+            .filter(|zone| {
+                !zone
+                    .status
+                    .as_ref()
+                    .and_then(|status| status.secondary.as_ref())
+                    .is_some_and(|secondary| secondary.expired)
+            })
+            .max_by_key(|zone| zone.fqdn().map(str::len))?;
+
+        self.observe(zone)
+    }
+
+    /// Forget the versions and keys of zones which no longer exist, or whose
+    /// origin has changed.
+    pub(crate) fn prune(&self) {
+        let origins: HashSet<_> = self
+            .zones
+            .zones()
+            .iter()
+            .filter_map(|zone| zone.fqdn().map(str::to_lowercase))
+            .collect();
+
+        // Unwrap safety: the locks are never held across anything that could panic.
+        self.versions
+            .lock()
+            .unwrap()
+            .retain(|origin, _| origins.contains(origin));
+        self.keys
+            .lock()
+            .unwrap()
+            .retain(|origin, _| origins.contains(origin));
+    }
+
+    /// Replace the keys transfers of the zone `origin` may be signed with.
+    pub fn set_keys(&self, origin: &str, keys: Vec<tsig::Key>) {
+        // Unwrap safety: the lock is never held across anything that could panic.
+        self.keys.lock().unwrap().insert(
+            origin.to_lowercase(),
+            keys.into_iter().map(Arc::new).collect(),
+        );
+    }
+
+    /// Respond to a zone transfer request over TCP.
+    fn transfer(
+        &self,
+        raw: &[u8],
+        request: &Message,
+        name: &str,
+        peer: IpAddr,
+    ) -> Result<Vec<Vec<u8>>, u8> {
+        let authority = self.authority(name).ok_or(rcode::REFUSED)?;
+        if authority.origin != name {
+            return Err(rcode::NOTAUTH);
+        }
+
+        // Unwrap safety: the locks are never held across anything that could panic.
+        let previous = self
+            .versions
+            .lock()
+            .unwrap()
+            .get(name)
+            .map(|versions| versions.previous.clone())
+            .unwrap_or_default();
+
+        let keys = self
+            .keys
+            .lock()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .unwrap_or_default();
+
+        transfer::respond(&authority, &previous, &keys, raw, request, peer)
+    }
+
+    /// Respond to the `request` from `peer`, limiting UDP responses to the size
+    /// the client accepts. Zone transfers over TCP may span several messages.
+    pub fn respond(&self, raw: &[u8], peer: IpAddr, udp: bool) -> Vec<Vec<u8>> {
+        // Messages which can't even be decoded are most likely not DNS at all.
+        let Ok(request) = Message::decode(raw) else {
+            return Vec::new();
+        };
+
+        if request.header.response {
+            return Vec::new();
+        }
+
+        let mut response = Message {
+            header: request.header.clone(),
+            questions: request.questions.clone(),
+            ..Default::default()
+        };
+        response.header.response = true;
+        response.header.authoritative = false;
+        response.header.truncated = false;
+        response.header.recursion_available = false;
+        response.header.rcode = rcode::NOERROR;
+
+        let edns = request
+            .additionals
+            .iter()
+            .find(|record| record.type_ == types::OPT);
+
+        match (request.header.opcode, request.questions.as_slice()) {
+            (opcode::QUERY, [question]) if matches!(question.type_, types::AXFR | types::IXFR) => {
+                // Clients retry truncated responses over TCP.
+                if udp {
+                    response.header.truncated = true;
+                } else {
+                    match self.transfer(raw, &request, &question.name.to_lowercase(), peer) {
+                        Ok(messages) => return messages,
+                        Err(code) => response.header.rcode = code,
+                    }
+                }
+            }
+            (opcode::QUERY, [question]) if matches!(question.class, CLASS_IN | CLASS_ANY) => {
+                let name = question.name.to_lowercase();
+                match self.authority(&name) {
+                    Some(authority) => authority.answer(&name, question.type_, &mut response),
+                    None => response.header.rcode = rcode::REFUSED,
+                }
+            }
+            (opcode::QUERY, [_]) => response.header.rcode = rcode::REFUSED,
+            (opcode::QUERY, _) => response.header.rcode = rcode::FORMERR,
+            _ => response.header.rcode = rcode::NOTIMP,
+        }
+
+        let mut limit = MAX_UDP_SIZE;
+        if let Some(edns) = edns {
+            limit = usize::from(edns.class.clamp(MAX_UDP_SIZE as u16, MAX_EDNS_UDP_SIZE));
+            response.additionals.push(ResourceRecord {
+                name: String::from("."),
+                type_: types::OPT,
+                class: MAX_EDNS_UDP_SIZE,
+                ttl: 0,
+                rdata: Vec::new(),
+            });
+        }
+
+        let Ok(encoded) = response.encode() else {
+            return Vec::new();
+        };
+
+        if !udp || encoded.len() <= limit {
+            return vec![encoded];
+        }
+
+        // Clients retry truncated responses over TCP.
+        response.header.truncated = true;
+        response.answers.clear();
+        response.authorities.clear();
+        response
+            .additionals
+            .retain(|record| record.type_ == types::OPT);
+
+        response.encode().into_iter().collect()
+    }
+
+    async fn serve_udp(self: Arc<Self>, socket: UdpSocket) -> io::Result<()> {
+        let mut buffer = vec![0u8; 65535];
+        loop {
+            // Receiving fails for reasons which don't affect other clients, such as
+            // ICMP errors caused by earlier responses, so the socket is kept open.
+            let (length, peer) = match socket.recv_from(&mut buffer).await {
+                Ok(received) => received,
+                Err(err) => {
+                    warn!("failed to receive datagram: {err}");
+                    tokio::time::sleep(ACCEPT_BACKOFF).await;
+                    continue;
+                }
+            };
+            for response in self.respond(&buffer[..length], peer.ip(), true) {
+                if let Err(err) = socket.send_to(&response, peer).await {
+                    debug!("failed to respond to {peer}: {err}");
+                }
+            }
+        }
+    }
+
+    async fn serve_connection(&self, mut stream: TcpStream, peer: IpAddr) -> io::Result<()> {
+        loop {
+            let mut length = [0u8; 2];
+            match tokio::time::timeout(TCP_IDLE_TIMEOUT, stream.read_exact(&mut length)).await {
+                Ok(result) => result?,
+                Err(_) => return Ok(()),
+            };
+
+            // The request itself must follow its length without delay.
+            let mut request = vec![0u8; u16::from_be_bytes(length) as usize];
+            tokio::time::timeout(TCP_IDLE_TIMEOUT, stream.read_exact(&mut request))
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "request timed out"))??;
+
+            let responses = self.respond(&request, peer, false);
+            if responses.is_empty() {
+                return Ok(());
+            }
+
+            for response in responses {
+                let length = u16::try_from(response.len()).map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidData, "response too large")
+                })?;
+
+                stream.write_all(&length.to_be_bytes()).await?;
+                stream.write_all(&response).await?;
+            }
+        }
+    }
+
+    async fn serve_tcp(self: Arc<Self>, listener: TcpListener) -> io::Result<()> {
+        loop {
+            // Failing to accept one connection is no reason to stop serving.
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    warn!("failed to accept connection: {err}");
+                    tokio::time::sleep(ACCEPT_BACKOFF).await;
+                    continue;
+                }
+            };
+
+            let server = self.clone();
+            tokio::spawn(async move {
+                if let Err(err) = server.serve_connection(stream, peer.ip()).await {
+                    debug!("connection with {peer} failed: {err}");
+                }
+            });
+        }
+    }
+
+    /// Answer queries over both UDP and TCP on `address`.
+    pub async fn serve(self: Arc<Self>, address: SocketAddr) -> io::Result<()> {
+        let socket = UdpSocket::bind(address).await?;
+        let listener = TcpListener::bind(socket.local_addr()?).await?;
+        info!("serving zones on {}", socket.local_addr()?);
+
+        let server = self;
+        tokio::try_join!(server.clone().serve_udp(socket), server.serve_tcp(listener)).map(|_| ())
+    }
+}
+
+/// Read the keys transfers of `zone` may be signed with from their Secrets.
+async fn load_keys<Z: Zones>(client: Client, server: &Server<Z>, zone: &Zone) {
+    let (Some(origin), Some(transfer)) = (zone.fqdn(), &zone.spec.transfer) else {
+        return;
+    };
+
+    let mut keys = Vec::new();
+    for tsig in &transfer.keys {
+        // Unwrap safety: zones are namespaced.
+        match tsig::Key::from_secret(client.clone(), zone.namespace().as_ref().unwrap(), tsig).await
+        {
+            Ok(key) => keys.push(key),
+            Err(err) => warn!(
+                "transfers of zone {zone} cannot be signed with key {}: {err}",
+                tsig.name
+            ),
+        }
+    }
+
+    server.set_keys(origin, keys);
+}
+
+/// Serve the zones within `scope` on `address`, as they change, keeping
+/// `history` previous versions of each zone for IXFR.
+pub async fn run(
+    client: Client,
+    scope: Scope,
+    address: SocketAddr,
+    history: usize,
+) -> io::Result<()> {
+    let (stores, writers): (Vec<_>, Vec<_>) = scope
+        .apis::<Zone>(&client)
+        .into_iter()
+        .map(|zones| {
+            let (reader, writer) = reflector::store();
+            (reader, (zones, writer))
+        })
+        .unzip();
+
+    let server = Arc::new(Server::new(stores, history));
+
+    // Every version of each zone is recorded as it's observed, rather than
+    // when it's first queried, so IXFR can be answered with differences.
+    let watchers = writers.into_iter().map(|(zones, writer)| {
+        let server = server.clone();
+        let client = client.clone();
+
+        reflector(writer, watcher(zones, scope.watcher_config()))
+            .default_backoff()
+            .touched_objects()
+            .for_each(move |zone| {
+                let server = server.clone();
+                let client = client.clone();
+                async move {
+                    match zone {
+                        Ok(zone) => {
+                            // Deleted zones are touched as well, but are
+                            // already gone from the store by now.
+                            let exists = server
+                                .zones
+                                .zones()
+                                .iter()
+                                .any(|current| current.uid() == zone.uid());
+
+                            if exists {
+                                server.observe(&zone);
+                                load_keys(client, &server, &zone).await;
+                            }
+
+                            server.prune();
+                        }
+                        Err(err) => warn!("watch failed: {err}"),
+                    }
+                }
+            })
+    });
+
+    // Keys are also re-read periodically, since Secrets aren't watched.
+    let refresh = async {
+        let mut interval = tokio::time::interval(KEY_REFRESH);
+        loop {
+            interval.tick().await;
+            for zone in server.zones.zones() {
+                load_keys(client.clone(), &server, &zone).await;
+            }
+        }
+    };
+
+    tokio::select! {
+        _ = futures::future::join_all(watchers) => Ok(()),
+        _ = refresh => Ok(()),
+        result = server.clone().serve(address) => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::Ipv4Addr, sync::Arc};
+
+    use kube::api::ObjectMeta;
+    use kubizone_crds::v1alpha1::{Zone, ZoneEntry, ZoneSpec, ZoneStatus};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpStream, UdpSocket},
+    };
+
+    use crate::wire::{opcode, rcode, types, Header, Message, Question, CLASS_IN};
+
+    use super::Server;
+
+    fn zone(origin: &str, entries: &[(&str, &str, &str)]) -> Arc<Zone> {
+        Arc::new(Zone {
+            metadata: ObjectMeta {
+                name: Some(origin.replace('.', "-")),
+                namespace: Some(String::from("default")),
+                ..Default::default()
+            },
+            spec: ZoneSpec {
+                domain_name: origin.to_string(),
+                negative_response_cache: 60,
+                ..Default::default()
+            },
+            status: Some(ZoneStatus {
+                fqdn: Some(origin.to_string()),
+                hash: Some(String::from("hash")),
+                entries: entries
+                    .iter()
+                    .map(|(fqdn, type_, rdata)| ZoneEntry {
+                        fqdn: fqdn.to_string(),
+                        type_: type_.to_string(),
+                        class: String::from("IN"),
+                        ttl: 300,
+                        rdata: rdata.to_string(),
+                    })
+                    .collect(),
+                ..Default::default()
+            }),
+        })
+    }
+
+    fn zones() -> Vec<Arc<Zone>> {
+        vec![
+            zone(
+                "example.org.",
+                &[
+                    (
+                        "example.org.",
+                        "SOA",
+                        "ns.example.org. noc.example.org. (1 86400 7200 3600000 60)",
+                    ),
+                    ("example.org.", "NS", "ns.example.org."),
+                    ("ns.example.org.", "A", "192.0.2.1"),
+                    ("www.example.org.", "A", "192.0.2.2"),
+                    ("alias.example.org.", "CNAME", "www"),
+                    ("external.example.org.", "CNAME", "example.com."),
+                    ("*.wild.example.org.", "TXT", "\"wildcard\""),
+                    ("a.b.example.org.", "A", "192.0.2.3"),
+                    ("sub.example.org.", "NS", "ns.sub.example.org."),
+                    ("sub.example.org.", "DS", "1 13 2 abcd"),
+                    ("ns.sub.example.org.", "A", "192.0.2.4"),
+                ],
+            ),
+            zone(
+                "other.example.org.",
+                &[(
+                    "other.example.org.",
+                    "SOA",
+                    "ns.example.org. noc.example.org. (1 86400 7200 3600000 60)",
+                )],
+            ),
+        ]
+    }
+
+    fn query(name: &str, type_: u16) -> Message {
+        Message {
+            header: Header {
+                id: 42,
+                opcode: opcode::QUERY,
+                recursion_desired: true,
+                ..Default::default()
+            },
+            questions: vec![Question {
+                name: name.to_string(),
+                type_,
+                class: CLASS_IN,
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn ask(server: &Server<Vec<Arc<Zone>>>, name: &str, type_: u16) -> Message {
+        let request = query(name, type_).encode().unwrap();
+        let responses = server.respond(&request, Ipv4Addr::LOCALHOST.into(), true);
+        assert_eq!(responses.len(), 1);
+        Message::decode(&responses[0]).unwrap()
+    }
+
+    #[test]
+    fn answers() {
+        let server = Server::new(zones(), 16);
+
+        let response = ask(&server, "WWW.example.org.", types::A);
+        assert!(response.header.response && response.header.authoritative);
+        assert!(response.header.recursion_desired);
+        assert_eq!(response.header.id, 42);
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(response.answers[0].rdata, [192, 0, 2, 2]);
+
+        let response = ask(&server, "example.org.", types::SOA);
+        assert_eq!(response.answers[0].type_, types::SOA);
+
+        // CNAMEs are followed within the zone.
+        let response = ask(&server, "alias.example.org.", types::A);
+        assert_eq!(response.answers.len(), 2);
+        assert_eq!(response.answers[0].type_, types::CNAME);
+        assert_eq!(response.answers[1].name, "www.example.org.");
+
+        let response = ask(&server, "external.example.org.", types::A);
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(response.header.rcode, rcode::NOERROR);
+
+        // Wildcards are synthesized with the name queried for.
+        let response = ask(&server, "anything.wild.example.org.", types::TXT);
+        assert_eq!(response.answers[0].name, "anything.wild.example.org.");
+
+        // Names with existing descendants exist, but have no data.
+        let response = ask(&server, "b.example.org.", types::A);
+        assert_eq!(response.header.rcode, rcode::NOERROR);
+        assert!(response.answers.is_empty());
+        assert_eq!(response.authorities[0].type_, types::SOA);
+        assert_eq!(response.authorities[0].ttl, 60);
+
+        let response = ask(&server, "www.example.org.", types::AAAA);
+        assert_eq!(response.header.rcode, rcode::NOERROR);
+        assert_eq!(response.authorities[0].type_, types::SOA);
+
+        let response = ask(&server, "missing.example.org.", types::A);
+        assert_eq!(response.header.rcode, rcode::NXDOMAIN);
+        assert!(response.header.authoritative);
+        assert_eq!(response.authorities[0].ttl, 60);
+
+        // Delegated names are referred to the child's name servers, with glue.
+        let response = ask(&server, "www.sub.example.org.", types::A);
+        assert!(!response.header.authoritative);
+        assert!(response.answers.is_empty());
+        assert_eq!(response.authorities[0].type_, types::NS);
+        assert_eq!(response.additionals[0].rdata, [192, 0, 2, 4]);
+
+        // Except for the DS records at the cut, which belong to the parent.
+        let response = ask(&server, "sub.example.org.", types::DS);
+        assert!(response.header.authoritative);
+        assert_eq!(response.answers[0].type_, types::DS);
+
+        // The most specific zone answers.
+        let response = ask(&server, "other.example.org.", types::SOA);
+        assert_eq!(response.answers[0].rdata[..4], *b"\x02ns\x07");
+        let response = ask(&server, "www.other.example.org.", types::A);
+        assert_eq!(response.header.rcode, rcode::NXDOMAIN);
+
+        let response = ask(&server, "example.com.", types::A);
+        assert_eq!(response.header.rcode, rcode::REFUSED);
+        assert!(!response.header.authoritative);
+    }
+
+    #[test]
+    fn prune_removed_zones() {
+        let server = Server::new(zones(), 1);
+        let removed = zone("removed.example.org.", &[]);
+
+        server.observe(&zones()[0]);
+        server.observe(&removed);
+        server.set_keys("removed.example.org.", Vec::new());
+
+        server.prune();
+        let versions = server.versions.lock().unwrap();
+        assert!(versions.contains_key("example.org."));
+        assert!(!versions.contains_key("removed.example.org."));
+        assert!(server.keys.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn resolver() {
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        drop(socket);
+
+        tokio::spawn(Arc::new(Server::new(zones(), 16)).serve(address));
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(address).await.unwrap();
+
+        // The server may not be listening yet.
+        let mut buffer = [0u8; 512];
+        let request = query("www.example.org.", types::A).encode().unwrap();
+        let length = loop {
+            client.send(&request).await.unwrap();
+            if let Ok(Ok(length)) = tokio::time::timeout(
+                std::time::Duration::from_millis(100),
+                client.recv(&mut buffer),
+            )
+            .await
+            {
+                break length;
+            }
+        };
+
+        let response = Message::decode(&buffer[..length]).unwrap();
+        assert_eq!(response.answers[0].rdata, [192, 0, 2, 2]);
+
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        for _ in 0..2 {
+            let request = query("alias.example.org.", types::A).encode().unwrap();
+            stream
+                .write_all(&(request.len() as u16).to_be_bytes())
+                .await
+                .unwrap();
+            stream.write_all(&request).await.unwrap();
+
+            let mut length = [0u8; 2];
+            stream.read_exact(&mut length).await.unwrap();
+            let mut response = vec![0u8; u16::from_be_bytes(length) as usize];
+            stream.read_exact(&mut response).await.unwrap();
+
+            assert_eq!(Message::decode(&response).unwrap().answers.len(), 2);
+        }
+    }
+}
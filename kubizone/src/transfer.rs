@@ -0,0 +1,578 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    net::IpAddr,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tracing::log::*;
+
+use crate::{
+    serve::Authority,
+    tsig,
+    wire::{rcode, types, Message, Reader, ResourceRecord},
+};
+
+/// Upper limit on the size of each message of a zone transfer, well below
+/// the 64KiB allowed over TCP, so records are spread over several messages.
+const MAX_MESSAGE_SIZE: usize = 16_384;
+
+/// Whether `address` is within one of the addresses or CIDR blocks in `allow`.
+pub fn allowed(allow: &[String], address: IpAddr) -> bool {
+    // Connections over IPv6 sockets from IPv4 clients use mapped addresses.
+    let address = match address {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(address),
+        address => address,
+    };
+
+    allow.iter().any(|entry| {
+        let (network, prefix) = match entry.split_once('/') {
+            Some((network, prefix)) => (network, Some(prefix)),
+            None => (entry.as_str(), None),
+        };
+
+        let Ok(network) = network.parse::<IpAddr>() else {
+            warn!("ignoring invalid transfer acl entry {entry:?}");
+            return false;
+        };
+
+        let (network, address, bits) = match (network, address) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => (
+                u128::from(u32::from(network)),
+                u128::from(u32::from(address)),
+                32,
+            ),
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                (u128::from(network), u128::from(address), 128)
+            }
+            _ => return false,
+        };
+
+        let prefix = match prefix.map(str::parse::<u32>) {
+            None => bits,
+            Some(Ok(prefix)) if prefix <= bits => prefix,
+            Some(_) => {
+                warn!("ignoring invalid transfer acl entry {entry:?}");
+                return false;
+            }
+        };
+
+        let shift = bits - prefix;
+        network.checked_shr(shift).unwrap_or(0) == address.checked_shr(shift).unwrap_or(0)
+    })
+}
+
+/// Serial of the secondary's version of the zone, given in the SOA record
+/// of the authority section of an IXFR request.
+///
+/// The request is read as a whole, since the names within the SOA record
+/// may be compressed against the rest of the message.
+fn ixfr_serial(raw: &[u8]) -> Option<u32> {
+    let mut reader = Reader::new(raw);
+    reader.bytes(4).ok()?;
+    let questions = reader.u16().ok()?;
+    let answers = reader.u16().ok()?;
+    let authorities = reader.u16().ok()?;
+    let _additionals = reader.u16().ok()?;
+
+    if authorities == 0 {
+        return None;
+    }
+
+    for _ in 0..questions {
+        reader.question().ok()?;
+    }
+
+    for _ in 0..answers {
+        reader.record().ok()?;
+    }
+
+    reader.name().ok()?;
+    if reader.u16().ok()? != types::SOA {
+        return None;
+    }
+
+    // Class, TTL and length of the record data.
+    reader.bytes(8).ok()?;
+
+    reader.name().ok()?;
+    reader.name().ok()?;
+    reader.u32().ok()
+}
+
+/// The SOA record of a version of the zone.
+fn soa_record(authority: &Authority) -> Option<ResourceRecord> {
+    authority
+        .rrset(&authority.origin, types::SOA)
+        .next()
+        .map(|soa| soa.with_owner(&authority.origin))
+}
+
+/// All records of a version of the zone, except for its SOA record.
+fn records(authority: &Authority) -> Vec<ResourceRecord> {
+    authority
+        .records
+        .iter()
+        .flat_map(|(name, records)| {
+            records
+                .iter()
+                .filter(move |record| name != &authority.origin || record.type_ != types::SOA)
+                .map(move |record| record.with_owner(name))
+        })
+        .collect()
+}
+
+/// Records of a full transfer of the zone, enclosed in its SOA record.
+fn full(authority: &Authority, soa: ResourceRecord) -> Vec<ResourceRecord> {
+    let mut transfer = vec![soa.clone()];
+    transfer.extend(records(authority));
+    transfer.push(soa);
+    transfer
+}
+
+/// Records of an incremental transfer from the version of the zone with
+/// `serial`, condensed into a single difference, or a full transfer if that
+/// version is no longer known.
+fn incremental(
+    authority: &Authority,
+    previous: &VecDeque<Arc<Authority>>,
+    serial: u32,
+    soa: ResourceRecord,
+) -> Vec<ResourceRecord> {
+    // Secondaries which are up to date receive the current SOA record only.
+    if serial == authority.serial {
+        return vec![soa];
+    }
+
+    let Some((old, old_soa)) = previous
+        .iter()
+        .find(|old| old.serial == serial)
+        .and_then(|old| Some((old, soa_record(old)?)))
+    else {
+        return full(authority, soa);
+    };
+
+    let old_records = records(old);
+    let new_records = records(authority);
+    let old_set: HashSet<_> = old_records.iter().collect();
+    let new_set: HashSet<_> = new_records.iter().collect();
+
+    let mut transfer = vec![soa.clone(), old_soa];
+    transfer.extend(
+        old_records
+            .iter()
+            .filter(|record| !new_set.contains(record))
+            .cloned(),
+    );
+    transfer.push(soa.clone());
+    transfer.extend(
+        new_records
+            .iter()
+            .filter(|record| !old_set.contains(record))
+            .cloned(),
+    );
+    transfer.push(soa);
+    transfer
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Spread `records` over as many messages as needed, signing each of them
+/// with the `signer` key in response to the request with its MAC, if any.
+fn messages(
+    request: &Message,
+    records: Vec<ResourceRecord>,
+    signer: Option<(&tsig::Key, Vec<u8>)>,
+) -> Result<Vec<Vec<u8>>, u8> {
+    let template = || {
+        let mut message = Message {
+            header: request.header.clone(),
+            ..Default::default()
+        };
+        message.header.response = true;
+        message.header.authoritative = true;
+        message.header.rcode = rcode::NOERROR;
+        message
+    };
+
+    // Only the first message repeats the question.
+    let mut first = template();
+    first.questions = request.questions.clone();
+
+    let mut messages = vec![first];
+    let mut size = 0;
+    for record in records {
+        let record_size = record.name.len() + 11 + record.rdata.len();
+        // Unwrap safety: there is always at least one message.
+        let message = messages.last_mut().unwrap();
+        if !message.answers.is_empty() && size + record_size > MAX_MESSAGE_SIZE {
+            messages.push(template());
+            size = 0;
+        }
+
+        // Unwrap safety: as above.
+        messages.last_mut().unwrap().answers.push(record);
+        size += record_size;
+    }
+
+    let Some((key, mut mac)) = signer else {
+        return messages
+            .iter()
+            .map(Message::encode)
+            .collect::<Result<_, _>>()
+            .map_err(|_| rcode::SERVFAIL);
+    };
+
+    let now = unix_time();
+    let mut signed = Vec::with_capacity(messages.len());
+    for (index, message) in messages.iter().enumerate() {
+        let (encoded, message_mac) = if index == 0 {
+            key.sign(message, &mac, now)
+        } else {
+            key.sign_subsequent(message, &mac, now)
+        }
+        .map_err(|_| rcode::SERVFAIL)?;
+
+        signed.push(encoded);
+        mac = message_mac;
+    }
+
+    Ok(signed)
+}
+
+/// Respond to the AXFR or IXFR `request` from `peer`, for the current version
+/// of the zone `authority`, given its `previous` versions, and the `keys` the
+/// request may be signed with. Fails with the response code to refuse it with.
+pub(crate) fn respond(
+    authority: &Authority,
+    previous: &VecDeque<Arc<Authority>>,
+    keys: &[Arc<tsig::Key>],
+    raw: &[u8],
+    request: &Message,
+    peer: IpAddr,
+) -> Result<Vec<Vec<u8>>, u8> {
+    let origin = &authority.origin;
+    let Some(transfer) = &authority.transfer else {
+        info!("refusing transfer of zone {origin} to {peer}, since transfers are not enabled");
+        return Err(rcode::REFUSED);
+    };
+
+    // Zones are only ever transferred to secondaries which are named by
+    // address, or which hold one of the keys.
+    if transfer.allow.is_empty() && transfer.keys.is_empty() {
+        info!("refusing transfer of zone {origin} to {peer}, since neither addresses nor keys are allowed");
+        return Err(rcode::REFUSED);
+    }
+
+    if !transfer.allow.is_empty() && !allowed(&transfer.allow, peer) {
+        info!("refusing transfer of zone {origin} to {peer}, which is not allowed");
+        return Err(rcode::REFUSED);
+    }
+
+    // Requests must be signed with one of the keys, if any are configured,
+    // even if none of them could be loaded.
+    let signer = if transfer.keys.is_empty() {
+        None
+    } else {
+        let now = unix_time();
+        let signer = keys
+            .iter()
+            .find_map(|key| Some((key.as_ref(), key.verify(raw, &[], now).ok()?)));
+
+        if signer.is_none() {
+            info!("refusing transfer of zone {origin} to {peer}, which is not signed with a known key");
+            return Err(rcode::NOTAUTH);
+        }
+
+        signer
+    };
+
+    let Some(soa) = soa_record(authority) else {
+        return Err(rcode::SERVFAIL);
+    };
+
+    let records = if request.questions[0].type_ == types::IXFR {
+        let serial = ixfr_serial(raw).ok_or(rcode::FORMERR)?;
+        info!(
+            "transferring zone {origin} from serial {serial} to {} to {peer}",
+            authority.serial
+        );
+        incremental(authority, previous, serial, soa)
+    } else {
+        info!(
+            "transferring zone {origin} with serial {} to {peer}",
+            authority.serial
+        );
+        full(authority, soa)
+    };
+
+    messages(request, records, signer)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        sync::Arc,
+    };
+
+    use kube::api::ObjectMeta;
+    use kubizone_crds::v1alpha1::{
+        SecretKeyRef, TsigAlgorithm, TsigKey, Zone, ZoneEntry, ZoneSpec, ZoneStatus, ZoneTransfer,
+    };
+
+    use crate::{
+        serve::Server,
+        tsig::Key,
+        wire::{opcode, rcode, types, Header, Message, Question, ResourceRecord, CLASS_IN},
+    };
+
+    use super::allowed;
+
+    const PEER: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 10));
+
+    fn zone(serial: u32, addresses: &[&str], transfer: ZoneTransfer) -> Zone {
+        let soa = format!("ns.example.org. noc.example.org. ({serial} 86400 7200 3600000 60)");
+        let mut entries = vec![("example.org.", "SOA", soa.as_str())];
+        entries.extend(
+            addresses
+                .iter()
+                .map(|address| ("www.example.org.", "A", *address)),
+        );
+
+        Zone {
+            metadata: ObjectMeta {
+                name: Some(String::from("example-org")),
+                namespace: Some(String::from("default")),
+                ..Default::default()
+            },
+            spec: ZoneSpec {
+                domain_name: String::from("example.org."),
+                transfer: Some(transfer),
+                ..Default::default()
+            },
+            status: Some(ZoneStatus {
+                fqdn: Some(String::from("example.org.")),
+                hash: Some(format!("{serial}")),
+                serial: Some(serial),
+                entries: entries
+                    .into_iter()
+                    .map(|(fqdn, type_, rdata)| ZoneEntry {
+                        fqdn: fqdn.to_string(),
+                        type_: type_.to_string(),
+                        class: String::from("IN"),
+                        ttl: 300,
+                        rdata: rdata.to_string(),
+                    })
+                    .collect(),
+                ..Default::default()
+            }),
+        }
+    }
+
+    fn request(type_: u16, serial: Option<u32>) -> Message {
+        Message {
+            header: Header {
+                id: 7,
+                opcode: opcode::QUERY,
+                ..Default::default()
+            },
+            questions: vec![Question {
+                name: String::from("example.org."),
+                type_,
+                class: CLASS_IN,
+            }],
+            authorities: serial
+                .map(|serial| {
+                    let mut rdata = b"\x02ns\x00\x03noc\x00".to_vec();
+                    rdata.extend_from_slice(&serial.to_be_bytes());
+                    rdata.extend_from_slice(&[0; 16]);
+                    ResourceRecord {
+                        name: String::from("example.org."),
+                        type_: types::SOA,
+                        class: CLASS_IN,
+                        ttl: 0,
+                        rdata,
+                    }
+                })
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn transfer(server: &Server<Vec<Arc<Zone>>>, request: &[u8]) -> Vec<Message> {
+        server
+            .respond(request, PEER, false)
+            .iter()
+            .map(|message| Message::decode(message).unwrap())
+            .collect()
+    }
+
+    fn rdata(records: &[ResourceRecord]) -> Vec<(u16, Vec<u8>)> {
+        records
+            .iter()
+            .map(|record| {
+                (
+                    record.type_,
+                    record.rdata[..4.min(record.rdata.len())].to_vec(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn acl() {
+        let allow = [String::from("192.0.2.0/24"), String::from("2001:db8::1")];
+        assert!(allowed(&allow, PEER));
+        assert!(allowed(&allow, "::ffff:192.0.2.1".parse().unwrap()));
+        assert!(allowed(&allow, "2001:db8::1".parse().unwrap()));
+        assert!(!allowed(&allow, "2001:db8::2".parse().unwrap()));
+        assert!(!allowed(&allow, "198.51.100.1".parse().unwrap()));
+
+        assert!(allowed(&[String::from("0.0.0.0/0")], PEER));
+        assert!(!allowed(&[String::from("192.0.2.0/33")], PEER));
+        assert!(!allowed(&[String::from("invalid")], PEER));
+    }
+
+    #[test]
+    fn full_and_incremental() {
+        let transfer_settings = ZoneTransfer {
+            allow: vec![String::from("192.0.2.0/24")],
+            keys: Vec::new(),
+        };
+
+        let v1 = zone(1, &["192.0.2.1", "192.0.2.2"], transfer_settings.clone());
+        let v2 = zone(2, &["192.0.2.2", "192.0.2.3"], transfer_settings.clone());
+        let server = Server::new(vec![Arc::new(v2.clone())], 16);
+        server.observe(&v1);
+        server.observe(&v2);
+
+        // Full transfers are enclosed in the SOA record.
+        let axfr = transfer(&server, &request(types::AXFR, None).encode().unwrap());
+        assert_eq!(axfr.len(), 1);
+        assert!(axfr[0].header.authoritative);
+        let answers = rdata(&axfr[0].answers);
+        assert_eq!(answers.len(), 4);
+        assert_eq!(answers[0], answers[3]);
+        assert_eq!(answers[0].0, types::SOA);
+
+        // Incremental transfers contain the difference between the versions.
+        let ixfr = transfer(&server, &request(types::IXFR, Some(1)).encode().unwrap());
+        let answers = rdata(&ixfr[0].answers);
+        assert_eq!(
+            answers[1..5],
+            [
+                (types::SOA, b"\x02ns\x07".to_vec()),
+                (types::A, vec![192, 0, 2, 1]),
+                (types::SOA, b"\x02ns\x07".to_vec()),
+                (types::A, vec![192, 0, 2, 3]),
+            ]
+        );
+        assert_eq!(ixfr[0].answers[1].rdata[33..37], 1u32.to_be_bytes());
+        assert_eq!(ixfr[0].answers[3].rdata[33..37], 2u32.to_be_bytes());
+        assert_eq!(answers.len(), 6);
+
+        // Up to date secondaries only receive the SOA record, and unknown
+        // versions receive the full zone.
+        let ixfr = transfer(&server, &request(types::IXFR, Some(2)).encode().unwrap());
+        assert_eq!(ixfr[0].answers.len(), 1);
+        let ixfr = transfer(&server, &request(types::IXFR, Some(0)).encode().unwrap());
+        assert_eq!(ixfr[0].answers.len(), 4);
+
+        // Transfers are only allowed from the listed addresses, and over TCP.
+        let refused = server.respond(
+            &request(types::AXFR, None).encode().unwrap(),
+            "198.51.100.1".parse().unwrap(),
+            false,
+        );
+        assert_eq!(
+            Message::decode(&refused[0]).unwrap().header.rcode,
+            rcode::REFUSED
+        );
+
+        let udp = server.respond(&request(types::AXFR, None).encode().unwrap(), PEER, true);
+        assert!(Message::decode(&udp[0]).unwrap().header.truncated);
+
+        // Allowing neither addresses nor keys refuses everyone.
+        let open = ZoneTransfer {
+            allow: Vec::new(),
+            keys: Vec::new(),
+        };
+        let server = Server::new(vec![Arc::new(zone(2, &["192.0.2.2"], open))], 16);
+        let refused = server.respond(&request(types::AXFR, None).encode().unwrap(), PEER, false);
+        assert_eq!(
+            Message::decode(&refused[0]).unwrap().header.rcode,
+            rcode::REFUSED
+        );
+    }
+
+    #[test]
+    fn signed() {
+        let key = || Key {
+            name: String::from("transfer."),
+            algorithm: TsigAlgorithm::HmacSha256,
+            secret: b"secret".to_vec(),
+        };
+
+        let addresses: Vec<String> = (0..1000)
+            .map(|i| format!("10.0.{}.{}", i / 256, i % 256))
+            .collect();
+        let addresses: Vec<&str> = addresses.iter().map(String::as_str).collect();
+        let zone = zone(
+            1,
+            &addresses,
+            ZoneTransfer {
+                allow: Vec::new(),
+                keys: vec![TsigKey {
+                    name: String::from("transfer"),
+                    algorithm: TsigAlgorithm::HmacSha256,
+                    secret_ref: SecretKeyRef {
+                        name: String::from("transfer"),
+                        key: String::from("secret"),
+                    },
+                }],
+            },
+        );
+
+        let server = Server::new(vec![Arc::new(zone)], 16);
+        let request = request(types::AXFR, None);
+
+        // Requests must be signed, even before the keys are loaded.
+        let unsigned = transfer(&server, &request.encode().unwrap());
+        assert_eq!(unsigned[0].header.rcode, rcode::NOTAUTH);
+
+        server.set_keys("example.org.", vec![key()]);
+        let signed = transfer(&server, &request.encode().unwrap());
+        assert_eq!(signed[0].header.rcode, rcode::NOTAUTH);
+
+        let (signed, request_mac) = key().sign(&request, &[], super::unix_time()).unwrap();
+        let responses = server.respond(&signed, PEER, false);
+
+        // Large zones are spread over several messages, each of them signed.
+        assert!(responses.len() > 1);
+        key()
+            .verify(&responses[0], &request_mac, super::unix_time())
+            .unwrap();
+
+        let messages: Vec<_> = responses
+            .iter()
+            .map(|response| Message::decode(response).unwrap())
+            .collect();
+        assert!(messages
+            .iter()
+            .all(|message| message.additionals.last().unwrap().type_ == types::TSIG));
+        assert_eq!(
+            messages
+                .iter()
+                .map(|message| message.answers.len())
+                .sum::<usize>(),
+            1002
+        );
+    }
+}
@@ -0,0 +1,434 @@
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use k8s_openapi::api::core::v1::Secret;
+use kube::{Api, Client};
+use kubizone_crds::v1alpha1::{TsigAlgorithm, TsigKey};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+
+use crate::{
+    error::Error,
+    wire::{encode_name, types, Message, Reader, ResourceRecord, WireError, CLASS_ANY},
+};
+
+/// Seconds of clock skew tolerated between us and the name server.
+pub const FUDGE: u16 = 300;
+
+/// Errors reported in TSIG records, in addition to the response codes.
+mod error {
+    pub const BADSIG: u16 = 16;
+    pub const BADKEY: u16 = 17;
+    pub const BADTIME: u16 = 18;
+    pub const BADTRUNC: u16 = 22;
+
+    pub fn name(error: u16) -> String {
+        match error {
+            BADSIG => String::from("BADSIG"),
+            BADKEY => String::from("BADKEY"),
+            BADTIME => String::from("BADTIME"),
+            BADTRUNC => String::from("BADTRUNC"),
+            error => format!("error {error}"),
+        }
+    }
+}
+
+/// Key shared with a name server, for authenticating the messages exchanged
+/// with it, as described in [RFC 8945](https://datatracker.ietf.org/doc/html/rfc8945).
+pub struct Key {
+    /// Fully qualified name of the key.
+    pub name: String,
+    pub algorithm: TsigAlgorithm,
+    pub secret: Vec<u8>,
+}
+
+/// Fields of a TSIG record covered by its MAC, other than the key and algorithm.
+struct Signature {
+    time_signed: u64,
+    fudge: u16,
+    mac: Vec<u8>,
+    original_id: u16,
+    error: u16,
+    other: Vec<u8>,
+}
+
+fn hmac<M: Mac + hmac::digest::KeyInit>(secret: &[u8], data: &[u8]) -> Vec<u8> {
+    // Unwrap safety: HMAC accepts keys of any length.
+    let mut mac = <M as Mac>::new_from_slice(secret).unwrap();
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Check `expected` against the MAC of `data` in constant time, so timing
+/// doesn't reveal how much of a forged MAC was correct.
+fn hmac_verify<M: Mac + hmac::digest::KeyInit>(
+    secret: &[u8],
+    data: &[u8],
+    expected: &[u8],
+) -> bool {
+    // Unwrap safety: HMAC accepts keys of any length.
+    let mut mac = <M as Mac>::new_from_slice(secret).unwrap();
+    mac.update(data);
+    mac.verify_slice(expected).is_ok()
+}
+
+impl Key {
+    /// Read the key referenced by `tsig` from its Secret in `namespace`.
+    pub async fn from_secret(
+        client: Client,
+        namespace: &str,
+        tsig: &TsigKey,
+    ) -> Result<Self, Error> {
+        let secret_ref = &tsig.secret_ref;
+        let secret = Api::<Secret>::namespaced(client, namespace)
+            .get_opt(&secret_ref.name)
+            .await?
+            .ok_or_else(|| {
+                Error::InvalidKey(format!("secret {} does not exist", secret_ref.name))
+            })?;
+
+        let value = secret
+            .data
+            .as_ref()
+            .and_then(|data| data.get(&secret_ref.key))
+            .ok_or_else(|| {
+                Error::InvalidKey(format!(
+                    "secret {} has no key {}",
+                    secret_ref.name, secret_ref.key
+                ))
+            })?;
+
+        let encoded = String::from_utf8_lossy(&value.0);
+        let secret = base64::engine::general_purpose::STANDARD
+            .decode(encoded.trim())
+            .map_err(|err| {
+                Error::InvalidKey(format!(
+                    "key {} of secret {} is not valid base64: {err}",
+                    secret_ref.key, secret_ref.name
+                ))
+            })?;
+
+        let name = tsig.name.trim_end_matches('.');
+        Ok(Key {
+            name: format!("{name}."),
+            algorithm: tsig.algorithm,
+            secret,
+        })
+    }
+
+    fn mac(&self, data: &[u8]) -> Vec<u8> {
+        match self.algorithm {
+            TsigAlgorithm::HmacSha1 => hmac::<Hmac<Sha1>>(&self.secret, data),
+            TsigAlgorithm::HmacSha256 => hmac::<Hmac<Sha256>>(&self.secret, data),
+            TsigAlgorithm::HmacSha512 => hmac::<Hmac<Sha512>>(&self.secret, data),
+        }
+    }
+
+    fn verify_mac(&self, data: &[u8], mac: &[u8]) -> bool {
+        match self.algorithm {
+            TsigAlgorithm::HmacSha1 => hmac_verify::<Hmac<Sha1>>(&self.secret, data, mac),
+            TsigAlgorithm::HmacSha256 => hmac_verify::<Hmac<Sha256>>(&self.secret, data, mac),
+            TsigAlgorithm::HmacSha512 => hmac_verify::<Hmac<Sha512>>(&self.secret, data, mac),
+        }
+    }
+
+    /// Variables of the TSIG record covered by the MAC, following the message itself.
+    ///
+    /// Only the timers are covered for all but the first message of a response
+    /// spanning several, such as zone transfers.
+    fn variables(&self, signature: &Signature, subsequent: bool) -> Result<Vec<u8>, WireError> {
+        let mut output = Vec::new();
+        if subsequent {
+            output.extend_from_slice(&signature.time_signed.to_be_bytes()[2..]);
+            output.extend_from_slice(&signature.fudge.to_be_bytes());
+            return Ok(output);
+        }
+
+        encode_name(&self.name.to_lowercase(), &mut output)?;
+        output.extend_from_slice(&CLASS_ANY.to_be_bytes());
+        output.extend_from_slice(&0u32.to_be_bytes());
+        encode_name(self.algorithm.name(), &mut output)?;
+        output.extend_from_slice(&signature.time_signed.to_be_bytes()[2..]);
+        output.extend_from_slice(&signature.fudge.to_be_bytes());
+        output.extend_from_slice(&signature.error.to_be_bytes());
+        output.extend_from_slice(&(signature.other.len() as u16).to_be_bytes());
+        output.extend_from_slice(&signature.other);
+        Ok(output)
+    }
+
+    /// Encode `message` with a TSIG record signing it at `time_signed`, in seconds
+    /// since the epoch, returning the MAC needed to verify the response.
+    ///
+    /// Responses are signed including the MAC of the `request_mac`,
+    /// which is empty for requests.
+    pub fn sign(
+        &self,
+        message: &Message,
+        request_mac: &[u8],
+        time_signed: u64,
+    ) -> Result<(Vec<u8>, Vec<u8>), WireError> {
+        self.sign_with(message, request_mac, time_signed, false)
+    }
+
+    /// Sign a message following the first one of a response spanning several,
+    /// given the MAC of the message before it, as described in
+    /// [RFC 8945 section 5.3.1](https://datatracker.ietf.org/doc/html/rfc8945#section-5.3.1).
+    pub fn sign_subsequent(
+        &self,
+        message: &Message,
+        prior_mac: &[u8],
+        time_signed: u64,
+    ) -> Result<(Vec<u8>, Vec<u8>), WireError> {
+        self.sign_with(message, prior_mac, time_signed, true)
+    }
+
+    fn sign_with(
+        &self,
+        message: &Message,
+        prior_mac: &[u8],
+        time_signed: u64,
+        subsequent: bool,
+    ) -> Result<(Vec<u8>, Vec<u8>), WireError> {
+        let mut signature = Signature {
+            time_signed,
+            fudge: FUDGE,
+            mac: Vec::new(),
+            original_id: message.header.id,
+            error: 0,
+            other: Vec::new(),
+        };
+
+        let mut data = Vec::new();
+        if !prior_mac.is_empty() {
+            data.extend_from_slice(&(prior_mac.len() as u16).to_be_bytes());
+            data.extend_from_slice(prior_mac);
+        }
+        data.extend(message.encode()?);
+        data.extend(self.variables(&signature, subsequent)?);
+        signature.mac = self.mac(&data);
+
+        let mut rdata = Vec::new();
+        encode_name(self.algorithm.name(), &mut rdata)?;
+        rdata.extend_from_slice(&signature.time_signed.to_be_bytes()[2..]);
+        rdata.extend_from_slice(&signature.fudge.to_be_bytes());
+        rdata.extend_from_slice(&(signature.mac.len() as u16).to_be_bytes());
+        rdata.extend_from_slice(&signature.mac);
+        rdata.extend_from_slice(&signature.original_id.to_be_bytes());
+        rdata.extend_from_slice(&signature.error.to_be_bytes());
+        rdata.extend_from_slice(&(signature.other.len() as u16).to_be_bytes());
+        rdata.extend_from_slice(&signature.other);
+
+        let mut signed = message.clone();
+        signed.additionals.push(ResourceRecord {
+            name: self.name.clone(),
+            type_: types::TSIG,
+            class: CLASS_ANY,
+            ttl: 0,
+            rdata,
+        });
+
+        Ok((signed.encode()?, signature.mac))
+    }
+
+    /// Verify the TSIG record of `message`, signed in response to a request
+    /// with the MAC `request_mac`, or as a request itself if it is empty.
+    /// Returns the MAC of the message, needed to sign the response to it.
+    pub fn verify(&self, message: &[u8], request_mac: &[u8], now: u64) -> Result<Vec<u8>, String> {
+        self.verify_with(message, request_mac, now, false)
+    }
+
+    /// Verify a message following the first one of a response spanning several,
+    /// given the MAC of the message before it.
+    ///
+    /// Every message of the response must be signed, which is what common name
+    /// servers do even though RFC 8945 allows leaving some of them unsigned.
+    pub fn verify_subsequent(
+        &self,
+        message: &[u8],
+        prior_mac: &[u8],
+        now: u64,
+    ) -> Result<Vec<u8>, String> {
+        self.verify_with(message, prior_mac, now, true)
+    }
+
+    fn verify_with(
+        &self,
+        message: &[u8],
+        prior_mac: &[u8],
+        now: u64,
+        subsequent: bool,
+    ) -> Result<Vec<u8>, String> {
+        let invalid = |err: WireError| format!("invalid message: {err}");
+
+        // The TSIG record is the last record of the additional section.
+        let mut reader = Reader::new(message);
+        reader.bytes(4).map_err(invalid)?;
+        let counts = [
+            reader.u16().map_err(invalid)?,
+            reader.u16().map_err(invalid)?,
+            reader.u16().map_err(invalid)?,
+            reader.u16().map_err(invalid)?,
+        ];
+
+        let Some(records) =
+            (u32::from(counts[1]) + u32::from(counts[2]) + u32::from(counts[3])).checked_sub(1)
+        else {
+            return Err(String::from("message is not signed"));
+        };
+
+        for _ in 0..counts[0] {
+            reader.question().map_err(invalid)?;
+        }
+
+        for _ in 0..records {
+            reader.record().map_err(invalid)?;
+        }
+
+        let start = reader.position();
+        let record = reader.record().map_err(invalid)?;
+        if record.type_ != types::TSIG || counts[3] == 0 {
+            return Err(String::from("message is not signed"));
+        }
+
+        let mut rdata = Reader::new(&record.rdata);
+        let algorithm = rdata.name().map_err(invalid)?;
+        let signature = Signature {
+            time_signed: (u64::from(rdata.u16().map_err(invalid)?) << 32)
+                | u64::from(rdata.u32().map_err(invalid)?),
+            fudge: rdata.u16().map_err(invalid)?,
+            mac: {
+                let length = rdata.u16().map_err(invalid)? as usize;
+                rdata.bytes(length).map_err(invalid)?.to_vec()
+            },
+            original_id: rdata.u16().map_err(invalid)?,
+            error: rdata.u16().map_err(invalid)?,
+            other: {
+                let length = rdata.u16().map_err(invalid)? as usize;
+                rdata.bytes(length).map_err(invalid)?.to_vec()
+            },
+        };
+
+        if signature.error != 0 {
+            return Err(format!(
+                "signature rejected with {}",
+                error::name(signature.error)
+            ));
+        }
+
+        if !record.name.eq_ignore_ascii_case(&self.name)
+            || !algorithm.eq_ignore_ascii_case(self.algorithm.name())
+        {
+            return Err(format!(
+                "message is signed with unknown key {}",
+                record.name
+            ));
+        }
+
+        // The MAC covers the message as it was before the TSIG record was added.
+        let mut unsigned = message[..start].to_vec();
+        unsigned[..2].copy_from_slice(&signature.original_id.to_be_bytes());
+        unsigned[10..12].copy_from_slice(&(counts[3] - 1).to_be_bytes());
+
+        let mut data = Vec::new();
+        if !prior_mac.is_empty() {
+            data.extend_from_slice(&(prior_mac.len() as u16).to_be_bytes());
+            data.extend_from_slice(prior_mac);
+        }
+        data.extend(unsigned);
+        data.extend(self.variables(&signature, subsequent).map_err(invalid)?);
+
+        if !self.verify_mac(&data, &signature.mac) {
+            return Err(String::from("message has an invalid signature"));
+        }
+
+        if now.abs_diff(signature.time_signed) > u64::from(signature.fudge) {
+            return Err(String::from(
+                "message was signed outside of the allowed time",
+            ));
+        }
+
+        Ok(signature.mac)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hmac::Hmac;
+    use kubizone_crds::v1alpha1::TsigAlgorithm;
+    use sha2::Sha256;
+
+    use crate::wire::{opcode, types, Header, Message, Question, CLASS_IN};
+
+    use super::{hmac, Key};
+
+    #[test]
+    fn hmac_sha256() {
+        // Test case 2 of RFC 4231.
+        assert_eq!(
+            hmac::<Hmac<Sha256>>(b"Jefe", b"what do ya want for nothing?"),
+            [
+                0x5b, 0xdc, 0xc1, 0x46, 0xbf, 0x60, 0x75, 0x4e, 0x6a, 0x04, 0x24, 0x26, 0x08, 0x95,
+                0x75, 0xc7, 0x5a, 0x00, 0x3f, 0x08, 0x9d, 0x27, 0x39, 0x83, 0x9d, 0xec, 0x58, 0xb9,
+                0x64, 0xec, 0x38, 0x43
+            ]
+        );
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        let key = Key {
+            name: String::from("kubizone."),
+            algorithm: TsigAlgorithm::HmacSha256,
+            secret: b"secret".to_vec(),
+        };
+
+        let request = Message {
+            header: Header {
+                id: 1234,
+                opcode: opcode::UPDATE,
+                ..Default::default()
+            },
+            questions: vec![Question {
+                name: String::from("example.org."),
+                type_: types::SOA,
+                class: CLASS_IN,
+            }],
+            ..Default::default()
+        };
+
+        let (signed, request_mac) = key.sign(&request, &[], 1701432000).unwrap();
+        assert_eq!(Message::decode(&signed).unwrap().additionals.len(), 1);
+        assert_eq!(key.verify(&signed, &[], 1701432100).unwrap(), request_mac);
+
+        // Signatures expire, and cover the entire message.
+        assert!(key.verify(&signed, &[], 1701432400).is_err());
+        let mut tampered = signed.clone();
+        tampered[13] ^= 1;
+        assert!(key.verify(&tampered, &[], 1701432000).is_err());
+
+        // Responses are signed including the MAC of the request.
+        let mut response = request.clone();
+        response.header.response = true;
+        let (signed_response, response_mac) =
+            key.sign(&response, &request_mac, 1701432000).unwrap();
+        key.verify(&signed_response, &request_mac, 1701432000)
+            .unwrap();
+        assert!(key.verify(&signed_response, &[], 1701432000).is_err());
+
+        // Subsequent messages are chained to the one before them.
+        let (subsequent, _) = key
+            .sign_subsequent(&response, &response_mac, 1701432001)
+            .unwrap();
+        key.verify_subsequent(&subsequent, &response_mac, 1701432001)
+            .unwrap();
+        assert!(key
+            .verify_subsequent(&subsequent, &request_mac, 1701432001)
+            .is_err());
+        assert!(key.verify(&subsequent, &response_mac, 1701432001).is_err());
+
+        let other = Key {
+            secret: b"other".to_vec(),
+            ..key
+        };
+        assert!(other.verify(&signed, &[], 1701432000).is_err());
+    }
+}
@@ -0,0 +1,685 @@
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use futures::StreamExt;
+use k8s_openapi::serde_json::json;
+use kube::{
+    api::{Patch, PatchParams},
+    runtime::{controller::Action, Controller},
+    Api, Client, ResourceExt,
+};
+use kubizone_common::{Backoff, Metrics, Scope};
+use kubizone_crds::v1alpha1::{DynamicUpdateStatus, Zone, ZoneEntry};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use tracing::log::*;
+
+use crate::{
+    error::{Error, SUCCESS},
+    notify, rdata, tsig,
+    wire::{opcode, rcode, types, Header, Message, Question, ResourceRecord, CLASS_IN, CLASS_NONE},
+    zone::{now, parse_time},
+};
+
+const CONTROLLER_NAME: &str = "kubi.zone/dynamic-update";
+
+/// Time to wait for the name server to respond to each UPDATE message.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Upper limit on the size of each UPDATE message, leaving room for
+/// the TSIG record within the 64KiB allowed over TCP.
+const MAX_MESSAGE_SIZE: usize = 60_000;
+
+struct Data {
+    client: Client,
+    backoff: Backoff,
+    metrics: Arc<Metrics>,
+    /// Interval at which successfully updated zones are re-checked.
+    requeue: Duration,
+}
+
+/// Resource record set, identified by its lowercased owner name and type.
+type RRSetKey = (String, u16);
+
+/// Entries of a zone which can be applied to a name server, grouped by the
+/// resource record set they belong to.
+struct RRSets(BTreeMap<RRSetKey, Vec<(ZoneEntry, ResourceRecord)>>);
+
+impl RRSets {
+    /// Group `entries` by resource record set, skipping the ones which cannot be
+    /// applied. The SOA record is left to the name server, which maintains its
+    /// own serial.
+    fn new(entries: &[ZoneEntry], origin: &str) -> Self {
+        let mut rrsets: BTreeMap<RRSetKey, Vec<(ZoneEntry, ResourceRecord)>> = BTreeMap::new();
+
+        for entry in entries {
+            let name = rdata::absolute(&entry.fqdn, origin).to_lowercase();
+            let in_zone = name == origin.to_lowercase()
+                || name.ends_with(&format!(".{}", origin.to_lowercase()));
+
+            if !in_zone {
+                warn!("skipping entry {} outside of zone {origin}", entry.fqdn);
+                continue;
+            }
+
+            if !entry.class.eq_ignore_ascii_case("IN") {
+                warn!(
+                    "skipping {} entry {} of class {}",
+                    entry.type_, entry.fqdn, entry.class
+                );
+                continue;
+            }
+
+            let Some(type_) = types::from_mnemonic(&entry.type_) else {
+                warn!(
+                    "skipping {} entry {} of unknown type",
+                    entry.type_, entry.fqdn
+                );
+                continue;
+            };
+
+            if type_ == types::SOA {
+                continue;
+            }
+
+            let rdata = match rdata::encode(type_, &entry.rdata, origin) {
+                Ok(rdata) => rdata,
+                Err(err) => {
+                    warn!("skipping {} entry {}: {err}", entry.type_, entry.fqdn);
+                    continue;
+                }
+            };
+
+            let record = ResourceRecord {
+                name: name.clone(),
+                type_,
+                class: CLASS_IN,
+                ttl: entry.ttl,
+                rdata,
+            };
+
+            let rrset = rrsets.entry((name, type_)).or_default();
+            if !rrset.iter().any(|(_, existing)| existing == &record) {
+                rrset.push((entry.clone(), record));
+            }
+        }
+
+        RRSets(rrsets)
+    }
+
+    fn entries(&self) -> Vec<ZoneEntry> {
+        self.0
+            .values()
+            .flatten()
+            .map(|(entry, _)| entry.clone())
+            .collect()
+    }
+}
+
+/// Change to a single resource record set.
+struct Change {
+    key: RRSetKey,
+    /// Records of the set as last applied, which must still be present on the
+    /// name server for the change to be applied.
+    previous: Vec<ResourceRecord>,
+    /// Entries the set consists of once the change is applied.
+    entries: Vec<(ZoneEntry, ResourceRecord)>,
+}
+
+impl Change {
+    fn removed(&self) -> impl Iterator<Item = &ResourceRecord> {
+        self.previous
+            .iter()
+            .filter(|record| !self.entries.iter().any(|(_, desired)| desired == *record))
+    }
+
+    fn added(&self) -> impl Iterator<Item = &ResourceRecord> {
+        self.entries
+            .iter()
+            .map(|(_, record)| record)
+            .filter(|record| !self.previous.contains(record))
+    }
+
+    /// Upper bound on the size the change adds to an UPDATE message.
+    fn size(&self) -> usize {
+        self.previous
+            .iter()
+            .chain(self.removed())
+            .chain(self.added())
+            .map(|record| record.name.len() + 11 + record.rdata.len())
+            .sum()
+    }
+}
+
+/// Compute the changes needed to go from the `applied` resource record sets
+/// to the `desired` ones.
+fn diff(applied: &RRSets, desired: &RRSets) -> Vec<Change> {
+    let mut keys: Vec<_> = applied.0.keys().chain(desired.0.keys()).collect();
+    keys.sort();
+    keys.dedup();
+
+    keys.into_iter()
+        .filter_map(|key| {
+            let previous: Vec<_> = applied
+                .0
+                .get(key)
+                .map(|rrset| rrset.iter().map(|(_, record)| record.clone()).collect())
+                .unwrap_or_default();
+
+            let entries = desired.0.get(key).cloned().unwrap_or_default();
+
+            let unchanged = previous.len() == entries.len()
+                && entries.iter().all(|(_, record)| previous.contains(record));
+
+            (!unchanged).then(|| Change {
+                key: key.clone(),
+                previous,
+                entries,
+            })
+        })
+        .collect()
+}
+
+/// Build an UPDATE message applying `changes` to the zone `origin`, as
+/// described in [RFC 2136](https://datatracker.ietf.org/doc/html/rfc2136).
+///
+/// Each previously applied record set must be unchanged on the name server
+/// for the update to go through, so records modified by others are never
+/// overwritten. Record sets kubizone has not applied before are added to.
+fn message(id: u16, origin: &str, changes: &[Change]) -> Message {
+    let mut message = Message {
+        header: Header {
+            id,
+            opcode: opcode::UPDATE,
+            ..Default::default()
+        },
+        questions: vec![Question {
+            name: origin.to_string(),
+            type_: types::SOA,
+            class: CLASS_IN,
+        }],
+        ..Default::default()
+    };
+
+    for change in changes {
+        message
+            .answers
+            .extend(change.previous.iter().map(|record| ResourceRecord {
+                ttl: 0,
+                ..record.clone()
+            }));
+
+        message
+            .authorities
+            .extend(change.removed().map(|record| ResourceRecord {
+                class: CLASS_NONE,
+                ttl: 0,
+                ..record.clone()
+            }));
+    }
+
+    for change in changes {
+        message.authorities.extend(change.added().cloned());
+    }
+
+    message
+}
+
+/// Split `changes` into batches small enough to fit in a single message.
+fn batches(changes: Vec<Change>) -> Vec<Vec<Change>> {
+    let mut batches: Vec<Vec<Change>> = Vec::new();
+    let mut size = 0;
+
+    for change in changes {
+        let change_size = change.size();
+
+        match batches.last_mut() {
+            Some(batch) if size + change_size <= MAX_MESSAGE_SIZE => {
+                batch.push(change);
+                size += change_size;
+            }
+            _ => {
+                batches.push(vec![change]);
+                size = change_size;
+            }
+        }
+    }
+
+    batches
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Send the UPDATE `request` to the name server at `address` over TCP,
+/// signing it with the `key` if any.
+async fn exchange(
+    address: SocketAddr,
+    request: &Message,
+    key: Option<&tsig::Key>,
+) -> Result<(), String> {
+    let (encoded, request_mac) = match key {
+        Some(key) => key.sign(request, &[], unix_time()),
+        None => request.encode().map(|encoded| (encoded, Vec::new())),
+    }
+    .map_err(|err| err.to_string())?;
+
+    let response = tokio::time::timeout(TIMEOUT, async {
+        let mut stream = TcpStream::connect(address).await?;
+        stream
+            .write_all(&(encoded.len() as u16).to_be_bytes())
+            .await?;
+        stream.write_all(&encoded).await?;
+
+        let mut length = [0u8; 2];
+        stream.read_exact(&mut length).await?;
+        let mut response = vec![0u8; u16::from_be_bytes(length) as usize];
+        stream.read_exact(&mut response).await?;
+        Ok::<_, std::io::Error>(response)
+    })
+    .await
+    .map_err(|_| format!("{address} did not respond within {}s", TIMEOUT.as_secs()))?
+    .map_err(|err| format!("failed to exchange messages with {address}: {err}"))?;
+
+    let decoded = Message::decode(&response)
+        .map_err(|err| format!("invalid response from {address}: {err}"))?;
+
+    if !decoded.header.response
+        || decoded.header.id != request.header.id
+        || decoded.header.opcode != opcode::UPDATE
+    {
+        return Err(format!("{address} responded to a different request"));
+    }
+
+    if let Some(key) = key {
+        key.verify(&response, &request_mac, unix_time())
+            .map_err(|err| format!("failed to authenticate response from {address}: {err}"))?;
+    }
+
+    match decoded.header.rcode {
+        rcode::NOERROR => Ok(()),
+        code @ (rcode::NXRRSET | rcode::YXRRSET) => Err(format!(
+            "{address} responded with {}: records previously applied by kubizone \
+            have since been changed on the name server",
+            rcode::name(code)
+        )),
+        code => Err(format!("{address} responded with {}", rcode::name(code))),
+    }
+}
+
+/// Apply the `entries` of the zone `origin` to the name server at `server`,
+/// given the entries `applied` to it so far, which are updated as batches of
+/// changes are applied.
+async fn apply(
+    server: &str,
+    origin: &str,
+    key: Option<&tsig::Key>,
+    applied: &mut Vec<ZoneEntry>,
+    entries: &[ZoneEntry],
+) -> Result<(), String> {
+    let mut rrsets = RRSets::new(applied, origin);
+    let changes = diff(&rrsets, &RRSets::new(entries, origin));
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    let address = notify::resolve(server).await?;
+
+    let mut result = Ok(());
+    for batch in batches(changes) {
+        result = exchange(address, &message(notify::message_id(), origin, &batch), key).await;
+        if result.is_err() {
+            break;
+        }
+
+        for change in batch {
+            if change.entries.is_empty() {
+                rrsets.0.remove(&change.key);
+            } else {
+                rrsets.0.insert(change.key, change.entries);
+            }
+        }
+    }
+
+    *applied = rrsets.entries();
+    result
+}
+
+async fn update_zone(zone: &Zone, ctx: &Data) -> Result<Action, Error> {
+    let Some(dynamic_update) = &zone.spec.dynamic_update else {
+        return Ok(Action::await_change());
+    };
+
+    let (Some(origin), Some(serial)) = (zone.fqdn(), zone.serial()) else {
+        debug!("zone {zone} has not computed its fqdn and serial yet");
+        return Ok(Action::await_change());
+    };
+
+    // Entries applied to a different name server tell us nothing about this one.
+    let status = zone
+        .status
+        .as_ref()
+        .and_then(|status| status.dynamic_update.as_ref())
+        .filter(|status| status.server == dynamic_update.server);
+
+    if status.and_then(|status| status.serial) == Some(serial) {
+        return Ok(Action::requeue(ctx.requeue));
+    }
+
+    // Failed updates are retried once the retry timer of the zone expires,
+    // rather than every time recording the failure triggers a reconciliation.
+    if let Some(status) = status.filter(|status| status.message.is_some()) {
+        let started = time::OffsetDateTime::now_utc();
+        let due = parse_time(status.last_attempt.as_deref())
+            .map(|last_attempt| last_attempt + time::Duration::seconds(zone.spec.retry.into()));
+
+        if let Some(due) = due.filter(|due| *due > started) {
+            return Ok(Action::requeue((due - started).unsigned_abs()));
+        }
+    }
+
+    let key = match &dynamic_update.tsig {
+        Some(tsig) => Some(
+            tsig::Key::from_secret(ctx.client.clone(), zone.namespace().as_ref().unwrap(), tsig)
+                .await?,
+        ),
+        None => None,
+    };
+
+    let mut applied = status
+        .map(|status| status.applied.clone())
+        .unwrap_or_default();
+
+    // Unwrap safety: the zone has a serial, so it has a status.
+    let entries = &zone.status.as_ref().unwrap().entries;
+    let result = apply(
+        &dynamic_update.server,
+        origin,
+        key.as_ref(),
+        &mut applied,
+        entries,
+    )
+    .await;
+
+    match &result {
+        Ok(()) => info!(
+            "applied serial {serial} of zone {zone} to {}",
+            dynamic_update.server
+        ),
+        Err(err) => warn!(
+            "failed to apply serial {serial} of zone {zone} to {}: {err}",
+            dynamic_update.server
+        ),
+    }
+
+    let status = DynamicUpdateStatus {
+        server: dynamic_update.server.clone(),
+        serial: result.is_ok().then_some(serial),
+        applied,
+        last_attempt: Some(now()),
+        message: result.clone().err(),
+    };
+
+    Api::<Zone>::namespaced(ctx.client.clone(), zone.namespace().as_ref().unwrap())
+        .patch_status(
+            &zone.name_any(),
+            &PatchParams::apply(CONTROLLER_NAME),
+            &Patch::Merge(json!({
+                "status": {
+                    "dynamicUpdate": status,
+                },
+            })),
+        )
+        .await?;
+
+    result
+        .map(|()| Action::requeue(ctx.requeue))
+        .map_err(Error::DynamicUpdate)
+}
+
+async fn reconcile(zone: Arc<Zone>, ctx: Arc<Data>) -> Result<Action, Error> {
+    let result = update_zone(&zone, &ctx).await;
+
+    ctx.metrics.reconciled(
+        "dynamic-update",
+        result
+            .as_ref()
+            .err()
+            .map(Error::category)
+            .unwrap_or(SUCCESS),
+    );
+
+    if result.is_ok() {
+        ctx.backoff.reset(&zone.to_string());
+    }
+
+    result
+}
+
+fn error_policy(zone: Arc<Zone>, error: &Error, ctx: Arc<Data>) -> Action {
+    let delay = if error.is_misconfiguration() {
+        ctx.backoff.misconfigured()
+    } else {
+        ctx.backoff.failure(&zone.to_string())
+    };
+
+    error!(
+        "dynamic update of zone {} encountered {} error: {error}, retrying in {}s",
+        zone.name_any(),
+        error.category(),
+        delay.as_secs()
+    );
+    Action::requeue(delay)
+}
+
+/// Apply the entries of zones with a `.spec.dynamicUpdate` to their name
+/// servers whenever their serial changes.
+pub async fn controller(
+    client: Client,
+    scope: Scope,
+    backoff: Backoff,
+    metrics: Arc<Metrics>,
+    requeue: Duration,
+) {
+    let data = Arc::new(Data {
+        client: client.clone(),
+        backoff,
+        metrics,
+        requeue,
+    });
+
+    let controllers = scope.apis::<Zone>(&client).into_iter().map(|zones| {
+        Controller::new(zones, scope.watcher_config())
+            .shutdown_on_signal()
+            .run(reconcile, error_policy, data.clone())
+            .for_each(|res| async move {
+                match res {
+                    Ok(o) => debug!("updated {:?}", o),
+                    Err(e) => warn!("dynamic update failed: {}", e),
+                }
+            })
+    });
+
+    futures::future::join_all(controllers).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use kubizone_crds::v1alpha1::{TsigAlgorithm, ZoneEntry};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use crate::{
+        tsig::Key,
+        wire::{rcode, types, Message, CLASS_IN, CLASS_NONE},
+    };
+
+    use super::{apply, diff, message, RRSets};
+
+    fn entry(fqdn: &str, type_: &str, ttl: u32, rdata: &str) -> ZoneEntry {
+        ZoneEntry {
+            fqdn: fqdn.to_string(),
+            type_: type_.to_string(),
+            class: String::from("IN"),
+            ttl,
+            rdata: rdata.to_string(),
+        }
+    }
+
+    fn key() -> Key {
+        Key {
+            name: String::from("kubizone."),
+            algorithm: TsigAlgorithm::HmacSha256,
+            secret: b"secret".to_vec(),
+        }
+    }
+
+    #[test]
+    fn changes() {
+        let origin = "example.org.";
+        let applied = RRSets::new(
+            &[
+                entry("www.example.org.", "A", 300, "192.0.2.1"),
+                entry("www.example.org.", "A", 300, "192.0.2.2"),
+                entry("mail.example.org.", "A", 300, "192.0.2.3"),
+            ],
+            origin,
+        );
+
+        let desired = RRSets::new(
+            &[
+                entry("example.org.", "SOA", 300, "ns. noc. 1 2 3 4 5"),
+                entry("WWW.example.org.", "A", 300, "192.0.2.1"),
+                entry("www.example.org.", "A", 300, "192.0.2.1"),
+                entry("mail.example.org.", "A", 300, "192.0.2.3"),
+                entry("example.org.", "MX", 300, "10 mail"),
+                entry("example.com.", "A", 300, "192.0.2.4"),
+            ],
+            origin,
+        );
+
+        // Duplicates, the SOA record and records outside of the zone are skipped.
+        assert_eq!(desired.entries().len(), 3);
+
+        let changes = diff(&applied, &desired);
+        assert_eq!(changes.len(), 2);
+
+        let update = message(1, origin, &changes);
+        assert_eq!(update.questions[0].name, origin);
+
+        // The previously applied records of the changed set are prerequisites.
+        assert_eq!(update.answers.len(), 2);
+        assert!(update
+            .answers
+            .iter()
+            .all(|record| record.ttl == 0 && record.class == CLASS_IN));
+
+        // Deletions precede additions.
+        assert_eq!(update.authorities.len(), 2);
+        assert_eq!(update.authorities[0].class, CLASS_NONE);
+        assert_eq!(update.authorities[0].rdata, [192, 0, 2, 2]);
+        assert_eq!(update.authorities[1].type_, types::MX);
+        assert_eq!(update.authorities[1].ttl, 300);
+
+        assert!(diff(&desired, &desired).is_empty());
+    }
+
+    /// Name server which verifies the signature of each request, and responds
+    /// with `rcode`, until `rcode` is anything but NOERROR.
+    async fn server(rcode: u8) -> (String, tokio::task::JoinHandle<Vec<Message>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let handle = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            loop {
+                let Ok((mut stream, _)) = listener.accept().await else {
+                    break;
+                };
+
+                let mut length = [0u8; 2];
+                stream.read_exact(&mut length).await.unwrap();
+                let mut request = vec![0u8; u16::from_be_bytes(length) as usize];
+                stream.read_exact(&mut request).await.unwrap();
+
+                let request_mac = key().verify(&request, &[], super::unix_time()).unwrap();
+
+                let mut decoded = Message::decode(&request).unwrap();
+                decoded.additionals.pop();
+
+                let mut response = decoded.clone();
+                response.header.response = true;
+                response.header.rcode = rcode;
+                response.answers.clear();
+                response.authorities.clear();
+
+                let (response, _) = key()
+                    .sign(&response, &request_mac, super::unix_time())
+                    .unwrap();
+                stream
+                    .write_all(&(response.len() as u16).to_be_bytes())
+                    .await
+                    .unwrap();
+                stream.write_all(&response).await.unwrap();
+
+                requests.push(decoded);
+                if rcode != rcode::NOERROR {
+                    break;
+                }
+            }
+
+            requests
+        });
+
+        (address, handle)
+    }
+
+    #[tokio::test]
+    async fn signed_updates() {
+        let origin = "example.org.";
+        let entries = [
+            entry("www.example.org.", "A", 300, "192.0.2.1"),
+            entry("example.org.", "TXT", 300, "\"hello\""),
+        ];
+
+        let (address, server_handle) = server(rcode::NOERROR).await;
+        let mut applied = Vec::new();
+        apply(&address, origin, Some(&key()), &mut applied, &entries)
+            .await
+            .unwrap();
+        assert_eq!(applied.len(), 2);
+
+        // Nothing is sent when nothing changed.
+        apply(&address, origin, Some(&key()), &mut applied, &entries)
+            .await
+            .unwrap();
+
+        server_handle.abort();
+
+        let (address, server_handle) = server(rcode::NXRRSET).await;
+        let error = apply(&address, origin, Some(&key()), &mut applied, &entries[..1])
+            .await
+            .unwrap_err();
+        assert!(error.contains("NXRRSET"), "{error}");
+
+        // Applied entries are only updated once the name server accepts them.
+        assert_eq!(applied.len(), 2);
+
+        let requests = server_handle.await.unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].answers.len(), 1);
+        assert_eq!(requests[0].authorities[0].class, CLASS_NONE);
+    }
+}
@@ -0,0 +1,503 @@
+use std::fmt::Display;
+
+/// Operation codes of DNS messages.
+pub mod opcode {
+    pub const QUERY: u8 = 0;
+    /// [RFC 1996](https://datatracker.ietf.org/doc/html/rfc1996)
+    pub const NOTIFY: u8 = 4;
+    /// [RFC 2136](https://datatracker.ietf.org/doc/html/rfc2136)
+    pub const UPDATE: u8 = 5;
+}
+
+/// Response codes of DNS messages.
+pub mod rcode {
+    pub const NOERROR: u8 = 0;
+    pub const FORMERR: u8 = 1;
+    pub const SERVFAIL: u8 = 2;
+    pub const NXDOMAIN: u8 = 3;
+    pub const NOTIMP: u8 = 4;
+    pub const REFUSED: u8 = 5;
+    pub const YXDOMAIN: u8 = 6;
+    pub const YXRRSET: u8 = 7;
+    pub const NXRRSET: u8 = 8;
+    pub const NOTAUTH: u8 = 9;
+    pub const NOTZONE: u8 = 10;
+
+    /// Mnemonic of the response code, as used in logs and zone files.
+    pub fn name(rcode: u8) -> String {
+        match rcode {
+            NOERROR => String::from("NOERROR"),
+            FORMERR => String::from("FORMERR"),
+            SERVFAIL => String::from("SERVFAIL"),
+            NXDOMAIN => String::from("NXDOMAIN"),
+            NOTIMP => String::from("NOTIMP"),
+            REFUSED => String::from("REFUSED"),
+            YXDOMAIN => String::from("YXDOMAIN"),
+            YXRRSET => String::from("YXRRSET"),
+            NXRRSET => String::from("NXRRSET"),
+            NOTAUTH => String::from("NOTAUTH"),
+            NOTZONE => String::from("NOTZONE"),
+            rcode => format!("RCODE{rcode}"),
+        }
+    }
+}
+
+/// Resource record types, by their mnemonic.
+pub mod types {
+    pub const A: u16 = 1;
+    pub const NS: u16 = 2;
+    pub const CNAME: u16 = 5;
+    pub const SOA: u16 = 6;
+    pub const PTR: u16 = 12;
+    pub const MX: u16 = 15;
+    pub const TXT: u16 = 16;
+    pub const AAAA: u16 = 28;
+    pub const SRV: u16 = 33;
+    pub const DNAME: u16 = 39;
+    /// EDNS pseudo-record, as described in [RFC 6891](https://datatracker.ietf.org/doc/html/rfc6891).
+    pub const OPT: u16 = 41;
+    pub const DS: u16 = 43;
+    pub const SPF: u16 = 99;
+    /// [RFC 8945](https://datatracker.ietf.org/doc/html/rfc8945)
+    pub const TSIG: u16 = 250;
+    /// Only valid in questions, requesting an incremental zone transfer as
+    /// described in [RFC 1995](https://datatracker.ietf.org/doc/html/rfc1995).
+    pub const IXFR: u16 = 251;
+    /// Only valid in questions, requesting a zone transfer as described in
+    /// [RFC 5936](https://datatracker.ietf.org/doc/html/rfc5936).
+    pub const AXFR: u16 = 252;
+    /// Only valid in questions, matching records of any type.
+    pub const ANY: u16 = 255;
+    pub const CAA: u16 = 257;
+
+    const MNEMONICS: [(&str, u16); 13] = [
+        ("A", A),
+        ("NS", NS),
+        ("CNAME", CNAME),
+        ("SOA", SOA),
+        ("PTR", PTR),
+        ("MX", MX),
+        ("TXT", TXT),
+        ("AAAA", AAAA),
+        ("SRV", SRV),
+        ("DNAME", DNAME),
+        ("DS", DS),
+        ("SPF", SPF),
+        ("CAA", CAA),
+    ];
+
+    /// Look up the type of a mnemonic such as `AAAA`, or `TYPE28` as described in
+    /// [RFC 3597](https://datatracker.ietf.org/doc/html/rfc3597).
+    pub fn from_mnemonic(mnemonic: &str) -> Option<u16> {
+        let mnemonic = mnemonic.to_uppercase();
+
+        MNEMONICS
+            .iter()
+            .find(|(known, _)| *known == mnemonic)
+            .map(|(_, type_)| *type_)
+            .or_else(|| mnemonic.strip_prefix("TYPE")?.parse().ok())
+    }
+
+    /// Mnemonic of the type, falling back to the generic `TYPE28` form.
+    pub fn mnemonic(type_: u16) -> String {
+        MNEMONICS
+            .iter()
+            .find(|(_, known)| *known == type_)
+            .map(|(mnemonic, _)| mnemonic.to_string())
+            .unwrap_or_else(|| format!("TYPE{type_}"))
+    }
+}
+
+pub const CLASS_IN: u16 = 1;
+/// Used by UPDATE messages to delete individual records.
+pub const CLASS_NONE: u16 = 254;
+pub const CLASS_ANY: u16 = 255;
+
+/// Names are limited to 255 octets in their wire format.
+const MAX_NAME_LENGTH: usize = 255;
+
+/// Labels are limited to 63 octets, the remaining two bits of
+/// the length octet marking compression pointers.
+const MAX_LABEL_LENGTH: usize = 63;
+
+/// Errors encountered while encoding or decoding DNS messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WireError {
+    /// The message ended before all of its contents could be read.
+    Truncated,
+    /// A domain name is empty, or exceeds the limits on label or name length.
+    InvalidName(String),
+    /// A compression pointer does not point backwards, and could loop.
+    InvalidPointer(usize),
+}
+
+impl Display for WireError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WireError::Truncated => f.write_str("message is truncated"),
+            WireError::InvalidName(name) => write!(f, "invalid domain name {name:?}"),
+            WireError::InvalidPointer(offset) => {
+                write!(f, "invalid compression pointer to offset {offset}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for WireError {}
+
+/// Header section of a DNS message, as described in
+/// [RFC 1035 section 4.1.1](https://datatracker.ietf.org/doc/html/rfc1035#section-4.1.1).
+///
+/// The section counts are left out, since they're derived from the message itself.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub id: u16,
+    pub response: bool,
+    pub opcode: u8,
+    pub authoritative: bool,
+    pub truncated: bool,
+    pub recursion_desired: bool,
+    pub recursion_available: bool,
+    pub rcode: u8,
+}
+
+impl Header {
+    fn flags(&self) -> u16 {
+        (u16::from(self.response) << 15)
+            | (u16::from(self.opcode & 0xf) << 11)
+            | (u16::from(self.authoritative) << 10)
+            | (u16::from(self.truncated) << 9)
+            | (u16::from(self.recursion_desired) << 8)
+            | (u16::from(self.recursion_available) << 7)
+            | u16::from(self.rcode & 0xf)
+    }
+
+    fn from_flags(id: u16, flags: u16) -> Self {
+        Header {
+            id,
+            response: flags & (1 << 15) != 0,
+            opcode: ((flags >> 11) & 0xf) as u8,
+            authoritative: flags & (1 << 10) != 0,
+            truncated: flags & (1 << 9) != 0,
+            recursion_desired: flags & (1 << 8) != 0,
+            recursion_available: flags & (1 << 7) != 0,
+            rcode: (flags & 0xf) as u8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Question {
+    /// Fully qualified domain name, such as `example.org.`
+    pub name: String,
+    pub type_: u16,
+    pub class: u16,
+}
+
+/// Resource record with its data in wire format.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ResourceRecord {
+    /// Fully qualified domain name, such as `example.org.`
+    pub name: String,
+    pub type_: u16,
+    pub class: u16,
+    pub ttl: u32,
+    pub rdata: Vec<u8>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub header: Header,
+    pub questions: Vec<Question>,
+    pub answers: Vec<ResourceRecord>,
+    pub authorities: Vec<ResourceRecord>,
+    pub additionals: Vec<ResourceRecord>,
+}
+
+/// Append the uncompressed wire format of the fully qualified `name` to `output`.
+pub fn encode_name(name: &str, output: &mut Vec<u8>) -> Result<(), WireError> {
+    let invalid = || WireError::InvalidName(name.to_string());
+    let start = output.len();
+
+    let labels = name.strip_suffix('.').ok_or_else(invalid)?;
+    if !labels.is_empty() {
+        for label in labels.split('.') {
+            if label.is_empty() || label.len() > MAX_LABEL_LENGTH {
+                return Err(invalid());
+            }
+
+            output.push(label.len() as u8);
+            output.extend_from_slice(label.as_bytes());
+        }
+    }
+
+    output.push(0);
+
+    if output.len() - start > MAX_NAME_LENGTH {
+        return Err(invalid());
+    }
+
+    Ok(())
+}
+
+/// Reads the fields of a DNS message in order.
+pub struct Reader<'a> {
+    message: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(message: &'a [u8]) -> Self {
+        Reader {
+            message,
+            position: 0,
+        }
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Continue reading at `position`, such as the end of record data
+    /// which has been decoded in place.
+    pub fn seek(&mut self, position: usize) {
+        self.position = position;
+    }
+
+    pub fn bytes(&mut self, length: usize) -> Result<&'a [u8], WireError> {
+        let bytes = self
+            .message
+            .get(self.position..self.position + length)
+            .ok_or(WireError::Truncated)?;
+
+        self.position += length;
+        Ok(bytes)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, WireError> {
+        // Unwrap safety: exactly two bytes were read.
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, WireError> {
+        // Unwrap safety: exactly four bytes were read.
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    /// Read a domain name, following any compression pointers.
+    pub fn name(&mut self) -> Result<String, WireError> {
+        let mut name = String::new();
+        let mut position = self.position;
+        // Position to continue reading from, once the name has been read.
+        let mut end = None;
+        // Pointers must point before the labels they're part of, which
+        // guarantees that following them terminates.
+        let mut limit = self.position;
+
+        loop {
+            let length = *self.message.get(position).ok_or(WireError::Truncated)? as usize;
+
+            if length & 0xc0 == 0xc0 {
+                let low = *self.message.get(position + 1).ok_or(WireError::Truncated)?;
+                let target = ((length & 0x3f) << 8) | low as usize;
+
+                if target >= limit {
+                    return Err(WireError::InvalidPointer(target));
+                }
+
+                end.get_or_insert(position + 2);
+                position = target;
+                limit = target;
+                continue;
+            }
+
+            position += 1;
+            if length == 0 {
+                break;
+            }
+
+            let label = self
+                .message
+                .get(position..position + length)
+                .ok_or(WireError::Truncated)?;
+
+            name.push_str(&String::from_utf8_lossy(label));
+            name.push('.');
+            position += length;
+
+            if name.len() > MAX_NAME_LENGTH {
+                return Err(WireError::InvalidName(name));
+            }
+        }
+
+        self.position = end.unwrap_or(position);
+
+        if name.is_empty() {
+            name.push('.');
+        }
+
+        Ok(name)
+    }
+
+    pub fn question(&mut self) -> Result<Question, WireError> {
+        Ok(Question {
+            name: self.name()?,
+            type_: self.u16()?,
+            class: self.u16()?,
+        })
+    }
+
+    pub fn record(&mut self) -> Result<ResourceRecord, WireError> {
+        let name = self.name()?;
+        let type_ = self.u16()?;
+        let class = self.u16()?;
+        let ttl = self.u32()?;
+        let length = self.u16()? as usize;
+
+        Ok(ResourceRecord {
+            name,
+            type_,
+            class,
+            ttl,
+            rdata: self.bytes(length)?.to_vec(),
+        })
+    }
+}
+
+impl Message {
+    pub fn encode(&self) -> Result<Vec<u8>, WireError> {
+        let mut output = Vec::with_capacity(512);
+        output.extend_from_slice(&self.header.id.to_be_bytes());
+        output.extend_from_slice(&self.header.flags().to_be_bytes());
+
+        for count in [
+            self.questions.len(),
+            self.answers.len(),
+            self.authorities.len(),
+            self.additionals.len(),
+        ] {
+            output.extend_from_slice(&(count as u16).to_be_bytes());
+        }
+
+        for question in &self.questions {
+            encode_name(&question.name, &mut output)?;
+            output.extend_from_slice(&question.type_.to_be_bytes());
+            output.extend_from_slice(&question.class.to_be_bytes());
+        }
+
+        for record in self
+            .answers
+            .iter()
+            .chain(&self.authorities)
+            .chain(&self.additionals)
+        {
+            encode_name(&record.name, &mut output)?;
+            output.extend_from_slice(&record.type_.to_be_bytes());
+            output.extend_from_slice(&record.class.to_be_bytes());
+            output.extend_from_slice(&record.ttl.to_be_bytes());
+            output.extend_from_slice(&(record.rdata.len() as u16).to_be_bytes());
+            output.extend_from_slice(&record.rdata);
+        }
+
+        Ok(output)
+    }
+
+    pub fn decode(message: &[u8]) -> Result<Message, WireError> {
+        let mut reader = Reader::new(message);
+
+        let id = reader.u16()?;
+        let header = Header::from_flags(id, reader.u16()?);
+        let counts = [reader.u16()?, reader.u16()?, reader.u16()?, reader.u16()?];
+
+        let questions = (0..counts[0])
+            .map(|_| reader.question())
+            .collect::<Result<_, _>>()?;
+
+        let mut sections = counts[1..].iter().map(|count| {
+            (0..*count)
+                .map(|_| reader.record())
+                .collect::<Result<Vec<_>, _>>()
+        });
+
+        // Unwrap safety: there are exactly three record sections.
+        let answers = sections.next().unwrap()?;
+        let authorities = sections.next().unwrap()?;
+        let additionals = sections.next().unwrap()?;
+
+        Ok(Message {
+            header,
+            questions,
+            answers,
+            authorities,
+            additionals,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        encode_name, opcode, types, Header, Message, Question, ResourceRecord, WireError, CLASS_IN,
+    };
+
+    #[test]
+    fn round_trip() {
+        let message = Message {
+            header: Header {
+                id: 0xbeef,
+                opcode: opcode::NOTIFY,
+                authoritative: true,
+                ..Default::default()
+            },
+            questions: vec![Question {
+                name: String::from("example.org."),
+                type_: types::SOA,
+                class: CLASS_IN,
+            }],
+            answers: vec![ResourceRecord {
+                name: String::from("www.example.org."),
+                type_: 1,
+                class: CLASS_IN,
+                ttl: 300,
+                rdata: vec![192, 168, 0, 1],
+            }],
+            ..Default::default()
+        };
+
+        let encoded = message.encode().unwrap();
+        assert_eq!(&encoded[..4], &[0xbe, 0xef, 0x24, 0x00]);
+        assert_eq!(Message::decode(&encoded).unwrap(), message);
+
+        // Every prefix of the message is missing something.
+        for length in 0..encoded.len() {
+            assert!(Message::decode(&encoded[..length]).is_err());
+        }
+    }
+
+    #[test]
+    fn names() {
+        let mut output = Vec::new();
+        encode_name(".", &mut output).unwrap();
+        assert_eq!(output, [0]);
+
+        assert!(encode_name("example.org", &mut output).is_err());
+        assert!(encode_name("example..org.", &mut output).is_err());
+        assert!(encode_name(&format!("{}.", "a".repeat(64)), &mut output).is_err());
+
+        // Header with two questions, the second of which has its name compressed.
+        let mut message = vec![0, 1, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0];
+        message.extend_from_slice(&[7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0, 0, 6, 0, 1]);
+        message.extend_from_slice(&[3, b'w', b'w', b'w', 0xc0, 12, 0, 6, 0, 1]);
+        assert_eq!(
+            Message::decode(&message).unwrap().questions[1].name,
+            "www.example."
+        );
+
+        // Pointers must point backwards, or they could form loops.
+        message[30] = 25;
+        assert_eq!(
+            Message::decode(&message),
+            Err(WireError::InvalidPointer(25))
+        );
+    }
+}
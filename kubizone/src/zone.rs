@@ -9,121 +9,257 @@ use futures::StreamExt;
 use k8s_openapi::serde_json::json;
 use kube::{
     api::{ListParams, Patch, PatchParams},
-    runtime::{controller::Action, watcher, Controller},
+    runtime::{controller::Action, Controller},
     Api, Client, ResourceExt,
 };
+use kubizone_common::{scope, Backoff, Metrics, Scope};
 use kubizone_crds::{
-    v1alpha1::{Record, Zone, ZoneEntry, ZoneRef, ZoneSpec},
-    PARENT_ZONE_LABEL,
+    v1alpha1::{
+        set_condition, Condition, Record, Zone, ZoneEntry, ZoneRef, ZoneRevision, ZoneSpec,
+    },
+    PARENT_ZONE_ANNOTATION, PARENT_ZONE_LABEL,
 };
 
 use tracing::log::*;
 
+use crate::{
+    error::{Error, RECONCILED_CONDITION, SUCCESS},
+    revision,
+};
+
 struct Data {
     client: Client,
+    scope: Scope,
+    backoff: Backoff,
+    metrics: Arc<Metrics>,
+    /// Interval at which successfully reconciled zones are re-checked.
+    requeue: Duration,
 }
 
 const CONTROLLER_NAME: &str = "kubi.zone/zone-resolver";
 
-pub async fn controller(client: Client) {
-    let zones = Api::<Zone>::all(client.clone());
+/// Maximum number of `zoneRef`s which will be followed when resolving the
+/// parents of a zone, before the chain is considered excessively deep.
+pub(crate) const MAX_ZONE_REF_DEPTH: usize = 16;
+
+/// Condition reporting whether the parent of a zone could be resolved.
+const PARENT_RESOLVED_CONDITION: &str = "ParentResolved";
+
+/// Outcome of following the `zoneRef`s of a zone up towards the root.
+pub(crate) enum ZoneRefChain {
+    /// The chain terminates, either in a fully qualified zone, or a zone which
+    /// does not exist (yet).
+    Terminated,
+    /// The chain loops back on itself. Contains the zones which make up the cycle.
+    Cycle(Vec<Zone>),
+    /// The chain exceeds [`MAX_ZONE_REF_DEPTH`]. Contains the zones visited.
+    TooDeep(Vec<Zone>),
+}
 
-    let zone_controller = Controller::new(zones.clone(), watcher::Config::default())
-        .watches(
-            Api::<Zone>::all(client.clone()),
-            watcher::Config::default(),
-            kubizone_crds::watch_reference(PARENT_ZONE_LABEL),
-        )
-        .watches(
-            Api::<Record>::all(client.clone()),
-            watcher::Config::default(),
-            kubizone_crds::watch_reference(PARENT_ZONE_LABEL),
-        )
-        .shutdown_on_signal()
-        .run(
-            reconcile_zones,
-            zone_error_policy,
-            Arc::new(Data {
-                client: client.clone(),
-            }),
-        )
-        .for_each(|res| async move {
-            match res {
-                Ok(o) => info!("reconciled {:?}", o),
-                Err(e) => warn!("reconcile failed: {}", e),
-            }
+pub async fn controller(
+    client: Client,
+    scope: Scope,
+    backoff: Backoff,
+    metrics: Arc<Metrics>,
+    requeue: Duration,
+) {
+    let data = Arc::new(Data {
+        client: client.clone(),
+        scope: scope.clone(),
+        backoff,
+        metrics,
+        requeue,
+    });
+
+    // A controller can only watch a single namespace (or all of them), so when
+    // limited to a set of namespaces, we run one controller per namespace. Zones
+    // can adopt records and zones from other namespaces, so children are watched
+    // across all namespaces in scope, once, and handed to every controller, each
+    // of which only picks out the children of its own zones.
+    let namespaces = scope.api_namespaces();
+    let zone_changes = scope.shared_watch::<Zone>(&client, namespaces.len());
+    let record_changes = scope.shared_watch::<Record>(&client, namespaces.len());
+
+    let zone_controllers = scope
+        .apis::<Zone>(&client)
+        .into_iter()
+        .zip(namespaces)
+        .zip(zone_changes.into_iter().zip(record_changes))
+        .map(|((zones, namespace), (zone_changes, record_changes))| {
+            Controller::new(zones, scope.watcher_config())
+                .watches_stream(
+                    zone_changes,
+                    scope::within(
+                        namespace.clone(),
+                        kubizone_crds::watch_reference(PARENT_ZONE_ANNOTATION),
+                    ),
+                )
+                .watches_stream(
+                    record_changes,
+                    scope::within(
+                        namespace,
+                        kubizone_crds::watch_reference(PARENT_ZONE_ANNOTATION),
+                    ),
+                )
+                .shutdown_on_signal()
+                .run(reconcile_zones, zone_error_policy, data.clone())
+                .for_each(|res| async move {
+                    match res {
+                        Ok(o) => info!("reconciled {:?}", o),
+                        Err(e) => warn!("reconcile failed: {}", e),
+                    }
+                })
         });
 
-    zone_controller.await;
+    futures::future::join_all(zone_controllers).await;
 }
 
-async fn reconcile_zones(zone: Arc<Zone>, ctx: Arc<Data>) -> Result<Action, kube::Error> {
-    match (
-        zone.spec.zone_ref.as_ref(),
-        zone.spec.domain_name.ends_with('.'),
-    ) {
-        (Some(zone_ref), false) => {
+async fn reconcile_zones(zone: Arc<Zone>, ctx: Arc<Data>) -> Result<Action, Error> {
+    let result = reconcile_zone(&zone, &ctx).await;
+
+    let condition = match &result {
+        Ok(_) => {
+            ctx.backoff.reset(&zone.to_string());
+            Condition::new(RECONCILED_CONDITION, true, SUCCESS, "", now())
+        }
+        Err(err) => Condition::new(
+            RECONCILED_CONDITION,
+            false,
+            err.category(),
+            err.to_string(),
+            now(),
+        ),
+    };
+
+    ctx.metrics.reconciled(
+        "zone",
+        result
+            .as_ref()
+            .err()
+            .map(Error::category)
+            .unwrap_or(SUCCESS),
+    );
+
+    // The zone might have been modified during reconciliation, so make sure
+    // we're working on the latest conditions before reporting the outcome.
+    let latest = Api::<Zone>::namespaced(ctx.client.clone(), zone.namespace().as_ref().unwrap())
+        .get_opt(&zone.name_any())
+        .await;
+
+    if let Ok(Some(latest)) = latest {
+        if let Err(err) = set_zone_condition(ctx.client.clone(), &latest, condition).await {
+            warn!("failed to report reconciliation outcome for zone {zone}: {err}");
+        }
+    }
+
+    result
+}
+
+async fn reconcile_zone(zone: &Arc<Zone>, ctx: &Data) -> Result<Action, Error> {
+    let parent_ref = zone_parent_ref(zone)?;
+
+    // Fetch all zones from across the cluster, both for following the chain of
+    // zoneRefs and for finding the longest parent of fully qualified zones.
+    let zones = ctx
+        .scope
+        .list::<Zone>(&ctx.client, &ListParams::default())
+        .await?;
+
+    match parent_ref {
+        Some(parent_ref) => {
+            // A zone which (indirectly) references itself will never have its fqdn
+            // resolved, so rather than waiting for one to appear, report the problem
+            // on all involved zones and back off until someone fixes the references.
+            match walk_zone_ref_chain(zone, &zones) {
+                ZoneRefChain::Terminated => (),
+                ZoneRefChain::Cycle(cycle) => {
+                    let path = format_chain(cycle.iter().chain(cycle.first()));
+
+                    warn!("zone {zone} is part of, or references, a zoneRef cycle: {path}");
+
+                    let condition = Condition::new(
+                        PARENT_RESOLVED_CONDITION,
+                        false,
+                        "ZoneRefCycle",
+                        format!("zoneRef chain forms a cycle: {path}"),
+                        now(),
+                    );
+
+                    set_zone_condition(ctx.client.clone(), zone, condition.clone()).await?;
+                    for member in cycle.iter().filter(|member| member.uid() != zone.uid()) {
+                        set_zone_condition(ctx.client.clone(), member, condition.clone()).await?;
+                    }
+
+                    return Err(Error::ZoneRefCycle(path));
+                }
+                ZoneRefChain::TooDeep(chain) => {
+                    warn!(
+                        "zone {zone}'s zoneRef chain exceeds the maximum depth of {MAX_ZONE_REF_DEPTH}"
+                    );
+
+                    let path = format_chain(&chain);
+
+                    let condition = Condition::new(
+                        PARENT_RESOLVED_CONDITION,
+                        false,
+                        "ZoneRefTooDeep",
+                        format!(
+                            "zoneRef chain exceeds the maximum depth of {MAX_ZONE_REF_DEPTH}: {path}"
+                        ),
+                        now(),
+                    );
+
+                    set_zone_condition(ctx.client.clone(), zone, condition).await?;
+                    return Err(Error::ZoneRefTooDeep(path));
+                }
+            }
+
             // Follow the zoneRef to the supposed parent zone, if it exists
-            // or requeue later if it does not.
-            let Some(parent_zone) = Api::<Zone>::namespaced(
-                ctx.client.clone(),
-                &zone_ref
-                    .namespace
-                    .as_ref()
-                    .or(zone.namespace().as_ref())
-                    .cloned()
-                    .unwrap(),
-            )
-            .get_opt(&zone_ref.name)
-            .await?
+            // or retry later if it does not.
+            let Some(parent_zone) = zones
+                .iter()
+                .find(|candidate| candidate.zone_ref() == parent_ref)
             else {
-                warn!("zone {zone} references unknown zone {zone_ref}");
-                return Ok(Action::requeue(Duration::from_secs(30)));
-            };
-
-            // If the parent does not have a fully qualified domain name defined
-            // yet, we can't check if the delegations provided by it are valid.
-            // Postpone the reconcilliation until a later time, when the fqdn
-            // has (hopefully) been determined.
-            let Some(parent_fqdn) = parent_zone.fqdn() else {
-                info!(
-                    "parent zone {} missing fqdn, requeuing.",
-                    parent_zone.name_any()
-                );
-                return Ok(Action::requeue(Duration::from_secs(5)));
+                warn!("zone {zone} references unknown zone {parent_ref}");
+                return Err(Error::MissingParent(parent_ref));
             };
 
-            // This is only "alleged", since we don't know yet if the referenced
-            // zone's delegations allow the adoption.
-            let alleged_fqdn = format!("{}.{}", zone.spec.domain_name, parent_fqdn);
+            let fqdn = adopt_zone(zone, parent_zone)?;
 
-            if parent_zone.spec.delegations.iter().any(|delegation| {
-                delegation.covers_namespace(zone.namespace().as_deref().unwrap())
-                    && delegation.validate_zone(parent_fqdn, &alleged_fqdn)
-            }) {
-                set_zone_fqdn(ctx.client.clone(), &zone, &alleged_fqdn).await?;
-                set_zone_parent_ref(ctx.client.clone(), &zone, parent_zone.zone_ref()).await?;
-            } else {
-                warn!("parent zone {parent_zone} was found, but its delegations does not allow adoption of {zone} with {alleged_fqdn}");
-                return Ok(Action::requeue(Duration::from_secs(300)));
-            }
+            set_zone_fqdn(ctx.client.clone(), zone, &fqdn).await?;
+            set_zone_parent_ref(ctx.client.clone(), zone, parent_zone.zone_ref()).await?;
+            set_zone_condition(
+                ctx.client.clone(),
+                zone,
+                Condition::new(
+                    PARENT_RESOLVED_CONDITION,
+                    true,
+                    "Resolved",
+                    format!("zone is a sub-zone of {parent_zone}"),
+                    now(),
+                ),
+            )
+            .await?;
         }
-        (None, true) => {
-            set_zone_fqdn(ctx.client.clone(), &zone, &zone.spec.domain_name).await?;
-
-            // Fetch all zones from across the cluster and then filter down results to only parent
-            // zones which are valid parent zones for this one.
-            //
-            // This means filtering out parent zones without fqdns, as well as ones which do not
-            // have appropriate delegations for our `zone`'s namespace and suffix.
-            if let Some(longest_parent_zone) = Api::<Zone>::all(ctx.client.clone())
-                .list(&ListParams::default())
-                .await?
-                .into_iter()
-                .filter(|parent| parent.validate_zone(&zone))
-                .max_by_key(|parent| parent.fqdn().unwrap().len())
-            {
-                set_zone_parent_ref(ctx.client.clone(), &zone, longest_parent_zone.zone_ref())
+        None => {
+            set_zone_fqdn(ctx.client.clone(), zone, &zone.spec.domain_name).await?;
+            set_zone_condition(
+                ctx.client.clone(),
+                zone,
+                Condition::new(
+                    PARENT_RESOLVED_CONDITION,
+                    true,
+                    "FullyQualified",
+                    "zone has a fully qualified domain name",
+                    now(),
+                ),
+            )
+            .await?;
+
+            // Pick the most specific of all the zones in the cluster which is
+            // a valid parent for this one.
+            if let Some(longest_parent_zone) = longest_parent_zone(zone, &zones) {
+                set_zone_parent_ref(ctx.client.clone(), zone, longest_parent_zone.zone_ref())
                     .await?;
             } else {
                 warn!(
@@ -133,18 +269,173 @@ async fn reconcile_zones(zone: Arc<Zone>, ctx: Arc<Data>) -> Result<Action, kube
                 );
             };
         }
+    }
+
+    update_zone_status(zone.clone(), ctx.client.clone(), &ctx.scope).await?;
+    Ok(Action::requeue(ctx.requeue))
+}
+
+/// Determine the parent referenced by the zone's `zoneRef`, if it has one.
+///
+/// Zones must have *either* a `zoneRef` or a fully qualified domain name,
+/// anything else is an invalid spec.
+pub(crate) fn zone_parent_ref(zone: &Zone) -> Result<Option<ZoneRef>, Error> {
+    match (
+        zone.spec.zone_ref.as_ref(),
+        zone.spec.domain_name.ends_with('.'),
+    ) {
+        (Some(zone_ref), false) => Ok(Some(ZoneRef {
+            name: zone_ref.name.clone(),
+            namespace: zone_ref.namespace.clone().or(zone.namespace()),
+        })),
+        (None, true) => Ok(None),
         (Some(zone_ref), true) => {
             warn!("zone {zone} has both a fully qualified domain_name ({}) and a zoneRef({zone_ref}). It cannot have both.", zone.spec.domain_name);
-            return Ok(Action::requeue(Duration::from_secs(300)));
+            Err(Error::InvalidSpec(String::from(
+                "zone cannot have both a zoneRef and a fully qualified domainName",
+            )))
         }
         (None, false) => {
             warn!("{zone} has neither zoneRef nor a fully qualified domainName, making it impossible to deduce its parent zone.");
-            return Ok(Action::requeue(Duration::from_secs(300)));
+            Err(Error::InvalidSpec(String::from(
+                "zone must have either a zoneRef or a fully qualified domainName",
+            )))
+        }
+    }
+}
+
+/// Check if `parent_zone`'s delegations allow it to adopt `zone`, producing
+/// the fully qualified domain name `zone` would have as its sub-zone.
+pub(crate) fn adopt_zone(zone: &Zone, parent_zone: &Zone) -> Result<String, Error> {
+    // If the parent does not have a fully qualified domain name defined
+    // yet, we can't check if the delegations provided by it are valid.
+    // Postpone the reconcilliation until a later time, when the fqdn
+    // has (hopefully) been determined.
+    let Some(parent_fqdn) = parent_zone.fqdn() else {
+        info!(
+            "parent zone {} missing fqdn, requeuing.",
+            parent_zone.name_any()
+        );
+        return Err(Error::ParentNotReady(parent_zone.zone_ref()));
+    };
+
+    // This is only "alleged", since we don't know yet if the referenced
+    // zone's delegations allow the adoption.
+    let alleged_fqdn = format!("{}.{}", zone.spec.domain_name, parent_fqdn);
+
+    if parent_zone.spec.delegations.iter().any(|delegation| {
+        delegation.covers_namespace(zone.namespace().as_deref().unwrap())
+            && delegation.validate_zone(parent_fqdn, &alleged_fqdn)
+    }) {
+        Ok(alleged_fqdn)
+    } else {
+        warn!("parent zone {parent_zone} was found, but its delegations does not allow adoption of {zone} with {alleged_fqdn}");
+        Err(Error::DelegationDenied {
+            parent: parent_zone.to_string(),
+            fqdn: alleged_fqdn,
+        })
+    }
+}
+
+/// Pick the most specific of `zones` which is a valid parent zone for `zone`.
+///
+/// This means filtering out parent zones without fqdns, as well as ones which do not
+/// have appropriate delegations for our `zone`'s namespace and suffix.
+pub(crate) fn longest_parent_zone<'a>(zone: &Zone, zones: &'a [Zone]) -> Option<&'a Zone> {
+    zones
+        .iter()
+        .filter(|parent| parent.validate_zone(zone))
+        .max_by_key(|parent| parent.fqdn().unwrap().len())
+}
+
+/// Render a chain of zones as `namespace/a -> namespace/b -> ...`.
+pub(crate) fn format_chain<'a>(chain: impl IntoIterator<Item = &'a Zone>) -> String {
+    chain
+        .into_iter()
+        .map(|member| member.to_string())
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
+/// Follow the `zoneRef`s of `zone` through `zones` until reaching a zone without
+/// one, detecting cycles and excessive depth along the way.
+pub(crate) fn walk_zone_ref_chain(zone: &Zone, zones: &[Zone]) -> ZoneRefChain {
+    let mut chain = vec![zone.clone()];
+
+    loop {
+        // Unwrap safety: the chain is never empty.
+        let current = chain.last().unwrap();
+
+        let Some(zone_ref) = current.spec.zone_ref.as_ref() else {
+            return ZoneRefChain::Terminated;
+        };
+
+        let parent_ref = ZoneRef {
+            name: zone_ref.name.clone(),
+            namespace: zone_ref.namespace.clone().or(current.namespace()),
+        };
+
+        if let Some(position) = chain
+            .iter()
+            .position(|visited| visited.zone_ref() == parent_ref)
+        {
+            return ZoneRefChain::Cycle(chain.split_off(position));
         }
+
+        if chain.len() > MAX_ZONE_REF_DEPTH {
+            return ZoneRefChain::TooDeep(chain);
+        }
+
+        let Some(parent) = zones
+            .iter()
+            .find(|candidate| candidate.zone_ref() == parent_ref)
+        else {
+            return ZoneRefChain::Terminated;
+        };
+
+        chain.push(parent.clone());
+    }
+}
+
+/// Current time formatted for use in [`Condition`]s.
+pub(crate) fn now() -> String {
+    time::OffsetDateTime::now_utc()
+        .format(&time::format_description::well_known::Rfc3339)
+        .unwrap_or_default()
+}
+
+/// Parse a timestamp written by [`now`], such as `.status.secondary.lastAttempt`.
+pub(crate) fn parse_time(time: Option<&str>) -> Option<time::OffsetDateTime> {
+    time::OffsetDateTime::parse(time?, &time::format_description::well_known::Rfc3339).ok()
+}
+
+async fn set_zone_condition(
+    client: Client,
+    zone: &Zone,
+    condition: Condition,
+) -> Result<(), kube::Error> {
+    let mut conditions = zone
+        .status
+        .as_ref()
+        .map(|status| status.conditions.clone())
+        .unwrap_or_default();
+
+    if set_condition(&mut conditions, condition) {
+        debug!("updating conditions of zone {zone}");
+        Api::<Zone>::namespaced(client, zone.namespace().as_ref().unwrap())
+            .patch_status(
+                &zone.name_any(),
+                &PatchParams::apply(CONTROLLER_NAME),
+                &Patch::Merge(json!({
+                    "status": {
+                        "conditions": conditions,
+                    }
+                })),
+            )
+            .await?;
     }
 
-    update_zone_status(zone, ctx.client.clone()).await?;
-    Ok(Action::requeue(Duration::from_secs(300)))
+    Ok(())
 }
 
 async fn set_zone_fqdn(client: Client, zone: &Zone, fqdn: &str) -> Result<(), kube::Error> {
@@ -177,7 +468,12 @@ async fn set_zone_parent_ref(
     zone: &Arc<Zone>,
     parent_ref: ZoneRef,
 ) -> Result<(), kube::Error> {
-    if zone.labels().get(PARENT_ZONE_LABEL) != Some(&parent_ref.as_label()) {
+    // Objects labelled by earlier versions of the controller carry a `name.namespace`
+    // label and no annotation. These are migrated transparently here, since the
+    // label will not match the hashed representation.
+    if zone.labels().get(PARENT_ZONE_LABEL) != Some(&parent_ref.as_label())
+        || zone.annotations().get(PARENT_ZONE_ANNOTATION) != Some(&parent_ref.as_annotation())
+    {
         info!(
             "updating zone {}'s {PARENT_ZONE_LABEL} to {parent_ref}",
             zone.name_any()
@@ -192,6 +488,9 @@ async fn set_zone_parent_ref(
                         "labels": {
                             PARENT_ZONE_LABEL: parent_ref.as_label()
                         },
+                        "annotations": {
+                            PARENT_ZONE_ANNOTATION: parent_ref.as_annotation()
+                        },
                     }
                 })),
             )
@@ -205,17 +504,17 @@ async fn set_zone_parent_ref(
     Ok(())
 }
 
-async fn find_zone_nameserver_records(
-    zone: &Zone,
-    client: Client,
-) -> Result<Vec<ZoneEntry>, kube::Error> {
-    // Reference to this zone, which other zones and records will use to refer to it by.
-    let zone_ref = ListParams::default().labels(&format!(
-        "{PARENT_ZONE_LABEL}={}",
-        zone.zone_ref().as_label()
-    ));
-
-    let records = Api::<Record>::all(client.clone()).list(&zone_ref).await?;
+/// Produce the NS records of `child_zone` which point at its own domain, along with
+/// any glue records for them, so they can be reflected in the parent zone.
+///
+/// `records` may contain unrelated records, which are ignored.
+fn nameserver_entries(parent_zone: &Zone, child_zone: &Zone, records: &[Record]) -> Vec<ZoneEntry> {
+    let records: Vec<_> = records
+        .iter()
+        .filter(|record| {
+            kubizone_crds::references(*record, PARENT_ZONE_ANNOTATION, &child_zone.zone_ref())
+        })
+        .collect();
 
     // Reflect self-referential NS records from the subzone in the parent zone.
     let mut ns_records: Vec<_> = records
@@ -223,12 +522,14 @@ async fn find_zone_nameserver_records(
         .map(|record| &record.spec)
         .filter(|spec| spec.class.to_uppercase() == "IN")
         .filter(|spec| spec.type_.to_uppercase() == "NS")
-        .filter(|spec| spec.domain_name == "@" || Some(spec.domain_name.as_str()) == zone.fqdn())
+        .filter(|spec| {
+            spec.domain_name == "@" || Some(spec.domain_name.as_str()) == child_zone.fqdn()
+        })
         .map(|spec| ZoneEntry {
-            fqdn: zone.spec.domain_name.clone(),
+            fqdn: child_zone.spec.domain_name.clone(),
             type_: spec.type_.clone(),
             class: spec.class.clone(),
-            ttl: spec.ttl.unwrap_or(zone.spec.ttl),
+            ttl: spec.ttl.unwrap_or(parent_zone.spec.ttl),
             rdata: spec.rdata.clone(),
         })
         .collect();
@@ -253,43 +554,53 @@ async fn find_zone_nameserver_records(
             fqdn: record.fqdn().unwrap().to_string(),
             type_: record.spec.type_.clone(),
             class: record.spec.class.clone(),
-            ttl: record.spec.ttl.unwrap_or(zone.spec.ttl),
+            ttl: record.spec.ttl.unwrap_or(parent_zone.spec.ttl),
             rdata: record.spec.rdata.clone(),
         })
         .collect();
 
-    ns_records.extend(glue_records.into_iter());
-    Ok(ns_records)
+    ns_records.extend(glue_records);
+    ns_records
 }
 
-async fn update_zone_status(zone: Arc<Zone>, client: Client) -> Result<(), kube::Error> {
-    let Some(origin) = zone.fqdn() else {
-        return Ok(());
-    };
+/// Status of a zone as computed from its children.
+pub(crate) struct ZoneContents {
+    pub hash: String,
+    pub serial: u32,
+    pub entries: Vec<ZoneEntry>,
+}
 
-    // Reference to this zone, which other zones and records will use to refer to it by.
-    let zone_ref = ListParams::default().labels(&format!(
-        "{PARENT_ZONE_LABEL}={}",
-        zone.zone_ref().as_label()
-    ));
+/// Compute the entries, hash and serial of `zone` from the `records` and `zones`
+/// referencing it, as well as the records of those zones.
+///
+/// `zones` and `records` may contain unrelated resources, which are ignored.
+/// Returns `None` if the zone has not had its fully qualified domain name determined yet.
+pub(crate) fn zone_contents(
+    zone: &Zone,
+    zones: &[Zone],
+    records: &[Record],
+    now: time::OffsetDateTime,
+) -> Option<ZoneContents> {
+    let origin = zone.fqdn()?;
 
     // Using a VecDeque here because we want to push_front an SOA record
     // after all other records have been hashed.
     let mut entries = VecDeque::new();
 
     // Insert all child records into the entries list
-    for record in Api::<Record>::all(client.clone())
-        .list(&zone_ref)
-        .await?
-        .into_iter()
-        .map(|record| record.spec)
+    for record in records
+        .iter()
+        .filter(|record| {
+            kubizone_crds::references(*record, PARENT_ZONE_ANNOTATION, &zone.zone_ref())
+        })
+        .map(|record| &record.spec)
     {
         entries.push_back(ZoneEntry {
-            fqdn: record.domain_name,
-            type_: record.type_,
-            class: record.class,
+            fqdn: record.domain_name.clone(),
+            type_: record.type_.clone(),
+            class: record.class.clone(),
             ttl: record.ttl.unwrap_or(zone.spec.ttl),
-            rdata: record.rdata,
+            rdata: record.rdata.clone(),
         })
     }
 
@@ -301,32 +612,87 @@ async fn update_zone_status(zone: Arc<Zone>, client: Client) -> Result<(), kube:
     //
     // Which also need to be represented in the parent zone, so delegation works
     // without having to manually configure NS records in the parent.
-    for child_zone in Api::<Zone>::all(client.clone())
-        .list(&zone_ref)
-        .await?
-        .into_iter()
+    for child_zone in zones
+        .iter()
+        .filter(|child| kubizone_crds::references(*child, PARENT_ZONE_ANNOTATION, &zone.zone_ref()))
     {
-        entries.extend(find_zone_nameserver_records(&child_zone, client.clone()).await?)
+        entries.extend(nameserver_entries(zone, child_zone, records))
     }
 
-    let mut hasher = DefaultHasher::new();
-    (&zone.spec, &entries).hash(&mut hasher);
-    let new_hash = hasher.finish().to_string();
+    Some(finish_contents(zone, origin, entries, now))
+}
+
+/// Compute the contents of `zone` pinned to the entries of `revision`. The SOA
+/// record of the revision is replaced, since the zone moves on to a new serial.
+///
+/// Returns `None` if the zone has not had its fully qualified domain name determined yet.
+fn pinned_contents(
+    zone: &Zone,
+    revision: &ZoneRevision,
+    now: time::OffsetDateTime,
+) -> Option<ZoneContents> {
+    let origin = zone.fqdn()?;
+
+    let entries = revision
+        .spec
+        .entries
+        .iter()
+        .filter(|entry| !entry.type_.eq_ignore_ascii_case("SOA"))
+        .cloned()
+        .collect();
 
-    let current_hash = zone.status.as_ref().and_then(|status| status.hash.as_ref());
+    Some(finish_contents(zone, origin, entries, now))
+}
 
-    let last_serial = zone
-        .status
-        .as_ref()
-        .and_then(|status| status.serial)
-        .unwrap_or_default();
+/// Hash the `entries` of `zone`, bumping its serial if they changed, and
+/// prepend the SOA record.
+fn finish_contents(
+    zone: &Zone,
+    origin: &str,
+    mut entries: VecDeque<ZoneEntry>,
+    now: time::OffsetDateTime,
+) -> ZoneContents {
+    // Hash the fields of the spec individually, which is equivalent to hashing
+    // the spec itself, but leaves out those which don't affect the contents,
+    // so changing them neither bumps the serial, nor ever did.
+    let ZoneSpec {
+        domain_name,
+        zone_ref,
+        delegations,
+        ttl,
+        refresh,
+        retry,
+        expire,
+        negative_response_cache,
+        notify: _,
+        dynamic_update: _,
+        transfer: _,
+        secondary: _,
+        revision_history_limit: _,
+        pinned_revision: _,
+    } = &zone.spec;
+
+    let mut hasher = DefaultHasher::new();
+    (
+        domain_name,
+        zone_ref,
+        delegations,
+        ttl,
+        refresh,
+        retry,
+        expire,
+        negative_response_cache,
+        &entries,
+    )
+        .hash(&mut hasher);
+    let hash = hasher.finish().to_string();
+
+    let last_serial = zone.serial().unwrap_or_default();
 
     // If the hash changed, we need to update the serial.
-    let serial = if current_hash != Some(&new_hash) {
-        info!("zone {zone}'s hash changed (before: {current_hash:?}, now: {new_hash}), updating serial.");
+    let serial = if zone.hash() != Some(hash.as_str()) {
         // Compute a serial based on the current datetime in UTC as per:
         // https://datatracker.ietf.org/doc/html/rfc1912#section-2.2
-        let now = time::OffsetDateTime::now_utc();
         #[rustfmt::skip]
         let now_serial
             = now.year()  as u32 * 1000000
@@ -358,15 +724,127 @@ async fn update_zone_status(zone: Arc<Zone>, client: Client) -> Result<(), kube:
         rdata: format!("ns.{origin} noc.{origin} ({serial} {refresh} {retry} {expire} {negative_response_cache})"),
     });
 
-    Api::<Zone>::namespaced(client, zone.namespace().as_ref().unwrap())
+    ZoneContents {
+        hash,
+        serial,
+        entries: entries.into(),
+    }
+}
+
+/// List the zones or records in scope which have been adopted by `parent`.
+///
+/// Children which have not been migrated by their controller yet still carry
+/// the legacy `name.namespace` label, so those are listed as well, rather than
+/// temporarily dropping out of their parent during an upgrade.
+async fn list_children<K>(
+    client: &Client,
+    scope: &Scope,
+    parent: &ZoneRef,
+) -> Result<Vec<K>, kube::Error>
+where
+    K: kube::Resource<Scope = kube::core::NamespaceResourceScope>
+        + Clone
+        + k8s_openapi::serde::de::DeserializeOwned
+        + std::fmt::Debug,
+    <K as kube::Resource>::DynamicType: Default,
+{
+    let labels = std::iter::once(parent.as_label()).chain(parent.as_legacy_label());
+
+    let mut children: Vec<K> = Vec::new();
+    for label in labels {
+        let params = ListParams::default().labels(&format!("{PARENT_ZONE_LABEL}={label}"));
+
+        for child in scope.list::<K>(client, &params).await? {
+            // Objects carrying both labels during migration are listed twice.
+            if !children.iter().any(|listed| listed.uid() == child.uid()) {
+                children.push(child);
+            }
+        }
+    }
+
+    // Weed out hash collisions, and legacy labels of since-migrated objects.
+    children.retain(|child| kubizone_crds::references(child, PARENT_ZONE_ANNOTATION, parent));
+    Ok(children)
+}
+
+async fn update_zone_status(zone: Arc<Zone>, client: Client, scope: &Scope) -> Result<(), Error> {
+    if zone.fqdn().is_none() {
+        return Ok(());
+    }
+
+    // The entries of secondary zones are transferred from their primary
+    // by the secondary controller instead.
+    if zone.spec.secondary.is_some() {
+        return Ok(());
+    }
+
+    let now = time::OffsetDateTime::now_utc();
+
+    // Zones pinned to a revision ignore their records and sub-zones entirely.
+    if let Some(serial) = zone.spec.pinned_revision {
+        let name = ZoneRevision::name_for(&zone, serial);
+        let Some(revision) =
+            Api::<ZoneRevision>::namespaced(client.clone(), zone.namespace().as_ref().unwrap())
+                .get_opt(&name)
+                .await?
+        else {
+            warn!("zone {zone} is pinned to revision {name}, which does not exist");
+            return Err(Error::MissingRevision(name));
+        };
+
+        let Some(contents) = pinned_contents(&zone, &revision, now) else {
+            return Ok(());
+        };
+
+        return write_contents(client, &zone, contents).await;
+    }
+
+    let zones = list_children::<Zone>(&client, scope, &zone.zone_ref()).await?;
+    let mut records = list_children::<Record>(&client, scope, &zone.zone_ref()).await?;
+
+    // The records of child zones are needed as well, for delegating to them.
+    for child_zone in &zones {
+        records.extend(list_children::<Record>(&client, scope, &child_zone.zone_ref()).await?);
+    }
+
+    let Some(contents) = zone_contents(&zone, &zones, &records, now) else {
+        return Ok(());
+    };
+
+    write_contents(client, &zone, contents).await
+}
+
+/// Store the computed `contents` in the status of the `zone`.
+async fn write_contents(client: Client, zone: &Zone, contents: ZoneContents) -> Result<(), Error> {
+    if zone.hash() != Some(contents.hash.as_str()) {
+        info!(
+            "zone {zone}'s hash changed (before: {:?}, now: {}), updating serial.",
+            zone.hash(),
+            contents.hash
+        );
+    }
+
+    // Record the revision before bumping the serial, so none go unrecorded.
+    if zone.serial() != Some(contents.serial) {
+        revision::record(
+            &client,
+            zone,
+            contents.serial,
+            &contents.hash,
+            &contents.entries,
+        )
+        .await?;
+    }
+
+    Api::<Zone>::namespaced(client.clone(), zone.namespace().as_ref().unwrap())
         .patch_status(
             &zone.name_any(),
             &PatchParams::apply(CONTROLLER_NAME),
             &Patch::Merge(json!({
                 "status": {
-                    "hash": new_hash,
-                    "entries": entries,
-                    "serial": Some(serial)
+                    "hash": contents.hash,
+                    "entries": contents.entries,
+                    "serial": Some(contents.serial)
                 },
             })),
         )
@@ -375,10 +853,18 @@ async fn update_zone_status(zone: Arc<Zone>, client: Client) -> Result<(), kube:
     Ok(())
 }
 
-fn zone_error_policy(zone: Arc<Zone>, error: &kube::Error, _ctx: Arc<Data>) -> Action {
+fn zone_error_policy(zone: Arc<Zone>, error: &Error, ctx: Arc<Data>) -> Action {
+    let delay = if error.is_misconfiguration() {
+        ctx.backoff.misconfigured()
+    } else {
+        ctx.backoff.failure(&zone.to_string())
+    };
+
     error!(
-        "zone {} reconciliation encountered error: {error}",
-        zone.name_any()
+        "zone {} reconciliation encountered {} error: {error}, retrying in {}s",
+        zone.name_any(),
+        error.category(),
+        delay.as_secs()
     );
-    Action::requeue(Duration::from_secs(60))
+    Action::requeue(delay)
 }
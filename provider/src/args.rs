@@ -0,0 +1,130 @@
+use std::{sync::Arc, time::Duration};
+
+use kube::Client;
+use reqwest::Url;
+use tracing::log::*;
+
+use crate::{
+    cloudflare::api_token, powerdns::api_key, AwsCredentials, Cloudflare, Credentials, DnsProvider,
+    Error, PowerDns, Route53,
+};
+
+/// Interval at which the credentials of a provider are re-read from their Secret.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Command line arguments selecting a provider and its credentials, shared by
+/// the `kubizone-provider` operator and `kubizone adopt`.
+#[derive(Debug, clap::Subcommand)]
+pub enum ProviderArgs {
+    /// AWS Route53 hosted zones. The credentials Secret holds the keys
+    /// `accessKeyId`, `secretAccessKey` and optionally `sessionToken`.
+    Route53 {
+        /// Secret holding the credentials of the provider, given as `namespace/name`.
+        #[arg(long, value_parser = parse_secret_ref)]
+        credentials: (String, String),
+
+        /// Route53 API endpoint.
+        #[arg(long, default_value = "https://route53.amazonaws.com")]
+        endpoint: Url,
+    },
+    /// Cloudflare. The credentials Secret holds an API token with permission
+    /// to edit DNS records in the key `apiToken`.
+    Cloudflare {
+        /// Secret holding the credentials of the provider, given as `namespace/name`.
+        #[arg(long, value_parser = parse_secret_ref)]
+        credentials: (String, String),
+
+        /// Cloudflare API endpoint.
+        #[arg(long, default_value = "https://api.cloudflare.com/client/v4")]
+        endpoint: Url,
+    },
+    /// PowerDNS authoritative server, through its HTTP API. The credentials
+    /// Secret holds the API key in the key `apiKey`.
+    Powerdns {
+        /// Secret holding the credentials of the provider, given as `namespace/name`.
+        #[arg(long, value_parser = parse_secret_ref)]
+        credentials: (String, String),
+
+        /// PowerDNS API endpoint, such as `http://powerdns:8081`.
+        #[arg(long)]
+        endpoint: Url,
+
+        /// Id of the server within the PowerDNS API.
+        #[arg(long, default_value = "localhost")]
+        server: String,
+    },
+}
+
+/// Parse a reference to a Secret given as `namespace/name`, such as in the
+/// `--credentials` argument.
+pub fn parse_secret_ref(value: &str) -> Result<(String, String), String> {
+    match value.split_once('/') {
+        Some((namespace, name)) if !namespace.is_empty() && !name.is_empty() => {
+            Ok((namespace.to_string(), name.to_string()))
+        }
+        _ => Err(format!("expected namespace/name, got {value}")),
+    }
+}
+
+async fn credentials(client: &Client, secret: &(String, String)) -> Result<Credentials, Error> {
+    let (namespace, name) = secret;
+    Credentials::from_secret(client.clone(), namespace, name).await
+}
+
+impl ProviderArgs {
+    /// Secret holding the credentials of the selected provider.
+    fn secret(&self) -> &(String, String) {
+        match self {
+            ProviderArgs::Route53 { credentials, .. }
+            | ProviderArgs::Cloudflare { credentials, .. }
+            | ProviderArgs::Powerdns { credentials, .. } => credentials,
+        }
+    }
+
+    /// Read the credentials of the selected provider from their Secret, and
+    /// construct it.
+    pub async fn connect(&self, client: &Client) -> Result<Arc<dyn DnsProvider>, Error> {
+        Ok(match self {
+            ProviderArgs::Route53 {
+                credentials: secret,
+                endpoint,
+            } => {
+                let credentials =
+                    AwsCredentials::from_credentials(&credentials(client, secret).await?)?;
+                Arc::new(Route53::new(endpoint.clone(), credentials))
+            }
+            ProviderArgs::Cloudflare {
+                credentials: secret,
+                endpoint,
+            } => {
+                let token = api_token(&credentials(client, secret).await?)?;
+                Arc::new(Cloudflare::new(endpoint.clone(), token))
+            }
+            ProviderArgs::Powerdns {
+                credentials: secret,
+                endpoint,
+                server,
+            } => {
+                let api_key = api_key(&credentials(client, secret).await?)?;
+                Arc::new(PowerDns::new(endpoint.clone(), server.clone(), api_key))
+            }
+        })
+    }
+
+    /// Re-read the credentials of the `provider` from their Secret every
+    /// minute, so rotated credentials such as expiring AWS session tokens are
+    /// picked up. Credentials which cannot be read are kept as they were.
+    pub async fn reload(&self, client: &Client, provider: &dyn DnsProvider) {
+        loop {
+            tokio::time::sleep(RELOAD_INTERVAL).await;
+
+            let (namespace, name) = self.secret();
+            if let Err(err) = credentials(client, self.secret())
+                .await
+                .and_then(|credentials| provider.reload(&credentials))
+            {
+                warn!("failed to reload credentials from secret {namespace}/{name}, keeping the current ones: {err}");
+            }
+        }
+    }
+}
@@ -0,0 +1,577 @@
+use std::{collections::BTreeMap, sync::Mutex, time::Duration};
+
+use async_trait::async_trait;
+use k8s_openapi::serde_json::{self, json, Value};
+use reqwest::{Method, Url};
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::{http::Http, Change, Credentials, DnsProvider, Error, ProviderError, RecordSet};
+
+/// Cloudflare allows 1200 requests per five minutes for each user.
+const REQUEST_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Records listed per page.
+const PAGE_SIZE: u32 = 100;
+
+/// Prefix of the records standing in for records proxied by Cloudflare.
+/// Recreating them would drop the proxying and expose the origin, so
+/// kubizone never owns them.
+const PROXIED_PREFIX: &str = "cloudflare:proxied ";
+
+#[derive(Deserialize)]
+struct Page<T> {
+    result: Vec<T>,
+    #[serde(default)]
+    result_info: Option<ResultInfo>,
+}
+
+#[derive(Deserialize)]
+struct ResultInfo {
+    total_pages: u32,
+}
+
+#[derive(Deserialize)]
+struct CloudflareZone {
+    id: String,
+    name: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct DnsRecord {
+    id: String,
+    name: String,
+    #[serde(rename = "type")]
+    type_: String,
+    #[serde(default)]
+    content: String,
+    ttl: u32,
+    #[serde(default)]
+    priority: Option<u16>,
+    #[serde(default)]
+    data: Option<Value>,
+    #[serde(default)]
+    proxied: bool,
+}
+
+impl DnsRecord {
+    /// Record data in presentation format.
+    fn rdata(&self) -> String {
+        let field = |name: &str| {
+            self.data
+                .as_ref()
+                .and_then(|data| data.get(name))
+                .map(|value| match value {
+                    Value::String(string) => string.clone(),
+                    value => value.to_string(),
+                })
+                .unwrap_or_default()
+        };
+
+        match self.type_.as_str() {
+            "MX" => format!(
+                "{} {}",
+                self.priority.unwrap_or_default(),
+                fully_qualified(&self.content)
+            ),
+            "SRV" => format!(
+                "{} {} {} {}",
+                field("priority"),
+                field("weight"),
+                field("port"),
+                fully_qualified(&field("target"))
+            ),
+            "CAA" => format!(
+                "{} {} \"{}\"",
+                field("flags"),
+                field("tag"),
+                field("value").replace('"', "\\\"")
+            ),
+            "CNAME" | "NS" | "PTR" => fully_qualified(&self.content),
+            // TXT records are not necessarily quoted, in which case the content
+            // is a single character string, spaces and all.
+            "TXT" | "SPF" if !self.content.starts_with('"') => {
+                format!("\"{}\"", self.content.replace('"', "\\\""))
+            }
+            _ => self.content.clone(),
+        }
+    }
+
+    /// Record data as listed, marking proxied records as such.
+    fn listed(&self) -> String {
+        if self.proxied {
+            format!("{PROXIED_PREFIX}{}", self.rdata())
+        } else {
+            self.rdata()
+        }
+    }
+}
+
+/// Cloudflare omits the trailing dot of fully qualified names.
+fn fully_qualified(name: &str) -> String {
+    format!("{}.", name.trim_end_matches('.'))
+}
+
+/// Body of a record created from the `rdata` of a record set.
+fn record(set: &RecordSet, rdata: &str) -> Value {
+    let name = set.name.trim_end_matches('.');
+    let fields: Vec<_> = rdata.split_whitespace().collect();
+    let unqualified = |name: &str| name.trim_end_matches('.').to_string();
+
+    match (set.type_.as_str(), fields.as_slice()) {
+        ("MX", [priority, exchange]) => json!({
+            "name": name,
+            "type": "MX",
+            "ttl": set.ttl,
+            "priority": priority.parse::<u16>().unwrap_or_default(),
+            "content": unqualified(exchange),
+        }),
+        ("SRV", [priority, weight, port, target]) => json!({
+            "name": name,
+            "type": "SRV",
+            "ttl": set.ttl,
+            "data": {
+                "priority": priority.parse::<u16>().unwrap_or_default(),
+                "weight": weight.parse::<u16>().unwrap_or_default(),
+                "port": port.parse::<u16>().unwrap_or_default(),
+                "target": unqualified(target),
+            },
+        }),
+        ("CAA", [flags, tag, ..]) => {
+            let value = rdata
+                .splitn(3, char::is_whitespace)
+                .nth(2)
+                .unwrap_or_default()
+                .trim()
+                .trim_matches('"');
+
+            json!({
+                "name": name,
+                "type": "CAA",
+                "ttl": set.ttl,
+                "data": {
+                    "flags": flags.parse::<u8>().unwrap_or_default(),
+                    "tag": tag,
+                    "value": value,
+                },
+            })
+        }
+        ("CNAME" | "NS" | "PTR", _) => json!({
+            "name": name,
+            "type": set.type_,
+            "ttl": set.ttl,
+            "content": unqualified(rdata),
+        }),
+        _ => json!({
+            "name": name,
+            "type": set.type_,
+            "ttl": set.ttl,
+            "content": rdata,
+        }),
+    }
+}
+
+/// API token of Cloudflare, read from the keys of a Secret.
+pub(crate) fn api_token(credentials: &Credentials) -> Result<String, Error> {
+    credentials.required("apiToken")
+}
+
+/// Zones of the [Cloudflare API](https://developers.cloudflare.com/api/).
+pub struct Cloudflare {
+    endpoint: Url,
+    /// Replaced whenever the credentials are [reloaded](DnsProvider::reload).
+    token: Mutex<String>,
+    http: Http,
+    /// Zone ids, keyed by the lowercased origin of the zone.
+    zones: Mutex<BTreeMap<String, String>>,
+    /// Ids of the records of each record set, as last listed by Cloudflare,
+    /// keyed by the lowercased origin of the zone.
+    listed: Mutex<BTreeMap<String, BTreeMap<RecordSet, Vec<String>>>>,
+}
+
+impl Cloudflare {
+    /// Cloudflare API at `endpoint`, usually `https://api.cloudflare.com/client/v4`,
+    /// authenticating using the API `token`.
+    pub fn new(endpoint: Url, token: String) -> Self {
+        Cloudflare {
+            endpoint,
+            token: Mutex::new(token),
+            http: Http::new(REQUEST_INTERVAL),
+            zones: Mutex::default(),
+            listed: Mutex::default(),
+        }
+    }
+
+    #[cfg(test)]
+    fn with_http(mut self, http: Http) -> Self {
+        self.http = http;
+        self
+    }
+
+    fn url(&self, path: &str) -> Url {
+        let mut url = self.endpoint.clone();
+        let base = url.path().trim_end_matches('/').to_string();
+        url.set_path(&format!("{base}{path}"));
+        url
+    }
+
+    async fn request(
+        &self,
+        method: Method,
+        url: Url,
+        body: Option<&Value>,
+    ) -> Result<String, ProviderError> {
+        self.http
+            .send(
+                |client| {
+                    let request = client
+                        .request(method.clone(), url.clone())
+                        .bearer_auth(self.token.lock().unwrap().as_str());
+
+                    match body {
+                        Some(body) => request.json(body),
+                        None => request,
+                    }
+                },
+                |_, _| false,
+            )
+            .await
+    }
+
+    /// Fetch all pages of the listing at `path`.
+    async fn paginated<T: DeserializeOwned>(&self, path: &str) -> Result<Vec<T>, ProviderError> {
+        let mut items = Vec::new();
+
+        for page in 1.. {
+            let mut url = self.url(path);
+            url.query_pairs_mut()
+                .append_pair("page", &page.to_string())
+                .append_pair("per_page", &PAGE_SIZE.to_string());
+
+            let body = self.request(Method::GET, url, None).await?;
+            let response: Page<T> = serde_json::from_str(&body).map_err(|err| {
+                ProviderError::Unavailable(format!("invalid response from cloudflare: {err}"))
+            })?;
+
+            items.extend(response.result);
+
+            let pages = response.result_info.map(|info| info.total_pages);
+            if pages.unwrap_or_default() <= page {
+                break;
+            }
+        }
+
+        Ok(items)
+    }
+
+    async fn zone_id(&self, origin: &str) -> Result<String, ProviderError> {
+        if let Some(id) = self.zones.lock().unwrap().get(&origin.to_lowercase()) {
+            return Ok(id.clone());
+        }
+
+        self.zones().await?;
+        self.zones
+            .lock()
+            .unwrap()
+            .get(&origin.to_lowercase())
+            .cloned()
+            .ok_or_else(|| ProviderError::UnknownZone(origin.to_string()))
+    }
+}
+
+#[async_trait]
+impl DnsProvider for Cloudflare {
+    fn name(&self) -> &str {
+        "cloudflare"
+    }
+
+    async fn zones(&self) -> Result<Vec<String>, ProviderError> {
+        let zones: BTreeMap<_, _> = self
+            .paginated::<CloudflareZone>("/zones")
+            .await?
+            .into_iter()
+            .map(|zone| (fully_qualified(&zone.name).to_lowercase(), zone.id))
+            .collect();
+
+        // Forget the record sets of zones which are no longer hosted.
+        self.listed
+            .lock()
+            .unwrap()
+            .retain(|origin, _| zones.contains_key(origin));
+
+        let origins = zones.keys().cloned().collect();
+        *self.zones.lock().unwrap() = zones;
+        Ok(origins)
+    }
+
+    async fn list(&self, origin: &str) -> Result<Vec<RecordSet>, ProviderError> {
+        let id = self.zone_id(origin).await?;
+        let records = self
+            .paginated::<DnsRecord>(&format!("/zones/{id}/dns_records"))
+            .await?;
+
+        let mut grouped: BTreeMap<(String, String), Vec<DnsRecord>> = BTreeMap::new();
+        for record in records {
+            grouped
+                .entry((fully_qualified(&record.name), record.type_.clone()))
+                .or_default()
+                .push(record);
+        }
+
+        let mut listed = BTreeMap::new();
+        let sets = grouped
+            .into_iter()
+            .map(|((name, type_), records)| {
+                let ttl = records
+                    .iter()
+                    .map(|record| record.ttl)
+                    .min()
+                    .unwrap_or_default();
+                let set = RecordSet::new(
+                    origin,
+                    &name,
+                    &type_,
+                    ttl,
+                    records.iter().map(DnsRecord::listed),
+                );
+
+                listed.insert(
+                    set.clone(),
+                    records.into_iter().map(|record| record.id).collect(),
+                );
+                set
+            })
+            .collect();
+
+        self.listed
+            .lock()
+            .unwrap()
+            .insert(origin.to_lowercase(), listed);
+
+        Ok(sets)
+    }
+
+    fn owns(&self, origin: &str, set: &RecordSet) -> bool {
+        !set.reserved(origin)
+            && !set
+                .records
+                .iter()
+                .any(|record| record.starts_with(PROXIED_PREFIX))
+    }
+
+    fn reload(&self, credentials: &Credentials) -> Result<(), Error> {
+        *self.token.lock().unwrap() = api_token(credentials)?;
+        Ok(())
+    }
+
+    fn batch_size(&self) -> usize {
+        // Each change can take several operations, of which a batch allows 200.
+        50
+    }
+
+    async fn apply(&self, origin: &str, changes: &[Change]) -> Result<(), ProviderError> {
+        let id = self.zone_id(origin).await?;
+
+        let mut deletes = Vec::new();
+        let mut posts = Vec::new();
+        {
+            let listed = self.listed.lock().unwrap();
+            let listed = listed.get(&origin.to_lowercase());
+            for change in changes {
+                if let Some(current) = change.current() {
+                    let ids = listed
+                        .and_then(|listed| listed.get(current))
+                        .ok_or_else(|| {
+                            ProviderError::Rejected(format!(
+                                "record set {} {} has changed since it was listed",
+                                current.name, current.type_
+                            ))
+                        })?;
+                    deletes.extend(ids.iter().map(|id| json!({ "id": id })));
+                }
+
+                if let Some(desired) = change.desired() {
+                    posts.extend(desired.records.iter().map(|rdata| record(desired, rdata)));
+                }
+            }
+        }
+
+        // Batches are applied atomically, with deletions before creations.
+        let body = json!({
+            "deletes": deletes,
+            "posts": posts,
+        });
+
+        self.request(
+            Method::POST,
+            self.url(&format!("/zones/{id}/dns_records/batch")),
+            Some(&body),
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use k8s_openapi::serde_json::{self, json, Value};
+    use kubizone_crds::v1alpha1::ZoneEntry;
+    use reqwest::Url;
+
+    use crate::{
+        apply_plan,
+        http::{
+            mock::{serve, Response},
+            Http,
+        },
+        DnsProvider, RecordSet,
+    };
+
+    use super::Cloudflare;
+
+    const ORIGIN: &str = "example.org.";
+
+    fn entry(fqdn: &str, type_: &str, rdata: &str) -> ZoneEntry {
+        ZoneEntry {
+            fqdn: fqdn.to_string(),
+            type_: type_.to_string(),
+            class: String::from("IN"),
+            ttl: 300,
+            rdata: rdata.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn cloudflare() {
+        let limited = Arc::new(AtomicUsize::new(0));
+        let limit = limited.clone();
+
+        let (address, requests) = serve(move |request| {
+            if request.headers.get("authorization").map(String::as_str) != Some("Bearer token") {
+                return Response::new(403, r#"{"success":false}"#);
+            }
+
+            let path = request.path.as_str();
+            let page = if path.contains("page=2") { 2 } else { 1 };
+
+            if path.starts_with("/client/v4/zones?") {
+                let zone = if page == 1 {
+                    json!({"id": "z1", "name": "example.com"})
+                } else {
+                    json!({"id": "z2", "name": "example.org"})
+                };
+
+                return Response::new(
+                    200,
+                    json!({"success": true, "result": [zone], "result_info": {"total_pages": 2}})
+                        .to_string(),
+                );
+            }
+
+            if path.starts_with("/client/v4/zones/z2/dns_records?") {
+                let records = if page == 1 {
+                    json!([
+                        {"id": "r1", "name": "example.org", "type": "MX", "content": "mail.example.org", "priority": 10, "ttl": 300},
+                        {"id": "r2", "name": "www.example.org", "type": "A", "content": "192.0.2.1", "ttl": 300},
+                    ])
+                } else {
+                    json!([
+                        {"id": "r3", "name": "www.example.org", "type": "A", "content": "192.0.2.2", "ttl": 300},
+                        {"id": "r4", "name": "example.org", "type": "TXT", "content": "v=spf1 -all", "ttl": 300},
+                        {"id": "r5", "name": "_sip._tcp.example.org", "type": "SRV", "ttl": 300,
+                         "data": {"priority": 0, "weight": 5, "port": 5060, "target": "sip.example.org"}},
+                        {"id": "r6", "name": "app.example.org", "type": "A", "content": "192.0.2.6", "ttl": 1, "proxied": true},
+                    ])
+                };
+
+                return Response::new(
+                    200,
+                    json!({"success": true, "result": records, "result_info": {"total_pages": 2}})
+                        .to_string(),
+                );
+            }
+
+            if path == "/client/v4/zones/z2/dns_records/batch" {
+                if limit.fetch_add(1, Ordering::SeqCst) == 0 {
+                    return Response::new(429, r#"{"success":false}"#).header("retry-after", "0");
+                }
+
+                return Response::new(200, r#"{"success":true,"result":{}}"#);
+            }
+
+            Response::new(404, r#"{"success":false}"#)
+        })
+        .await;
+
+        let provider = Cloudflare::new(
+            Url::parse(&format!("{address}/client/v4")).unwrap(),
+            String::from("token"),
+        )
+        .with_http(Http::new(Duration::ZERO));
+
+        assert_eq!(
+            provider.zones().await.unwrap(),
+            ["example.com.", "example.org."]
+        );
+
+        let sets = provider.list(ORIGIN).await.unwrap();
+        assert!(sets.contains(&RecordSet::new(
+            ORIGIN,
+            "www",
+            "A",
+            300,
+            ["192.0.2.1", "192.0.2.2"]
+        )));
+        assert!(sets.contains(&RecordSet::new(
+            ORIGIN,
+            "@",
+            "TXT",
+            300,
+            ["\"v=spf1 -all\""]
+        )));
+
+        let entries = [
+            entry("example.org.", "MX", "10 mail"),
+            entry("example.org.", "TXT", "\"v=spf1 -all\""),
+            entry("_sip._tcp.example.org.", "SRV", "0 5 5060 sip"),
+            entry("www.example.org.", "A", "192.0.2.3"),
+            entry("example.org.", "CAA", "0 issue \"letsencrypt.org\""),
+            entry("app.example.org.", "A", "192.0.2.6"),
+        ];
+
+        let current = provider.list(ORIGIN).await.unwrap();
+        let plan = provider.plan(ORIGIN, RecordSet::from_entries(ORIGIN, &entries), current);
+        assert_eq!(plan.changes.len(), 2);
+
+        // Proxied records are left alone, rather than recreated unproxied.
+        assert_eq!(
+            plan.conflicts,
+            [RecordSet::new(ORIGIN, "app", "A", 300, ["192.0.2.6"])]
+        );
+
+        apply_plan(&provider, ORIGIN, &plan).await.unwrap();
+        assert_eq!(limited.load(Ordering::SeqCst), 2);
+
+        let requests = requests.lock().unwrap();
+        let body: Value = serde_json::from_str(&requests.last().unwrap().body).unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "deletes": [{"id": "r2"}, {"id": "r3"}],
+                "posts": [
+                    {"name": "www.example.org", "type": "A", "ttl": 300, "content": "192.0.2.3"},
+                    {"name": "example.org", "type": "CAA", "ttl": 300,
+                     "data": {"flags": 0, "tag": "issue", "value": "letsencrypt.org"}},
+                ],
+            })
+        );
+    }
+}
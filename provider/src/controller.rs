@@ -0,0 +1,442 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use futures::StreamExt;
+use k8s_openapi::serde_json::json;
+use kube::{
+    api::{Patch, PatchParams},
+    runtime::{controller::Action, Controller},
+    Api, Client, ResourceExt,
+};
+use kubizone_common::{Backoff, Metrics, Scope};
+use kubizone_crds::{
+    v1alpha1::{ProviderStatus, Zone, ZoneEntry},
+    PROVIDER_TAKEOVER_ANNOTATION,
+};
+use tracing::log::*;
+
+use crate::{
+    error::{Error, SUCCESS},
+    DnsProvider, Owner, Plan, ProviderError, RecordSet,
+};
+
+const CONTROLLER_NAME: &str = "kubi.zone/provider";
+
+/// How the [`controller`] synchronizes zones with its provider.
+pub struct Settings {
+    /// Identity of this installation, recorded in the ownership markers.
+    pub owner: String,
+    /// Only plan changes, without applying them.
+    pub dry_run: bool,
+    /// Interval at which zones are re-checked for drift.
+    pub requeue: Duration,
+}
+
+struct Data {
+    client: Client,
+    backoff: Backoff,
+    metrics: Arc<Metrics>,
+    provider: Arc<dyn DnsProvider>,
+    settings: Settings,
+    /// Zones hosted by the provider, along with when they were listed.
+    zones: Mutex<Option<(Instant, Vec<String>)>>,
+}
+
+impl Data {
+    /// Whether the provider hosts the zone `origin`. The hosted zones are
+    /// listed at most once every `requeue`, rather than for every zone.
+    async fn hosts(&self, origin: &str) -> Result<bool, ProviderError> {
+        let cached = self
+            .zones
+            .lock()
+            .unwrap()
+            .as_ref()
+            .filter(|(listed, _)| listed.elapsed() < self.settings.requeue)
+            .map(|(_, zones)| zones.clone());
+
+        let zones = match cached {
+            Some(zones) => zones,
+            None => {
+                let zones = self.provider.zones().await?;
+                *self.zones.lock().unwrap() = Some((Instant::now(), zones.clone()));
+                zones
+            }
+        };
+
+        Ok(zones
+            .iter()
+            .any(|hosted| hosted.eq_ignore_ascii_case(origin)))
+    }
+}
+
+/// Current time formatted for use in the [`ProviderStatus`].
+fn now() -> String {
+    time::OffsetDateTime::now_utc()
+        .format(&time::format_description::well_known::Rfc3339)
+        .unwrap_or_default()
+}
+
+/// Plan the changes needed to bring the record sets of the zone `origin` at
+/// the `provider` owned by `owner` in line with the zone's `entries`.
+pub async fn plan_zone(
+    provider: &dyn DnsProvider,
+    owner: &Owner,
+    origin: &str,
+    entries: &[ZoneEntry],
+) -> Result<Plan, ProviderError> {
+    let current = provider.list(origin).await?;
+    let desired = RecordSet::from_entries(origin, entries);
+
+    Ok(owner.plan(provider, origin, desired, current))
+}
+
+/// Apply the changes of the `plan` to the zone `origin` at the `provider`, in
+/// batches of at most [`DnsProvider::batch_size`] changes.
+///
+/// Application stops at the first batch which fails, leaving the batches
+/// applied before it in place.
+pub async fn apply_plan(
+    provider: &dyn DnsProvider,
+    origin: &str,
+    plan: &Plan,
+) -> Result<(), ProviderError> {
+    for batch in plan.batches(provider.batch_size()) {
+        provider.apply(origin, batch).await?;
+    }
+
+    Ok(())
+}
+
+async fn sync_zone(zone: &Zone, ctx: &Data) -> Result<(), Error> {
+    let (Some(origin), Some(serial)) = (zone.fqdn(), zone.serial()) else {
+        debug!("zone {zone} has not computed its fqdn and serial yet");
+        return Ok(());
+    };
+
+    // Unwrap safety: the zone has a serial, so it has a status.
+    let status = zone.status.as_ref().unwrap();
+
+    // The entries of expired secondary zones are gone, which must not be
+    // mistaken for all of the records having been removed.
+    if status
+        .secondary
+        .as_ref()
+        .is_some_and(|secondary| secondary.expired)
+    {
+        debug!("zone {zone} has expired, leaving it alone");
+        return Ok(());
+    }
+
+    let provider = ctx.provider.as_ref();
+    if !ctx.hosts(origin).await? {
+        debug!("zone {zone} is not hosted by {}", provider.name());
+        return Ok(());
+    }
+
+    let owner = Owner {
+        id: ctx.settings.owner.clone(),
+        zone: zone.to_string(),
+        takeover: zone
+            .annotations()
+            .get(PROVIDER_TAKEOVER_ANNOTATION)
+            .is_some_and(|takeover| takeover == "true"),
+    };
+
+    let plan = plan_zone(provider, &owner, origin, &status.entries).await;
+    let result = match &plan {
+        Ok(plan) if ctx.settings.dry_run || plan.changes.is_empty() => Ok(()),
+        Ok(plan) => apply_plan(provider, origin, plan).await,
+        Err(err) => Err(err.clone()),
+    };
+
+    let (drift, conflicts) = match &plan {
+        Ok(plan) => (
+            plan.changes.iter().map(ToString::to_string).collect(),
+            plan.conflicts.iter().map(ToString::to_string).collect(),
+        ),
+        Err(_) => (Vec::new(), Vec::new()),
+    };
+
+    // Planned changes have been applied, unless this is a dry run.
+    let in_sync = result.is_ok()
+        && plan.as_ref().is_ok_and(|plan| {
+            plan.conflicts.is_empty() && (plan.changes.is_empty() || !ctx.settings.dry_run)
+        });
+
+    match (&result, &plan) {
+        (Err(err), _) => warn!(
+            "failed to synchronize serial {serial} of zone {zone} with {}: {err}",
+            provider.name()
+        ),
+        (Ok(()), Ok(plan)) if !plan.changes.is_empty() => info!(
+            "{} {} changes to serial {serial} of zone {zone} to {}",
+            if ctx.settings.dry_run {
+                "planned"
+            } else {
+                "applied"
+            },
+            plan.changes.len(),
+            provider.name()
+        ),
+        _ => debug!("zone {zone} is in sync with {}", provider.name()),
+    }
+
+    let previous = status.providers.get(provider.name());
+    let status = ProviderStatus {
+        serial: if in_sync {
+            Some(serial)
+        } else {
+            previous.and_then(|previous| previous.serial)
+        },
+        in_sync,
+        dry_run: ctx.settings.dry_run,
+        drift,
+        conflicts,
+        last_check: Some(now()),
+        message: result.as_ref().err().map(ToString::to_string),
+    };
+
+    // Patching the status triggers another reconciliation, so checks which
+    // didn't change anything but the time are not recorded. Drift is still
+    // checked for every requeue.
+    let unchanged = previous.is_some_and(|previous| {
+        *previous
+            == ProviderStatus {
+                last_check: previous.last_check.clone(),
+                ..status.clone()
+            }
+    });
+    if unchanged {
+        return result.map_err(Error::Provider);
+    }
+
+    Api::<Zone>::namespaced(ctx.client.clone(), zone.namespace().as_ref().unwrap())
+        .patch_status(
+            &zone.name_any(),
+            &PatchParams::apply(CONTROLLER_NAME),
+            &Patch::Merge(json!({
+                "status": {
+                    "providers": {
+                        provider.name(): status,
+                    },
+                },
+            })),
+        )
+        .await?;
+
+    result.map_err(Error::Provider)
+}
+
+async fn reconcile(zone: Arc<Zone>, ctx: Arc<Data>) -> Result<Action, Error> {
+    let result = sync_zone(&zone, &ctx).await;
+
+    ctx.metrics.reconciled(
+        "provider",
+        result
+            .as_ref()
+            .err()
+            .map(Error::category)
+            .unwrap_or(SUCCESS),
+    );
+
+    result?;
+    ctx.backoff.reset(&zone.to_string());
+    Ok(Action::requeue(ctx.settings.requeue))
+}
+
+fn error_policy(zone: Arc<Zone>, error: &Error, ctx: Arc<Data>) -> Action {
+    let delay = if error.is_misconfiguration() {
+        ctx.backoff.misconfigured()
+    } else {
+        ctx.backoff.failure(&zone.to_string())
+    };
+
+    error!(
+        "synchronizing zone {} with {} encountered {} error: {error}, retrying in {}s",
+        zone.name_any(),
+        ctx.provider.name(),
+        error.category(),
+        delay.as_secs()
+    );
+    Action::requeue(delay)
+}
+
+/// Keep the record sets of each zone hosted by the `provider` in line with
+/// the zone's entries, reporting drift in the zone's `.status.providers`.
+///
+/// Zones are re-checked every [`Settings::requeue`], so changes made at the
+/// provider to record sets claimed by the owner are found and reverted. With
+/// [`Settings::dry_run`], changes are only reported.
+pub async fn controller(
+    client: Client,
+    scope: Scope,
+    backoff: Backoff,
+    metrics: Arc<Metrics>,
+    provider: Arc<dyn DnsProvider>,
+    settings: Settings,
+) {
+    let data = Arc::new(Data {
+        client: client.clone(),
+        backoff,
+        metrics,
+        provider,
+        settings,
+        zones: Mutex::new(None),
+    });
+
+    let controllers = scope.apis::<Zone>(&client).into_iter().map(|zones| {
+        Controller::new(zones, scope.watcher_config())
+            .shutdown_on_signal()
+            .run(reconcile, error_policy, data.clone())
+            .for_each(|res| async move {
+                match res {
+                    Ok(o) => debug!("synchronized {:?}", o),
+                    Err(e) => warn!("provider synchronization failed: {}", e),
+                }
+            })
+    });
+
+    futures::future::join_all(controllers).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use kubizone_crds::v1alpha1::ZoneEntry;
+
+    use crate::{Change, DnsProvider, InMemory, Owner, ProviderError, RecordSet};
+
+    use super::{apply_plan, plan_zone};
+
+    const ORIGIN: &str = "example.org.";
+
+    fn entry(fqdn: &str, type_: &str, rdata: &str) -> ZoneEntry {
+        ZoneEntry {
+            fqdn: fqdn.to_string(),
+            type_: type_.to_string(),
+            class: String::from("IN"),
+            ttl: 300,
+            rdata: rdata.to_string(),
+        }
+    }
+
+    fn set(name: &str, type_: &str, records: &[&str]) -> RecordSet {
+        RecordSet::new(ORIGIN, name, type_, 300, records)
+    }
+
+    fn provider() -> InMemory {
+        let provider = InMemory::new("memory", 2);
+        provider.insert_zone(
+            ORIGIN,
+            vec![
+                set(
+                    "@",
+                    "SOA",
+                    &["ns.provider.net. hostmaster.provider.net. 1 7200 3600 86400 300"],
+                ),
+                set("@", "NS", &["ns.provider.net."]),
+                set("www", "A", &["192.0.2.1"]),
+                set("old", "A", &["192.0.2.9"]),
+            ],
+        );
+        provider
+    }
+
+    /// Record sets of the zone at the provider, without ownership markers.
+    fn records(provider: &InMemory) -> Vec<RecordSet> {
+        let mut sets = provider.record_sets(ORIGIN).unwrap();
+        sets.retain(|set| !set.name.starts_with("_kubizone-"));
+        sets
+    }
+
+    #[tokio::test]
+    async fn synchronization() {
+        let provider = provider();
+        // The zone was managed by hand before, so its record sets are taken over.
+        let owner = Owner {
+            id: String::from("test"),
+            zone: String::from("default/example-org"),
+            takeover: true,
+        };
+        let entries = [
+            entry(
+                "example.org.",
+                "SOA",
+                "ns1.example.org. hostmaster.example.org. 5 7200 3600 86400 300",
+            ),
+            entry("example.org.", "NS", "ns1.example.org."),
+            entry("www.example.org.", "A", "192.0.2.2"),
+            entry("www.example.org.", "AAAA", "2001:db8::2"),
+            entry("mail.example.org.", "A", "192.0.2.3"),
+            entry("example.org.", "MX", "10 mail"),
+        ];
+
+        // Planning alone, as in a dry run, leaves the provider untouched.
+        let plan = plan_zone(&provider, &owner, ORIGIN, &entries)
+            .await
+            .unwrap();
+        // Five changes to the record sets, and four markers claiming them.
+        assert_eq!(plan.changes.len(), 9);
+        assert!(plan.conflicts.is_empty());
+        assert!(provider.batches().is_empty());
+
+        apply_plan(&provider, ORIGIN, &plan).await.unwrap();
+        assert_eq!(provider.batches(), [2, 2, 2, 2, 1]);
+
+        // The SOA and apex name servers of the provider are left alone.
+        assert_eq!(
+            records(&provider),
+            vec![
+                set("@", "MX", &["10 mail.example.org."]),
+                set("@", "NS", &["ns.provider.net."]),
+                set(
+                    "@",
+                    "SOA",
+                    &["ns.provider.net. hostmaster.provider.net. 1 7200 3600 86400 300"]
+                ),
+                set("mail", "A", &["192.0.2.3"]),
+                set("www", "A", &["192.0.2.2"]),
+                set("www", "AAAA", &["2001:db8::2"]),
+            ]
+        );
+
+        assert!(plan_zone(&provider, &owner, ORIGIN, &entries)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // Changes made at the provider by others show up as drift.
+        let mut drifted = provider.record_sets(ORIGIN).unwrap();
+        drifted.retain(|set| set.type_ != "AAAA");
+        drifted.push(set("extra", "TXT", &["hand-made"]));
+        provider.insert_zone(ORIGIN, drifted);
+
+        let plan = plan_zone(&provider, &owner, ORIGIN, &entries)
+            .await
+            .unwrap();
+        assert_eq!(
+            plan.changes,
+            vec![
+                Change::Delete(set("extra", "TXT", &["hand-made"])),
+                Change::Create(set("www", "AAAA", &["2001:db8::2"])),
+            ]
+        );
+
+        // Plans are only applied to the record sets they were made against.
+        provider.insert_zone(ORIGIN, vec![set("extra", "TXT", &["edited"])]);
+        assert!(matches!(
+            apply_plan(&provider, ORIGIN, &plan).await,
+            Err(ProviderError::Rejected(_))
+        ));
+        assert_eq!(
+            provider.record_sets(ORIGIN).unwrap(),
+            vec![set("extra", "TXT", &["edited"])]
+        );
+
+        assert_eq!(
+            provider.list("example.com.").await,
+            Err(ProviderError::UnknownZone(String::from("example.com.")))
+        );
+    }
+}
@@ -0,0 +1,45 @@
+use std::collections::BTreeMap;
+
+use k8s_openapi::api::core::v1::Secret;
+use kube::{Api, Client};
+
+use crate::Error;
+
+/// Credentials of a provider, read from the keys of a Secret.
+pub struct Credentials {
+    /// `namespace/name` of the Secret, for use in error messages.
+    secret: String,
+    values: BTreeMap<String, String>,
+}
+
+impl Credentials {
+    pub async fn from_secret(client: Client, namespace: &str, name: &str) -> Result<Self, Error> {
+        let secret = Api::<Secret>::namespaced(client, namespace)
+            .get_opt(name)
+            .await?
+            .ok_or_else(|| {
+                Error::Credentials(format!("secret {namespace}/{name} does not exist"))
+            })?;
+
+        let values = secret
+            .data
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(key, value)| (key, String::from_utf8_lossy(&value.0).trim().to_string()))
+            .collect();
+
+        Ok(Credentials {
+            secret: format!("{namespace}/{name}"),
+            values,
+        })
+    }
+
+    pub fn required(&self, key: &str) -> Result<String, Error> {
+        self.optional(key)
+            .ok_or_else(|| Error::Credentials(format!("secret {} has no key {key}", self.secret)))
+    }
+
+    pub fn optional(&self, key: &str) -> Option<String> {
+        self.values.get(key).cloned()
+    }
+}
@@ -0,0 +1,74 @@
+use std::fmt::Display;
+
+use crate::ProviderError;
+
+/// Result label of successful synchronizations in the metrics.
+pub const SUCCESS: &str = "Success";
+
+/// Errors encountered while synchronizing zones with a provider.
+#[derive(Debug)]
+pub enum Error {
+    /// Communication with the Kubernetes API failed. Usually transient.
+    Kube(kube::Error),
+    /// The provider failed to list or apply record sets.
+    Provider(ProviderError),
+    /// The credentials of the provider could not be read.
+    Credentials(String),
+}
+
+impl Error {
+    /// Short, CamelCase name of the kind of error.
+    pub fn category(&self) -> &'static str {
+        match self {
+            Error::Kube(_) => "KubeApi",
+            Error::Provider(ProviderError::UnknownZone(_)) => "UnknownZone",
+            Error::Provider(ProviderError::Rejected(_)) => "ProviderRejected",
+            Error::Provider(ProviderError::Unavailable(_)) => "ProviderUnavailable",
+            Error::Credentials(_) => "InvalidCredentials",
+        }
+    }
+
+    /// Whether the error is caused by the configuration of the operator or
+    /// the provider, such as invalid credentials, and therefore won't go away
+    /// by retrying sooner.
+    pub fn is_misconfiguration(&self) -> bool {
+        matches!(
+            self,
+            Error::Credentials(_)
+                | Error::Provider(ProviderError::UnknownZone(_))
+                | Error::Provider(ProviderError::Rejected(_))
+        )
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Kube(error) => write!(f, "kubernetes api error: {error}"),
+            Error::Provider(error) => write!(f, "provider error: {error}"),
+            Error::Credentials(reason) => write!(f, "invalid credentials: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Kube(error) => Some(error),
+            Error::Provider(error) => Some(error),
+            Error::Credentials(_) => None,
+        }
+    }
+}
+
+impl From<kube::Error> for Error {
+    fn from(error: kube::Error) -> Self {
+        Error::Kube(error)
+    }
+}
+
+impl From<ProviderError> for Error {
+    fn from(error: ProviderError) -> Self {
+        Error::Provider(error)
+    }
+}
@@ -0,0 +1,267 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use reqwest::{header::RETRY_AFTER, RequestBuilder, StatusCode};
+use tracing::log::*;
+
+use crate::ProviderError;
+
+/// Time to wait for each request to a provider to complete.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Upper limit on how long to wait before retrying a rate limited request,
+/// regardless of what the provider asks for.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// HTTP client shared by the providers, which paces requests to stay within
+/// the rate limits of the provider, and retries requests which exceed them anyway.
+pub(crate) struct Http {
+    client: reqwest::Client,
+    /// Minimum time between the start of consecutive requests.
+    interval: Duration,
+    /// Attempts made at each rate limited request before giving up.
+    attempts: u32,
+    /// Delay before retrying a rate limited request which doesn't say how long
+    /// to wait, doubling with every attempt.
+    delay: Duration,
+    /// Earliest time the next request may be sent.
+    next: Mutex<Instant>,
+}
+
+impl Http {
+    pub fn new(interval: Duration) -> Self {
+        Http {
+            // Unwrap safety: only fails if the TLS backend cannot be initialized.
+            client: reqwest::Client::builder().timeout(TIMEOUT).build().unwrap(),
+            interval,
+            attempts: 5,
+            delay: Duration::from_secs(1),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Change the delay before retrying rate limited requests.
+    #[cfg(test)]
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Wait for the next slot to send a request in.
+    async fn pace(&self) {
+        let slot = {
+            let mut next = self.next.lock().unwrap();
+            let slot = (*next).max(Instant::now());
+            *next = slot + self.interval;
+            slot
+        };
+
+        tokio::time::sleep_until(slot.into()).await;
+    }
+
+    /// Send the request produced by `request`, returning the body of the
+    /// response if successful.
+    ///
+    /// The request is built anew for every attempt, so it can be signed with
+    /// the current time. Responses with status `429 Too Many Requests`, or for
+    /// which `rate_limited` holds, are retried after the delay given by their
+    /// `Retry-After` header, if any.
+    pub async fn send(
+        &self,
+        request: impl Fn(&reqwest::Client) -> RequestBuilder,
+        rate_limited: impl Fn(StatusCode, &str) -> bool,
+    ) -> Result<String, ProviderError> {
+        let mut delay = self.delay;
+        let mut attempt = 0;
+
+        loop {
+            attempt += 1;
+            self.pace().await;
+
+            let response = request(&self.client)
+                .send()
+                .await
+                .map_err(|err| ProviderError::Unavailable(err.to_string()))?;
+
+            let status = response.status();
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse().ok())
+                .map(Duration::from_secs);
+
+            let url = response.url().clone();
+            let body = response
+                .text()
+                .await
+                .map_err(|err| ProviderError::Unavailable(err.to_string()))?;
+
+            if status.is_success() {
+                return Ok(body);
+            }
+
+            if status == StatusCode::TOO_MANY_REQUESTS || rate_limited(status, &body) {
+                if attempt >= self.attempts {
+                    return Err(ProviderError::Unavailable(format!(
+                        "{url} is still rate limited after {attempt} attempts"
+                    )));
+                }
+
+                let wait = retry_after.unwrap_or(delay).min(MAX_RETRY_DELAY);
+                debug!("{url} is rate limited, retrying in {}ms", wait.as_millis());
+                tokio::time::sleep(wait).await;
+
+                delay = delay.saturating_mul(2);
+                continue;
+            }
+
+            let message = format!("{url} responded with {status}: {}", body.trim());
+            return Err(if status.is_server_error() {
+                ProviderError::Unavailable(message)
+            } else {
+                ProviderError::Rejected(message)
+            });
+        }
+    }
+}
+
+/// Minimal HTTP server for testing the providers, answering each request with
+/// the response produced by a handler, and recording the requests it receives.
+#[cfg(test)]
+pub(crate) mod mock {
+    use std::{
+        collections::BTreeMap,
+        sync::{Arc, Mutex},
+    };
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    #[derive(Clone, Debug)]
+    pub struct Request {
+        pub method: String,
+        /// Path of the request, including the query string.
+        pub path: String,
+        /// Headers of the request, with lowercased names.
+        pub headers: BTreeMap<String, String>,
+        pub body: String,
+    }
+
+    pub struct Response {
+        pub status: u16,
+        pub headers: Vec<(&'static str, String)>,
+        pub body: String,
+    }
+
+    impl Response {
+        pub fn new(status: u16, body: impl Into<String>) -> Self {
+            Response {
+                status,
+                headers: Vec::new(),
+                body: body.into(),
+            }
+        }
+
+        pub fn header(mut self, name: &'static str, value: &str) -> Self {
+            self.headers.push((name, value.to_string()));
+            self
+        }
+    }
+
+    async fn read(stream: &mut tokio::net::TcpStream) -> Option<Request> {
+        let mut buffer = Vec::new();
+        let mut chunk = [0u8; 4096];
+
+        let head_end = loop {
+            if let Some(end) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+                break end;
+            }
+
+            let read = stream.read(&mut chunk).await.ok()?;
+            if read == 0 {
+                return None;
+            }
+            buffer.extend_from_slice(&chunk[..read]);
+        };
+
+        let head = String::from_utf8_lossy(&buffer[..head_end]).to_string();
+        let mut lines = head.lines();
+        let mut request_line = lines.next()?.split(' ');
+        let (method, path) = (request_line.next()?, request_line.next()?);
+
+        let headers: BTreeMap<_, _> = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+            .collect();
+
+        let length: usize = headers
+            .get("content-length")
+            .and_then(|length| length.parse().ok())
+            .unwrap_or_default();
+
+        let mut body = buffer[head_end + 4..].to_vec();
+        while body.len() < length {
+            let read = stream.read(&mut chunk).await.ok()?;
+            if read == 0 {
+                break;
+            }
+            body.extend_from_slice(&chunk[..read]);
+        }
+
+        Some(Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            headers,
+            body: String::from_utf8_lossy(&body).to_string(),
+        })
+    }
+
+    /// Serve requests on a local port using `handler`, returning the address
+    /// of the server and the requests it has received so far.
+    pub async fn serve(
+        handler: impl Fn(&Request) -> Response + Send + Sync + 'static,
+    ) -> (String, Arc<Mutex<Vec<Request>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        let handler = Arc::new(handler);
+
+        tokio::spawn(async move {
+            loop {
+                let Ok((mut stream, _)) = listener.accept().await else {
+                    continue;
+                };
+
+                let Some(request) = read(&mut stream).await else {
+                    continue;
+                };
+
+                let response = handler(&request);
+                received.lock().unwrap().push(request);
+
+                let mut head = format!(
+                    "HTTP/1.1 {} Mock\r\ncontent-length: {}\r\nconnection: close\r\n",
+                    response.status,
+                    response.body.len()
+                );
+                for (name, value) in &response.headers {
+                    head.push_str(&format!("{name}: {value}\r\n"));
+                }
+                head.push_str("\r\n");
+
+                let _ = stream.write_all(head.as_bytes()).await;
+                let _ = stream.write_all(response.body.as_bytes()).await;
+                let _ = stream.shutdown().await;
+            }
+        });
+
+        (address, requests)
+    }
+}
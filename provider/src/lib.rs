@@ -0,0 +1,115 @@
+//! Synchronizes the entries of kubizone [`Zone`](kubizone_crds::v1alpha1::Zone)s
+//! with DNS providers which are managed through an API rather than zone files,
+//! such as hosted DNS services.
+//!
+//! Providers implement [`DnsProvider`], and are kept in sync with the zones by
+//! the generic [`controller`]. Implementations are included for [`Route53`],
+//! [`Cloudflare`] and [`PowerDns`].
+//!
+//! Record sets are claimed through TXT markers at the provider, so only those
+//! created by the same [`Owner`] are ever changed or removed.
+
+use std::fmt::Display;
+
+use async_trait::async_trait;
+
+mod args;
+mod cloudflare;
+mod controller;
+mod credentials;
+mod error;
+mod http;
+mod memory;
+mod plan;
+mod powerdns;
+mod registry;
+mod route53;
+mod rrset;
+
+pub use args::{parse_secret_ref, ProviderArgs};
+pub use cloudflare::Cloudflare;
+pub use controller::{apply_plan, controller, plan_zone, Settings};
+pub use credentials::Credentials;
+pub use error::Error;
+pub use memory::InMemory;
+pub use plan::{Change, Plan};
+pub use powerdns::PowerDns;
+pub use registry::Owner;
+pub use route53::{AwsCredentials, Route53};
+pub use rrset::{absolute, normalize, within, RecordSet};
+
+/// Errors reported by [`DnsProvider`]s.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProviderError {
+    /// The provider does not host the zone.
+    UnknownZone(String),
+    /// The provider refused the request, such as due to invalid credentials,
+    /// or because the record sets changed since they were listed.
+    Rejected(String),
+    /// The provider could not be reached, or failed to respond. Usually transient.
+    Unavailable(String),
+}
+
+impl Display for ProviderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProviderError::UnknownZone(origin) => write!(f, "zone {origin} is not hosted"),
+            ProviderError::Rejected(reason) => write!(f, "request rejected: {reason}"),
+            ProviderError::Unavailable(reason) => write!(f, "provider unavailable: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for ProviderError {}
+
+/// DNS provider whose zones can be listed and modified one record set at a time.
+#[async_trait]
+pub trait DnsProvider: Send + Sync {
+    /// Name of the provider, such as `route53`, identifying it in the
+    /// `.status.providers` of zones.
+    fn name(&self) -> &str;
+
+    /// Fully qualified origins of the zones hosted by the provider.
+    async fn zones(&self) -> Result<Vec<String>, ProviderError>;
+
+    /// All record sets of the zone `origin`, as currently found at the provider.
+    async fn list(&self, origin: &str) -> Result<Vec<RecordSet>, ProviderError>;
+
+    /// Whether kubizone may update or delete the record set `set` of the zone
+    /// `origin`, which has been listed at the provider.
+    ///
+    /// Defaults to all record sets which are not [reserved](RecordSet::reserved)
+    /// for the provider itself.
+    fn owns(&self, origin: &str, set: &RecordSet) -> bool {
+        !set.reserved(origin)
+    }
+
+    /// Plan the changes bringing the `current` record sets of the zone `origin`
+    /// at the provider in line with the `desired` ones.
+    ///
+    /// Ownership markers are handled by [`Owner::plan`], which only passes on
+    /// the record sets it owns.
+    fn plan(&self, origin: &str, desired: Vec<RecordSet>, current: Vec<RecordSet>) -> Plan {
+        Plan::new(desired, current, |set| self.owns(origin, set))
+    }
+
+    /// Replace the credentials of the provider with those re-read from its
+    /// Secret, so rotated credentials are used without restarting.
+    ///
+    /// Defaults to keeping the credentials the provider was constructed with.
+    fn reload(&self, _credentials: &Credentials) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Maximum number of changes passed to a single call to [`DnsProvider::apply`].
+    fn batch_size(&self) -> usize {
+        100
+    }
+
+    /// Apply a batch of `changes` to the zone `origin`.
+    ///
+    /// Providers should fail the entire batch if any of the changes cannot be
+    /// applied, and refuse to update or delete record sets which no longer match
+    /// the [`Change::current`] record set.
+    async fn apply(&self, origin: &str, changes: &[Change]) -> Result<(), ProviderError>;
+}
@@ -0,0 +1,119 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use clap::Parser;
+use kube::Client;
+use kubizone_common::{metrics, Backoff, Metrics, ScopeArgs};
+use kubizone_provider::{controller, ProviderArgs, Settings};
+
+#[derive(Debug, Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Provider to synchronize zones with.
+    #[command(subcommand)]
+    pub provider: ProviderArgs,
+
+    #[command(flatten)]
+    common: CommonArgs,
+}
+
+#[derive(Debug, clap::Args)]
+struct CommonArgs {
+    #[command(flatten)]
+    scope: ScopeArgs,
+
+    /// Identity of this kubizone installation, such as the name of the cluster,
+    /// recorded in the TXT records marking the record sets it owns. Record sets
+    /// marked by other installations are never changed.
+    #[arg(long, default_value = "default", value_parser = parse_owner_id, global = true)]
+    owner_id: String,
+
+    /// Only plan changes and report them in the status of each zone,
+    /// without applying them.
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    /// Seconds between periodic checks of each zone for drift.
+    #[arg(long, default_value_t = 300, global = true)]
+    requeue: u64,
+
+    /// Seconds before retrying a failed synchronization for the first time.
+    /// The delay doubles with every consecutive failure.
+    #[arg(long, default_value_t = 5, global = true)]
+    backoff_base: u64,
+
+    /// Upper limit in seconds for the delay between retries of failed synchronizations.
+    #[arg(long, default_value_t = 300, global = true)]
+    backoff_max: u64,
+
+    /// Seconds before re-checking zones which cannot be synchronized without
+    /// user intervention, such as due to invalid credentials.
+    #[arg(long, default_value_t = 300, global = true)]
+    misconfigured_requeue: u64,
+
+    /// Address on which to serve Prometheus metrics, such as `0.0.0.0:9090`.
+    #[arg(long, global = true)]
+    metrics_address: Option<SocketAddr>,
+}
+
+fn parse_owner_id(value: &str) -> Result<String, String> {
+    if value.is_empty() || value.contains([',', '=', '"', '\\']) {
+        return Err(format!(
+            "owner id must be non-empty, without commas, equal signs, quotes or backslashes, got {value}"
+        ));
+    }
+
+    Ok(value.to_string())
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let args = Args::parse();
+
+    tracing_subscriber::fmt::init();
+    let client = Client::try_default().await.unwrap();
+
+    let common = args.common;
+    let provider = match args.provider.connect(&client).await {
+        Ok(provider) => provider,
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    };
+
+    let metrics = Arc::new(Metrics::default());
+    let metrics_server = {
+        let metrics = metrics.clone();
+        async move {
+            match common.metrics_address {
+                Some(address) => metrics::serve(address, metrics).await,
+                None => std::future::pending().await,
+            }
+        }
+    };
+
+    let controller = controller(
+        client.clone(),
+        common.scope.into_scope(),
+        Backoff::new(
+            Duration::from_secs(common.backoff_base),
+            Duration::from_secs(common.backoff_max),
+        )
+        .with_misconfigured(Duration::from_secs(common.misconfigured_requeue)),
+        metrics,
+        provider.clone(),
+        Settings {
+            owner: common.owner_id,
+            dry_run: common.dry_run,
+            requeue: Duration::from_secs(common.requeue),
+        },
+    );
+
+    // The controller only stops on shutdown, while reloading and serving
+    // metrics never stop.
+    tokio::select! {
+        _ = controller => {},
+        _ = args.provider.reload(&client, provider.as_ref()) => {},
+        _ = metrics_server => {},
+    }
+}
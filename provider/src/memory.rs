@@ -0,0 +1,110 @@
+use std::{collections::BTreeMap, sync::Mutex};
+
+use async_trait::async_trait;
+
+use crate::{Change, DnsProvider, ProviderError, RecordSet};
+
+/// Provider keeping its zones in memory, which is useful for testing, and for
+/// trying out planned changes.
+pub struct InMemory {
+    name: String,
+    batch_size: usize,
+    zones: Mutex<BTreeMap<String, Vec<RecordSet>>>,
+    /// Number of changes in each batch applied so far.
+    batches: Mutex<Vec<usize>>,
+}
+
+impl InMemory {
+    pub fn new(name: &str, batch_size: usize) -> Self {
+        InMemory {
+            name: name.to_string(),
+            batch_size,
+            zones: Mutex::default(),
+            batches: Mutex::default(),
+        }
+    }
+
+    /// Host the zone `origin`, replacing all of its record sets with `sets`.
+    pub fn insert_zone(&self, origin: &str, sets: Vec<RecordSet>) {
+        self.zones
+            .lock()
+            .unwrap()
+            .insert(origin.to_lowercase(), sets);
+    }
+
+    /// Record sets of the zone `origin`, sorted by name and type.
+    pub fn record_sets(&self, origin: &str) -> Option<Vec<RecordSet>> {
+        let mut sets = self
+            .zones
+            .lock()
+            .unwrap()
+            .get(&origin.to_lowercase())
+            .cloned()?;
+
+        sets.sort();
+        Some(sets)
+    }
+
+    /// Number of changes in each batch applied so far.
+    pub fn batches(&self) -> Vec<usize> {
+        self.batches.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl DnsProvider for InMemory {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn zones(&self) -> Result<Vec<String>, ProviderError> {
+        Ok(self.zones.lock().unwrap().keys().cloned().collect())
+    }
+
+    async fn list(&self, origin: &str) -> Result<Vec<RecordSet>, ProviderError> {
+        self.record_sets(origin)
+            .ok_or_else(|| ProviderError::UnknownZone(origin.to_string()))
+    }
+
+    fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    async fn apply(&self, origin: &str, changes: &[Change]) -> Result<(), ProviderError> {
+        let mut zones = self.zones.lock().unwrap();
+        let Some(zone) = zones.get(&origin.to_lowercase()) else {
+            return Err(ProviderError::UnknownZone(origin.to_string()));
+        };
+
+        // Changes are applied to a copy, so the batch either applies as a whole or not at all.
+        let mut sets = zone.clone();
+        for change in changes {
+            let (name, type_) = match change.desired().or(change.current()) {
+                Some(set) => (&set.name, &set.type_),
+                None => continue,
+            };
+
+            let position = sets
+                .iter()
+                .position(|set| &set.name == name && &set.type_ == type_);
+
+            match (position, change.current()) {
+                (None, None) => (),
+                (Some(position), Some(current)) if &sets[position] == current => {
+                    sets.remove(position);
+                }
+                _ => {
+                    return Err(ProviderError::Rejected(format!(
+                        "record set {name} {type_} has changed"
+                    )))
+                }
+            }
+
+            sets.extend(change.desired().cloned());
+        }
+
+        zones.insert(origin.to_lowercase(), sets);
+        self.batches.lock().unwrap().push(changes.len());
+        Ok(())
+    }
+}
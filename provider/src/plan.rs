@@ -0,0 +1,179 @@
+use std::{collections::BTreeMap, fmt::Display};
+
+use crate::rrset::RecordSet;
+
+/// Change to a single record set at a provider.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Change {
+    /// Create a record set which does not exist at the provider yet.
+    Create(RecordSet),
+    /// Replace the record set `from`, as currently found at the provider, with `to`.
+    Update { from: RecordSet, to: RecordSet },
+    /// Delete a record set which no longer exists in the zone.
+    Delete(RecordSet),
+}
+
+impl Change {
+    /// The record set as it should exist after the change, if at all.
+    pub fn desired(&self) -> Option<&RecordSet> {
+        match self {
+            Change::Create(set) | Change::Update { to: set, .. } => Some(set),
+            Change::Delete(_) => None,
+        }
+    }
+
+    /// The record set as it exists at the provider before the change, if at all.
+    pub fn current(&self) -> Option<&RecordSet> {
+        match self {
+            Change::Create(_) => None,
+            Change::Update { from: set, .. } | Change::Delete(set) => Some(set),
+        }
+    }
+
+    /// Deletions go first, so names freed up by them can be reused by creations
+    /// in the same plan, such as when replacing a CNAME with an A record.
+    fn order(&self) -> u8 {
+        match self {
+            Change::Delete(_) => 0,
+            Change::Update { .. } => 1,
+            Change::Create(_) => 2,
+        }
+    }
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Change::Create(set) => write!(f, "create {set}"),
+            Change::Update { from, to } => write!(f, "update {from} to {to}"),
+            Change::Delete(set) => write!(f, "delete {set}"),
+        }
+    }
+}
+
+/// Changes bringing the record sets at a provider in line with a zone.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Plan {
+    /// Changes to apply, with deletions first and creations last.
+    pub changes: Vec<Change>,
+
+    /// Record sets of the zone which could not be planned, because a record set
+    /// of the same name and type exists at the provider, but isn't owned by kubizone.
+    pub conflicts: Vec<RecordSet>,
+}
+
+impl Plan {
+    /// Plan the changes needed to go from the `current` record sets at the
+    /// provider to the `desired` ones.
+    ///
+    /// Only record sets for which `owns` holds are ever updated or deleted.
+    pub fn new(
+        desired: Vec<RecordSet>,
+        current: Vec<RecordSet>,
+        owns: impl Fn(&RecordSet) -> bool,
+    ) -> Self {
+        let key = |set: &RecordSet| (set.name.clone(), set.type_.clone());
+
+        let mut current: BTreeMap<_, _> = current.into_iter().map(|set| (key(&set), set)).collect();
+        let mut plan = Plan::default();
+
+        for set in desired {
+            match current.remove(&key(&set)) {
+                None => plan.changes.push(Change::Create(set)),
+                Some(existing) if !owns(&existing) => plan.conflicts.push(set),
+                Some(existing) if existing == set => (),
+                Some(existing) => plan.changes.push(Change::Update {
+                    from: existing,
+                    to: set,
+                }),
+            }
+        }
+
+        plan.changes.extend(
+            current
+                .into_values()
+                .filter(|set| owns(set))
+                .map(Change::Delete),
+        );
+
+        plan.changes.sort_by_key(Change::order);
+        plan
+    }
+
+    /// Add the changes and conflicts of `other` to this plan, keeping deletions
+    /// first and creations last.
+    pub fn extend(&mut self, other: Plan) {
+        self.changes.extend(other.changes);
+        self.changes.sort_by_key(Change::order);
+
+        self.conflicts.extend(other.conflicts);
+        self.conflicts.sort();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty() && self.conflicts.is_empty()
+    }
+
+    /// Split the changes into batches of at most `size` changes each.
+    pub fn batches(&self, size: usize) -> impl Iterator<Item = &[Change]> {
+        self.changes.chunks(size.max(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rrset::RecordSet;
+
+    use super::{Change, Plan};
+
+    fn set(name: &str, type_: &str, records: &[&str]) -> RecordSet {
+        RecordSet::new("example.org.", name, type_, 300, records)
+    }
+
+    #[test]
+    fn planning() {
+        let desired = vec![
+            set("www", "A", &["192.0.2.1"]),
+            set("www", "AAAA", &["2001:db8::1"]),
+            set("mail", "A", &["192.0.2.3"]),
+            set("foreign", "A", &["192.0.2.4"]),
+            set("ftp", "A", &["192.0.2.5"]),
+        ];
+
+        let current = vec![
+            set("www", "A", &["192.0.2.1"]),
+            set("mail", "A", &["192.0.2.2"]),
+            set("old", "CNAME", &["www"]),
+            set("foreign", "A", &["198.51.100.1"]),
+            set("other", "TXT", &["hand-made"]),
+        ];
+
+        let owned = |set: &RecordSet| {
+            !matches!(
+                set.name.as_str(),
+                "foreign.example.org." | "other.example.org."
+            )
+        };
+        let plan = Plan::new(desired, current, owned);
+
+        assert_eq!(
+            plan.changes,
+            vec![
+                Change::Delete(set("old", "CNAME", &["www"])),
+                Change::Update {
+                    from: set("mail", "A", &["192.0.2.2"]),
+                    to: set("mail", "A", &["192.0.2.3"]),
+                },
+                Change::Create(set("www", "AAAA", &["2001:db8::1"])),
+                Change::Create(set("ftp", "A", &["192.0.2.5"])),
+            ]
+        );
+        assert_eq!(plan.conflicts, vec![set("foreign", "A", &["192.0.2.4"])]);
+
+        let batches: Vec<_> = plan.batches(3).map(<[Change]>::len).collect();
+        assert_eq!(batches, [3, 1]);
+
+        let desired = vec![set("www", "A", &["192.0.2.1"])];
+        assert!(Plan::new(desired.clone(), desired, |_| true).is_empty());
+    }
+}
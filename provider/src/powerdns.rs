@@ -0,0 +1,343 @@
+use std::{sync::Mutex, time::Duration};
+
+use async_trait::async_trait;
+use k8s_openapi::serde_json::{self, json, Value};
+use reqwest::{Method, Url};
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::{http::Http, Change, Credentials, DnsProvider, Error, ProviderError, RecordSet};
+
+#[derive(Deserialize)]
+struct PowerDnsZone {
+    id: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct ZoneContents {
+    #[serde(default)]
+    rrsets: Vec<RRSet>,
+}
+
+#[derive(Deserialize)]
+struct RRSet {
+    name: String,
+    #[serde(rename = "type")]
+    type_: String,
+    ttl: u32,
+    #[serde(default)]
+    records: Vec<PowerDnsRecord>,
+}
+
+#[derive(Deserialize)]
+struct PowerDnsRecord {
+    content: String,
+    #[serde(default)]
+    disabled: bool,
+}
+
+/// Key of the PowerDNS API, read from the keys of a Secret.
+pub(crate) fn api_key(credentials: &Credentials) -> Result<String, Error> {
+    credentials.required("apiKey")
+}
+
+/// Zones of an authoritative [PowerDNS](https://doc.powerdns.com/authoritative/http-api/)
+/// server, managed through its HTTP API.
+///
+/// The API does not paginate, so zones are always listed in their entirety.
+pub struct PowerDns {
+    endpoint: Url,
+    server: String,
+    /// Replaced whenever the credentials are [reloaded](DnsProvider::reload).
+    api_key: Mutex<String>,
+    http: Http,
+}
+
+impl PowerDns {
+    /// The server `server`, usually `localhost`, of the PowerDNS API at `endpoint`,
+    /// such as `http://powerdns:8081`, authenticating with the `api_key`.
+    pub fn new(endpoint: Url, server: String, api_key: String) -> Self {
+        PowerDns {
+            endpoint,
+            server,
+            api_key: Mutex::new(api_key),
+            // PowerDNS does not rate limit its API, but proxies in front of it might.
+            http: Http::new(Duration::ZERO),
+        }
+    }
+
+    /// Url of the path made up of `segments` below the server's API.
+    fn url(&self, segments: &[&str]) -> Url {
+        let mut url = self.endpoint.clone();
+        // Unwrap safety: base urls of http(s) endpoints can always have paths.
+        url.path_segments_mut()
+            .unwrap()
+            .pop_if_empty()
+            .extend(["api", "v1", "servers", &self.server])
+            .extend(segments);
+        url
+    }
+
+    async fn request(
+        &self,
+        method: Method,
+        url: Url,
+        body: Option<&Value>,
+    ) -> Result<String, ProviderError> {
+        self.http
+            .send(
+                |client| {
+                    let request = client
+                        .request(method.clone(), url.clone())
+                        .header("X-API-Key", self.api_key.lock().unwrap().as_str());
+
+                    match body {
+                        Some(body) => request.json(body),
+                        None => request,
+                    }
+                },
+                |_, _| false,
+            )
+            .await
+    }
+
+    async fn get<T: DeserializeOwned>(&self, segments: &[&str]) -> Result<T, ProviderError> {
+        let body = self.request(Method::GET, self.url(segments), None).await?;
+        serde_json::from_str(&body).map_err(|err| {
+            ProviderError::Unavailable(format!("invalid response from powerdns: {err}"))
+        })
+    }
+
+    async fn zone_id(&self, origin: &str) -> Result<String, ProviderError> {
+        self.get::<Vec<PowerDnsZone>>(&["zones"])
+            .await?
+            .into_iter()
+            .find(|zone| zone.name.eq_ignore_ascii_case(origin))
+            .map(|zone| zone.id)
+            .ok_or_else(|| ProviderError::UnknownZone(origin.to_string()))
+    }
+
+    async fn record_sets(&self, origin: &str, id: &str) -> Result<Vec<RecordSet>, ProviderError> {
+        let zone: ZoneContents = self.get(&["zones", id]).await?;
+
+        Ok(zone
+            .rrsets
+            .into_iter()
+            .map(|rrset| {
+                let records = rrset
+                    .records
+                    .iter()
+                    .filter(|record| !record.disabled)
+                    .map(|record| record.content.as_str());
+
+                RecordSet::new(origin, &rrset.name, &rrset.type_, rrset.ttl, records)
+            })
+            .filter(|set| !set.records.is_empty())
+            .collect())
+    }
+}
+
+#[async_trait]
+impl DnsProvider for PowerDns {
+    fn name(&self) -> &str {
+        "powerdns"
+    }
+
+    async fn zones(&self) -> Result<Vec<String>, ProviderError> {
+        Ok(self
+            .get::<Vec<PowerDnsZone>>(&["zones"])
+            .await?
+            .into_iter()
+            .map(|zone| zone.name.to_lowercase())
+            .collect())
+    }
+
+    async fn list(&self, origin: &str) -> Result<Vec<RecordSet>, ProviderError> {
+        let id = self.zone_id(origin).await?;
+        self.record_sets(origin, &id).await
+    }
+
+    fn reload(&self, credentials: &Credentials) -> Result<(), Error> {
+        *self.api_key.lock().unwrap() = api_key(credentials)?;
+        Ok(())
+    }
+
+    fn batch_size(&self) -> usize {
+        1000
+    }
+
+    async fn apply(&self, origin: &str, changes: &[Change]) -> Result<(), ProviderError> {
+        let id = self.zone_id(origin).await?;
+
+        // PowerDNS has no way of making changes conditional, so the record sets
+        // are checked right before applying them instead.
+        let live = self.record_sets(origin, &id).await?;
+        for change in changes {
+            let (name, type_) = match change.current().or(change.desired()) {
+                Some(set) => (&set.name, &set.type_),
+                None => continue,
+            };
+
+            let current = live
+                .iter()
+                .find(|set| &set.name == name && &set.type_ == type_);
+
+            if current != change.current() {
+                return Err(ProviderError::Rejected(format!(
+                    "record set {name} {type_} has changed since it was listed"
+                )));
+            }
+        }
+
+        let rrsets: Vec<_> = changes
+            .iter()
+            .map(|change| match change {
+                Change::Create(set) | Change::Update { to: set, .. } => json!({
+                    "name": set.name,
+                    "type": set.type_,
+                    "ttl": set.ttl,
+                    "changetype": "REPLACE",
+                    "records": set.records.iter().map(|content| json!({
+                        "content": content,
+                        "disabled": false,
+                    })).collect::<Vec<_>>(),
+                }),
+                Change::Delete(set) => json!({
+                    "name": set.name,
+                    "type": set.type_,
+                    "changetype": "DELETE",
+                }),
+            })
+            .collect();
+
+        self.request(
+            Method::PATCH,
+            self.url(&["zones", &id]),
+            Some(&json!({ "rrsets": rrsets })),
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use k8s_openapi::serde_json::{self, json, Value};
+    use kubizone_crds::v1alpha1::ZoneEntry;
+    use reqwest::Url;
+
+    use crate::{
+        apply_plan,
+        http::mock::{serve, Response},
+        DnsProvider, ProviderError, RecordSet,
+    };
+
+    use super::PowerDns;
+
+    const ORIGIN: &str = "example.org.";
+
+    fn entry(fqdn: &str, type_: &str, rdata: &str) -> ZoneEntry {
+        ZoneEntry {
+            fqdn: fqdn.to_string(),
+            type_: type_.to_string(),
+            class: String::from("IN"),
+            ttl: 300,
+            rdata: rdata.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn powerdns() {
+        let zone = Arc::new(Mutex::new(json!({
+            "rrsets": [
+                {"name": "example.org.", "type": "SOA", "ttl": 3600, "records": [
+                    {"content": "ns1.example.org. hostmaster.example.org. 1 10800 3600 604800 3600", "disabled": false}
+                ]},
+                {"name": "www.example.org.", "type": "A", "ttl": 300, "records": [
+                    {"content": "192.0.2.1", "disabled": false},
+                    {"content": "192.0.2.9", "disabled": true}
+                ]},
+                {"name": "old.example.org.", "type": "CNAME", "ttl": 300, "records": [
+                    {"content": "www.example.org.", "disabled": false}
+                ]}
+            ]
+        })));
+        let contents = zone.clone();
+
+        let (address, requests) = serve(move |request| {
+            if request.headers.get("x-api-key").map(String::as_str) != Some("secret") {
+                return Response::new(401, "Unauthorized");
+            }
+
+            match (request.method.as_str(), request.path.as_str()) {
+                ("GET", "/api/v1/servers/localhost/zones") => Response::new(
+                    200,
+                    json!([
+                        {"id": "example.org.", "name": "example.org."},
+                        {"id": "=2Fexample.net.", "name": "example.net."},
+                    ])
+                    .to_string(),
+                ),
+                ("GET", "/api/v1/servers/localhost/zones/example.org.") => {
+                    Response::new(200, contents.lock().unwrap().to_string())
+                }
+                ("PATCH", "/api/v1/servers/localhost/zones/example.org.") => Response::new(204, ""),
+                _ => Response::new(404, "Not Found"),
+            }
+        })
+        .await;
+
+        let provider = PowerDns::new(
+            Url::parse(&address).unwrap(),
+            String::from("localhost"),
+            String::from("secret"),
+        );
+
+        assert_eq!(
+            provider.zones().await.unwrap(),
+            ["example.org.", "example.net."]
+        );
+
+        // Disabled records are not part of the record set.
+        let sets = provider.list(ORIGIN).await.unwrap();
+        assert!(sets.contains(&RecordSet::new(ORIGIN, "www", "A", 300, ["192.0.2.1"])));
+
+        let entries = [
+            entry("www.example.org.", "A", "192.0.2.1"),
+            entry("www.example.org.", "A", "192.0.2.2"),
+            entry("example.org.", "TXT", "\"hello world\""),
+        ];
+
+        let current = provider.list(ORIGIN).await.unwrap();
+        let plan = provider.plan(ORIGIN, RecordSet::from_entries(ORIGIN, &entries), current);
+        apply_plan(&provider, ORIGIN, &plan).await.unwrap();
+
+        let body: Value =
+            serde_json::from_str(&requests.lock().unwrap().last().unwrap().body).unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "rrsets": [
+                    {"name": "old.example.org.", "type": "CNAME", "changetype": "DELETE"},
+                    {"name": "www.example.org.", "type": "A", "ttl": 300, "changetype": "REPLACE",
+                     "records": [
+                        {"content": "192.0.2.1", "disabled": false},
+                        {"content": "192.0.2.2", "disabled": false},
+                     ]},
+                    {"name": "example.org.", "type": "TXT", "ttl": 300, "changetype": "REPLACE",
+                     "records": [{"content": "\"hello world\"", "disabled": false}]},
+                ]
+            })
+        );
+
+        // Plans made against record sets which have since changed are refused.
+        zone.lock().unwrap()["rrsets"][2]["records"][0]["content"] = json!("ftp.example.org.");
+        assert!(matches!(
+            apply_plan(&provider, ORIGIN, &plan).await,
+            Err(ProviderError::Rejected(_))
+        ));
+    }
+}
@@ -0,0 +1,315 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{Change, DnsProvider, Plan, RecordSet};
+
+/// Prefix of the names of the TXT record sets marking ownership.
+const MARKER_PREFIX: &str = "_kubizone-";
+
+/// Value of the `heritage` field of all markers written by kubizone.
+const HERITAGE: &str = "kubizone";
+
+/// Markers are only read by kubizone itself, so their TTL is of little concern.
+const MARKER_TTL: u32 = 300;
+
+/// Identity with which kubizone claims the record sets it manages at a provider.
+///
+/// Ownership of each record set is recorded in a TXT record set next to it,
+/// much like the TXT registry of external-dns. The record set `www.example.org.`
+/// of type `A` is marked by `_kubizone-a.www.example.org.`, holding
+/// `"heritage=kubizone,owner=<id>,zone=<namespace>/<name>"`.
+///
+/// Record sets marked by any other owner or zone are never updated or deleted,
+/// and neither are unmarked ones, unless [`Owner::takeover`] is set.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Owner {
+    /// Identifies the kubizone installation, such as the name of the cluster.
+    pub id: String,
+    /// `namespace/name` reference to the zone the record sets belong to.
+    pub zone: String,
+    /// Claim the record sets which are not marked as owned by anyone, such as
+    /// those created by hand before the zone was managed by kubizone. Unmarked
+    /// record sets which are not part of the zone are deleted.
+    pub takeover: bool,
+}
+
+/// Owner recorded by a marker found at the provider.
+#[derive(Debug, PartialEq, Eq)]
+struct Marker {
+    owner: String,
+    zone: String,
+}
+
+impl Marker {
+    /// Parse the marker record set `set`, if it is one.
+    fn parse(set: &RecordSet) -> Option<Self> {
+        if set.type_ != "TXT" || !set.name.starts_with(MARKER_PREFIX) {
+            return None;
+        }
+
+        let [record] = set.records.as_slice() else {
+            return None;
+        };
+
+        let fields: BTreeMap<_, _> = record
+            .trim_matches('"')
+            .split(',')
+            .filter_map(|field| field.split_once('='))
+            .collect();
+
+        if fields.get("heritage") != Some(&HERITAGE) {
+            return None;
+        }
+
+        Some(Marker {
+            owner: fields.get("owner")?.to_string(),
+            zone: fields.get("zone")?.to_string(),
+        })
+    }
+}
+
+impl Owner {
+    /// Name of the TXT record set marking the ownership of `set`.
+    ///
+    /// Wildcards are only allowed as the leftmost label, so the marker of
+    /// `*.example.org.` is `_kubizone-a._wildcard.example.org.`.
+    pub fn marker_name(set: &RecordSet) -> String {
+        let name = match set.name.strip_prefix("*.") {
+            Some(parent) => format!("_wildcard.{parent}"),
+            None => set.name.clone(),
+        };
+
+        format!("{MARKER_PREFIX}{}.{name}", set.type_.to_lowercase())
+    }
+
+    /// Whether `set` is a marker written by kubizone, rather than part of the zone.
+    pub fn is_marker(set: &RecordSet) -> bool {
+        Marker::parse(set).is_some()
+    }
+
+    /// Marker record set claiming ownership of the record set `set` of the zone `origin`.
+    pub fn marker(&self, origin: &str, set: &RecordSet) -> RecordSet {
+        RecordSet::new(
+            origin,
+            &Self::marker_name(set),
+            "TXT",
+            MARKER_TTL,
+            [format!(
+                "\"heritage={HERITAGE},owner={},zone={}\"",
+                self.id, self.zone
+            )],
+        )
+    }
+
+    fn claims(&self, marker: &Marker) -> bool {
+        marker.owner == self.id && marker.zone == self.zone
+    }
+
+    /// Plan the changes bringing the `current` record sets of the zone `origin`
+    /// at the `provider` in line with the `desired` ones, touching only the
+    /// record sets owned by this owner, and maintaining their markers.
+    ///
+    /// Desired record sets which collide with ones owned by others, or with
+    /// unmarked ones when not taking over, are reported as conflicts.
+    pub fn plan(
+        &self,
+        provider: &dyn DnsProvider,
+        origin: &str,
+        desired: Vec<RecordSet>,
+        current: Vec<RecordSet>,
+    ) -> Plan {
+        let (markers, current): (Vec<_>, Vec<_>) = current.into_iter().partition(Self::is_marker);
+
+        // Whether the marker of each name claims it for this owner.
+        let claimed: BTreeMap<String, bool> = markers
+            .iter()
+            .filter_map(|set| Some((set.name.clone(), self.claims(&Marker::parse(set)?))))
+            .collect();
+
+        let ownership = |set: &RecordSet| claimed.get(&Self::marker_name(set)).copied();
+        let manages = |set: &RecordSet| ownership(set).unwrap_or(self.takeover);
+
+        // Record sets which are not ours are kept from the provider's plan
+        // altogether, so they are neither updated nor deleted.
+        let (current, foreign): (Vec<_>, Vec<_>) = current.into_iter().partition(manages);
+
+        let foreign: BTreeSet<_> = foreign
+            .iter()
+            .map(|set| (set.name.as_str(), set.type_.as_str()))
+            .collect();
+
+        // Markers of others are respected even when their record set is gone.
+        let (desired, conflicts): (Vec<_>, Vec<_>) = desired.into_iter().partition(|set| {
+            !foreign.contains(&(set.name.as_str(), set.type_.as_str()))
+                && ownership(set) != Some(false)
+        });
+
+        let mut plan = provider.plan(origin, desired.clone(), current);
+        plan.conflicts.extend(conflicts);
+
+        let claims = desired
+            .iter()
+            .filter(|set| !plan.conflicts.contains(set))
+            .map(|set| self.marker(origin, set))
+            .collect();
+
+        plan.extend(Plan::new(claims, markers, |marker| {
+            claimed.get(&marker.name) == Some(&true)
+        }));
+
+        // Plans are applied in batches, of which any may fail. Markers are
+        // therefore created before the record sets they claim, and deleted
+        // after them, so a record set of ours is never left unmarked, which
+        // would disown it. A marker left without its record set is still
+        // ours, and completed or removed by the next plan.
+        plan.changes
+            .sort_by_key(|change| matches!(change, Change::Create(set) if !Self::is_marker(set)));
+
+        plan
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Change, InMemory, RecordSet};
+
+    use super::Owner;
+
+    const ORIGIN: &str = "example.org.";
+
+    fn set(name: &str, type_: &str, records: &[&str]) -> RecordSet {
+        RecordSet::new(ORIGIN, name, type_, 300, records)
+    }
+
+    fn owner(id: &str, zone: &str) -> Owner {
+        Owner {
+            id: id.to_string(),
+            zone: zone.to_string(),
+            takeover: false,
+        }
+    }
+
+    #[test]
+    fn markers() {
+        let owner = owner("cluster-a", "default/example-org");
+
+        assert_eq!(
+            owner.marker(ORIGIN, &set("www", "AAAA", &["2001:db8::1"])),
+            set(
+                "_kubizone-aaaa.www",
+                "TXT",
+                &["\"heritage=kubizone,owner=cluster-a,zone=default/example-org\""]
+            )
+        );
+
+        assert_eq!(
+            Owner::marker_name(&set("*.dev", "CNAME", &["www"])),
+            "_kubizone-cname._wildcard.dev.example.org."
+        );
+    }
+
+    #[test]
+    fn ownership() {
+        let provider = InMemory::new("memory", 100);
+        let ours = owner("cluster-a", "default/example-org");
+        let others = owner("cluster-b", "default/example-org");
+
+        let mut current = vec![
+            set("www", "A", &["192.0.2.1"]),
+            set("old", "A", &["192.0.2.9"]),
+            set("shared", "A", &["198.51.100.1"]),
+            set("manual", "TXT", &["hand-made"]),
+            set("stale", "TXT", &["gone"]),
+        ];
+        current.extend([
+            ours.marker(ORIGIN, &set("www", "A", &[])),
+            ours.marker(ORIGIN, &set("old", "A", &[])),
+            others.marker(ORIGIN, &set("shared", "A", &[])),
+            others.marker(ORIGIN, &set("taken", "A", &[])),
+        ]);
+
+        let desired = vec![
+            set("www", "A", &["192.0.2.2"]),
+            set("shared", "A", &["192.0.2.3"]),
+            set("manual", "TXT", &["replaced"]),
+            set("taken", "A", &["192.0.2.4"]),
+            set("new", "A", &["192.0.2.5"]),
+        ];
+
+        // Record sets of others are left alone, as are unmarked ones.
+        let plan = ours.plan(&provider, ORIGIN, desired.clone(), current.clone());
+        assert_eq!(
+            plan.changes,
+            vec![
+                Change::Delete(set("old", "A", &["192.0.2.9"])),
+                Change::Delete(ours.marker(ORIGIN, &set("old", "A", &[]))),
+                Change::Update {
+                    from: set("www", "A", &["192.0.2.1"]),
+                    to: set("www", "A", &["192.0.2.2"]),
+                },
+                Change::Create(ours.marker(ORIGIN, &set("new", "A", &[]))),
+                Change::Create(set("new", "A", &["192.0.2.5"])),
+            ]
+        );
+        assert_eq!(
+            plan.conflicts,
+            vec![
+                set("manual", "TXT", &["replaced"]),
+                set("shared", "A", &["192.0.2.3"]),
+                set("taken", "A", &["192.0.2.4"]),
+            ]
+        );
+
+        // The same cluster managing another zone of the same name is foreign too.
+        let other_zone = owner("cluster-a", "other/example-org");
+        let plan = other_zone.plan(&provider, ORIGIN, Vec::new(), current.clone());
+        assert!(plan.is_empty());
+
+        // Taking over claims unmarked record sets, deleting those not in the
+        // zone, but still leaves record sets owned by others alone.
+        let takeover = Owner {
+            takeover: true,
+            ..ours.clone()
+        };
+        let plan = takeover.plan(&provider, ORIGIN, desired, current);
+        assert_eq!(
+            plan.changes,
+            vec![
+                Change::Delete(set("old", "A", &["192.0.2.9"])),
+                Change::Delete(set("stale", "TXT", &["gone"])),
+                Change::Delete(ours.marker(ORIGIN, &set("old", "A", &[]))),
+                Change::Update {
+                    from: set("www", "A", &["192.0.2.1"]),
+                    to: set("www", "A", &["192.0.2.2"]),
+                },
+                Change::Update {
+                    from: set("manual", "TXT", &["hand-made"]),
+                    to: set("manual", "TXT", &["replaced"]),
+                },
+                Change::Create(ours.marker(ORIGIN, &set("manual", "TXT", &[]))),
+                Change::Create(ours.marker(ORIGIN, &set("new", "A", &[]))),
+                Change::Create(set("new", "A", &["192.0.2.5"])),
+            ]
+        );
+        assert_eq!(
+            plan.conflicts,
+            vec![
+                set("shared", "A", &["192.0.2.3"]),
+                set("taken", "A", &["192.0.2.4"]),
+            ]
+        );
+
+        // A marker whose record set was never created is still ours, after
+        // a later batch of the plan failed.
+        let marker = ours.marker(ORIGIN, &set("new", "A", &[]));
+        let plan = ours.plan(
+            &provider,
+            ORIGIN,
+            vec![set("new", "A", &["192.0.2.5"])],
+            vec![marker],
+        );
+        assert_eq!(
+            plan.changes,
+            vec![Change::Create(set("new", "A", &["192.0.2.5"]))]
+        );
+    }
+}
@@ -0,0 +1,807 @@
+use std::{collections::BTreeMap, sync::Mutex, time::Duration};
+
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use reqwest::{Method, StatusCode, Url};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::{http::Http, Change, Credentials, DnsProvider, Error, ProviderError, RecordSet};
+
+const API_VERSION: &str = "2013-04-01";
+
+/// Route53 is a global service, whose requests are signed for this region.
+const REGION: &str = "us-east-1";
+
+const SERVICE: &str = "route53";
+
+/// Requests per second allowed by Route53 for each account.
+const REQUESTS_PER_SECOND: u32 = 5;
+
+/// Prefix of the records standing in for alias records and records with a
+/// routing policy. Kubizone cannot represent those, so it never owns them.
+const ROUTING_PREFIX: &str = "route53:";
+
+/// AWS credentials, as found in the keys `accessKeyId`, `secretAccessKey` and
+/// optionally `sessionToken` of a Secret.
+#[derive(Clone)]
+pub struct AwsCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+impl AwsCredentials {
+    /// Read the AWS credentials from the keys of a Secret.
+    pub fn from_credentials(credentials: &Credentials) -> Result<Self, Error> {
+        Ok(AwsCredentials {
+            access_key_id: credentials.required("accessKeyId")?,
+            secret_access_key: credentials.required("secretAccessKey")?,
+            session_token: credentials.optional("sessionToken"),
+        })
+    }
+}
+
+/// Record set as listed by Route53, which must be passed back verbatim when
+/// deleting it.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ResourceRecordSet {
+    name: String,
+    #[serde(rename = "Type")]
+    type_: String,
+    #[serde(rename = "TTL", default)]
+    ttl: u32,
+    #[serde(default)]
+    resource_records: Option<ResourceRecords>,
+    #[serde(default)]
+    alias_target: Option<AliasTarget>,
+    #[serde(default)]
+    set_identifier: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct ResourceRecords {
+    #[serde(rename = "ResourceRecord", default)]
+    records: Vec<ResourceRecord>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ResourceRecord {
+    value: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct AliasTarget {
+    #[serde(rename = "DNSName")]
+    dns_name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListResourceRecordSetsResponse {
+    resource_record_sets: ResourceRecordSets,
+    is_truncated: bool,
+    #[serde(default)]
+    next_record_name: Option<String>,
+    #[serde(default)]
+    next_record_type: Option<String>,
+    #[serde(default)]
+    next_record_identifier: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ResourceRecordSets {
+    #[serde(rename = "ResourceRecordSet", default)]
+    sets: Vec<ResourceRecordSet>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListHostedZonesResponse {
+    hosted_zones: HostedZones,
+    is_truncated: bool,
+    #[serde(default)]
+    next_marker: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct HostedZones {
+    #[serde(rename = "HostedZone", default)]
+    zones: Vec<HostedZone>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct HostedZone {
+    id: String,
+    name: String,
+}
+
+/// [AWS Route53](https://aws.amazon.com/route53/) hosted zones.
+pub struct Route53 {
+    endpoint: Url,
+    /// Replaced whenever the credentials are [reloaded](DnsProvider::reload),
+    /// since session tokens expire.
+    credentials: Mutex<AwsCredentials>,
+    http: Http,
+    /// Hosted zone ids, keyed by the lowercased origin of the zone.
+    zones: Mutex<BTreeMap<String, String>>,
+    /// Record sets of each zone as last listed by Route53, keyed by the
+    /// lowercased origin of the zone and the normalized form of the set.
+    listed: Mutex<BTreeMap<String, BTreeMap<RecordSet, ResourceRecordSet>>>,
+}
+
+impl Route53 {
+    /// Route53 API at `endpoint`, usually `https://route53.amazonaws.com`.
+    pub fn new(endpoint: Url, credentials: AwsCredentials) -> Self {
+        Route53 {
+            endpoint,
+            credentials: Mutex::new(credentials),
+            http: Http::new(Duration::from_secs(1) / REQUESTS_PER_SECOND),
+            zones: Mutex::default(),
+            listed: Mutex::default(),
+        }
+    }
+
+    #[cfg(test)]
+    fn with_http(mut self, http: Http) -> Self {
+        self.http = http;
+        self
+    }
+
+    async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(&str, &str)],
+        body: String,
+    ) -> Result<String, ProviderError> {
+        let mut url = self.endpoint.join(path).map_err(|err| {
+            ProviderError::Rejected(format!("invalid endpoint {}: {err}", self.endpoint))
+        })?;
+        if !query.is_empty() {
+            url.query_pairs_mut().extend_pairs(query);
+        }
+
+        self.http
+            .send(
+                |client| {
+                    let headers = signed_headers(
+                        &self.credentials.lock().unwrap(),
+                        method.as_str(),
+                        &url,
+                        body.as_bytes(),
+                        &timestamp(),
+                    );
+
+                    headers
+                        .into_iter()
+                        .filter(|(name, _)| name != "host")
+                        .fold(
+                            client.request(method.clone(), url.clone()),
+                            |request, (name, value)| request.header(name, value),
+                        )
+                        .body(body.clone())
+                },
+                |status, body| {
+                    status == StatusCode::BAD_REQUEST
+                        && (body.contains("<Code>Throttling</Code>")
+                            || body.contains("<Code>PriorRequestNotComplete</Code>"))
+                },
+            )
+            .await
+    }
+
+    async fn zone_id(&self, origin: &str) -> Result<String, ProviderError> {
+        if let Some(id) = self.zones.lock().unwrap().get(&origin.to_lowercase()) {
+            return Ok(id.clone());
+        }
+
+        self.zones().await?;
+        self.zones
+            .lock()
+            .unwrap()
+            .get(&origin.to_lowercase())
+            .cloned()
+            .ok_or_else(|| ProviderError::UnknownZone(origin.to_string()))
+    }
+}
+
+fn invalid_response(err: impl std::fmt::Display) -> ProviderError {
+    ProviderError::Unavailable(format!("invalid response from route53: {err}"))
+}
+
+/// Decode the `\DDD` octal escapes Route53 uses for special characters in names, such as `\052` for `*`.
+fn unescape(name: &str) -> String {
+    let mut output = String::with_capacity(name.len());
+    let mut rest = name;
+
+    while let Some(position) = rest.find('\\') {
+        output.push_str(&rest[..position]);
+        let escape = rest.get(position + 1..position + 4);
+
+        match escape.and_then(|digits| u8::from_str_radix(digits, 8).ok()) {
+            Some(byte) => {
+                output.push(byte as char);
+                rest = &rest[position + 4..];
+            }
+            None => {
+                output.push('\\');
+                rest = &rest[position + 1..];
+            }
+        }
+    }
+
+    output.push_str(rest);
+    output
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+impl ResourceRecordSet {
+    fn from_record_set(set: &RecordSet) -> Self {
+        ResourceRecordSet {
+            name: set.name.clone(),
+            type_: set.type_.clone(),
+            ttl: set.ttl,
+            resource_records: Some(ResourceRecords {
+                records: set
+                    .records
+                    .iter()
+                    .map(|value| ResourceRecord {
+                        value: value.clone(),
+                    })
+                    .collect(),
+            }),
+            alias_target: None,
+            set_identifier: None,
+        }
+    }
+
+    fn xml(&self) -> String {
+        let records: String = self
+            .resource_records
+            .iter()
+            .flat_map(|records| &records.records)
+            .map(|record| {
+                format!(
+                    "<ResourceRecord><Value>{}</Value></ResourceRecord>",
+                    escape_xml(&record.value)
+                )
+            })
+            .collect();
+
+        format!(
+            "<ResourceRecordSet><Name>{}</Name><Type>{}</Type><TTL>{}</TTL>\
+             <ResourceRecords>{records}</ResourceRecords></ResourceRecordSet>",
+            escape_xml(&self.name),
+            escape_xml(&self.type_),
+            self.ttl
+        )
+    }
+}
+
+/// Body of a `ChangeResourceRecordSets` request applying the `changes`.
+///
+/// Updates delete the record set as it was listed before creating the new one,
+/// so the batch fails if the record set has been changed since.
+fn change_batch(changes: &[Change], listed: &BTreeMap<RecordSet, ResourceRecordSet>) -> String {
+    let change = |action: &str, set: &ResourceRecordSet| {
+        format!("<Change><Action>{action}</Action>{}</Change>", set.xml())
+    };
+
+    let mut body = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+         <ChangeResourceRecordSetsRequest xmlns=\"https://route53.amazonaws.com/doc/{API_VERSION}/\">\
+         <ChangeBatch><Comment>kubizone</Comment><Changes>"
+    );
+
+    for item in changes {
+        if let Some(current) = item.current() {
+            let listed = listed
+                .get(current)
+                .cloned()
+                .unwrap_or_else(|| ResourceRecordSet::from_record_set(current));
+            body.push_str(&change("DELETE", &listed));
+        }
+
+        if let Some(desired) = item.desired() {
+            body.push_str(&change(
+                "CREATE",
+                &ResourceRecordSet::from_record_set(desired),
+            ));
+        }
+    }
+
+    body.push_str("</Changes></ChangeBatch></ChangeResourceRecordSetsRequest>");
+    body
+}
+
+#[async_trait]
+impl DnsProvider for Route53 {
+    fn name(&self) -> &str {
+        "route53"
+    }
+
+    async fn zones(&self) -> Result<Vec<String>, ProviderError> {
+        let mut zones = BTreeMap::new();
+        let mut marker = None;
+
+        loop {
+            let query: Vec<_> = marker
+                .as_deref()
+                .map(|marker| ("marker", marker))
+                .into_iter()
+                .collect();
+
+            let body = self
+                .request(
+                    Method::GET,
+                    &format!("/{API_VERSION}/hostedzone"),
+                    &query,
+                    String::new(),
+                )
+                .await?;
+
+            let response: ListHostedZonesResponse =
+                quick_xml::de::from_str(&body).map_err(invalid_response)?;
+
+            for zone in response.hosted_zones.zones {
+                let id = zone.id.trim_start_matches("/hostedzone/").to_string();
+                zones.insert(unescape(&zone.name).to_lowercase(), id);
+            }
+
+            match response.next_marker {
+                Some(next) if response.is_truncated => marker = Some(next),
+                _ => break,
+            }
+        }
+
+        // Forget the record sets of zones which are no longer hosted.
+        self.listed
+            .lock()
+            .unwrap()
+            .retain(|origin, _| zones.contains_key(origin));
+
+        let origins = zones.keys().cloned().collect();
+        *self.zones.lock().unwrap() = zones;
+        Ok(origins)
+    }
+
+    async fn list(&self, origin: &str) -> Result<Vec<RecordSet>, ProviderError> {
+        let id = self.zone_id(origin).await?;
+        let path = format!("/{API_VERSION}/hostedzone/{id}/rrset");
+
+        let mut listed: Vec<ResourceRecordSet> = Vec::new();
+        let mut next: Option<(String, String, Option<String>)> = None;
+
+        loop {
+            let mut query = vec![("maxitems", "300")];
+            if let Some((name, type_, identifier)) = &next {
+                query.push(("name", name));
+                query.push(("type", type_));
+                if let Some(identifier) = identifier {
+                    query.push(("identifier", identifier));
+                }
+            }
+
+            let body = self
+                .request(Method::GET, &path, &query, String::new())
+                .await?;
+
+            let response: ListResourceRecordSetsResponse =
+                quick_xml::de::from_str(&body).map_err(invalid_response)?;
+            listed.extend(response.resource_record_sets.sets);
+
+            match (response.next_record_name, response.next_record_type) {
+                (Some(name), Some(type_)) if response.is_truncated => {
+                    next = Some((name, type_, response.next_record_identifier))
+                }
+                _ => break,
+            }
+        }
+
+        // Alias records and records with a routing policy are grouped into
+        // a single record set per name and type, which kubizone won't touch.
+        let mut routed: BTreeMap<(String, String), Vec<String>> = BTreeMap::new();
+        let mut sets = Vec::new();
+        let mut cache = BTreeMap::new();
+
+        for set in listed {
+            let name = unescape(&set.name);
+
+            if set.alias_target.is_some() || set.set_identifier.is_some() {
+                let description = match (&set.alias_target, &set.set_identifier) {
+                    (Some(alias), _) => format!("{ROUTING_PREFIX}alias {}", alias.dns_name),
+                    (None, identifier) => format!(
+                        "{ROUTING_PREFIX}set-identifier {}",
+                        identifier.as_deref().unwrap_or_default()
+                    ),
+                };
+
+                routed
+                    .entry((name, set.type_.clone()))
+                    .or_default()
+                    .push(description);
+                continue;
+            }
+
+            let values = set
+                .resource_records
+                .iter()
+                .flat_map(|records| &records.records)
+                .map(|record| record.value.as_str());
+
+            let normalized = RecordSet::new(origin, &name, &set.type_, set.ttl, values);
+            cache.insert(normalized.clone(), set);
+            sets.push(normalized);
+        }
+
+        sets.extend(routed.into_iter().map(|((name, type_), records)| {
+            let mut set = RecordSet::new(origin, &name, &type_, 0, Vec::<String>::new());
+            set.records = records;
+            set
+        }));
+
+        self.listed
+            .lock()
+            .unwrap()
+            .insert(origin.to_lowercase(), cache);
+
+        Ok(sets)
+    }
+
+    fn owns(&self, origin: &str, set: &RecordSet) -> bool {
+        !set.reserved(origin)
+            && !set
+                .records
+                .iter()
+                .any(|record| record.starts_with(ROUTING_PREFIX))
+    }
+
+    fn reload(&self, credentials: &Credentials) -> Result<(), Error> {
+        *self.credentials.lock().unwrap() = AwsCredentials::from_credentials(credentials)?;
+        Ok(())
+    }
+
+    fn batch_size(&self) -> usize {
+        // Updates take two changes each, well within the limit of 1000 per request.
+        100
+    }
+
+    async fn apply(&self, origin: &str, changes: &[Change]) -> Result<(), ProviderError> {
+        let id = self.zone_id(origin).await?;
+        let body = change_batch(
+            changes,
+            self.listed
+                .lock()
+                .unwrap()
+                .get(&origin.to_lowercase())
+                .unwrap_or(&BTreeMap::new()),
+        );
+
+        self.request(
+            Method::POST,
+            &format!("/{API_VERSION}/hostedzone/{id}/rrset"),
+            &[],
+            body,
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Current time, formatted as expected by the `x-amz-date` header.
+fn timestamp() -> String {
+    let now = time::OffsetDateTime::now_utc();
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        now.year(),
+        now.month() as u8,
+        now.day(),
+        now.hour(),
+        now.minute(),
+        now.second()
+    )
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    // Unwrap safety: HMAC accepts keys of any length.
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encode everything but the unreserved characters, as required by
+/// the canonical query string.
+fn uri_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+/// Headers authenticating a request to `url` using
+/// [Signature Version 4](https://docs.aws.amazon.com/IAM/latest/UserGuide/reference_aws-signing.html),
+/// including the `host` and `x-amz-date` headers which are part of the signature.
+fn signed_headers(
+    credentials: &AwsCredentials,
+    method: &str,
+    url: &Url,
+    body: &[u8],
+    timestamp: &str,
+) -> Vec<(String, String)> {
+    let host = match url.port() {
+        Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+        None => url.host_str().unwrap_or_default().to_string(),
+    };
+
+    let mut headers = vec![
+        (String::from("host"), host),
+        (String::from("x-amz-date"), timestamp.to_string()),
+    ];
+    if let Some(token) = &credentials.session_token {
+        headers.push((String::from("x-amz-security-token"), token.clone()));
+    }
+    headers.sort();
+
+    let mut query: Vec<_> = url
+        .query_pairs()
+        .map(|(key, value)| (uri_encode(&key), uri_encode(&value)))
+        .collect();
+    query.sort();
+
+    let canonical_query = query
+        .iter()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect::<Vec<_>>()
+        .join("&");
+
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+        .collect();
+
+    let signed = headers
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>()
+        .join(";");
+
+    let canonical_request = format!(
+        "{method}\n{}\n{canonical_query}\n{canonical_headers}\n{signed}\n{}",
+        url.path(),
+        hex(&Sha256::digest(body))
+    );
+
+    let date = &timestamp[..8];
+    let scope = format!("{date}/{REGION}/{SERVICE}/aws4_request");
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{timestamp}\n{scope}\n{}",
+        hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+
+    let key = [date, REGION, SERVICE, "aws4_request"].into_iter().fold(
+        format!("AWS4{}", credentials.secret_access_key).into_bytes(),
+        |key, data| hmac(&key, data),
+    );
+
+    headers.push((
+        String::from("authorization"),
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed}, Signature={}",
+            credentials.access_key_id,
+            hex(&hmac(&key, &string_to_sign))
+        ),
+    ));
+
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use kubizone_crds::v1alpha1::ZoneEntry;
+    use reqwest::Url;
+
+    use crate::{
+        apply_plan,
+        http::{
+            mock::{serve, Response},
+            Http,
+        },
+        DnsProvider, RecordSet,
+    };
+
+    use super::{signed_headers, unescape, AwsCredentials, Route53};
+
+    const ORIGIN: &str = "example.org.";
+
+    fn credentials() -> AwsCredentials {
+        AwsCredentials {
+            access_key_id: String::from("AKIDEXAMPLE"),
+            secret_access_key: String::from("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY"),
+            session_token: None,
+        }
+    }
+
+    fn entry(fqdn: &str, type_: &str, rdata: &str) -> ZoneEntry {
+        ZoneEntry {
+            fqdn: fqdn.to_string(),
+            type_: type_.to_string(),
+            class: String::from("IN"),
+            ttl: 300,
+            rdata: rdata.to_string(),
+        }
+    }
+
+    #[test]
+    fn signing() {
+        // The get-vanilla example of the AWS Signature Version 4 test suite,
+        // signed for the route53 service rather than the generic one.
+        let url = Url::parse("https://example.amazonaws.com/").unwrap();
+        let headers = signed_headers(&credentials(), "GET", &url, b"", "20150830T123600Z");
+
+        assert_eq!(
+            headers.last().unwrap().1,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/route53/aws4_request, \
+             SignedHeaders=host;x-amz-date, \
+             Signature=355d3a093d0eb6b05abf613663212bae3be37d064c6da458fcbcf1b7e53642c3"
+        );
+        assert_eq!(
+            headers[0],
+            (String::from("host"), String::from("example.amazonaws.com"))
+        );
+
+        assert_eq!(unescape(r"\052.example.org."), "*.example.org.");
+        assert_eq!(unescape(r"a\\b"), r"a\\b");
+    }
+
+    #[tokio::test]
+    async fn route53() {
+        let throttled = Arc::new(AtomicUsize::new(0));
+        let throttle = throttled.clone();
+
+        let (address, requests) = serve(move |request| {
+            let path = request.path.as_str();
+
+            if path.starts_with("/2013-04-01/hostedzone?") || path == "/2013-04-01/hostedzone" {
+                let body = if path.contains("marker=page2") {
+                    "<ListHostedZonesResponse><HostedZones><HostedZone><Id>/hostedzone/Z2</Id>\
+                     <Name>example.org.</Name></HostedZone></HostedZones>\
+                     <IsTruncated>false</IsTruncated></ListHostedZonesResponse>"
+                } else {
+                    "<ListHostedZonesResponse><HostedZones><HostedZone><Id>/hostedzone/Z1</Id>\
+                     <Name>example.com.</Name></HostedZone></HostedZones>\
+                     <IsTruncated>true</IsTruncated><NextMarker>page2</NextMarker>\
+                     </ListHostedZonesResponse>"
+                };
+                return Response::new(200, body);
+            }
+
+            if request.method == "GET" && path.starts_with("/2013-04-01/hostedzone/Z2/rrset") {
+                let body = if path.contains("name=www.example.org.") {
+                    "<ListResourceRecordSetsResponse><ResourceRecordSets>\
+                     <ResourceRecordSet><Name>www.example.org.</Name><Type>A</Type><TTL>300</TTL>\
+                     <ResourceRecords><ResourceRecord><Value>192.0.2.1</Value></ResourceRecord>\
+                     </ResourceRecords></ResourceRecordSet>\
+                     <ResourceRecordSet><Name>\\052.example.org.</Name><Type>A</Type>\
+                     <AliasTarget><HostedZoneId>Z3</HostedZoneId><DNSName>lb.example.net.</DNSName>\
+                     </AliasTarget></ResourceRecordSet>\
+                     </ResourceRecordSets><IsTruncated>false</IsTruncated>\
+                     </ListResourceRecordSetsResponse>"
+                } else {
+                    "<ListResourceRecordSetsResponse><ResourceRecordSets>\
+                     <ResourceRecordSet><Name>example.org.</Name><Type>NS</Type><TTL>172800</TTL>\
+                     <ResourceRecords><ResourceRecord><Value>ns-1.awsdns-01.org.</Value>\
+                     </ResourceRecord></ResourceRecords></ResourceRecordSet>\
+                     <ResourceRecordSet><Name>old.example.org.</Name><Type>TXT</Type><TTL>300</TTL>\
+                     <ResourceRecords><ResourceRecord><Value>\"Old &amp; busted\"</Value>\
+                     </ResourceRecord></ResourceRecords></ResourceRecordSet>\
+                     </ResourceRecordSets><IsTruncated>true</IsTruncated>\
+                     <NextRecordName>www.example.org.</NextRecordName><NextRecordType>A</NextRecordType>\
+                     </ListResourceRecordSetsResponse>"
+                };
+                return Response::new(200, body);
+            }
+
+            if request.method == "POST" && path == "/2013-04-01/hostedzone/Z2/rrset" {
+                if throttle.fetch_add(1, Ordering::SeqCst) == 0 {
+                    return Response::new(
+                        400,
+                        "<ErrorResponse><Error><Code>Throttling</Code></Error></ErrorResponse>",
+                    );
+                }
+
+                return Response::new(
+                    200,
+                    "<ChangeResourceRecordSetsResponse><ChangeInfo><Id>/change/C1</Id>\
+                     <Status>PENDING</Status></ChangeInfo></ChangeResourceRecordSetsResponse>",
+                );
+            }
+
+            Response::new(404, "")
+        })
+        .await;
+
+        let provider = Route53::new(Url::parse(&address).unwrap(), credentials())
+            .with_http(Http::new(Duration::ZERO).with_delay(Duration::from_millis(10)));
+
+        assert_eq!(
+            provider.zones().await.unwrap(),
+            ["example.com.", "example.org."]
+        );
+
+        let sets = provider.list(ORIGIN).await.unwrap();
+        assert_eq!(sets.len(), 4);
+        assert!(sets.contains(&RecordSet::new(
+            ORIGIN,
+            "old",
+            "TXT",
+            300,
+            ["\"Old & busted\""]
+        )));
+
+        // The alias record is not owned, so it is reported as a conflict.
+        let entries = [
+            entry("www.example.org.", "A", "192.0.2.2"),
+            entry("*.example.org.", "A", "192.0.2.3"),
+        ];
+        let current = provider.list(ORIGIN).await.unwrap();
+        let plan = provider.plan(ORIGIN, RecordSet::from_entries(ORIGIN, &entries), current);
+        assert_eq!(plan.changes.len(), 2);
+        assert_eq!(plan.conflicts.len(), 1);
+
+        apply_plan(&provider, ORIGIN, &plan).await.unwrap();
+        assert_eq!(throttled.load(Ordering::SeqCst), 2);
+
+        let requests = requests.lock().unwrap();
+        let change = requests.last().unwrap();
+        assert!(
+            change.headers["authorization"].starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/")
+        );
+        assert!(change.headers.contains_key("x-amz-date"));
+
+        // The old record set is deleted exactly as it was listed, and the
+        // www record set is replaced.
+        assert!(change.body.contains(
+            "<Change><Action>DELETE</Action><ResourceRecordSet><Name>old.example.org.</Name>\
+             <Type>TXT</Type><TTL>300</TTL><ResourceRecords><ResourceRecord>\
+             <Value>&quot;Old &amp; busted&quot;</Value></ResourceRecord></ResourceRecords>\
+             </ResourceRecordSet></Change>"
+        ));
+        assert!(change.body.contains(
+            "<Change><Action>DELETE</Action><ResourceRecordSet><Name>www.example.org.</Name>\
+             <Type>A</Type><TTL>300</TTL><ResourceRecords><ResourceRecord><Value>192.0.2.1</Value>"
+        ));
+        assert!(change.body.contains(
+            "<Change><Action>CREATE</Action><ResourceRecordSet><Name>www.example.org.</Name>\
+             <Type>A</Type><TTL>300</TTL><ResourceRecords><ResourceRecord><Value>192.0.2.2</Value>"
+        ));
+    }
+}
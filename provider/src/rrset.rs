@@ -0,0 +1,283 @@
+use std::{collections::BTreeMap, fmt::Display};
+
+use kubizone_crds::v1alpha1::ZoneEntry;
+use tracing::log::*;
+
+/// Resource record set: all records of a zone sharing an owner name and type.
+///
+/// Record sets are kept in a normalized form, so ones listed by a provider can
+/// be compared directly against the ones produced from a zone's entries.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RecordSet {
+    /// Fully qualified, lowercased owner name.
+    pub name: String,
+    /// Uppercased type mnemonic, such as `AAAA`.
+    pub type_: String,
+    pub ttl: u32,
+    /// Record data of each record in the set, normalized by [`normalize`], sorted.
+    pub records: Vec<String>,
+}
+
+impl RecordSet {
+    /// Build a record set of the zone `origin`, normalizing its name and records.
+    ///
+    /// Relative names, both as owner name and within the record data, are
+    /// taken to be relative to the `origin`.
+    pub fn new(
+        origin: &str,
+        name: &str,
+        type_: &str,
+        ttl: u32,
+        records: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Self {
+        let type_ = type_.to_uppercase();
+
+        let mut records: Vec<_> = records
+            .into_iter()
+            .map(|rdata| normalize(origin, &type_, rdata.as_ref()))
+            .collect();
+        records.sort();
+        records.dedup();
+
+        RecordSet {
+            name: absolute(name, origin).to_lowercase(),
+            type_,
+            ttl,
+            records,
+        }
+    }
+
+    /// Group the `entries` of the zone `origin` into record sets, as they
+    /// should exist at a provider.
+    ///
+    /// Entries outside of the zone, of classes other than `IN`, and those
+    /// [reserved](RecordSet::reserved) for the provider are skipped. Providers
+    /// only support a single TTL per record set, so each gets the lowest one,
+    /// to avoid caching any of the records for longer than intended.
+    pub fn from_entries(origin: &str, entries: &[ZoneEntry]) -> Vec<Self> {
+        let mut sets: BTreeMap<(String, String), (u32, Vec<&str>)> = BTreeMap::new();
+
+        for entry in entries {
+            let name = absolute(&entry.fqdn, origin).to_lowercase();
+            if !within(&name, origin) {
+                warn!("skipping entry {} outside of zone {origin}", entry.fqdn);
+                continue;
+            }
+
+            if !entry.class.eq_ignore_ascii_case("IN") {
+                warn!(
+                    "skipping {} entry {} of class {}",
+                    entry.type_, entry.fqdn, entry.class
+                );
+                continue;
+            }
+
+            let (ttl, records) = sets
+                .entry((name, entry.type_.to_uppercase()))
+                .or_insert((entry.ttl, Vec::new()));
+
+            *ttl = (*ttl).min(entry.ttl);
+            records.push(&entry.rdata);
+        }
+
+        sets.into_iter()
+            .map(|((name, type_), (ttl, records))| {
+                RecordSet::new(origin, &name, &type_, ttl, records)
+            })
+            .filter(|set| !set.reserved(origin))
+            .collect()
+    }
+
+    /// Whether the record set is maintained by the provider itself, rather
+    /// than kubizone. This is the case for the SOA record and the name servers
+    /// at the apex of the zone, since those are specific to the provider.
+    pub fn reserved(&self, origin: &str) -> bool {
+        self.type_ == "SOA" || (self.type_ == "NS" && self.name == origin.to_lowercase())
+    }
+}
+
+impl Display for RecordSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {} [{}]",
+            self.name,
+            self.ttl,
+            self.type_,
+            self.records.join(", ")
+        )
+    }
+}
+
+/// Make `name` fully qualified, by appending the `origin` to relative names.
+pub fn absolute(name: &str, origin: &str) -> String {
+    if name == "@" || name.is_empty() {
+        origin.to_string()
+    } else if name.ends_with('.') && !name.ends_with("\\.") {
+        name.to_string()
+    } else if origin == "." {
+        format!("{name}.")
+    } else {
+        format!("{name}.{origin}")
+    }
+}
+
+/// Whether the fully qualified `name` lies within the zone `origin`.
+pub fn within(name: &str, origin: &str) -> bool {
+    let (name, origin) = (name.to_lowercase(), origin.to_lowercase());
+    origin == "." || name == origin || name.ends_with(&format!(".{origin}"))
+}
+
+/// Indices of the fields within the record data of `type_` which hold domain names.
+fn domain_name_fields(type_: &str) -> &'static [usize] {
+    match type_ {
+        "NS" | "CNAME" | "DNAME" | "PTR" => &[0],
+        "MX" => &[1],
+        "SRV" => &[3],
+        "SOA" => &[0, 1],
+        _ => &[],
+    }
+}
+
+/// Split TXT record data into its character strings, each of which is
+/// either quoted, or a single unquoted word. Escapes are kept as-is.
+fn character_strings(rdata: &str) -> Vec<String> {
+    let mut strings = Vec::new();
+    let mut chars = rdata.trim().chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let quoted = c == '"';
+        if quoted {
+            chars.next();
+        }
+
+        let mut string = String::new();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    string.push(c);
+                    string.extend(chars.next());
+                }
+                '"' if quoted => break,
+                c if !quoted && c.is_whitespace() => break,
+                c => string.push(c),
+            }
+        }
+
+        strings.push(string);
+    }
+
+    strings
+}
+
+/// Bring the record data of `type_` into a canonical presentation format, so
+/// differences in formatting between kubizone and providers don't show up as
+/// changes.
+///
+/// Whitespace is collapsed, domain names are fully qualified and lowercased,
+/// and TXT records consist of quoted character strings.
+pub fn normalize(origin: &str, type_: &str, rdata: &str) -> String {
+    if type_ == "TXT" || type_ == "SPF" {
+        return character_strings(rdata)
+            .into_iter()
+            .map(|string| format!("\"{string}\""))
+            .collect::<Vec<_>>()
+            .join(" ");
+    }
+
+    let fields = domain_name_fields(type_);
+
+    rdata
+        .split(|c: char| c.is_whitespace() || c == '(' || c == ')')
+        .filter(|field| !field.is_empty())
+        .enumerate()
+        .map(|(index, field)| {
+            if fields.contains(&index) {
+                absolute(field, origin).to_lowercase()
+            } else {
+                field.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use kubizone_crds::v1alpha1::ZoneEntry;
+
+    use super::{normalize, RecordSet};
+
+    fn entry(fqdn: &str, type_: &str, ttl: u32, rdata: &str) -> ZoneEntry {
+        ZoneEntry {
+            fqdn: fqdn.to_string(),
+            type_: type_.to_string(),
+            class: String::from("IN"),
+            ttl,
+            rdata: rdata.to_string(),
+        }
+    }
+
+    #[test]
+    fn normalization() {
+        let origin = "example.org.";
+
+        assert_eq!(normalize(origin, "CNAME", "WWW"), "www.example.org.");
+        assert_eq!(
+            normalize(origin, "MX", "10   Mail.Example.org."),
+            "10 mail.example.org."
+        );
+        assert_eq!(
+            normalize(origin, "SRV", "0 5 5060 sip"),
+            "0 5 5060 sip.example.org."
+        );
+        assert_eq!(
+            normalize(origin, "TXT", r#"v=spf1 "include:_spf.example.org \"x\"""#),
+            r#""v=spf1" "include:_spf.example.org \"x\"""#
+        );
+        assert_eq!(
+            normalize(origin, "A", " 192.0.2.1 "),
+            normalize(origin, "A", "192.0.2.1")
+        );
+    }
+
+    #[test]
+    fn grouping() {
+        let origin = "example.org.";
+        let sets = RecordSet::from_entries(
+            origin,
+            &[
+                entry(
+                    "example.org.",
+                    "SOA",
+                    3600,
+                    "ns1.example.org. hostmaster.example.org. 1 2 3 4 5",
+                ),
+                entry("example.org.", "NS", 3600, "ns1.example.org."),
+                entry("WWW.example.org.", "a", 300, "192.0.2.2"),
+                entry("www.example.org.", "A", 60, "192.0.2.1"),
+                entry("www.example.org.", "A", 300, "192.0.2.1"),
+                entry("sub.example.org.", "NS", 3600, "ns1.sub"),
+                entry("www.example.com.", "A", 300, "192.0.2.1"),
+            ],
+        );
+
+        assert_eq!(
+            sets,
+            vec![
+                RecordSet::new(origin, "sub", "NS", 3600, ["ns1.sub.example.org."]),
+                RecordSet {
+                    name: String::from("www.example.org."),
+                    type_: String::from("A"),
+                    ttl: 60,
+                    records: vec![String::from("192.0.2.1"), String::from("192.0.2.2")],
+                },
+            ]
+        );
+    }
+}
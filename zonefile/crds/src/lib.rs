@@ -1,17 +1,22 @@
 use std::collections::BTreeMap;
 
 use kube::{CustomResource, ResourceExt};
-use kubizone_crds::v1alpha1::ZoneRef;
+use kubizone_crds::v1alpha1::{Condition, Zone, ZoneRef};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-/// Label attached to [`Zone`](kubizone_crds::Zone)s as backreferences
+/// Label previously attached to [`Zone`](kubizone_crds::Zone)s as backreferences
 /// to a single downstream [`ZoneFile`] generated from it.
 ///
-/// Used by the controller to trigger reconciliation when upstream
-/// zones change.
+/// Since a zone can feed any number of zonefiles, the controller now finds the
+/// zonefiles referencing a zone using its cache of zonefiles instead, and removes
+/// this label and the [`TARGET_ZONEFILE_ANNOTATION`] from zones it encounters.
 pub const TARGET_ZONEFILE_LABEL: &str = "kubi.zone/zonefile";
 
+/// Annotation previously holding the full `namespace/name` reference to the
+/// [`ZoneFile`] generated from a [`Zone`](kubizone_crds::Zone).
+pub const TARGET_ZONEFILE_ANNOTATION: &str = "kubi.zone/zonefile";
+
 /// A [`ZoneFile`] references an upstream [`Zone`](kubizone_crds::Zone) and (re)builds
 /// a configmap of the same name, whenever the zone changes, automatically incrementing
 /// serials as necessary.
@@ -29,10 +34,167 @@ pub const TARGET_ZONEFILE_LABEL: &str = "kubi.zone/zonefile";
 #[serde(rename_all = "camelCase")]
 pub struct ZoneFileSpec {
     /// Reference to a [`Zone`](kubizone_crds::Zone), optionally in a different namespace.
+    #[serde(default)]
     pub zone_refs: Vec<ZoneRef>,
 
+    /// Include all [`Zone`](kubizone_crds::Zone)s matching this selector,
+    /// in addition to the ones referenced by `zoneRefs`.
+    #[serde(default)]
+    pub zone_selector: Option<ZoneSelector>,
+
+    /// Name of the `ConfigMap` to write the zones to. Defaults to the name of the
+    /// [`ZoneFile`]. Superseded by the `output.name` template, if set.
     #[serde(default)]
     pub config_map_name: Option<String>,
+
+    /// Kind, names and layout of the objects the zones are written to.
+    #[serde(default)]
+    pub output: ZoneFileOutput,
+
+    /// Workloads serving the zones, which are rolled out whenever the output
+    /// of the zonefile changes, so they pick up the changes immediately.
+    #[serde(default)]
+    pub rollout: Vec<WorkloadRef>,
+
+    /// Format to write the zones in.
+    #[serde(default)]
+    pub format: ZoneFileFormat,
+
+    /// Write domain names within record data, such as the targets of NS,
+    /// CNAME and MX records, relative to the zone's origin where possible.
+    ///
+    /// Owner names are always written relative to the origin.
+    #[serde(default)]
+    pub relative_rdata: bool,
+}
+
+/// Selects [`Zone`](kubizone_crds::Zone)s by their labels and namespaces.
+#[derive(Default, Deserialize, Serialize, Clone, Debug, JsonSchema, Hash, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ZoneSelector {
+    /// Labels which zones must have, with these exact values.
+    /// Empty matches all zones.
+    #[serde(default)]
+    pub match_labels: BTreeMap<String, String>,
+
+    /// Namespaces to select zones from. Defaults to the namespace of the [`ZoneFile`].
+    #[serde(default)]
+    pub namespaces: Vec<String>,
+}
+
+impl ZoneSelector {
+    /// Label selector equivalent to `match_labels`, such as `key=value,other=value`.
+    pub fn label_selector(&self) -> String {
+        self.match_labels
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+/// Label attached to every object written by a [`ZoneFile`], holding the hashed
+/// reference to it, like the [`PARENT_ZONE_LABEL`](kubizone_crds::PARENT_ZONE_LABEL).
+///
+/// Used to find and remove objects which are no longer written to, such as
+/// those of zones which have been removed from the zonefile.
+pub const ZONEFILE_OUTPUT_LABEL: &str = "kubi.zone/zonefile-output";
+
+/// Describes the objects a [`ZoneFile`]'s zones are written to.
+///
+/// The `name` and `key` templates may contain the following variables:
+///
+/// * `{zonefile}`, the name of the [`ZoneFile`].
+/// * `{zone}` and `{namespace}`, the name and namespace of the [`Zone`](kubizone_crds::Zone).
+/// * `{origin}`, the fully qualified domain name of the zone, such as `example.org.`
+/// * `{domain}`, the same domain name without the trailing dot.
+///
+/// Zone variables are only available in object names when writing one object per zone.
+#[derive(Default, Deserialize, Serialize, Clone, Debug, JsonSchema, Hash, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ZoneFileOutput {
+    /// Kind of object to write the zones to.
+    #[serde(default)]
+    pub kind: OutputKind,
+
+    /// Write each zone to an object of its own, rather than all zones to one.
+    #[serde(default)]
+    pub per_zone: bool,
+
+    /// Template for the names of the objects. Defaults to `{zonefile}-{zone}`
+    /// when writing one object per zone, and the `configMapName` otherwise.
+    ///
+    /// Zones which do not fit within a single object are spread across
+    /// additional objects, named by appending `-1`, `-2` and so on.
+    #[serde(default)]
+    pub name: Option<String>,
+
+    /// Template for the key of each zone within its object. Defaults to `{origin}`.
+    /// Compressed zones have `.gz` appended to their key.
+    #[serde(default)]
+    pub key: Option<String>,
+
+    /// Whether to gzip-compress the zones.
+    #[serde(default)]
+    pub compression: Compression,
+}
+
+/// Kind of object a [`ZoneFile`]'s zones are written to.
+#[derive(Default, Deserialize, Serialize, Clone, Copy, Debug, JsonSchema, Hash, PartialEq, Eq)]
+pub enum OutputKind {
+    #[default]
+    ConfigMap,
+    /// Secrets suit output which should not be readable by everyone who can
+    /// read configmaps, such as zones containing TSIG keys.
+    Secret,
+}
+
+/// Compression of the zones written by a [`ZoneFile`].
+#[derive(Default, Deserialize, Serialize, Clone, Copy, Debug, JsonSchema, Hash, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    /// Only compress zones which would not fit in an object otherwise.
+    #[default]
+    Auto,
+    /// Never compress zones.
+    None,
+    /// Always compress zones.
+    Gzip,
+}
+
+/// Reference to a workload within the namespace of a [`ZoneFile`].
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, Hash, PartialEq, Eq)]
+pub struct WorkloadRef {
+    pub kind: WorkloadKind,
+    pub name: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, JsonSchema, Hash, PartialEq, Eq)]
+pub enum WorkloadKind {
+    Deployment,
+    StatefulSet,
+    DaemonSet,
+}
+
+/// Output format of a [`ZoneFile`], allowing a zone to feed whichever
+/// authoritative name server is in use.
+#[derive(Default, Deserialize, Serialize, Clone, Copy, Debug, JsonSchema, Hash, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ZoneFileFormat {
+    /// Zone file format described in RFC 1035, as read by BIND and most other servers.
+    #[default]
+    Rfc1035,
+    /// RFC 1035 zone file with only fully qualified names, explicit TTLs and
+    /// a single line per record, for strict parsers such as those of Knot DNS and NSD.
+    Knot,
+    /// The `data` file format of djbdns' tinydns.
+    Tinydns,
+    /// Zone JSON as accepted by the PowerDNS HTTP API.
+    PowerDns,
+    /// Zone configuration read by octoDNS' `YamlProvider`.
+    Octodns,
+    /// `D_EXTEND` statement for a DNSControl `dnsconfig.js`.
+    Dnscontrol,
 }
 
 impl ZoneFile {
@@ -52,18 +214,81 @@ impl ZoneFile {
             })
             .collect()
     }
+
+    /// Pod template annotation holding a hash of the zonefile's output, which
+    /// is updated to roll out the workloads referenced by the zonefile.
+    ///
+    /// Each zonefile has an annotation of its own, so zonefiles sharing a
+    /// workload don't overwrite each other's hash. Like the backreference
+    /// labels, the key is derived from a hash of the reference, to keep it
+    /// within the length limits of annotation keys.
+    pub fn rollout_annotation(&self) -> String {
+        let zonefile_ref = ZoneRef {
+            name: self.name_any(),
+            namespace: self.namespace(),
+        };
+
+        format!("kubi.zone/zonefile-{}", zonefile_ref.as_label())
+    }
+
+    /// Namespaces in which the `zoneSelector` selects zones, if any.
+    pub fn selected_namespaces(&self) -> Vec<String> {
+        match &self.spec.zone_selector {
+            None => Vec::new(),
+            Some(selector) if selector.namespaces.is_empty() => {
+                self.namespace().into_iter().collect()
+            }
+            Some(selector) => selector.namespaces.clone(),
+        }
+    }
+
+    /// Check if `zone` is included in this [`ZoneFile`], either by reference or selector.
+    pub fn includes(&self, zone: &Zone) -> bool {
+        self.zone_ref().contains(&zone.zone_ref()) || self.selects(zone)
+    }
+
+    /// Check if a change to `zone` concerns this [`ZoneFile`], because the zone
+    /// is included in it, or was included when it was last rendered, such as
+    /// before the zone was relabelled out of its `zoneSelector`.
+    pub fn concerns(&self, zone: &Zone) -> bool {
+        let zone_ref = zone.zone_ref();
+
+        self.includes(zone)
+            || self.status.as_ref().is_some_and(|status| {
+                status
+                    .zones
+                    .iter()
+                    .any(|included| included.zone_ref == zone_ref)
+            })
+    }
+
+    /// Check if `zone` is included in this [`ZoneFile`] by its `zoneSelector`.
+    pub fn selects(&self, zone: &Zone) -> bool {
+        let Some(selector) = &self.spec.zone_selector else {
+            return false;
+        };
+
+        let labels = zone.labels();
+        self.selected_namespaces()
+            .contains(&zone.namespace().unwrap_or_default())
+            && selector
+                .match_labels
+                .iter()
+                .all(|(key, value)| labels.get(key) == Some(value))
+    }
 }
 
 /// Describes the current state of the [`ZoneFile`], tracks state of
 /// the upstream [`Zone`](kubizone_crds::Zone), to determine when the
 /// output `ConfigMap` should be re-generated.
-#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[derive(Default, Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ZoneFileStatus {
     /// Last observed hash of the upstream [`Zone`](kubizone_crds::Zone)
     ///
     /// Used by the zonefile controller to trigger configmap rebuilds
     /// and zone serial rotation.
+    #[serde(default)]
     pub hash: BTreeMap<String, String>,
 
     /// Serial of the latest generated zonefile.
@@ -71,5 +296,169 @@ pub struct ZoneFileStatus {
     /// The zonefile controller will automatically increment this value
     /// whenever the zonefile configmap is rebuilt, in accordance with
     /// [RFC 1912](https://datatracker.ietf.org/doc/html/rfc1912#section-2.2)
+    #[serde(default)]
     pub serial: BTreeMap<String, u32>,
+
+    /// State of each [`Zone`](kubizone_crds::Zone) referenced or selected by the zonefile.
+    #[serde(default)]
+    pub zones: Vec<IncludedZone>,
+
+    /// Observations of the zonefile's state made by the controller, such as
+    /// the outcome of the latest reconciliation.
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+}
+
+/// State of a single [`Zone`](kubizone_crds::Zone) included in a [`ZoneFile`].
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct IncludedZone {
+    /// Reference to the zone, with the namespace always populated.
+    pub zone_ref: ZoneRef,
+
+    pub state: ZoneState,
+
+    /// Fully qualified domain name of the zone, and key of its entry in the `ConfigMap`.
+    #[serde(default)]
+    pub origin: Option<String>,
+
+    /// Hash of the zone, as of its latest rendering.
+    #[serde(default)]
+    pub hash: Option<String>,
+
+    /// Serial of the zone, as of its latest rendering.
+    #[serde(default)]
+    pub serial: Option<u32>,
+
+    /// Set when the zone is currently unavailable, and the `ConfigMap` holds
+    /// the last rendering of it instead.
+    #[serde(default)]
+    pub stale: bool,
+}
+
+/// Availability of a [`Zone`](kubizone_crds::Zone) included in a [`ZoneFile`].
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, JsonSchema, PartialEq, Eq)]
+pub enum ZoneState {
+    /// The zone is rendered into the `ConfigMap`.
+    Present,
+    /// The zone has not been assigned a fully qualified domain name yet.
+    PendingFqdn,
+    /// The contents of the zone have not been hashed and assigned a serial yet.
+    PendingSerial,
+    /// The referenced zone does not exist, or is outside the scope of the controller.
+    Missing,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use kube::core::ObjectMeta;
+    use kubizone_crds::v1alpha1::{Zone, ZoneRef};
+
+    use super::{IncludedZone, ZoneFile, ZoneFileSpec, ZoneFileStatus, ZoneSelector, ZoneState};
+
+    fn zone(namespace: &str, labels: &[(&str, &str)]) -> Zone {
+        Zone {
+            metadata: ObjectMeta {
+                name: Some(String::from("example-org")),
+                namespace: Some(namespace.to_string()),
+                labels: Some(
+                    labels
+                        .iter()
+                        .map(|(key, value)| (key.to_string(), value.to_string()))
+                        .collect(),
+                ),
+                ..Default::default()
+            },
+            spec: Default::default(),
+            status: None,
+        }
+    }
+
+    #[test]
+    fn zone_selector() {
+        let mut zonefile = ZoneFile::new(
+            "example",
+            ZoneFileSpec {
+                zone_refs: Vec::new(),
+                zone_selector: Some(ZoneSelector {
+                    match_labels: BTreeMap::from([(
+                        String::from("kubi.zone/nameserver"),
+                        String::from("ns1"),
+                    )]),
+                    namespaces: Vec::new(),
+                }),
+                config_map_name: None,
+                output: Default::default(),
+                rollout: Vec::new(),
+                format: Default::default(),
+                relative_rdata: false,
+            },
+        );
+        zonefile.metadata.namespace = Some(String::from("default"));
+
+        // Selectors without namespaces only apply to the zonefile's own.
+        assert!(zonefile.selects(&zone("default", &[("kubi.zone/nameserver", "ns1")])));
+        assert!(!zonefile.selects(&zone("dev", &[("kubi.zone/nameserver", "ns1")])));
+        assert!(!zonefile.selects(&zone("default", &[("kubi.zone/nameserver", "ns2")])));
+        assert!(!zonefile.selects(&zone("default", &[])));
+
+        zonefile.spec.zone_selector = Some(ZoneSelector {
+            match_labels: BTreeMap::new(),
+            namespaces: vec![String::from("dev")],
+        });
+        assert!(zonefile.selects(&zone("dev", &[])));
+        assert!(!zonefile.selects(&zone("default", &[])));
+
+        // Referenced zones are included regardless of the selector.
+        zonefile.spec.zone_refs = vec![ZoneRef {
+            name: String::from("example-org"),
+            namespace: None,
+        }];
+        assert!(zonefile.includes(&zone("default", &[])));
+        assert!(!zonefile.selects(&zone("default", &[])));
+    }
+
+    #[test]
+    fn relabelled_zones() {
+        let mut zonefile = ZoneFile::new(
+            "example",
+            ZoneFileSpec {
+                zone_refs: Vec::new(),
+                zone_selector: Some(ZoneSelector {
+                    match_labels: BTreeMap::from([(
+                        String::from("kubi.zone/nameserver"),
+                        String::from("ns1"),
+                    )]),
+                    namespaces: Vec::new(),
+                }),
+                config_map_name: None,
+                output: Default::default(),
+                rollout: Vec::new(),
+                format: Default::default(),
+                relative_rdata: false,
+            },
+        );
+        zonefile.metadata.namespace = Some(String::from("default"));
+
+        let relabelled = zone("default", &[("kubi.zone/nameserver", "ns2")]);
+        assert!(!zonefile.concerns(&relabelled));
+
+        // Zones rendered before they were relabelled still concern the
+        // zonefile, which must drop them.
+        zonefile.status = Some(ZoneFileStatus {
+            zones: vec![IncludedZone {
+                zone_ref: relabelled.zone_ref(),
+                state: ZoneState::Present,
+                origin: Some(String::from("example.org.")),
+                hash: None,
+                serial: None,
+                stale: false,
+            }],
+            ..Default::default()
+        });
+        assert!(zonefile.concerns(&relabelled));
+        assert!(!zonefile.includes(&relabelled));
+    }
 }
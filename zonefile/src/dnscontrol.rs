@@ -0,0 +1,168 @@
+use k8s_openapi::serde_json;
+use kubizone_crds::v1alpha1::ZoneEntry;
+
+use crate::rfc1035::{absolute, decode_txt, presentation_rdata, relative_to, split_caa};
+
+/// Serialize `entries` of the zone `origin` into a `D_EXTEND` statement for a
+/// DNSControl `dnsconfig.js`, as described in <https://docs.dnscontrol.org/>.
+///
+/// Using `D_EXTEND` rather than `D` lets the registrar and DNS providers of the
+/// domain be declared elsewhere in the configuration, which then only needs to
+/// `require` this file. The SOA record is left out, since DNSControl manages it
+/// itself, and records it has no function for are listed in comments.
+pub fn serialize(origin: &str, entries: &[ZoneEntry]) -> String {
+    let mut unsupported = Vec::new();
+    let mut records = Vec::new();
+
+    for entry in entries {
+        if entry.type_.eq_ignore_ascii_case("SOA") {
+            continue;
+        }
+
+        let name = absolute(&entry.fqdn, origin);
+        let Some(relative) = relative_to(origin, &name) else {
+            unsupported.push(format!("// {name} is outside of {origin}"));
+            continue;
+        };
+
+        match record(origin, relative, entry) {
+            Some(record) => records.push(format!("    {record}")),
+            None => unsupported.push(format!(
+                "// unsupported {} record for {name}: {}",
+                entry.type_, entry.rdata
+            )),
+        }
+    }
+
+    let mut output = String::new();
+    for comment in unsupported {
+        output.push_str(&comment);
+        output.push('\n');
+    }
+
+    // Domains are named without the trailing dot, and each record is a further argument.
+    records.insert(0, string(origin.strip_suffix('.').unwrap_or(origin)));
+    output.push_str(&format!("D_EXTEND({}\n);\n", records.join(",\n")));
+
+    output
+}
+
+/// Quote `value` as a javascript string literal.
+fn string(value: &str) -> String {
+    // Unwrap safety: serializing a string cannot fail.
+    serde_json::to_string(value).unwrap()
+}
+
+fn record(origin: &str, name: &str, entry: &ZoneEntry) -> Option<String> {
+    let type_ = entry.type_.to_uppercase();
+    let rdata = presentation_rdata(origin, entry);
+    let fields: Vec<_> = rdata.split_whitespace().collect();
+
+    let arguments = match (type_.as_str(), &fields[..]) {
+        ("A" | "AAAA" | "NS" | "PTR" | "CNAME" | "ALIAS", [target]) => {
+            vec![string(target)]
+        }
+        ("TXT" | "SPF", _) => {
+            let strings: Vec<_> = decode_txt(&entry.rdata)
+                .iter()
+                .map(|bytes| string(&String::from_utf8_lossy(bytes)))
+                .collect();
+
+            match &strings[..] {
+                [single] => vec![single.clone()],
+                _ => vec![format!("[{}]", strings.join(", "))],
+            }
+        }
+        ("MX", [preference, exchange]) => {
+            vec![
+                preference.parse::<u16>().ok()?.to_string(),
+                string(exchange),
+            ]
+        }
+        ("SRV", [priority, weight, port, target]) => vec![
+            priority.parse::<u16>().ok()?.to_string(),
+            weight.parse::<u16>().ok()?.to_string(),
+            port.parse::<u16>().ok()?.to_string(),
+            string(target),
+        ],
+        ("CAA", _) => {
+            let (flags, tag, value) = split_caa(&rdata)?;
+            let mut arguments = vec![string(tag), string(&value)];
+            match flags.parse::<u8>().ok()? {
+                0 => (),
+                128 => arguments.push(String::from("CAA_CRITICAL")),
+                _ => return None,
+            }
+            arguments
+        }
+        _ => return None,
+    };
+
+    Some(format!(
+        "{type_}({}, {}, TTL({}))",
+        string(name),
+        arguments.join(", "),
+        entry.ttl
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use kubizone_crds::v1alpha1::ZoneEntry;
+
+    use super::serialize;
+
+    fn entry(fqdn: &str, type_: &str, rdata: &str) -> ZoneEntry {
+        ZoneEntry {
+            fqdn: fqdn.to_string(),
+            type_: type_.to_string(),
+            class: String::from("IN"),
+            ttl: 300,
+            rdata: rdata.to_string(),
+        }
+    }
+
+    #[test]
+    fn domain_extension() {
+        let config = serialize(
+            "example.org.",
+            &[
+                entry(
+                    "example.org.",
+                    "SOA",
+                    "ns.example.org. noc.example.org. (2023120100 86400 7200 3600000 360)",
+                ),
+                entry("example.org.", "NS", "ns1"),
+                entry("example.org.", "MX", "10 mail.example.org."),
+                entry("www", "A", "192.168.0.1"),
+                entry("www", "TXT", "\"v=spf1 -all\" \"say \\\"hi\\\"\""),
+                entry("_sip._tcp", "SRV", "10 60 5060 sip.example.com."),
+                entry("example.org.", "CAA", "128 issue \"letsencrypt.org\""),
+                entry(
+                    "example.org.",
+                    "CAA",
+                    "0 issue \"ca.example.net; accounturi=https://ca.example.net/acct/1\"",
+                ),
+                entry("example.org.", "HINFO", "\"PC\" \"Linux\""),
+                entry("example.com.", "A", "192.168.0.2"),
+            ],
+        );
+
+        assert_eq!(
+            config.lines().collect::<Vec<_>>(),
+            [
+                "// unsupported HINFO record for example.org.: \"PC\" \"Linux\"",
+                "// example.com. is outside of example.org.",
+                "D_EXTEND(\"example.org\",",
+                "    NS(\"@\", \"ns1.example.org.\", TTL(300)),",
+                "    MX(\"@\", 10, \"mail.example.org.\", TTL(300)),",
+                "    A(\"www\", \"192.168.0.1\", TTL(300)),",
+                "    TXT(\"www\", [\"v=spf1 -all\", \"say \\\"hi\\\"\"], TTL(300)),",
+                "    SRV(\"_sip._tcp\", 10, 60, 5060, \"sip.example.com.\", TTL(300)),",
+                "    CAA(\"@\", \"issue\", \"letsencrypt.org\", CAA_CRITICAL, TTL(300)),",
+                "    CAA(\"@\", \"issue\", \"ca.example.net; accounturi=https://ca.example.net/acct/1\", TTL(300))",
+                ");",
+            ]
+        );
+    }
+}
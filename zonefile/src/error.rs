@@ -0,0 +1,61 @@
+use std::fmt::Display;
+
+use kubizone_crds::v1alpha1::ZoneRef;
+
+/// Condition reporting the outcome of the most recent reconciliation of a
+/// [`ZoneFile`](zonefile_crds::ZoneFile).
+///
+/// On failure, the reason of the condition is the [`Error::category`].
+pub const RECONCILED_CONDITION: &str = "Reconciled";
+
+/// Reason used for the [`RECONCILED_CONDITION`] and metrics when reconciliation succeeds.
+pub const SUCCESS: &str = "Success";
+
+/// Errors encountered while reconciling zonefiles.
+#[derive(Debug)]
+pub enum Error {
+    /// Communication with the Kubernetes API failed. Usually transient.
+    Kube(kube::Error),
+    /// A referenced zone does not exist (yet).
+    MissingZone(ZoneRef),
+    /// The output of the zonefile is misconfigured, such as a template
+    /// using an unknown variable.
+    InvalidOutput(String),
+}
+
+impl Error {
+    /// Short, CamelCase name of the kind of error, used as the reason in the
+    /// [`RECONCILED_CONDITION`] and as a label on metrics.
+    pub fn category(&self) -> &'static str {
+        match self {
+            Error::Kube(_) => "KubeApi",
+            Error::MissingZone(_) => "MissingZone",
+            Error::InvalidOutput(_) => "InvalidOutput",
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Kube(error) => write!(f, "kubernetes api error: {error}"),
+            Error::MissingZone(zone_ref) => write!(f, "zone {zone_ref} does not exist"),
+            Error::InvalidOutput(reason) => write!(f, "invalid output: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Kube(error) => Some(error),
+            Error::MissingZone(_) | Error::InvalidOutput(_) => None,
+        }
+    }
+}
+
+impl From<kube::Error> for Error {
+    fn from(error: kube::Error) -> Self {
+        Error::Kube(error)
+    }
+}
@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+
+use k8s_openapi::serde_json::{json, Value};
+
+use crate::{parser::Entry, rfc1035::relative_to};
+
+/// How imported records refer to the zone they belong to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum NamePolicy {
+    /// Reference the imported zone using a `zoneRef`, with domain names relative
+    /// to it. Records at the apex of the zone, or outside of it, are always
+    /// fully qualified, since they cannot be expressed relative to the zone.
+    #[default]
+    ZoneRef,
+    /// Use fully qualified domain names, leaving it to the Kubizone controller
+    /// to find the most specific zone which delegates to them.
+    Fqdn,
+}
+
+/// Describes the [`Zone`](kubizone_crds::v1alpha1::Zone) which records are imported into.
+pub struct Import<'a> {
+    /// Fully qualified domain name of the zone.
+    pub origin: &'a str,
+    /// Name of the resulting `Zone` resource.
+    pub zone_name: &'a str,
+    /// Namespace of the resulting resources.
+    pub namespace: &'a str,
+    pub policy: NamePolicy,
+}
+
+/// Produce a resource name from a domain name, such as `example-org` for `example.org.`.
+pub fn resource_name(domain: &str) -> String {
+    let mut name = String::with_capacity(domain.len());
+    for c in domain.to_lowercase().chars() {
+        match c {
+            'a'..='z' | '0'..='9' => name.push(c),
+            '*' => name.push_str("wildcard"),
+            _ if !name.is_empty() && !name.ends_with('-') => name.push('-'),
+            _ => (),
+        }
+    }
+
+    // Resource names are limited to 253 characters, and must end in an alphanumeric.
+    name.truncate(253);
+    name.trim_end_matches('-').to_string()
+}
+
+/// Serialize the zone, and the records parsed from its zone file, into a
+/// multi-document YAML stream of Kubizone resources.
+///
+/// The SOA record is not imported, since Kubizone produces its own, but its
+/// timers are carried over to the `Zone`.
+pub fn manifests(import: &Import, entries: &[Entry]) -> String {
+    let soa = entries.iter().find(|entry| entry.type_ == "SOA");
+    let ttl = soa.map(|soa| soa.ttl);
+
+    let mut documents = vec![zone(import, soa)];
+    let mut names: HashMap<String, usize> = HashMap::new();
+
+    for entry in entries.iter().filter(|entry| entry.type_ != "SOA") {
+        let relative = relative_to(import.origin, &entry.name).filter(|name| *name != "@");
+
+        let base = match relative {
+            Some(relative) => format!(
+                "{}-{}-{}",
+                import.zone_name,
+                resource_name(relative),
+                entry.type_.to_lowercase()
+            ),
+            None if entry.name == import.origin => {
+                format!("{}-{}", import.zone_name, entry.type_.to_lowercase())
+            }
+            None => format!(
+                "{}-{}",
+                resource_name(&entry.name),
+                entry.type_.to_lowercase()
+            ),
+        };
+
+        // Owner names may hold several records of the same type.
+        let count = names.entry(base.clone()).or_default();
+        *count += 1;
+        let name = match *count {
+            1 => resource_name(&base),
+            count => resource_name(&format!("{base}-{count}")),
+        };
+
+        let mut spec = json!({
+            "type": entry.type_,
+            "rdata": entry.rdata.join(" "),
+        });
+
+        // Unwrap safety: the spec was constructed as an object above.
+        let object = spec.as_object_mut().unwrap();
+        match (import.policy, relative) {
+            (NamePolicy::ZoneRef, Some(relative)) => {
+                object.insert("domainName".to_string(), json!(relative));
+                object.insert("zoneRef".to_string(), json!({ "name": import.zone_name }));
+            }
+            _ => {
+                object.insert("domainName".to_string(), json!(entry.name));
+            }
+        }
+
+        if entry.class != "IN" {
+            object.insert("class".to_string(), json!(entry.class));
+        }
+
+        // Records inherit the zone's TTL, unless they specify their own.
+        if Some(entry.ttl) != ttl {
+            object.insert("ttl".to_string(), json!(entry.ttl));
+        }
+
+        documents.push(json!({
+            "apiVersion": "kubi.zone/v1alpha1",
+            "kind": "Record",
+            "metadata": {
+                "name": name,
+                "namespace": import.namespace,
+            },
+            "spec": spec,
+        }));
+    }
+
+    documents
+        .iter()
+        // Unwrap safety: serializing a json Value cannot fail.
+        .map(|document| serde_yaml::to_string(document).unwrap())
+        .collect::<Vec<_>>()
+        .join("---\n")
+}
+
+fn zone(import: &Import, soa: Option<&Entry>) -> Value {
+    let mut spec = json!({
+        "domainName": import.origin,
+        // Allow the imported records, and only those, to be adopted by the zone.
+        "delegations": [{
+            "namespaces": [import.namespace],
+            "records": [{ "pattern": "*.@" }],
+        }],
+    });
+
+    if let Some(soa) = soa {
+        // Unwrap safety: the spec was constructed as an object above.
+        let object = spec.as_object_mut().unwrap();
+        object.insert("ttl".to_string(), json!(soa.ttl));
+
+        let timers = ["refresh", "retry", "expire", "negativeResponseCache"];
+        for (field, value) in timers.into_iter().zip(soa.rdata.iter().skip(3)) {
+            if let Ok(value) = value.parse::<u32>() {
+                object.insert(field.to_string(), json!(value));
+            }
+        }
+    }
+
+    json!({
+        "apiVersion": "kubi.zone/v1alpha1",
+        "kind": "Zone",
+        "metadata": {
+            "name": import.zone_name,
+            "namespace": import.namespace,
+        },
+        "spec": spec,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::serde_json::{json, Value};
+
+    use crate::parser::parse;
+
+    use super::{manifests, resource_name, Import, NamePolicy};
+
+    const ZONE: &str = "$TTL 300\n\
+        @ IN SOA ns noc ( 1 86400 7200 3600000 360 )\n\
+        \x20 NS ns.example.org.\n\
+        www A 192.168.0.1\n\
+        www A 192.168.0.2\n\
+        mail 60 MX 10 mx.example.com.\n";
+
+    fn import(policy: NamePolicy) -> Vec<Value> {
+        let output = manifests(
+            &Import {
+                origin: "example.org.",
+                zone_name: "example-org",
+                namespace: "dns",
+                policy,
+            },
+            &parse(ZONE, "example.org.").unwrap(),
+        );
+
+        output
+            .split("---\n")
+            .map(|document| serde_yaml::from_str(document).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn resource_names() {
+        assert_eq!(resource_name("example.org."), "example-org");
+        assert_eq!(
+            resource_name("_sip._tcp.Example.org."),
+            "sip-tcp-example-org"
+        );
+        assert_eq!(resource_name("*.example.org."), "wildcard-example-org");
+    }
+
+    #[test]
+    fn zone_ref_policy() {
+        let documents = import(NamePolicy::ZoneRef);
+        assert_eq!(documents.len(), 5);
+
+        assert_eq!(
+            documents[0]["spec"],
+            json!({
+                "domainName": "example.org.",
+                "delegations": [{ "namespaces": ["dns"], "records": [{ "pattern": "*.@" }] }],
+                "ttl": 300,
+                "refresh": 86400,
+                "retry": 7200,
+                "expire": 3600000,
+                "negativeResponseCache": 360,
+            })
+        );
+
+        // Apex records cannot be relative to the zone.
+        assert_eq!(documents[1]["metadata"]["name"], "example-org-ns");
+        assert_eq!(
+            documents[1]["spec"],
+            json!({ "domainName": "example.org.", "type": "NS", "rdata": "ns.example.org." })
+        );
+
+        assert_eq!(documents[2]["metadata"]["name"], "example-org-www-a");
+        assert_eq!(documents[3]["metadata"]["name"], "example-org-www-a-2");
+        assert_eq!(
+            documents[3]["spec"],
+            json!({
+                "domainName": "www",
+                "zoneRef": { "name": "example-org" },
+                "type": "A",
+                "rdata": "192.168.0.2",
+            })
+        );
+
+        assert_eq!(documents[4]["spec"]["ttl"], 60);
+        assert_eq!(documents[4]["spec"]["rdata"], "10 mx.example.com.");
+    }
+
+    #[test]
+    fn fqdn_policy() {
+        let documents = import(NamePolicy::Fqdn);
+        assert_eq!(
+            documents[2]["spec"],
+            json!({ "domainName": "www.example.org.", "type": "A", "rdata": "192.168.0.1" })
+        );
+    }
+}
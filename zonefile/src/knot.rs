@@ -0,0 +1,62 @@
+use kubizone_crds::v1alpha1::ZoneEntry;
+
+use crate::rfc1035::{absolute, presentation_rdata};
+
+/// Serialize `entries` of the zone `origin` into a zone file which avoids the
+/// conveniences of the RFC 1035 format that stricter parsers, like those of
+/// Knot DNS and NSD, may reject or interpret differently.
+///
+/// Every record is written on a single line with a fully qualified owner name
+/// and an explicit TTL and class, so no directives besides `$ORIGIN` are needed.
+pub fn serialize(origin: &str, entries: &[ZoneEntry]) -> String {
+    let mut output = format!("$ORIGIN {origin}\n");
+
+    for entry in entries {
+        output.push_str(&format!(
+            "{} {} {} {} {}\n",
+            absolute(&entry.fqdn, origin),
+            entry.ttl,
+            entry.class,
+            entry.type_,
+            presentation_rdata(origin, entry)
+        ));
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use kubizone_crds::v1alpha1::ZoneEntry;
+
+    use super::serialize;
+
+    #[test]
+    fn fully_qualified_single_lines() {
+        let entries = [
+            ZoneEntry {
+                fqdn: String::from("example.org."),
+                type_: String::from("SOA"),
+                class: String::from("IN"),
+                ttl: 360,
+                rdata: String::from(
+                    "ns.example.org. noc.example.org. (2023120100 86400 7200 3600000 360)",
+                ),
+            },
+            ZoneEntry {
+                fqdn: String::from("www"),
+                type_: String::from("CNAME"),
+                class: String::from("IN"),
+                ttl: 300,
+                rdata: String::from("web"),
+            },
+        ];
+
+        assert_eq!(
+            serialize("example.org.", &entries),
+            "$ORIGIN example.org.\n\
+             example.org. 360 IN SOA ns.example.org. noc.example.org. 2023120100 86400 7200 3600000 360\n\
+             www.example.org. 300 IN CNAME web.example.org.\n"
+        );
+    }
+}
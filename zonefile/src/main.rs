@@ -1,6 +1,28 @@
-use clap::{command, Parser, Subcommand};
+use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+
+use clap::{Parser, Subcommand};
+use k8s_openapi::serde_json::{self, json};
 use kube::Client;
+use kubizone_crds::v1alpha1::ZoneRef;
+use zonefile_crds::ZoneFileFormat;
+
+mod dnscontrol;
+mod error;
+mod import;
+mod knot;
+mod octodns;
+mod output;
+mod parser;
+mod powerdns;
 mod reconciliation;
+mod rfc1035;
+mod rrset;
+mod sidecar;
+mod tinydns;
+
+use import::{Import, NamePolicy};
+use kubizone_common::{metrics, Backoff, Metrics, ScopeArgs};
+use sidecar::{Sidecar, Source};
 
 #[derive(Debug, Parser)]
 #[command(author, version, about, long_about = None)]
@@ -11,20 +33,205 @@ struct Args {
 
 #[derive(Debug, Subcommand)]
 enum Command {
-    Reconcile,
+    Reconcile(ReconcileArgs),
+    /// Convert an existing zone file into Zone and Record manifests.
+    Import(ImportArgs),
+    /// Write zones to files in a local directory, reloading the name server
+    /// whenever they change.
+    Sidecar(SidecarArgs),
+}
+
+#[derive(Debug, clap::Args)]
+struct ReconcileArgs {
+    #[command(flatten)]
+    scope: ScopeArgs,
+
+    /// Seconds between periodic reconciliations of healthy zonefiles.
+    #[arg(long, default_value_t = 300)]
+    requeue: u64,
+
+    /// Seconds before retrying a failed reconciliation for the first time.
+    /// The delay doubles with every consecutive failure.
+    #[arg(long, default_value_t = 5)]
+    backoff_base: u64,
+
+    /// Upper limit in seconds for the delay between retries of failed reconciliations.
+    #[arg(long, default_value_t = 300)]
+    backoff_max: u64,
+
+    /// Address on which to serve Prometheus metrics, such as `0.0.0.0:9090`.
+    #[arg(long)]
+    metrics_address: Option<SocketAddr>,
+}
+
+#[derive(Debug, clap::Args)]
+struct ImportArgs {
+    /// Zone file to import. `$INCLUDE`d files are resolved relative to its directory.
+    file: PathBuf,
+
+    /// Fully qualified domain name of the zone, used as the initial `$ORIGIN`.
+    #[arg(long)]
+    origin: String,
+
+    /// Name of the produced Zone resource. Defaults to the origin, with dots replaced by dashes.
+    #[arg(long)]
+    zone_name: Option<String>,
+
+    /// Namespace of the produced resources.
+    #[arg(long, short = 'n', default_value = "default")]
+    namespace: String,
+
+    /// Whether records should reference the zone by `zoneRef`, or use fully qualified domain names.
+    #[arg(long, value_enum, default_value_t)]
+    names: NamePolicy,
+}
+
+#[derive(Debug, clap::Args)]
+struct SidecarArgs {
+    /// Directory to write the zone files to.
+    #[arg(long)]
+    directory: PathBuf,
+
+    /// Write the zones of this ZoneFile, given as `namespace/name`, in its format.
+    /// Defaults to all zones in scope.
+    #[arg(long, value_parser = parse_zonefile_ref)]
+    zonefile: Option<ZoneRef>,
+
+    #[command(flatten)]
+    scope: ScopeArgs,
+
+    /// Format to write the zones in, unless writing the zones of a ZoneFile.
+    #[arg(long, value_parser = parse_format, default_value = "rfc1035")]
+    format: ZoneFileFormat,
+
+    /// Write domain names within record data relative to the zone's origin,
+    /// unless writing the zones of a ZoneFile.
+    #[arg(long)]
+    relative_rdata: bool,
+
+    /// Template for the name of each zone's file, which may use the variables
+    /// `{zone}`, `{namespace}`, `{origin}` and `{domain}`.
+    #[arg(long, default_value = "{origin}")]
+    file_name: String,
+
+    /// Command to run for each zone whose serial changed, such as `rndc reload {domain}`.
+    /// It is split on whitespace, rather than run by a shell, and its arguments may use
+    /// the same variables as the file name, as well as the `{file}` path.
+    #[arg(long)]
+    reload: Option<String>,
+}
+
+fn parse_zonefile_ref(value: &str) -> Result<ZoneRef, String> {
+    match value.split_once('/') {
+        Some((namespace, name)) if !namespace.is_empty() && !name.is_empty() => Ok(ZoneRef {
+            name: name.to_string(),
+            namespace: Some(namespace.to_string()),
+        }),
+        _ => Err(format!("expected namespace/name, got {value}")),
+    }
+}
+
+fn parse_format(value: &str) -> Result<ZoneFileFormat, String> {
+    serde_json::from_value(json!(value)).map_err(|_| format!("unknown format {value}"))
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
-    tracing_subscriber::fmt::init();
-
     let args = Args::parse();
 
     match args.command {
-        Command::Reconcile => {
+        Command::Reconcile(args) => {
+            tracing_subscriber::fmt::init();
             let client = Client::try_default().await.unwrap();
+            let metrics = Arc::new(Metrics::default());
+
+            let metrics_server = {
+                let metrics = metrics.clone();
+                async move {
+                    match args.metrics_address {
+                        Some(address) => metrics::serve(address, metrics).await,
+                        None => std::future::pending().await,
+                    }
+                }
+            };
+
+            tokio::select! {
+                _ = reconciliation::reconcile(
+                    client,
+                    args.scope.into_scope(),
+                    Backoff::new(
+                        Duration::from_secs(args.backoff_base),
+                        Duration::from_secs(args.backoff_max),
+                    ),
+                    metrics,
+                    Duration::from_secs(args.requeue),
+                ) => (),
+                _ = metrics_server => (),
+            }
+        }
+        Command::Import(args) => {
+            let origin = rfc1035::absolute(&args.origin, ".");
+            let zone_name = args
+                .zone_name
+                .unwrap_or_else(|| import::resource_name(&origin));
+
+            let entries = std::fs::read_to_string(&args.file)
+                .map_err(|err| err.to_string())
+                .and_then(|input| {
+                    // Unwrap safety: the file was read successfully, so it has a parent directory.
+                    let directory = args.file.parent().unwrap();
+                    parser::parse_with_includes(&input, &origin, directory)
+                        .map_err(|err| err.to_string())
+                });
+
+            match entries {
+                Ok(entries) => print!(
+                    "{}",
+                    import::manifests(
+                        &Import {
+                            origin: &origin,
+                            zone_name: &zone_name,
+                            namespace: &args.namespace,
+                            policy: args.names,
+                        },
+                        &entries,
+                    )
+                ),
+                Err(err) => {
+                    eprintln!("failed to import {}: {err}", args.file.display());
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::Sidecar(args) => {
+            tracing_subscriber::fmt::init();
+
+            let sidecar = Sidecar {
+                client: Client::try_default().await.unwrap(),
+                scope: args.scope.into_scope(),
+                source: match args.zonefile {
+                    Some(zonefile_ref) => Source::ZoneFile(zonefile_ref),
+                    None => Source::Zones {
+                        format: args.format,
+                        relative_rdata: args.relative_rdata,
+                    },
+                },
+                directory: args.directory,
+                file_name: args.file_name,
+                reload: args
+                    .reload
+                    .iter()
+                    .flat_map(|command| command.split_whitespace())
+                    .map(String::from)
+                    .collect(),
+            };
+
+            if let Err(err) = sidecar.validate() {
+                eprintln!("invalid template: {err}");
+                std::process::exit(1);
+            }
 
-            reconciliation::reconcile(client).await;
+            sidecar.run().await;
         }
     }
 }
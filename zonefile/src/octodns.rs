@@ -0,0 +1,237 @@
+use k8s_openapi::serde_json::{json, Map, Value};
+use kubizone_crds::v1alpha1::ZoneEntry;
+
+use crate::{
+    rfc1035::{decode_txt, relative_to, split_caa},
+    rrset::{self, RRSet},
+};
+
+/// Serialize `entries` of the zone `origin` into the YAML zone configuration read
+/// by octoDNS' `YamlProvider`, as described in <https://github.com/octodns/octodns>.
+///
+/// The SOA record is left out, since octoDNS leaves it to the providers. Records
+/// octoDNS has no representation for are listed in comments at the top.
+pub fn serialize(origin: &str, entries: &[ZoneEntry]) -> String {
+    let mut unsupported = Vec::new();
+    let mut names = Map::new();
+
+    for rrset in rrset::group(origin, entries) {
+        if rrset.type_ == "SOA" {
+            continue;
+        }
+
+        // Names are relative to the zone, with the apex being the empty string.
+        let name = match relative_to(origin, &rrset.name) {
+            Some("@") => "",
+            Some(name) => name,
+            None => {
+                unsupported.push(format!("# {} is outside of {origin}", rrset.name));
+                continue;
+            }
+        };
+
+        let Some(record) = record(&rrset) else {
+            unsupported.push(format!(
+                "# unsupported {} record for {}",
+                rrset.type_, rrset.name
+            ));
+            continue;
+        };
+
+        // Names with a single record map directly to it, while names with
+        // records of multiple types map to a list of them.
+        match names.get_mut(name) {
+            None => {
+                names.insert(name.to_string(), record);
+            }
+            Some(Value::Array(records)) => records.push(record),
+            Some(existing) => *existing = Value::Array(vec![existing.take(), record]),
+        }
+    }
+
+    let mut output = String::new();
+    for comment in unsupported {
+        output.push_str(&comment);
+        output.push('\n');
+    }
+
+    // Unwrap safety: serializing a json Value cannot fail.
+    output.push_str(&serde_yaml::to_string(&names).unwrap());
+    output
+}
+
+fn record(rrset: &RRSet) -> Option<Value> {
+    let values: Vec<Value> = match rrset.type_.as_str() {
+        "A" | "AAAA" | "NS" | "PTR" | "CNAME" | "ALIAS" | "DNAME" => rrset
+            .contents
+            .iter()
+            .map(|content| json!(content))
+            .collect(),
+        // octoDNS chunks long values on its own, but requires semicolons to be escaped.
+        "TXT" | "SPF" => rrset
+            .contents
+            .iter()
+            .map(|content| {
+                let text = String::from_utf8_lossy(&decode_txt(content).concat()).to_string();
+                json!(text.replace(';', "\\;"))
+            })
+            .collect(),
+        "MX" => fields(rrset, |[preference, exchange]| {
+            json!({
+                "preference": preference.parse::<u16>().ok()?,
+                "exchange": exchange,
+            })
+            .into()
+        })?,
+        "SRV" => fields(rrset, |[priority, weight, port, target]| {
+            json!({
+                "priority": priority.parse::<u16>().ok()?,
+                "weight": weight.parse::<u16>().ok()?,
+                "port": port.parse::<u16>().ok()?,
+                "target": target,
+            })
+            .into()
+        })?,
+        "CAA" => rrset
+            .contents
+            .iter()
+            .map(|content| {
+                let (flags, tag, value) = split_caa(content)?;
+                json!({
+                    "flags": flags.parse::<u8>().ok()?,
+                    "tag": tag,
+                    "value": value,
+                })
+                .into()
+            })
+            .collect::<Option<_>>()?,
+        _ => return None,
+    };
+
+    let mut record = json!({
+        "type": rrset.type_,
+        "ttl": rrset.ttl,
+    });
+
+    // Unwrap safety: the record was constructed as an object above.
+    let object = record.as_object_mut().unwrap();
+    match <[Value; 1]>::try_from(values) {
+        Ok([value]) => object.insert(String::from("value"), value),
+        Err(values) => object.insert(String::from("values"), Value::Array(values)),
+    };
+
+    Some(record)
+}
+
+/// Split the contents of each record in the set into exactly `N` fields,
+/// and convert them into values, failing if any record doesn't fit.
+fn fields<const N: usize>(
+    rrset: &RRSet,
+    convert: impl Fn([&str; N]) -> Option<Value>,
+) -> Option<Vec<Value>> {
+    rrset
+        .contents
+        .iter()
+        .map(|content| {
+            let fields: [&str; N] = content
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .try_into()
+                .ok()?;
+
+            convert(fields)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::serde_json::{json, Value};
+    use kubizone_crds::v1alpha1::ZoneEntry;
+
+    use super::serialize;
+
+    fn entry(fqdn: &str, type_: &str, rdata: &str) -> ZoneEntry {
+        ZoneEntry {
+            fqdn: fqdn.to_string(),
+            type_: type_.to_string(),
+            class: String::from("IN"),
+            ttl: 300,
+            rdata: rdata.to_string(),
+        }
+    }
+
+    #[test]
+    fn yaml_provider_config() {
+        let config = serialize(
+            "example.org.",
+            &[
+                entry(
+                    "example.org.",
+                    "SOA",
+                    "ns.example.org. noc.example.org. (2023120100 86400 7200 3600000 360)",
+                ),
+                entry("example.org.", "NS", "ns1"),
+                entry("example.org.", "NS", "ns2"),
+                entry("example.org.", "MX", "10 mail.example.org."),
+                entry("www", "A", "192.168.0.1"),
+                entry("www", "TXT", "v=spf1 -all; comment"),
+                entry(
+                    "www",
+                    "CAA",
+                    "0 issue \"ca.example.net; accounturi=https://ca.example.net/acct/1\"",
+                ),
+                entry("_sip._tcp", "SRV", "10 60 5060 sip.example.com."),
+                entry("example.org.", "HINFO", "\"PC\" \"Linux\""),
+                entry("example.com.", "A", "192.168.0.2"),
+            ],
+        );
+
+        assert!(config.starts_with(
+            "# unsupported HINFO record for example.org.\n\
+             # example.com. is outside of example.org.\n"
+        ));
+
+        let config: Value = serde_yaml::from_str(&config).unwrap();
+        assert_eq!(
+            config,
+            json!({
+                "": [
+                    {
+                        "type": "NS",
+                        "ttl": 300,
+                        "values": ["ns1.example.org.", "ns2.example.org."],
+                    },
+                    {
+                        "type": "MX",
+                        "ttl": 300,
+                        "value": { "preference": 10, "exchange": "mail.example.org." },
+                    },
+                ],
+                "www": [
+                    { "type": "A", "ttl": 300, "value": "192.168.0.1" },
+                    { "type": "TXT", "ttl": 300, "value": "v=spf1 -all\\; comment" },
+                    {
+                        "type": "CAA",
+                        "ttl": 300,
+                        "value": {
+                            "flags": 0,
+                            "tag": "issue",
+                            "value": "ca.example.net; accounturi=https://ca.example.net/acct/1",
+                        },
+                    },
+                ],
+                "_sip._tcp": {
+                    "type": "SRV",
+                    "ttl": 300,
+                    "value": {
+                        "priority": 10,
+                        "weight": 60,
+                        "port": 5060,
+                        "target": "sip.example.com.",
+                    },
+                },
+            })
+        );
+    }
+}
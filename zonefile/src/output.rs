@@ -0,0 +1,443 @@
+use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder};
+use zonefile_crds::{Compression, OutputKind, ZoneFileOutput};
+
+/// Objects are limited to 1 MiB in total, so leave some room for metadata.
+pub const MAX_OBJECT_SIZE: usize = 1000 * 1024;
+
+/// Suffix appended to the keys of compressed zones.
+const COMPRESSED_SUFFIX: &str = ".gz";
+
+/// A zone rendered in the format of a zonefile, ready to be written out.
+pub struct Rendered {
+    /// Name of the [`Zone`](kubizone_crds::v1alpha1::Zone) resource.
+    pub zone: String,
+    pub namespace: String,
+    pub origin: String,
+    pub contents: String,
+}
+
+/// Value stored under a single key of an output object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Content {
+    Text(String),
+    Compressed(Vec<u8>),
+}
+
+impl Content {
+    /// Size of the content as stored, taking into account that binary data,
+    /// and everything stored in secrets, is base64 encoded.
+    fn size(&self, kind: OutputKind) -> usize {
+        let size = match self {
+            Content::Text(text) => text.len(),
+            Content::Compressed(bytes) => bytes.len(),
+        };
+
+        match (kind, self) {
+            (OutputKind::ConfigMap, Content::Text(_)) => size,
+            _ => size.div_ceil(3) * 4,
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        match self {
+            Content::Text(text) => text.into_bytes(),
+            Content::Compressed(bytes) => bytes,
+        }
+    }
+}
+
+/// Object to write, with its zones keyed by their (templated) keys.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Object {
+    pub name: String,
+    pub data: BTreeMap<String, Content>,
+}
+
+/// Substitute `{variable}`s in `template` with their values.
+pub fn template(template: &str, variables: &[(&str, &str)]) -> Result<String, String> {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        output.push_str(&rest[..start]);
+
+        let Some(end) = rest[start..].find('}') else {
+            return Err(format!("unterminated variable in template {template}"));
+        };
+
+        let name = &rest[start + 1..start + end];
+        let Some((_, value)) = variables.iter().find(|(variable, _)| *variable == name) else {
+            return Err(format!(
+                "unknown variable {{{name}}} in template {template}"
+            ));
+        };
+
+        output.push_str(value);
+        rest = &rest[start + end + 1..];
+    }
+
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// Check that `name` is a valid object name, which must be a DNS-1123
+/// subdomain: lowercase alphanumerics, `-` and `.`, starting and ending with
+/// an alphanumeric.
+fn object_name(name: String) -> Result<String, String> {
+    let alphanumeric = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit();
+
+    let valid = name.len() <= 253
+        && name.starts_with(alphanumeric)
+        && name.ends_with(alphanumeric)
+        && name
+            .chars()
+            .all(|c| alphanumeric(c) || c == '-' || c == '.');
+
+    if valid {
+        Ok(name)
+    } else {
+        Err(format!(
+            "invalid object name {name}, which must consist of lowercase alphanumerics, '-' and '.', and start and end with an alphanumeric"
+        ))
+    }
+}
+
+fn compress(text: &str) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::best());
+
+    // Unwrap safety: writing to a Vec cannot fail.
+    encoder.write_all(text.as_bytes()).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// Recover the key and contents of a zone read back from an output object,
+/// decompressing it if necessary.
+pub fn decode(key: &str, bytes: &[u8]) -> Option<(String, String)> {
+    match key.strip_suffix(COMPRESSED_SUFFIX) {
+        Some(key) => {
+            let mut contents = String::new();
+            GzDecoder::new(bytes).read_to_string(&mut contents).ok()?;
+            Some((key.to_string(), contents))
+        }
+        None => Some((key.to_string(), String::from_utf8(bytes.to_vec()).ok()?)),
+    }
+}
+
+/// Hash of the names and contents of `objects`, which changes whenever any of them do.
+pub fn digest(objects: &[Object]) -> String {
+    // 64-bit FNV-1a, like the hashed references, since the hash must be
+    // stable across controller versions to avoid needless rollouts.
+    let mut hash = 0xcbf29ce484222325u64;
+    let mut write = |bytes: &[u8]| {
+        for &byte in bytes.iter().chain([0].iter()) {
+            hash = (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3);
+        }
+    };
+
+    for object in objects {
+        write(object.name.as_bytes());
+        for (key, content) in &object.data {
+            write(key.as_bytes());
+            match content {
+                Content::Text(text) => write(text.as_bytes()),
+                Content::Compressed(bytes) => write(bytes),
+            }
+        }
+    }
+
+    format!("{hash:016x}")
+}
+
+/// Determines which objects, and keys within them, zones are written to.
+pub struct Layout<'a> {
+    pub output: &'a ZoneFileOutput,
+    /// Name of the [`ZoneFile`](zonefile_crds::ZoneFile).
+    pub zonefile: &'a str,
+    /// Name of the object to write all zones to, unless overridden by the template.
+    pub default_name: &'a str,
+}
+
+impl Layout<'_> {
+    fn variables<'a>(
+        &'a self,
+        zone: &'a str,
+        namespace: &'a str,
+        origin: &'a str,
+    ) -> [(&'a str, &'a str); 5] {
+        [
+            ("zonefile", self.zonefile),
+            ("zone", zone),
+            ("namespace", namespace),
+            ("origin", origin),
+            ("domain", origin.strip_suffix('.').unwrap_or(origin)),
+        ]
+    }
+
+    /// Key of the zone within its object, excluding the suffix of compressed zones.
+    pub fn key(&self, zone: &str, namespace: &str, origin: &str) -> Result<String, String> {
+        template(
+            self.output.key.as_deref().unwrap_or("{origin}"),
+            &self.variables(zone, namespace, origin),
+        )
+    }
+
+    fn name(&self, rendered: &Rendered) -> Result<String, String> {
+        if self.output.per_zone {
+            template(
+                self.output.name.as_deref().unwrap_or("{zonefile}-{zone}"),
+                &self.variables(&rendered.zone, &rendered.namespace, &rendered.origin),
+            )
+            .and_then(object_name)
+        } else {
+            self.shared_name()
+        }
+    }
+
+    fn shared_name(&self) -> Result<String, String> {
+        match &self.output.name {
+            Some(name) => template(name, &[("zonefile", self.zonefile)]).and_then(object_name),
+            None => Ok(self.default_name.to_string()),
+        }
+    }
+
+    /// Lay out the `zones` across objects, compressing and spreading them
+    /// across additional objects as needed to stay within [`MAX_OBJECT_SIZE`].
+    pub fn objects(&self, zones: &[Rendered]) -> Result<Vec<Object>, String> {
+        let kind = self.output.kind;
+        let mut groups: BTreeMap<String, Vec<(String, Content)>> = BTreeMap::new();
+
+        // A single shared object is written even when there are no zones,
+        // so consumers mounting it don't fail.
+        if !self.output.per_zone {
+            groups.insert(self.shared_name()?, Vec::new());
+        }
+
+        for rendered in zones {
+            let key = self.key(&rendered.zone, &rendered.namespace, &rendered.origin)?;
+            let text = Content::Text(rendered.contents.clone());
+
+            let compressed = match self.output.compression {
+                Compression::None => false,
+                Compression::Gzip => true,
+                Compression::Auto => key.len() + text.size(kind) > MAX_OBJECT_SIZE,
+            };
+
+            let entry = if compressed {
+                (
+                    format!("{key}{COMPRESSED_SUFFIX}"),
+                    Content::Compressed(compress(&rendered.contents)),
+                )
+            } else {
+                (key, text)
+            };
+
+            // Zones are never split across objects, so those which don't fit
+            // into one on their own can't be written at all.
+            let size = entry.0.len() + entry.1.size(kind);
+            if size > MAX_OBJECT_SIZE {
+                return Err(format!(
+                    "zone {}/{} takes up {size} bytes, more than the {MAX_OBJECT_SIZE} bytes fitting in an object{}",
+                    rendered.namespace,
+                    rendered.zone,
+                    if compressed { "" } else { ", consider compressing it" }
+                ));
+            }
+
+            groups.entry(self.name(rendered)?).or_default().push(entry);
+        }
+
+        let mut objects = Vec::new();
+        for (name, entries) in groups {
+            let mut shards = vec![BTreeMap::new()];
+            let mut size = 0;
+
+            for (key, content) in entries {
+                let entry_size = key.len() + content.size(kind);
+
+                // Unwrap safety: there is always at least one shard.
+                if size + entry_size > MAX_OBJECT_SIZE && !shards.last().unwrap().is_empty() {
+                    shards.push(BTreeMap::new());
+                    size = 0;
+                }
+
+                size += entry_size;
+                shards.last_mut().unwrap().insert(key, content);
+            }
+
+            objects.extend(shards.into_iter().enumerate().map(|(shard, data)| Object {
+                name: match shard {
+                    0 => name.clone(),
+                    shard => format!("{name}-{shard}"),
+                },
+                data,
+            }));
+        }
+
+        Ok(objects)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zonefile_crds::{Compression, ZoneFileOutput};
+
+    use super::{decode, digest, template, Content, Layout, Rendered, MAX_OBJECT_SIZE};
+
+    fn rendered(zone: &str, origin: &str, size: usize) -> Rendered {
+        Rendered {
+            zone: zone.to_string(),
+            namespace: String::from("default"),
+            origin: origin.to_string(),
+            contents: "a".repeat(size),
+        }
+    }
+
+    #[test]
+    fn templates() {
+        let variables = [("zonefile", "ns1"), ("domain", "example.org")];
+        assert_eq!(
+            template("{zonefile}-{domain}.zone", &variables).unwrap(),
+            "ns1-example.org.zone"
+        );
+        assert!(template("{zone}", &variables).is_err());
+        assert!(template("{zone", &variables).is_err());
+    }
+
+    #[test]
+    fn per_zone_objects() {
+        let output = ZoneFileOutput {
+            per_zone: true,
+            key: Some(String::from("{domain}.zone")),
+            compression: Compression::Gzip,
+            ..Default::default()
+        };
+
+        let layout = Layout {
+            output: &output,
+            zonefile: "ns1",
+            default_name: "ns1",
+        };
+
+        let objects = layout
+            .objects(&[
+                rendered("example-org", "example.org.", 10),
+                rendered("example-com", "example.com.", 10),
+            ])
+            .unwrap();
+
+        assert_eq!(objects.len(), 2);
+        assert_eq!(objects[0].name, "ns1-example-com");
+
+        let (key, Content::Compressed(bytes)) = objects[1].data.first_key_value().unwrap() else {
+            panic!("zone was not compressed");
+        };
+        assert_eq!(
+            decode(key, bytes),
+            Some((String::from("example.org.zone"), "a".repeat(10)))
+        );
+
+        // Zone variables are meaningless when all zones share an object.
+        let output = ZoneFileOutput {
+            name: Some(String::from("{zone}")),
+            ..Default::default()
+        };
+        assert!(Layout {
+            output: &output,
+            ..layout
+        }
+        .objects(&[])
+        .is_err());
+    }
+
+    #[test]
+    fn sharding() {
+        let output = ZoneFileOutput {
+            compression: Compression::None,
+            ..Default::default()
+        };
+
+        let layout = Layout {
+            output: &output,
+            zonefile: "ns1",
+            default_name: "ns1",
+        };
+
+        let objects = layout
+            .objects(&[
+                rendered("a", "a.org.", MAX_OBJECT_SIZE / 2),
+                rendered("b", "b.org.", MAX_OBJECT_SIZE / 2),
+                rendered("c", "c.org.", 10),
+            ])
+            .unwrap();
+
+        assert_eq!(
+            objects
+                .iter()
+                .map(|object| (object.name.as_str(), object.data.len()))
+                .collect::<Vec<_>>(),
+            [("ns1", 1), ("ns1-1", 2)]
+        );
+
+        // Without any zones, the shared object is still written.
+        assert_eq!(layout.objects(&[]).unwrap()[0].name, "ns1");
+
+        // Zones which don't fit into an object by themselves are refused.
+        let error = layout
+            .objects(&[rendered("huge", "huge.org.", MAX_OBJECT_SIZE)])
+            .unwrap_err();
+        assert!(error.contains("zone default/huge"), "{error}");
+    }
+
+    #[test]
+    fn object_names() {
+        let output = ZoneFileOutput {
+            per_zone: true,
+            name: Some(String::from("{origin}")),
+            ..Default::default()
+        };
+
+        let layout = Layout {
+            output: &output,
+            zonefile: "ns1",
+            default_name: "ns1",
+        };
+
+        // Origins keep their trailing dot, unlike domains.
+        assert!(layout
+            .objects(&[rendered("example-org", "example.org.", 10)])
+            .is_err());
+
+        let output = ZoneFileOutput {
+            name: Some(String::from("{domain}")),
+            ..output.clone()
+        };
+        let objects = Layout {
+            output: &output,
+            ..layout
+        }
+        .objects(&[rendered("example-org", "example.org.", 10)])
+        .unwrap();
+        assert_eq!(objects[0].name, "example.org");
+    }
+
+    #[test]
+    fn digests() {
+        let output = ZoneFileOutput::default();
+        let layout = Layout {
+            output: &output,
+            zonefile: "ns1",
+            default_name: "ns1",
+        };
+
+        let before = layout.objects(&[rendered("a", "a.org.", 10)]).unwrap();
+        let after = layout.objects(&[rendered("a", "a.org.", 11)]).unwrap();
+
+        assert_eq!(digest(&before), digest(&before.clone()));
+        assert_ne!(digest(&before), digest(&after));
+    }
+}
@@ -0,0 +1,600 @@
+use std::{fmt::Display, path::Path};
+
+use crate::rfc1035::{absolute, domain_name_fields};
+
+/// A single resource record read from a zone file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Fully qualified owner name of the record.
+    pub name: String,
+    pub ttl: u32,
+    pub class: String,
+    pub type_: String,
+    /// Fields of the record data in presentation format. Quoted strings keep
+    /// their quotes and escapes, and domain names are fully qualified.
+    pub rdata: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Logical line of a zone file, with parenthesized continuations joined
+/// together and comments removed.
+struct Line {
+    number: usize,
+    /// Lines starting with whitespace belong to the previous owner name.
+    indented: bool,
+    tokens: Vec<String>,
+}
+
+fn error(line: usize, message: impl Into<String>) -> ParseError {
+    ParseError {
+        line,
+        message: message.into(),
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<Line>, ParseError> {
+    let mut lines = Vec::new();
+    let mut tokens = Vec::new();
+    let mut number = 1;
+    let mut start = 1;
+    let mut depth = 0;
+    let mut indented = input.starts_with([' ', '\t']);
+
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => {
+                number += 1;
+                if depth == 0 {
+                    if !tokens.is_empty() {
+                        lines.push(Line {
+                            number: start,
+                            indented,
+                            tokens: std::mem::take(&mut tokens),
+                        });
+                    }
+
+                    start = number;
+                    indented = matches!(chars.peek(), Some(' ' | '\t'));
+                }
+            }
+            ' ' | '\t' | '\r' => (),
+            ';' => while chars.next_if(|c| *c != '\n').is_some() {},
+            '(' => depth += 1,
+            ')' => {
+                if depth == 0 {
+                    return Err(error(number, "unbalanced closing parenthesis"));
+                }
+                depth -= 1;
+            }
+            '"' => {
+                let mut token = String::from('"');
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => {
+                            token.push('\\');
+                            token.extend(chars.next());
+                        }
+                        Some('\n') | None => {
+                            return Err(error(number, "unterminated quoted string"))
+                        }
+                        Some(c) => token.push(c),
+                    }
+                }
+                token.push('"');
+                tokens.push(token);
+            }
+            _ => {
+                let mut token = String::from(c);
+                if c == '\\' {
+                    token.extend(chars.next());
+                }
+
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !";()\"".contains(*c)) {
+                    token.push(c);
+                    if c == '\\' {
+                        token.extend(chars.next());
+                    }
+                }
+                tokens.push(token);
+            }
+        }
+    }
+
+    if depth != 0 {
+        return Err(error(start, "unbalanced opening parenthesis"));
+    }
+
+    if !tokens.is_empty() {
+        lines.push(Line {
+            number: start,
+            indented,
+            tokens,
+        });
+    }
+
+    Ok(lines)
+}
+
+/// Parse a TTL, which is either a number of seconds, or a sequence of
+/// numbers suffixed with one of the units `s`, `m`, `h`, `d` or `w`, like `1h30m`.
+fn parse_ttl(token: &str) -> Option<u32> {
+    if let Ok(seconds) = token.parse() {
+        return Some(seconds);
+    }
+
+    let mut total: u32 = 0;
+    let mut value: Option<u32> = None;
+    for c in token.chars() {
+        match c.to_ascii_lowercase() {
+            digit @ '0'..='9' => {
+                value = Some(value.unwrap_or(0).checked_mul(10)? + digit.to_digit(10)?)
+            }
+            unit => {
+                let multiplier = match unit {
+                    's' => 1,
+                    'm' => 60,
+                    'h' => 3600,
+                    'd' => 86400,
+                    'w' => 604800,
+                    _ => return None,
+                };
+                total = total.checked_add(value.take()?.checked_mul(multiplier)?)?;
+            }
+        }
+    }
+
+    // Trailing numbers without a unit are not allowed once units are used.
+    value.is_none().then_some(total)
+}
+
+fn is_class(token: &str) -> bool {
+    matches!(token.to_uppercase().as_str(), "IN" | "CH" | "CS" | "HS")
+}
+
+/// Nesting limit for `$INCLUDE` directives, which also guards against include cycles.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// State carried between the lines of a zone file.
+struct Parser<'a> {
+    origin: String,
+    default_ttl: Option<u32>,
+    previous: Option<Entry>,
+    /// Directory which relative `$INCLUDE` paths are resolved against, or
+    /// `None` if the zone file was not read from disk, disallowing includes.
+    directory: Option<&'a Path>,
+    depth: usize,
+    entries: Vec<Entry>,
+}
+
+/// Parse the zone file `input`, resolving relative names against `origin`.
+///
+/// Since `input` was not read from a file, `$INCLUDE` directives are rejected.
+pub fn parse(input: &str, origin: &str) -> Result<Vec<Entry>, ParseError> {
+    let mut parser = Parser::new(absolute(origin, "."), None, 0);
+    parser.parse(input)?;
+    Ok(parser.entries)
+}
+
+/// Parse the zone file `input`, resolving relative names against `origin`,
+/// and `$INCLUDE` paths against `directory`, typically the one containing the zone file.
+pub fn parse_with_includes(
+    input: &str,
+    origin: &str,
+    directory: &Path,
+) -> Result<Vec<Entry>, ParseError> {
+    let mut parser = Parser::new(absolute(origin, "."), Some(directory), 0);
+    parser.parse(input)?;
+    Ok(parser.entries)
+}
+
+impl<'a> Parser<'a> {
+    fn new(origin: String, directory: Option<&'a Path>, depth: usize) -> Self {
+        Parser {
+            origin,
+            default_ttl: None,
+            previous: None,
+            directory,
+            depth,
+            entries: Vec::new(),
+        }
+    }
+
+    fn parse(&mut self, input: &str) -> Result<(), ParseError> {
+        for line in tokenize(input)? {
+            let mut tokens = line.tokens.into_iter().peekable();
+
+            // Unwrap safety: lines are never empty.
+            let first = tokens.peek().unwrap().clone();
+
+            match first.to_uppercase().as_str() {
+                "$ORIGIN" => {
+                    tokens.next();
+                    let name = tokens
+                        .next()
+                        .ok_or_else(|| error(line.number, "$ORIGIN without a domain name"))?;
+                    self.origin = absolute(&name, &self.origin);
+                }
+                "$TTL" => {
+                    tokens.next();
+                    self.default_ttl = Some(
+                        tokens
+                            .next()
+                            .as_deref()
+                            .and_then(parse_ttl)
+                            .ok_or_else(|| error(line.number, "$TTL without a valid TTL"))?,
+                    );
+                }
+                "$INCLUDE" => {
+                    tokens.next();
+                    let file = tokens
+                        .next()
+                        .ok_or_else(|| error(line.number, "$INCLUDE without a file name"))?;
+                    let origin = tokens.next();
+                    self.include(line.number, file.trim_matches('"'), origin.as_deref())?;
+                }
+                "$GENERATE" => {
+                    tokens.next();
+                    self.generate(line.number, tokens.collect())?;
+                }
+                directive if directive.starts_with('$') => {
+                    return Err(error(line.number, format!("unsupported directive {first}")));
+                }
+                _ => {
+                    let name = if line.indented {
+                        self.previous
+                            .as_ref()
+                            .map(|previous| previous.name.clone())
+                            .ok_or_else(|| error(line.number, "first record has no owner name"))?
+                    } else {
+                        // Unwrap safety: we peeked at the token above.
+                        absolute(&tokens.next().unwrap(), &self.origin)
+                    };
+
+                    self.record(line.number, name, tokens)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Parse the remainder of a record line, following its owner `name`.
+    fn record(
+        &mut self,
+        number: usize,
+        name: String,
+        tokens: impl Iterator<Item = String>,
+    ) -> Result<(), ParseError> {
+        let mut tokens = tokens.peekable();
+
+        // The TTL and class are both optional, and may appear in either order.
+        let mut ttl = None;
+        let mut class = None;
+        while let Some(token) = tokens.peek() {
+            if ttl.is_none() && parse_ttl(token).is_some() {
+                ttl = parse_ttl(token);
+            } else if class.is_none() && is_class(token) {
+                class = Some(token.to_uppercase());
+            } else {
+                break;
+            }
+            tokens.next();
+        }
+
+        let type_ = tokens
+            .next()
+            .ok_or_else(|| error(number, "record without a type"))?
+            .to_uppercase();
+
+        let fields = domain_name_fields(&type_);
+        let rdata = tokens
+            .enumerate()
+            .map(|(index, field)| {
+                if fields.contains(&index) {
+                    absolute(&field, &self.origin)
+                } else {
+                    field
+                }
+            })
+            .collect();
+
+        let previous = self.previous.as_ref();
+        let entry = Entry {
+            name,
+            ttl: ttl
+                .or(self.default_ttl)
+                .or(previous.map(|previous| previous.ttl))
+                .ok_or_else(|| error(number, "record without a TTL, and no $TTL set"))?,
+            class: class
+                .or(previous.map(|previous| previous.class.clone()))
+                .unwrap_or_else(|| String::from("IN")),
+            type_,
+            rdata,
+        };
+
+        self.previous = Some(entry.clone());
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Parse the included `file` in a parser of its own, since changes to the
+    /// origin within it must not affect the including file, as per RFC 1035.
+    fn include(
+        &mut self,
+        number: usize,
+        file: &str,
+        origin: Option<&str>,
+    ) -> Result<(), ParseError> {
+        let Some(directory) = self.directory else {
+            return Err(error(
+                number,
+                "$INCLUDE is only supported when reading from a file",
+            ));
+        };
+
+        if self.depth >= MAX_INCLUDE_DEPTH {
+            return Err(error(
+                number,
+                format!("$INCLUDE nested more than {MAX_INCLUDE_DEPTH} levels deep"),
+            ));
+        }
+
+        let path = directory.join(file);
+        let input = std::fs::read_to_string(&path)
+            .map_err(|err| error(number, format!("failed to read {file}: {err}")))?;
+
+        let origin = match origin {
+            Some(origin) => absolute(origin, &self.origin),
+            None => self.origin.clone(),
+        };
+
+        let mut parser = Parser::new(origin, path.parent(), self.depth + 1);
+        parser.default_ttl = self.default_ttl;
+        parser
+            .parse(&input)
+            .map_err(|err| error(number, format!("in {file}, {err}")))?;
+
+        self.entries.extend(parser.entries);
+        Ok(())
+    }
+
+    /// Expand a BIND-style `$GENERATE range lhs [ttl] [class] type rhs` directive
+    /// into one record per step of the range, substituting the iterator into
+    /// the owner name and record data.
+    fn generate(&mut self, number: usize, tokens: Vec<String>) -> Result<(), ParseError> {
+        let [range, lhs, rest @ ..] = &tokens[..] else {
+            return Err(error(
+                number,
+                "$GENERATE requires a range, owner name and record",
+            ));
+        };
+
+        let (start, stop, step) = parse_range(range)
+            .ok_or_else(|| error(number, format!("invalid $GENERATE range {range}")))?;
+
+        let mut iterator = start;
+        while iterator <= stop {
+            let name = absolute(&substitute(number, lhs, iterator)?, &self.origin);
+            let tokens = rest
+                .iter()
+                .map(|token| substitute(number, token, iterator))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            self.record(number, name, tokens.into_iter())?;
+
+            let Some(next) = iterator.checked_add(step) else {
+                break;
+            };
+            iterator = next;
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse a `$GENERATE` range of the form `start-stop` or `start-stop/step`.
+fn parse_range(range: &str) -> Option<(u32, u32, u32)> {
+    let (range, step) = match range.split_once('/') {
+        Some((range, step)) => (range, step.parse().ok()?),
+        None => (range, 1),
+    };
+
+    let (start, stop) = range.split_once('-')?;
+    let (start, stop) = (start.parse().ok()?, stop.parse().ok()?);
+
+    (start <= stop && step > 0).then_some((start, stop, step))
+}
+
+/// Replace each `$` in `template` with the `$GENERATE` iterator, optionally
+/// modified as `${offset[,width[,base]]}`, where base is one of `d`, `o`, `x` or `X`.
+/// Escaped dollar signs are left in place, as `\$`.
+fn substitute(number: usize, template: &str, iterator: u32) -> Result<String, ParseError> {
+    let mut output = String::with_capacity(template.len());
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                output.push(c);
+                output.extend(chars.next());
+            }
+            '$' => {
+                let mut modifier = String::new();
+                if chars.next_if_eq(&'{').is_some() {
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => modifier.push(c),
+                            None => {
+                                return Err(error(
+                                    number,
+                                    format!("unterminated modifier in {template}"),
+                                ))
+                            }
+                        }
+                    }
+                }
+
+                output.push_str(&format_iterator(&modifier, iterator).ok_or_else(|| {
+                    error(number, format!("invalid $GENERATE modifier {{{modifier}}}"))
+                })?);
+            }
+            c => output.push(c),
+        }
+    }
+
+    Ok(output)
+}
+
+fn format_iterator(modifier: &str, iterator: u32) -> Option<String> {
+    let mut parts = modifier.split(',');
+    let offset: i64 = match parts.next() {
+        Some("") | None => 0,
+        Some(offset) => offset.parse().ok()?,
+    };
+    let width: usize = match parts.next() {
+        Some(width) => width.parse().ok()?,
+        None => 0,
+    };
+    let value = u32::try_from(i64::from(iterator) + offset).ok()?;
+
+    Some(match parts.next().unwrap_or("d") {
+        "d" => format!("{value:0width$}"),
+        "o" => format!("{value:0width$o}"),
+        "x" => format!("{value:0width$x}"),
+        "X" => format!("{value:0width$X}"),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, parse_ttl, parse_with_includes};
+
+    #[test]
+    fn ttl_units() {
+        assert_eq!(parse_ttl("3600"), Some(3600));
+        assert_eq!(parse_ttl("1h30m"), Some(5400));
+        assert_eq!(parse_ttl("1W"), Some(604800));
+        assert_eq!(parse_ttl("1h30"), None);
+        assert_eq!(parse_ttl("IN"), None);
+    }
+
+    #[test]
+    fn directives_and_continuations() {
+        let entries = parse(
+            "$TTL 1h\n\
+             @ IN SOA ns noc ( 1 ; serial\n\
+                 2 3 4 5 )\n\
+             \x20 NS ns.example.org. ; comment\n\
+             $ORIGIN sub.example.org.\n\
+             www 60 A 192.168.0.1\n",
+            "example.org",
+        )
+        .unwrap();
+
+        assert_eq!(entries.len(), 3);
+
+        assert_eq!(entries[0].name, "example.org.");
+        assert_eq!(entries[0].ttl, 3600);
+        assert_eq!(
+            entries[0].rdata,
+            [
+                "ns.example.org.",
+                "noc.example.org.",
+                "1",
+                "2",
+                "3",
+                "4",
+                "5"
+            ]
+        );
+
+        // Indented lines inherit the owner name of the previous record.
+        assert_eq!(entries[1].name, "example.org.");
+        assert_eq!(entries[1].type_, "NS");
+
+        assert_eq!(entries[2].name, "www.sub.example.org.");
+        assert_eq!(entries[2].ttl, 60);
+        assert_eq!(entries[2].class, "IN");
+    }
+
+    #[test]
+    fn generate() {
+        let entries = parse(
+            "$TTL 300\n\
+             $GENERATE 1-3 host-$ A 10.0.0.$\n\
+             $GENERATE 8-24/8 ${-8,3,x}.rev PTR host-$.example.org.\n",
+            "example.org.",
+        )
+        .unwrap();
+
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| format!("{} {} {}", entry.name, entry.type_, entry.rdata.join(" ")))
+                .collect::<Vec<_>>(),
+            [
+                "host-1.example.org. A 10.0.0.1",
+                "host-2.example.org. A 10.0.0.2",
+                "host-3.example.org. A 10.0.0.3",
+                "000.rev.example.org. PTR host-8.example.org.",
+                "008.rev.example.org. PTR host-16.example.org.",
+                "010.rev.example.org. PTR host-24.example.org.",
+            ]
+        );
+    }
+
+    #[test]
+    fn include() {
+        let directory =
+            std::env::temp_dir().join(format!("zonefile-include-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(
+            directory.join("hosts.zone"),
+            "$ORIGIN hosts\nwww A 192.168.0.1\n",
+        )
+        .unwrap();
+
+        let entries = parse_with_includes(
+            "$TTL 300\n\
+             $INCLUDE hosts.zone\n\
+             $INCLUDE \"hosts.zone\" sub\n\
+             mail A 192.168.0.2\n",
+            "example.org.",
+            &directory,
+        );
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(
+            entries
+                .unwrap()
+                .iter()
+                .map(|entry| entry.name.as_str())
+                .collect::<Vec<_>>(),
+            [
+                "www.hosts.example.org.",
+                "www.hosts.sub.example.org.",
+                // The $ORIGIN of the included file does not leak into the including file.
+                "mail.example.org.",
+            ]
+        );
+
+        assert!(parse("$INCLUDE hosts.zone\n", "example.org.").is_err());
+    }
+}
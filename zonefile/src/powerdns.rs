@@ -0,0 +1,90 @@
+use k8s_openapi::serde_json::{self, json};
+use kubizone_crds::v1alpha1::ZoneEntry;
+
+use crate::rrset;
+
+/// Serialize `entries` of the zone `origin` into the zone JSON accepted by the
+/// PowerDNS HTTP API, either when creating a zone, or when replacing its RRsets.
+pub fn serialize(origin: &str, entries: &[ZoneEntry]) -> String {
+    // PowerDNS only supports a single TTL per RRset, and rejects duplicate records.
+    let rrsets = rrset::group(origin, entries);
+
+    let zone = json!({
+        "name": origin,
+        "kind": "Native",
+        "rrsets": rrsets.iter().map(|rrset| json!({
+            "name": rrset.name,
+            "type": rrset.type_,
+            "ttl": rrset.ttl,
+            "changetype": "REPLACE",
+            "records": rrset.contents.iter().map(|content| json!({
+                "content": content,
+                "disabled": false,
+            })).collect::<Vec<_>>(),
+        })).collect::<Vec<_>>(),
+    });
+
+    // Unwrap safety: serializing a json Value cannot fail.
+    serde_json::to_string_pretty(&zone).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::serde_json::{self, json, Value};
+    use kubizone_crds::v1alpha1::ZoneEntry;
+
+    use super::serialize;
+
+    fn entry(fqdn: &str, ttl: u32, type_: &str, rdata: &str) -> ZoneEntry {
+        ZoneEntry {
+            fqdn: fqdn.to_string(),
+            type_: type_.to_string(),
+            class: String::from("IN"),
+            ttl,
+            rdata: rdata.to_string(),
+        }
+    }
+
+    #[test]
+    fn grouped_into_rrsets() {
+        let zone: Value = serde_json::from_str(&serialize(
+            "example.org.",
+            &[
+                entry("www", 300, "A", "192.168.0.1"),
+                entry("www.example.org.", 60, "A", "192.168.0.2"),
+                entry("www.example.org.", 60, "A", "192.168.0.2"),
+                entry("example.org.", 360, "TXT", "hello world"),
+            ],
+        ))
+        .unwrap();
+
+        assert_eq!(
+            zone,
+            json!({
+                "name": "example.org.",
+                "kind": "Native",
+                "rrsets": [
+                    {
+                        "name": "www.example.org.",
+                        "type": "A",
+                        "ttl": 60,
+                        "changetype": "REPLACE",
+                        "records": [
+                            { "content": "192.168.0.1", "disabled": false },
+                            { "content": "192.168.0.2", "disabled": false },
+                        ]
+                    },
+                    {
+                        "name": "example.org.",
+                        "type": "TXT",
+                        "ttl": 360,
+                        "changetype": "REPLACE",
+                        "records": [
+                            { "content": "\"hello world\"", "disabled": false },
+                        ]
+                    }
+                ]
+            })
+        );
+    }
+}
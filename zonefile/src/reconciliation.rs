@@ -1,89 +1,149 @@
 use futures::StreamExt;
-use kubizone_crds::v1alpha1::{Zone, ZoneEntry};
-use zonefile_crds::{ZoneFile, TARGET_ZONEFILE_LABEL};
+use kubizone_common::{Backoff, Metrics, Scope};
+use kubizone_crds::v1alpha1::{set_condition, Condition, Zone, ZoneRef};
+use zonefile_crds::{
+    IncludedZone, OutputKind, WorkloadKind, ZoneFile, ZoneFileFormat, ZoneState,
+    TARGET_ZONEFILE_ANNOTATION, TARGET_ZONEFILE_LABEL, ZONEFILE_OUTPUT_LABEL,
+};
 
-use k8s_openapi::{api::core::v1::ConfigMap, serde_json::json};
+use k8s_openapi::{
+    api::{
+        apps::v1::{DaemonSet, Deployment, StatefulSet},
+        core::v1::{ConfigMap, PodTemplateSpec, Secret},
+    },
+    serde::de::DeserializeOwned,
+    serde_json::json,
+    ByteString,
+};
 use kube::{
-    api::{Patch, PatchParams},
-    core::ObjectMeta,
-    runtime::{controller::Action, watcher, Controller},
+    api::{DeleteParams, ListParams, Patch, PatchParams},
+    core::{NamespaceResourceScope, ObjectMeta},
+    runtime::{controller::Action, reflector::ObjectRef, Controller},
     Api, Client, Resource, ResourceExt,
 };
-use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use std::{collections::BTreeMap, fmt::Debug, sync::Arc, time::Duration};
 use tracing::log::*;
 
+use crate::{
+    dnscontrol,
+    error::{Error, RECONCILED_CONDITION, SUCCESS},
+    knot, octodns,
+    output::{self, Content, Layout, Object, Rendered},
+    parser, powerdns, rfc1035, tinydns,
+};
+
 struct Data {
     client: Client,
+    scope: Scope,
+    backoff: Backoff,
+    metrics: Arc<Metrics>,
+    /// Interval at which successfully reconciled zonefiles are re-checked.
+    requeue: Duration,
 }
 
 pub const CONTROLLER_NAME: &str = "kubi.zone/zonefile";
 
-fn build_zonefile(origin: &str, entries: &[ZoneEntry]) -> String {
-    // We use the longest domain name in the list for
-    // aligning the text in the output zonefile
-    let longest_name_length = entries
-        .iter()
-        .map(|entry| entry.fqdn.len())
-        .max()
+/// Serialize the entries of `zone` in the format requested by the `zonefile`.
+fn build_zonefile(zonefile: &ZoneFile, zone: &Zone, origin: &str) -> String {
+    render(
+        zonefile.spec.format,
+        zonefile.spec.relative_rdata,
+        zone,
+        origin,
+    )
+}
+
+/// Serialize the entries of `zone` in `format`, optionally writing domain
+/// names within record data relative to the `origin`.
+pub fn render(format: ZoneFileFormat, relative_rdata: bool, zone: &Zone, origin: &str) -> String {
+    let entries = zone
+        .status
+        .as_ref()
+        .map(|status| status.entries.as_slice())
         .unwrap_or_default();
 
-    let origin_suffix = format!(".{origin}");
+    match format {
+        ZoneFileFormat::Rfc1035 => validated(
+            zone,
+            rfc1035::serialize(origin, zone.spec.ttl, entries, relative_rdata),
+            origin,
+        ),
+        ZoneFileFormat::Knot => validated(zone, knot::serialize(origin, entries), origin),
+        ZoneFileFormat::Tinydns => tinydns::serialize(origin, entries),
+        ZoneFileFormat::PowerDns => powerdns::serialize(origin, entries),
+        ZoneFileFormat::Octodns => octodns::serialize(origin, entries),
+        ZoneFileFormat::Dnscontrol => dnscontrol::serialize(origin, entries),
+    }
+}
 
-    let serialized_records = entries
-        .iter()
-        .map(
-            |ZoneEntry {
-                 fqdn,
-                 type_,
-                 class,
-                 ttl,
-                 rdata,
-                 ..
-             }| {
-                let fqdn = fqdn.strip_suffix(&origin_suffix).unwrap_or(fqdn);
-
-                format!(
-                    "{fqdn:<width$} {ttl:<8} {class:<5} {type_:<6} {rdata}",
-                    width = longest_name_length
-                )
-            },
+/// Parse the zone file produced for `zone` back, to catch record data which
+/// would cause name servers to reject the zone, before they get to load it.
+fn validated(zone: &Zone, zonefile: String, origin: &str) -> String {
+    if let Err(err) = parser::parse(&zonefile, origin) {
+        warn!("zonefile produced for zone {zone} is invalid, {err}");
+    }
+
+    zonefile
+}
+
+/// Remove the [`TARGET_ZONEFILE_LABEL`] and [`TARGET_ZONEFILE_ANNOTATION`] previously
+/// used as backreferences from `zone`, which could only ever point to a single
+/// [`ZoneFile`], and are no longer used to trigger reconciliation.
+async fn remove_legacy_zonefile_backref(client: Client, zone: &Zone) -> Result<(), kube::Error> {
+    if !zone.labels().contains_key(TARGET_ZONEFILE_LABEL)
+        && !zone.annotations().contains_key(TARGET_ZONEFILE_ANNOTATION)
+    {
+        return Ok(());
+    }
+
+    info!("removing legacy {TARGET_ZONEFILE_LABEL} backreference from zone {zone}");
+
+    Api::<Zone>::namespaced(client, zone.namespace().as_ref().unwrap())
+        .patch_metadata(
+            &zone.name_any(),
+            &PatchParams::apply(CONTROLLER_NAME),
+            &Patch::Merge(json!({
+                "metadata": {
+                    "labels": {
+                        TARGET_ZONEFILE_LABEL: null
+                    },
+                    "annotations": {
+                        TARGET_ZONEFILE_ANNOTATION: null
+                    },
+                }
+            })),
         )
-        .collect::<Vec<_>>()
-        .join("\n");
+        .await?;
+
+    Ok(())
+}
 
-    format!("$ORIGIN {origin}\n\n{serialized_records}")
+/// Current time formatted for use in [`Condition`]s.
+fn now() -> String {
+    time::OffsetDateTime::now_utc()
+        .format(&time::format_description::well_known::Rfc3339)
+        .unwrap_or_default()
 }
 
-/// Applied a [`TARGET_ZONEFILE_LABEL`] label which references our zonefile.
-/// This label is monitored by our controller, causing reconciliation loops
-/// to fire for [`ZoneFile`]s referenced by [`Zone`]s, when the zone itself
-/// is updated.
-async fn apply_zonefile_backref(
+async fn set_zonefile_condition(
     client: Client,
     zonefile: &ZoneFile,
-    zone: &Zone,
+    condition: Condition,
 ) -> Result<(), kube::Error> {
-    let zonefile_ref = format!(
-        "{}.{}",
-        zonefile.name_any(),
-        zonefile.namespace().as_ref().unwrap()
-    );
-
-    if zone.labels().get(TARGET_ZONEFILE_LABEL) != Some(&zonefile_ref) {
-        info!(
-            "updating zone {}'s {TARGET_ZONEFILE_LABEL} to {zonefile_ref}",
-            zonefile.name_any()
-        );
+    let mut conditions = zonefile
+        .status
+        .as_ref()
+        .map(|status| status.conditions.clone())
+        .unwrap_or_default();
 
-        Api::<Zone>::namespaced(client, zone.namespace().as_ref().unwrap())
-            .patch_metadata(
-                &zone.name_any(),
+    if set_condition(&mut conditions, condition) {
+        Api::<ZoneFile>::namespaced(client, zonefile.namespace().as_ref().unwrap())
+            .patch_status(
+                &zonefile.name_any(),
                 &PatchParams::apply(CONTROLLER_NAME),
                 &Patch::Merge(json!({
-                    "metadata": {
-                        "labels": {
-                            TARGET_ZONEFILE_LABEL: zonefile_ref
-                        },
+                    "status": {
+                        "conditions": conditions,
                     }
                 })),
             )
@@ -93,92 +153,528 @@ async fn apply_zonefile_backref(
     Ok(())
 }
 
-async fn reconcile_zonefiles(
-    zonefile: Arc<ZoneFile>,
-    ctx: Arc<Data>,
-) -> Result<Action, kube::Error> {
-    struct SerializedZone {
-        origin: String,
-        serial: u32,
-        hash: String,
-        contents: String,
-    }
-
-    let mut serialized_zones = Vec::new();
-
-    for zone_ref in &zonefile.spec.zone_refs {
-        let zone = Api::<Zone>::namespaced(
-            ctx.client.clone(),
-            &zone_ref
-                .namespace
-                .as_ref()
-                .or(zonefile.namespace().as_ref())
-                .cloned()
-                .unwrap(),
-        )
-        .get(&zone_ref.name)
-        .await?;
+async fn reconcile_zonefiles(zonefile: Arc<ZoneFile>, ctx: Arc<Data>) -> Result<Action, Error> {
+    let result = reconcile_zonefile(&zonefile, &ctx).await;
 
-        apply_zonefile_backref(ctx.client.clone(), &zonefile, &zone).await?;
+    let condition = match &result {
+        Ok(_) => {
+            ctx.backoff.reset(&zonefile_key(&zonefile));
+            Condition::new(RECONCILED_CONDITION, true, SUCCESS, "", now())
+        }
+        Err(err) => Condition::new(
+            RECONCILED_CONDITION,
+            false,
+            err.category(),
+            err.to_string(),
+            now(),
+        ),
+    };
 
-        let Some(origin) = zone.fqdn() else {
-            debug!("zone {zone} has no fqdn, skipping.");
-            continue;
-        };
+    ctx.metrics.reconciled(
+        "zonefile",
+        result
+            .as_ref()
+            .err()
+            .map(Error::category)
+            .unwrap_or(SUCCESS),
+    );
 
-        let Some(hash) = zone.hash() else {
-            debug!("zone {zone} has not computed its hash yet, skipping");
-            continue;
-        };
+    if let Err(err) = set_zonefile_condition(ctx.client.clone(), &zonefile, condition).await {
+        warn!(
+            "failed to report reconciliation outcome for zonefile {}: {err}",
+            zonefile_key(&zonefile)
+        );
+    }
 
-        let Some(serial) = zone.serial() else {
-            debug!("zone {zone} has not produced a serial yet, skipping");
-            continue;
-        };
+    result
+}
+
+fn zonefile_key(zonefile: &ZoneFile) -> String {
+    format!(
+        "{}/{}",
+        zonefile.namespace().unwrap_or_default(),
+        zonefile.name_any()
+    )
+}
+
+/// Fetch the zones referenced or selected by the `zonefile`, along with
+/// the references to zones which do not exist within the `scope`.
+pub async fn included_zones(
+    client: &Client,
+    scope: &Scope,
+    zonefile: &ZoneFile,
+) -> Result<(Vec<Zone>, Vec<ZoneRef>), kube::Error> {
+    let mut zones = Vec::new();
+    let mut missing = Vec::new();
 
-        let serialized_zone = build_zonefile(origin, &zone.status.as_ref().unwrap().entries);
+    for zone_ref in zonefile.zone_ref() {
+        match scope
+            .get_opt::<Zone>(client, zone_ref.namespace.as_ref().unwrap(), &zone_ref.name)
+            .await?
+        {
+            Some(zone) => zones.push(zone),
+            None => missing.push(zone_ref),
+        }
+    }
 
-        serialized_zones.push(SerializedZone {
-            origin: origin.to_string(),
-            serial,
-            hash: hash.to_string(),
-            contents: serialized_zone,
+    if let Some(selector) = &zonefile.spec.zone_selector {
+        for namespace in zonefile.selected_namespaces() {
+            for zone in scope
+                .list_in::<Zone>(
+                    client,
+                    &namespace,
+                    &ListParams::default().labels(&selector.label_selector()),
+                )
+                .await?
+            {
+                // Zones may be both referenced explicitly and selected.
+                if !zones.iter().any(|existing| existing.uid() == zone.uid()) {
+                    zones.push(zone);
+                }
+            }
+        }
+    }
+
+    Ok((zones, missing))
+}
+
+/// Record that the zone referenced by `zone_ref` is unavailable, falling back to the
+/// last rendering of it, if the `existing` output of the zonefile still holds one.
+fn unavailable(
+    zone_ref: ZoneRef,
+    state: ZoneState,
+    previous: &[IncludedZone],
+    existing: &BTreeMap<String, String>,
+    layout: &Layout,
+    rendered: &mut Vec<Rendered>,
+) -> IncludedZone {
+    let namespace = zone_ref.namespace.clone().unwrap_or_default();
+    let last = previous
+        .iter()
+        .find(|included| included.zone_ref == zone_ref)
+        .and_then(|included| {
+            let origin = included.origin.as_ref()?;
+            let key = layout.key(&zone_ref.name, &namespace, origin).ok()?;
+            Some((included, origin, existing.get(&key)?))
         });
+
+    let Some((last, origin, contents)) = last else {
+        return IncludedZone {
+            zone_ref,
+            state,
+            origin: None,
+            hash: None,
+            serial: None,
+            stale: false,
+        };
+    };
+
+    rendered.push(Rendered {
+        zone: zone_ref.name.clone(),
+        namespace,
+        origin: origin.clone(),
+        contents: contents.clone(),
+    });
+
+    IncludedZone {
+        zone_ref,
+        state,
+        origin: Some(origin.clone()),
+        hash: last.hash.clone(),
+        serial: last.serial,
+        stale: true,
+    }
+}
+
+/// Objects currently written by a zonefile, and the zones within them.
+#[derive(Default)]
+struct ExistingOutput {
+    /// Kind and name of each object labeled as belonging to the zonefile.
+    objects: Vec<(OutputKind, String)>,
+    /// Contents of each zone, keyed by its key within the objects.
+    zones: BTreeMap<String, String>,
+}
+
+impl ExistingOutput {
+    fn add(&mut self, key: &str, bytes: &[u8]) {
+        if let Some((key, contents)) = output::decode(key, bytes) {
+            self.zones.insert(key, contents);
+        }
+    }
+
+    fn add_config_map(&mut self, config_map: ConfigMap) {
+        for (key, text) in config_map.data.unwrap_or_default() {
+            self.add(&key, text.as_bytes());
+        }
+
+        for (key, bytes) in config_map.binary_data.unwrap_or_default() {
+            self.add(&key, &bytes.0);
+        }
+    }
+}
+
+/// Read back the objects labeled as written by the `zonefile`, as well as the
+/// unlabeled `ConfigMap` named `legacy_name` written by earlier versions.
+async fn existing_output(
+    client: Client,
+    zonefile: &ZoneFile,
+    legacy_name: &str,
+) -> Result<ExistingOutput, kube::Error> {
+    let namespace = zonefile.namespace().unwrap();
+    let params = ListParams::default().labels(&format!(
+        "{ZONEFILE_OUTPUT_LABEL}={}",
+        zonefile_ref(zonefile).as_label()
+    ));
+
+    // Labels are hashed, so make sure the objects are actually ours.
+    let owned = |metadata: &ObjectMeta| {
+        metadata
+            .owner_references
+            .iter()
+            .flatten()
+            .any(|owner| Some(&owner.uid) == zonefile.uid().as_ref())
+    };
+
+    let mut existing = ExistingOutput::default();
+
+    let config_maps = Api::<ConfigMap>::namespaced(client.clone(), &namespace);
+    if let Some(config_map) = config_maps.get_opt(legacy_name).await? {
+        if owned(&config_map.metadata) {
+            existing.add_config_map(config_map);
+        }
+    }
+
+    for config_map in config_maps.list(&params).await?.items {
+        if owned(&config_map.metadata) {
+            existing
+                .objects
+                .push((OutputKind::ConfigMap, config_map.name_any()));
+            existing.add_config_map(config_map);
+        }
+    }
+
+    for secret in Api::<Secret>::namespaced(client, &namespace)
+        .list(&params)
+        .await?
+        .items
+    {
+        if owned(&secret.metadata) {
+            existing
+                .objects
+                .push((OutputKind::Secret, secret.name_any()));
+            for (key, bytes) in secret.data.unwrap_or_default() {
+                existing.add(&key, &bytes.0);
+            }
+        }
+    }
+
+    Ok(existing)
+}
+
+/// Write `object` as the `kind` of object configured for the `zonefile`.
+async fn write_output(
+    client: Client,
+    zonefile: &ZoneFile,
+    kind: OutputKind,
+    object: Object,
+) -> Result<(), kube::Error> {
+    let namespace = zonefile.namespace().unwrap();
+    let metadata = ObjectMeta {
+        name: Some(object.name.clone()),
+        namespace: Some(namespace.clone()),
+        owner_references: Some(vec![zonefile.controller_owner_ref(&()).unwrap()]),
+        labels: Some(BTreeMap::from([(
+            ZONEFILE_OUTPUT_LABEL.to_string(),
+            zonefile_ref(zonefile).as_label(),
+        )])),
+        ..ObjectMeta::default()
+    };
+
+    match kind {
+        OutputKind::ConfigMap => {
+            let mut data = BTreeMap::new();
+            let mut binary_data = BTreeMap::new();
+            for (key, content) in object.data {
+                match content {
+                    Content::Text(text) => {
+                        data.insert(key, text);
+                    }
+                    Content::Compressed(bytes) => {
+                        binary_data.insert(key, ByteString(bytes));
+                    }
+                }
+            }
+
+            Api::<ConfigMap>::namespaced(client, &namespace)
+                .patch(
+                    &object.name,
+                    &PatchParams::apply(CONTROLLER_NAME),
+                    &Patch::Apply(ConfigMap {
+                        metadata,
+                        data: Some(data),
+                        binary_data: Some(binary_data),
+                        ..Default::default()
+                    }),
+                )
+                .await?;
+        }
+        OutputKind::Secret => {
+            Api::<Secret>::namespaced(client, &namespace)
+                .patch(
+                    &object.name,
+                    &PatchParams::apply(CONTROLLER_NAME),
+                    &Patch::Apply(Secret {
+                        metadata,
+                        type_: Some(String::from("Opaque")),
+                        data: Some(
+                            object
+                                .data
+                                .into_iter()
+                                .map(|(key, content)| (key, ByteString(content.into_bytes())))
+                                .collect(),
+                        ),
+                        ..Default::default()
+                    }),
+                )
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Stamp `digest` onto the pod template of the workload `name`, rolling it out
+/// unless the template already carries it.
+async fn rollout<K>(
+    client: Client,
+    zonefile: &ZoneFile,
+    name: &str,
+    digest: &str,
+    template: impl Fn(K) -> Option<PodTemplateSpec>,
+) -> Result<(), kube::Error>
+where
+    K: Resource<Scope = NamespaceResourceScope, DynamicType = ()>
+        + Clone
+        + DeserializeOwned
+        + Debug,
+{
+    let api = Api::<K>::namespaced(client, zonefile.namespace().as_ref().unwrap());
+    let annotation = zonefile.rollout_annotation();
+
+    let Some(workload) = api.get_opt(name).await? else {
+        warn!(
+            "{} {name} referenced by zonefile {} does not exist",
+            K::kind(&()),
+            zonefile_key(zonefile)
+        );
+        return Ok(());
+    };
+
+    let current = template(workload)
+        .and_then(|template| template.metadata)
+        .and_then(|metadata| metadata.annotations)
+        .and_then(|mut annotations| annotations.remove(&annotation));
+
+    if current.as_deref() == Some(digest) {
+        return Ok(());
+    }
+
+    info!(
+        "rolling out {} {name} for changed output of zonefile {}",
+        K::kind(&()),
+        zonefile_key(zonefile)
+    );
+
+    api.patch(
+        name,
+        &PatchParams::apply(CONTROLLER_NAME),
+        &Patch::Merge(json!({
+            "spec": {
+                "template": {
+                    "metadata": {
+                        "annotations": {
+                            annotation: digest,
+                        },
+                    },
+                },
+            },
+        })),
+    )
+    .await?;
+
+    Ok(())
+}
+
+fn zonefile_ref(zonefile: &ZoneFile) -> ZoneRef {
+    ZoneRef {
+        name: zonefile.name_any(),
+        namespace: zonefile.namespace(),
     }
+}
 
-    let owner_reference = zonefile.controller_owner_ref(&()).unwrap();
-    let configmap_name = zonefile
+async fn reconcile_zonefile(zonefile: &ZoneFile, ctx: &Data) -> Result<Action, Error> {
+    let kind = zonefile.spec.output.kind;
+    let default_name = zonefile
         .spec
         .config_map_name
         .as_ref()
         .cloned()
         .unwrap_or(zonefile.name_any());
 
-    let config_map = ConfigMap {
-        metadata: ObjectMeta {
-            name: Some(configmap_name.clone()),
-            namespace: zonefile.namespace(),
-            owner_references: Some(vec![owner_reference]),
-            ..ObjectMeta::default()
-        },
-        data: Some(BTreeMap::from_iter(serialized_zones.iter().map(
-            |serialized_zone| {
-                (
-                    serialized_zone.origin.clone(),
-                    serialized_zone.contents.clone(),
-                )
-            },
-        ))),
-        ..Default::default()
+    let zonefile_name = zonefile.name_any();
+    let layout = Layout {
+        output: &zonefile.spec.output,
+        zonefile: &zonefile_name,
+        default_name: &default_name,
     };
 
-    Api::<ConfigMap>::namespaced(ctx.client.clone(), zonefile.namespace().as_ref().unwrap())
-        .patch(
-            &configmap_name,
-            &PatchParams::apply(CONTROLLER_NAME),
-            &Patch::Apply(config_map),
-        )
-        .await?;
+    // Zones which are temporarily unavailable keep their last rendering, rather
+    // than disappearing from the output, and thereby the name servers.
+    let existing = existing_output(ctx.client.clone(), zonefile, &default_name).await?;
+
+    let previous = zonefile
+        .status
+        .as_ref()
+        .map(|status| status.zones.clone())
+        .unwrap_or_default();
+
+    let mut included = Vec::new();
+    let mut rendered = Vec::new();
+
+    let (zones, missing) = included_zones(&ctx.client, &ctx.scope, zonefile).await?;
+    for zone_ref in &missing {
+        warn!(
+            "zone {zone_ref} referenced by zonefile {} does not exist",
+            zonefile_key(zonefile)
+        );
+
+        included.push(unavailable(
+            zone_ref.clone(),
+            ZoneState::Missing,
+            &previous,
+            &existing.zones,
+            &layout,
+            &mut rendered,
+        ));
+    }
+
+    for zone in zones {
+        remove_legacy_zonefile_backref(ctx.client.clone(), &zone).await?;
+
+        let state = match (zone.fqdn(), zone.hash(), zone.serial()) {
+            (Some(origin), Some(hash), Some(serial)) => {
+                rendered.push(Rendered {
+                    zone: zone.name_any(),
+                    namespace: zone.namespace().unwrap_or_default(),
+                    origin: origin.to_string(),
+                    contents: build_zonefile(zonefile, &zone, origin),
+                });
+
+                included.push(IncludedZone {
+                    zone_ref: zone.zone_ref(),
+                    state: ZoneState::Present,
+                    origin: Some(origin.to_string()),
+                    hash: Some(hash.to_string()),
+                    serial: Some(serial),
+                    stale: false,
+                });
+                continue;
+            }
+            (None, _, _) => {
+                debug!("zone {zone} has no fqdn yet");
+                ZoneState::PendingFqdn
+            }
+            (Some(_), _, _) => {
+                debug!("zone {zone} has not computed its hash and serial yet");
+                ZoneState::PendingSerial
+            }
+        };
+
+        included.push(unavailable(
+            zone.zone_ref(),
+            state,
+            &previous,
+            &existing.zones,
+            &layout,
+            &mut rendered,
+        ));
+    }
+
+    let objects = layout.objects(&rendered).map_err(Error::InvalidOutput)?;
+    let written: Vec<_> = objects
+        .iter()
+        .map(|object| (kind, object.name.clone()))
+        .collect();
+
+    let digest = output::digest(&objects);
+    for object in objects {
+        write_output(ctx.client.clone(), zonefile, kind, object).await?;
+    }
+
+    // Name servers which only read their zones on startup are rolled out,
+    // once the output they mount has been written.
+    for workload in &zonefile.spec.rollout {
+        let client = ctx.client.clone();
+        let name = &workload.name;
+        match workload.kind {
+            WorkloadKind::Deployment => {
+                rollout(client, zonefile, name, &digest, |deployment: Deployment| {
+                    Some(deployment.spec?.template)
+                })
+                .await?
+            }
+            WorkloadKind::StatefulSet => {
+                rollout(client, zonefile, name, &digest, |set: StatefulSet| {
+                    Some(set.spec?.template)
+                })
+                .await?
+            }
+            WorkloadKind::DaemonSet => {
+                rollout(client, zonefile, name, &digest, |set: DaemonSet| {
+                    Some(set.spec?.template)
+                })
+                .await?
+            }
+        }
+    }
+
+    // Remove objects left over from zones which are no longer included, or
+    // from before the zonefile's output was reconfigured.
+    for (kind, name) in existing.objects {
+        if written.contains(&(kind, name.clone())) {
+            continue;
+        }
+
+        info!(
+            "removing {kind:?} {name} no longer written by zonefile {}",
+            zonefile_key(zonefile)
+        );
+
+        let namespace = zonefile.namespace().unwrap();
+        let params = DeleteParams::default();
+        match kind {
+            OutputKind::ConfigMap => {
+                Api::<ConfigMap>::namespaced(ctx.client.clone(), &namespace)
+                    .delete(&name, &params)
+                    .await?;
+            }
+            OutputKind::Secret => {
+                Api::<Secret>::namespaced(ctx.client.clone(), &namespace)
+                    .delete(&name, &params)
+                    .await?;
+            }
+        }
+    }
+
+    let rendered = || {
+        included.iter().filter_map(|included| {
+            Some((
+                included.origin.as_ref()?,
+                included.hash.as_ref()?,
+                included.serial?,
+            ))
+        })
+    };
 
     Api::<ZoneFile>::namespaced(ctx.client.clone(), zonefile.namespace().as_ref().unwrap())
         .patch_status(
@@ -186,47 +682,164 @@ async fn reconcile_zonefiles(
             &PatchParams::apply(CONTROLLER_NAME),
             &Patch::Merge(json!({
                 "status": {
-                    "hash": BTreeMap::from_iter(serialized_zones.iter().map(|serialized_zone| (&serialized_zone.origin, &serialized_zone.hash))),
-                    "serial": BTreeMap::from_iter(serialized_zones.iter().map(|serialized_zone| (&serialized_zone.origin, serialized_zone.serial))),
+                    "zones": included,
+                    "hash": BTreeMap::from_iter(rendered().map(|(origin, hash, _)| (origin, hash))),
+                    "serial": BTreeMap::from_iter(rendered().map(|(origin, _, serial)| (origin, serial))),
                 },
             })),
         )
         .await?;
 
-    Ok(Action::requeue(Duration::from_secs(300)))
+    // The other zones have been updated regardless, but a referenced
+    // zone going missing must still be surfaced as a failure.
+    if let Some(zone_ref) = missing.into_iter().next() {
+        return Err(Error::MissingZone(zone_ref));
+    }
+
+    Ok(Action::requeue(ctx.requeue))
 }
 
-fn zonefile_error_policy(zone: Arc<ZoneFile>, error: &kube::Error, _ctx: Arc<Data>) -> Action {
+fn zonefile_error_policy(zonefile: Arc<ZoneFile>, error: &Error, ctx: Arc<Data>) -> Action {
+    let delay = ctx.backoff.failure(&zonefile_key(&zonefile));
+
     error!(
-        "zonefile {} reconciliation encountered error: {error}",
-        zone.name_any()
+        "zonefile {} reconciliation encountered {} error: {error}, retrying in {}s",
+        zonefile.name_any(),
+        error.category(),
+        delay.as_secs()
     );
-    Action::requeue(Duration::from_secs(60))
+    Action::requeue(delay)
+}
+
+pub async fn reconcile(
+    client: Client,
+    scope: Scope,
+    backoff: Backoff,
+    metrics: Arc<Metrics>,
+    requeue: Duration,
+) {
+    let data = Arc::new(Data {
+        client: client.clone(),
+        scope: scope.clone(),
+        backoff,
+        metrics,
+        requeue,
+    });
+
+    // A controller can only watch a single namespace (or all of them), so when
+    // limited to a set of namespaces, we run one controller per namespace. Zones
+    // are watched across all namespaces in scope, once, and handed to every
+    // controller.
+    let zone_changes = scope.shared_watch::<Zone>(&client, scope.api_namespaces().len());
+
+    let zonefile_controllers = scope
+        .apis::<ZoneFile>(&client)
+        .into_iter()
+        .zip(zone_changes)
+        .map(|(zonefiles, zone_changes)| {
+            let controller = Controller::new(zonefiles, scope.watcher_config());
+            let store = controller.store();
+
+            // A zone can feed any number of zonefiles, which may also select zones that
+            // have only just appeared, so we look up the zonefiles including a zone in
+            // the controller's cache of zonefiles, rather than through backreferences.
+            // Zonefiles which rendered the zone before it changed are triggered too, so
+            // they drop zones which were relabelled out of their selector.
+            controller
+                .watches_stream(zone_changes, move |zone| {
+                    store
+                        .state()
+                        .into_iter()
+                        .filter(|zonefile| zonefile.concerns(&zone))
+                        .map(|zonefile| ObjectRef::from_obj(&*zonefile))
+                        .collect::<Vec<_>>()
+                })
+                .shutdown_on_signal()
+                .run(reconcile_zonefiles, zonefile_error_policy, data.clone())
+                .for_each(|res| async move {
+                    match res {
+                        Ok(o) => info!("reconciled {:?}", o),
+                        Err(e) => warn!("reconcile failed: {}", e),
+                    }
+                })
+        });
+
+    futures::future::join_all(zonefile_controllers).await;
 }
 
-pub async fn reconcile(client: Client) {
-    let zonefiles = Api::<ZoneFile>::all(client.clone());
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
 
-    let zone_controller = Controller::new(zonefiles, watcher::Config::default())
-        .watches(
-            Api::<Zone>::all(client.clone()),
-            watcher::Config::default(),
-            kubizone_crds::watch_reference(TARGET_ZONEFILE_LABEL),
-        )
-        .shutdown_on_signal()
-        .run(
-            reconcile_zonefiles,
-            zonefile_error_policy,
-            Arc::new(Data {
-                client: client.clone(),
-            }),
-        )
-        .for_each(|res| async move {
-            match res {
-                Ok(o) => info!("reconciled {:?}", o),
-                Err(e) => warn!("reconcile failed: {}", e),
+    use kubizone_crds::v1alpha1::ZoneRef;
+    use zonefile_crds::{IncludedZone, ZoneFileOutput, ZoneState};
+
+    use crate::output::Layout;
+
+    use super::unavailable;
+
+    fn zone_ref(name: &str) -> ZoneRef {
+        ZoneRef {
+            name: name.to_string(),
+            namespace: Some(String::from("default")),
+        }
+    }
+
+    #[test]
+    fn unavailable_zones_keep_last_rendering() {
+        let previous = [IncludedZone {
+            zone_ref: zone_ref("example-org"),
+            state: ZoneState::Present,
+            origin: Some(String::from("example.org.")),
+            hash: Some(String::from("1234")),
+            serial: Some(2023120100),
+            stale: false,
+        }];
+
+        let existing = BTreeMap::from([(
+            String::from("example.org."),
+            String::from("$ORIGIN example.org.\n"),
+        )]);
+
+        let output = ZoneFileOutput::default();
+        let layout = Layout {
+            output: &output,
+            zonefile: "example",
+            default_name: "example",
+        };
+
+        let mut rendered = Vec::new();
+        let included = unavailable(
+            zone_ref("example-org"),
+            ZoneState::Missing,
+            &previous,
+            &existing,
+            &layout,
+            &mut rendered,
+        );
+
+        assert_eq!(
+            included,
+            IncludedZone {
+                state: ZoneState::Missing,
+                stale: true,
+                ..previous[0].clone()
             }
-        });
+        );
+        assert_eq!(rendered.len(), 1);
+        assert_eq!(rendered[0].contents, "$ORIGIN example.org.\n");
 
-    zone_controller.await;
+        // Zones which were never rendered have nothing to fall back to.
+        let included = unavailable(
+            zone_ref("example-com"),
+            ZoneState::PendingFqdn,
+            &previous,
+            &existing,
+            &layout,
+            &mut rendered,
+        );
+        assert!(!included.stale);
+        assert_eq!(included.origin, None);
+        assert_eq!(rendered.len(), 1);
+    }
 }
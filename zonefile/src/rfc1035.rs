@@ -0,0 +1,483 @@
+use kubizone_crds::v1alpha1::ZoneEntry;
+
+/// Maximum length in bytes of a single `<character-string>`, as per
+/// [RFC 1035 section 3.3](https://datatracker.ietf.org/doc/html/rfc1035#section-3.3).
+const MAX_CHARACTER_STRING_LENGTH: usize = 255;
+
+/// Indices of the fields within the record data of `type_` which hold domain names.
+pub fn domain_name_fields(type_: &str) -> &'static [usize] {
+    match type_.to_uppercase().as_str() {
+        "NS" | "CNAME" | "DNAME" | "PTR" => &[0],
+        "MX" => &[1],
+        "SRV" => &[3],
+        "SOA" => &[0, 1],
+        _ => &[],
+    }
+}
+
+/// Serialize `entries` of the zone `origin` into the zone file format described in
+/// [RFC 1035 section 5](https://datatracker.ietf.org/doc/html/rfc1035#section-5).
+///
+/// Owner names within the zone are written relative to the origin, using `@` for the
+/// apex, and entries with the zone's default `ttl` omit their TTL. If `relative_rdata`
+/// is set, domain names within the record data are made relative to the origin too.
+pub fn serialize(origin: &str, ttl: u32, entries: &[ZoneEntry], relative_rdata: bool) -> String {
+    let owners: Vec<_> = entries
+        .iter()
+        .map(|entry| owner_name(origin, &entry.fqdn))
+        .collect();
+
+    // We use the longest owner name in the list for
+    // aligning the text in the output zonefile
+    let width = owners.iter().map(String::len).max().unwrap_or_default();
+
+    let mut output = format!("$ORIGIN {origin}\n$TTL {ttl}\n\n");
+
+    for (owner, entry) in owners.iter().zip(entries) {
+        let entry_ttl = if entry.ttl == ttl {
+            String::new()
+        } else {
+            entry.ttl.to_string()
+        };
+
+        let prefix = format!(
+            "{owner:<width$} {entry_ttl:<8} {:<5} {:<6} ",
+            entry.class, entry.type_
+        );
+
+        let rdata = match entry.type_.to_uppercase().as_str() {
+            "TXT" | "SPF" => character_strings(&entry.rdata),
+            "SOA" => soa(origin, &entry.rdata, relative_rdata, prefix.len()),
+            _ => domain_names(origin, &entry.type_, &entry.rdata, relative_rdata),
+        };
+
+        output.push_str(&prefix);
+        output.push_str(&rdata);
+        output.push('\n');
+    }
+
+    output
+}
+
+/// Make `name` fully qualified, by appending the `origin` to relative names.
+pub fn absolute(name: &str, origin: &str) -> String {
+    if name == "@" {
+        origin.to_string()
+    } else if name.ends_with('.') && !name.ends_with("\\.") {
+        name.to_string()
+    } else if origin == "." {
+        format!("{name}.")
+    } else {
+        format!("{name}.{origin}")
+    }
+}
+
+/// Strip the `origin` from `name`, if `name` lies within it.
+pub fn relative_to<'a>(origin: &str, name: &'a str) -> Option<&'a str> {
+    if name == origin {
+        return Some("@");
+    }
+
+    name.strip_suffix(origin)?.strip_suffix('.')
+}
+
+/// Names which are not fully qualified are already relative to the origin,
+/// and are left as-is, as are fully qualified names outside of the zone.
+fn owner_name(origin: &str, name: &str) -> String {
+    match relative_to(origin, name).unwrap_or(name) {
+        "@" => String::from("@"),
+        name => escape_name(name),
+    }
+}
+
+/// Escape characters which have special meaning in zone files.
+///
+/// Names may already contain escape sequences, such as `\.` for a dot within
+/// a label, or `\DDD` for an arbitrary byte, which are passed through as-is.
+fn escape_name(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+
+    let mut bytes = name.bytes().peekable();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'\\' => {
+                escaped.push('\\');
+                match bytes.next_if(|next| (0x21..=0x7e).contains(next)) {
+                    Some(escape) => escaped.push(escape as char),
+                    None => escaped.push('\\'),
+                }
+            }
+            b'"' | b'(' | b')' | b';' | b'@' | b'$' | b' ' => {
+                escaped.push('\\');
+                escaped.push(byte as char);
+            }
+            0x21..=0x7e => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\{byte:03}")),
+        }
+    }
+
+    escaped
+}
+
+/// Rewrite the domain name fields of the record data relative to the origin, if requested.
+fn domain_names(origin: &str, type_: &str, rdata: &str, relative_rdata: bool) -> String {
+    let fields = domain_name_fields(type_);
+    if !relative_rdata || fields.is_empty() {
+        return rdata.to_string();
+    }
+
+    rdata
+        .split_whitespace()
+        .enumerate()
+        .map(|(index, field)| {
+            if fields.contains(&index) {
+                relative_to(origin, field).unwrap_or(field)
+            } else {
+                field
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Split SOA record data into its seven fields, ignoring any parentheses.
+pub fn soa_fields(rdata: &str) -> Option<[&str; 7]> {
+    rdata
+        .split(|c: char| c.is_whitespace() || c == '(' || c == ')')
+        .filter(|field| !field.is_empty())
+        .collect::<Vec<_>>()
+        .try_into()
+        .ok()
+}
+
+/// Record data of `entry` on a single line, with all domain names fully qualified
+/// and TXT records quoted, as expected by servers which don't read zone files.
+pub fn presentation_rdata(origin: &str, entry: &ZoneEntry) -> String {
+    let type_ = entry.type_.to_uppercase();
+    if type_ == "TXT" || type_ == "SPF" {
+        return character_strings(&entry.rdata);
+    }
+
+    let fields = domain_name_fields(&type_);
+    let rdata = if type_ == "SOA" {
+        soa_fields(&entry.rdata).map(|fields| fields.join(" "))
+    } else {
+        None
+    }
+    .unwrap_or_else(|| entry.rdata.clone());
+
+    rdata
+        .split_whitespace()
+        .enumerate()
+        .map(|(index, field)| {
+            if fields.contains(&index) {
+                absolute(field, origin)
+            } else {
+                field.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Lay out the SOA record data over multiple lines, one timer per line, indented by `indent`.
+///
+/// Falls back to the record data as-is, if it does not consist of the expected seven fields.
+fn soa(origin: &str, rdata: &str, relative_rdata: bool, indent: usize) -> String {
+    let Some([mname, rname, serial, refresh, retry, expire, minimum]) = soa_fields(rdata) else {
+        return rdata.to_string();
+    };
+
+    let (mname, rname) = if relative_rdata {
+        (
+            relative_to(origin, mname).unwrap_or(mname),
+            relative_to(origin, rname).unwrap_or(rname),
+        )
+    } else {
+        (mname, rname)
+    };
+
+    let indent = " ".repeat(indent);
+    format!(
+        "{mname} {rname} (\n\
+         {indent}    {serial:<10} ; serial\n\
+         {indent}    {refresh:<10} ; refresh\n\
+         {indent}    {retry:<10} ; retry\n\
+         {indent}    {expire:<10} ; expire\n\
+         {indent}    {minimum:<10} ; minimum\n\
+         {indent})"
+    )
+}
+
+/// Decode a `<character-string>` in presentation format into its raw bytes,
+/// removing the surrounding quotes, if any, and resolving escapes.
+pub fn decode_character_string(token: &str) -> Vec<u8> {
+    let token = token
+        .strip_prefix('"')
+        .and_then(|token| token.strip_suffix('"'))
+        .unwrap_or(token);
+
+    let mut bytes = token.bytes().peekable();
+    let mut decoded = Vec::with_capacity(token.len());
+
+    while let Some(byte) = bytes.next() {
+        if byte != b'\\' {
+            decoded.push(byte);
+            continue;
+        }
+
+        match bytes.next() {
+            Some(digit @ b'0'..=b'9') => {
+                let mut value = u32::from(digit - b'0');
+                for _ in 0..2 {
+                    if let Some(digit @ b'0'..=b'9') = bytes.peek().copied() {
+                        value = value * 10 + u32::from(digit - b'0');
+                        bytes.next();
+                    }
+                }
+                decoded.push(value.min(255) as u8);
+            }
+            Some(escaped) => decoded.push(escaped),
+            None => (),
+        }
+    }
+
+    decoded
+}
+
+/// Decode the contents of a TXT record into the raw bytes of its `<character-string>`s.
+///
+/// Record data starting with a quote is read as a sequence of quoted strings,
+/// anything else is taken literally as a single string.
+pub fn decode_txt(rdata: &str) -> Vec<Vec<u8>> {
+    if !rdata.trim_start().starts_with('"') {
+        return vec![rdata.as_bytes().to_vec()];
+    }
+
+    let mut strings = Vec::new();
+    let mut rest = rdata;
+
+    while let Some(start) = rest.find('"') {
+        let string = &rest[start + 1..];
+
+        // Find the closing quote, skipping over escaped ones.
+        let mut escaped = false;
+        let end = string
+            .char_indices()
+            .find(|&(_, c)| {
+                let closing = !escaped && c == '"';
+                escaped = !escaped && c == '\\';
+                closing
+            })
+            .map(|(end, _)| end)
+            .unwrap_or(string.len());
+
+        strings.push(decode_character_string(&string[..end]));
+        rest = string.get(end + 1..).unwrap_or_default();
+    }
+
+    strings
+}
+
+/// Split the contents of a CAA record into its flags, tag and value. The value
+/// is a single `<character-string>`, which may be quoted and contain spaces.
+pub fn split_caa(rdata: &str) -> Option<(&str, &str, String)> {
+    let (flags, rest) = rdata.trim().split_once(char::is_whitespace)?;
+    let (tag, value) = rest.trim_start().split_once(char::is_whitespace)?;
+    let value = decode_txt(value.trim()).concat();
+
+    Some((flags, tag, String::from_utf8_lossy(&value).to_string()))
+}
+
+/// Quote and escape the contents of a TXT record, splitting it into strings of
+/// at most [`MAX_CHARACTER_STRING_LENGTH`] bytes.
+fn character_strings(rdata: &str) -> String {
+    let strings = decode_txt(rdata);
+
+    strings
+        .iter()
+        .flat_map(|string| {
+            // Empty strings are valid, and must be preserved.
+            if string.is_empty() {
+                vec![&string[..]]
+            } else {
+                string.chunks(MAX_CHARACTER_STRING_LENGTH).collect()
+            }
+        })
+        .map(|chunk| {
+            let mut quoted = String::from("\"");
+            for &byte in chunk {
+                match byte {
+                    b'"' | b'\\' => {
+                        quoted.push('\\');
+                        quoted.push(byte as char);
+                    }
+                    0x20..=0x7e => quoted.push(byte as char),
+                    _ => quoted.push_str(&format!("\\{byte:03}")),
+                }
+            }
+            quoted.push('"');
+            quoted
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use kubizone_crds::v1alpha1::ZoneEntry;
+
+    use super::{decode_character_string, decode_txt, serialize};
+    use crate::parser;
+
+    fn entry(fqdn: &str, ttl: u32, type_: &str, rdata: &str) -> ZoneEntry {
+        ZoneEntry {
+            fqdn: fqdn.to_string(),
+            type_: type_.to_string(),
+            class: String::from("IN"),
+            ttl,
+            rdata: rdata.to_string(),
+        }
+    }
+
+    #[test]
+    fn relative_owner_names() {
+        let zonefile = serialize(
+            "example.org.",
+            360,
+            &[
+                entry("example.org.", 360, "NS", "ns.example.org."),
+                entry("www.example.org.", 300, "CNAME", "example.org."),
+                entry("api", 360, "CNAME", "www.example.com."),
+            ],
+            true,
+        );
+
+        assert_eq!(
+            zonefile,
+            "$ORIGIN example.org.\n\
+             $TTL 360\n\
+             \n\
+             @            IN    NS     ns\n\
+             www 300      IN    CNAME  @\n\
+             api          IN    CNAME  www.example.com.\n"
+        );
+    }
+
+    #[test]
+    fn escaped_names() {
+        let entries = vec![
+            entry("dotted\\.label.example.org.", 360, "A", "192.0.2.1"),
+            entry("byte\\255.example.org.", 360, "A", "192.0.2.2"),
+            entry("say;what.example.org.", 360, "A", "192.0.2.3"),
+        ];
+
+        let zonefile = serialize("example.org.", 360, &entries, true);
+        assert!(zonefile.contains("dotted\\.label "));
+        assert!(zonefile.contains("byte\\255 "));
+        assert!(zonefile.contains("say\\;what "));
+
+        let parsed = parser::parse(&zonefile, "example.org.").unwrap();
+        let names: Vec<_> = parsed.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "dotted\\.label.example.org.",
+                "byte\\255.example.org.",
+                "say\\;what.example.org.",
+            ]
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let long_text = format!("v=DKIM1; k=rsa; p={}", "A".repeat(600));
+
+        let entries = vec![
+            entry(
+                "example.org.",
+                360,
+                "SOA",
+                "ns.example.org. noc.example.org. (2023120100 86400 7200 3600000 360)",
+            ),
+            entry("example.org.", 360, "NS", "ns1.example.org."),
+            entry("example.org.", 3600, "MX", "10 mail.example.org."),
+            entry("www", 300, "A", "192.168.0.1"),
+            entry("sub.example.org.", 360, "NS", "ns.sub.example.org."),
+            entry(
+                "_sip._tcp.example.org.",
+                360,
+                "SRV",
+                "10 60 5060 sip.example.com.",
+            ),
+            entry("selector._domainkey.example.org.", 360, "TXT", &long_text),
+            entry(
+                "quoted.example.org.",
+                360,
+                "TXT",
+                r#""say \"hi\"" "back\\slash; and ünïcode""#,
+            ),
+        ];
+
+        for relative_rdata in [false, true] {
+            let zonefile = serialize("example.org.", 360, &entries, relative_rdata);
+            let parsed = parser::parse(&zonefile, "example.org.").unwrap();
+
+            assert_eq!(parsed.len(), entries.len());
+
+            let names: Vec<_> = parsed.iter().map(|entry| entry.name.as_str()).collect();
+            assert_eq!(
+                names,
+                [
+                    "example.org.",
+                    "example.org.",
+                    "example.org.",
+                    "www.example.org.",
+                    "sub.example.org.",
+                    "_sip._tcp.example.org.",
+                    "selector._domainkey.example.org.",
+                    "quoted.example.org.",
+                ]
+            );
+
+            for (original, parsed) in entries.iter().zip(&parsed) {
+                assert_eq!(original.ttl, parsed.ttl);
+                assert_eq!(original.type_, parsed.type_);
+                assert_eq!(original.class, parsed.class);
+            }
+
+            assert_eq!(
+                parsed[0].rdata,
+                [
+                    "ns.example.org.",
+                    "noc.example.org.",
+                    "2023120100",
+                    "86400",
+                    "7200",
+                    "3600000",
+                    "360"
+                ]
+            );
+            assert_eq!(parsed[2].rdata, ["10", "mail.example.org."]);
+            assert_eq!(parsed[5].rdata, ["10", "60", "5060", "sip.example.com."]);
+
+            // Long strings are split up, but decode to the same text.
+            assert_eq!(parsed[6].rdata.len(), 3);
+            assert_eq!(
+                parsed[6]
+                    .rdata
+                    .iter()
+                    .flat_map(|string| decode_character_string(string))
+                    .collect::<Vec<_>>(),
+                long_text.as_bytes()
+            );
+
+            let quoted: Vec<_> = parsed[7]
+                .rdata
+                .iter()
+                .map(|string| decode_character_string(string))
+                .collect();
+            assert_eq!(quoted, decode_txt(&entries[7].rdata));
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use kubizone_crds::v1alpha1::ZoneEntry;
+
+use crate::rfc1035::{absolute, presentation_rdata};
+
+/// Resource record set: all records sharing an owner name and type.
+pub struct RRSet {
+    /// Fully qualified owner name.
+    pub name: String,
+    pub type_: String,
+    pub ttl: u32,
+    /// Record data of each record in the set, as produced by [`presentation_rdata`].
+    pub contents: Vec<String>,
+}
+
+/// Group `entries` of the zone `origin` into RRsets, in the order in which
+/// they first appear, so the SOA record stays at the top.
+///
+/// Formats which only support a single TTL per RRset get the lowest one, to avoid
+/// caching any of the records for longer than intended. Duplicate records are dropped.
+pub fn group(origin: &str, entries: &[ZoneEntry]) -> Vec<RRSet> {
+    let mut rrsets: Vec<RRSet> = Vec::new();
+
+    for entry in entries {
+        let name = absolute(&entry.fqdn, origin);
+        let type_ = entry.type_.to_uppercase();
+        let content = presentation_rdata(origin, entry);
+
+        match rrsets
+            .iter_mut()
+            .find(|rrset| rrset.name == name && rrset.type_ == type_)
+        {
+            Some(rrset) => {
+                rrset.ttl = rrset.ttl.min(entry.ttl);
+
+                if !rrset.contents.contains(&content) {
+                    rrset.contents.push(content);
+                }
+            }
+            None => rrsets.push(RRSet {
+                name,
+                type_,
+                ttl: entry.ttl,
+                contents: vec![content],
+            }),
+        }
+    }
+
+    rrsets
+}
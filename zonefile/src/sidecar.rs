@@ -0,0 +1,322 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use futures::{stream, StreamExt};
+use kube::{
+    api::ListParams,
+    runtime::{watcher, WatchStreamExt},
+    Api, Client,
+};
+use kubizone_common::Scope;
+use kubizone_crds::v1alpha1::{Zone, ZoneRef};
+use tokio::process::Command;
+use tracing::log::*;
+use zonefile_crds::{ZoneFile, ZoneFileFormat};
+
+use crate::{output::template, reconciliation};
+
+/// Where the sidecar takes its zones from.
+pub enum Source {
+    /// The zones referenced or selected by a [`ZoneFile`], written in its format.
+    ZoneFile(ZoneRef),
+    /// All zones within the scope, written in `format`.
+    Zones {
+        format: ZoneFileFormat,
+        relative_rdata: bool,
+    },
+}
+
+/// Writes zones to files within a local directory, for name servers which
+/// are unaware of Kubernetes, and reloads them when their serial changes.
+pub struct Sidecar {
+    pub client: Client,
+    pub scope: Scope,
+    pub source: Source,
+    /// Directory to write the zone files to.
+    pub directory: PathBuf,
+    /// Template for the name of each zone's file within the `directory`.
+    pub file_name: String,
+    /// Command to run whenever a zone changes, with each argument templated.
+    pub reload: Vec<String>,
+}
+
+/// What the sidecar has written so far.
+#[derive(Default)]
+struct State {
+    /// Serial of the zone last written to each file.
+    serials: HashMap<PathBuf, u32>,
+    /// Files written without successfully reloading the name server since.
+    unreloaded: HashSet<PathBuf>,
+}
+
+/// Variables available to the file name and reload command templates.
+fn variables<'a>(zone: &'a Zone, origin: &'a str, file: &'a str) -> [(&'a str, &'a str); 5] {
+    [
+        ("zone", zone.metadata.name.as_deref().unwrap_or_default()),
+        (
+            "namespace",
+            zone.metadata.namespace.as_deref().unwrap_or_default(),
+        ),
+        ("origin", origin),
+        ("domain", origin.strip_suffix('.').unwrap_or(origin)),
+        ("file", file),
+    ]
+}
+
+/// Atomically replace the file at `path` with `contents`, by writing them to a
+/// temporary file next to it and renaming it, so name servers never read a
+/// partially written zone. Returns whether the contents of the file changed.
+fn write(path: &Path, contents: &str) -> io::Result<bool> {
+    if fs::read_to_string(path).is_ok_and(|existing| existing == contents) {
+        return Ok(false);
+    }
+
+    // Unwrap safety: paths are always a file name joined onto the directory.
+    let file_name = path.file_name().unwrap().to_string_lossy();
+    let temporary = path.with_file_name(format!(".{file_name}.tmp"));
+
+    let mut file = File::create(&temporary)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+
+    fs::rename(&temporary, path)?;
+    Ok(true)
+}
+
+impl Sidecar {
+    /// Check the templates, so mistakes surface on startup, rather than
+    /// whenever a zone changes.
+    pub fn validate(&self) -> Result<(), String> {
+        let variables = [
+            ("zone", ""),
+            ("namespace", ""),
+            ("origin", ""),
+            ("domain", ""),
+            ("file", ""),
+        ];
+
+        template(&self.file_name, &variables[..4])?;
+        for argument in &self.reload {
+            template(argument, &variables)?;
+        }
+
+        Ok(())
+    }
+
+    async fn zones(&self) -> Result<(Vec<Zone>, ZoneFileFormat, bool), kube::Error> {
+        match &self.source {
+            Source::ZoneFile(zonefile_ref) => {
+                // Unwrap safety: the reference is always given a namespace when parsed.
+                let zonefile = Api::<ZoneFile>::namespaced(
+                    self.client.clone(),
+                    zonefile_ref.namespace.as_ref().unwrap(),
+                )
+                .get_opt(&zonefile_ref.name)
+                .await?;
+
+                let Some(zonefile) = zonefile else {
+                    warn!("zonefile {zonefile_ref} does not exist");
+                    return Ok((Vec::new(), ZoneFileFormat::default(), false));
+                };
+
+                let (zones, missing) =
+                    reconciliation::included_zones(&self.client, &self.scope, &zonefile).await?;
+
+                for zone_ref in missing {
+                    warn!("zone {zone_ref} referenced by zonefile {zonefile_ref} does not exist");
+                }
+
+                Ok((zones, zonefile.spec.format, zonefile.spec.relative_rdata))
+            }
+            Source::Zones {
+                format,
+                relative_rdata,
+            } => {
+                let zones = self
+                    .scope
+                    .list::<Zone>(&self.client, &ListParams::default())
+                    .await?;
+
+                Ok((zones, *format, *relative_rdata))
+            }
+        }
+    }
+
+    /// Write every zone whose serial differs from the one last written to its
+    /// file, reloading the name server for those whose contents changed.
+    async fn sync(&self, state: &mut State) -> Result<(), kube::Error> {
+        let (zones, format, relative_rdata) = self.zones().await?;
+
+        for zone in zones {
+            // Zones keep their last written file until they are available again.
+            let (Some(origin), Some(serial)) = (zone.fqdn(), zone.serial()) else {
+                debug!("zone {zone} has not computed its fqdn and serial yet");
+                continue;
+            };
+
+            let file_name = match template(&self.file_name, &variables(&zone, origin, "")) {
+                Ok(file_name) => file_name,
+                Err(err) => {
+                    error!("cannot name the file of zone {zone}: {err}");
+                    continue;
+                }
+            };
+
+            if file_name.is_empty() || file_name.starts_with('.') || file_name.contains('/') {
+                error!("cannot write zone {zone} to invalid file name {file_name:?}");
+                continue;
+            }
+
+            let path = self.directory.join(file_name);
+            if state.serials.get(&path) == Some(&serial) {
+                continue;
+            }
+
+            let contents = reconciliation::render(format, relative_rdata, &zone, origin);
+            match write(&path, &contents) {
+                Ok(true) => {
+                    info!(
+                        "wrote zone {zone} with serial {serial} to {}",
+                        path.display()
+                    );
+                    state.unreloaded.insert(path.clone());
+                }
+                // Files left in place by an earlier run need no reload.
+                Ok(false) => (),
+                Err(err) => {
+                    error!("failed to write zone {zone} to {}: {err}", path.display());
+                    continue;
+                }
+            }
+
+            state.serials.insert(path.clone(), serial);
+
+            if state.unreloaded.contains(&path) {
+                match self.reload(&zone, origin, &path).await {
+                    Ok(()) => {
+                        state.unreloaded.remove(&path);
+                    }
+                    // Retried the next time anything changes.
+                    Err(err) => {
+                        error!("failed to reload zone {zone}: {err}");
+                        state.serials.remove(&path);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn reload(&self, zone: &Zone, origin: &str, path: &Path) -> Result<(), String> {
+        let file = path.to_string_lossy();
+        let variables = variables(zone, origin, &file);
+
+        let arguments = self
+            .reload
+            .iter()
+            .map(|argument| template(argument, &variables))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let Some((program, arguments)) = arguments.split_first() else {
+            return Ok(());
+        };
+
+        let output = Command::new(program)
+            .args(arguments)
+            .output()
+            .await
+            .map_err(|err| format!("failed to run {program}: {err}"))?;
+
+        if !output.status.success() {
+            return Err(format!(
+                "{program} exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        info!("reloaded zone {zone} using {program}");
+        Ok(())
+    }
+
+    /// Write the zones, and keep them up to date as they change.
+    pub async fn run(self) {
+        let trigger = |event: Result<(), watcher::Error>| {
+            if let Err(err) = event {
+                warn!("watch failed: {err}");
+            }
+        };
+
+        let mut triggers: Vec<_> = self
+            .scope
+            .apis::<Zone>(&self.client)
+            .into_iter()
+            .map(|zones| {
+                watcher(zones, self.scope.watcher_config())
+                    .default_backoff()
+                    .map(|event| trigger(event.map(|_| ())))
+                    .boxed()
+            })
+            .collect();
+
+        if let Source::ZoneFile(zonefile_ref) = &self.source {
+            let zonefiles = Api::<ZoneFile>::namespaced(
+                self.client.clone(),
+                zonefile_ref.namespace.as_ref().unwrap(),
+            );
+
+            let config =
+                watcher::Config::default().fields(&format!("metadata.name={}", zonefile_ref.name));
+
+            triggers.push(
+                watcher(zonefiles, config)
+                    .default_backoff()
+                    .map(|event| trigger(event.map(|_| ())))
+                    .boxed(),
+            );
+        }
+
+        // Every change triggers a sync of all zones, so there's no
+        // point in syncing again for each of a burst of changes.
+        let mut triggers = stream::select_all(triggers).ready_chunks(1024);
+
+        let mut state = State::default();
+        while triggers.next().await.is_some() {
+            if let Err(err) = self.sync(&mut state).await {
+                error!(
+                    "failed to sync zones to {}: {err}",
+                    self.directory.display()
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::write;
+
+    #[test]
+    fn atomic_write() {
+        let directory =
+            std::env::temp_dir().join(format!("zonefile-sidecar-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("example.org.");
+
+        assert!(write(&path, "a").unwrap());
+        assert!(!write(&path, "a").unwrap());
+        assert!(write(&path, "b").unwrap());
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "b");
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 1);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
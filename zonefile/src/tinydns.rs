@@ -0,0 +1,173 @@
+use std::net::Ipv6Addr;
+
+use kubizone_crds::v1alpha1::ZoneEntry;
+
+use crate::rfc1035::{absolute, decode_txt, soa_fields};
+
+/// Record type numbers, used for records without a dedicated tinydns line type.
+const AAAA: u16 = 28;
+const SRV: u16 = 33;
+
+/// Serialize `entries` of the zone `origin` into the `data` format read by
+/// `tinydns-data`, as described in <https://cr.yp.to/djbdns/tinydns-data.html>.
+///
+/// Record types tinydns has no line type for, and which can't be expressed
+/// as generic records either, are written as comments.
+pub fn serialize(origin: &str, entries: &[ZoneEntry]) -> String {
+    let mut output = String::new();
+
+    for entry in entries {
+        output.push_str(&line(origin, entry).unwrap_or_else(|| {
+            format!(
+                "# unsupported {} record for {}: {}",
+                entry.type_, entry.fqdn, entry.rdata
+            )
+        }));
+        output.push('\n');
+    }
+
+    output
+}
+
+/// Names in tinydns data are fully qualified, but without the trailing dot.
+fn name(origin: &str, name: &str) -> String {
+    let name = absolute(name, origin);
+    match name.strip_suffix('.') {
+        Some("") | None => name,
+        Some(name) => escape(name.as_bytes()),
+    }
+}
+
+/// Escape bytes which would otherwise end a field, or the line, as octal.
+fn escape(bytes: &[u8]) -> String {
+    let mut escaped = String::with_capacity(bytes.len());
+
+    for &byte in bytes {
+        match byte {
+            b':' | b'\\' => escaped.push_str(&format!("\\{byte:03o}")),
+            0x20..=0x7e => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\{byte:03o}")),
+        }
+    }
+
+    escaped
+}
+
+/// Encode a domain name in DNS wire format, for use in generic records.
+fn wire_name(name: &str) -> Vec<u8> {
+    let mut wire = Vec::with_capacity(name.len() + 2);
+
+    for label in name
+        .trim_end_matches('.')
+        .split('.')
+        .filter(|label| !label.is_empty())
+    {
+        wire.push(label.len().min(63) as u8);
+        wire.extend(label.bytes().take(63));
+    }
+
+    wire.push(0);
+    wire
+}
+
+fn line(origin: &str, entry: &ZoneEntry) -> Option<String> {
+    let fqdn = name(origin, &entry.fqdn);
+    let ttl = entry.ttl;
+    let rdata = entry.rdata.trim();
+    let fields: Vec<_> = rdata.split_whitespace().collect();
+
+    Some(match entry.type_.to_uppercase().as_str() {
+        "SOA" => {
+            let [mname, rname, serial, refresh, retry, expire, minimum] = soa_fields(rdata)?;
+            format!(
+                "Z{fqdn}:{}:{}:{serial}:{refresh}:{retry}:{expire}:{minimum}:{ttl}",
+                name(origin, mname),
+                name(origin, rname)
+            )
+        }
+        "NS" => format!("&{fqdn}::{}:{ttl}", name(origin, rdata)),
+        "A" => format!("+{fqdn}:{rdata}:{ttl}"),
+        "CNAME" => format!("C{fqdn}:{}:{ttl}", name(origin, rdata)),
+        "PTR" => format!("^{fqdn}:{}:{ttl}", name(origin, rdata)),
+        "MX" => {
+            let [preference, exchange] = fields[..] else {
+                return None;
+            };
+            format!("@{fqdn}::{}:{preference}:{ttl}", name(origin, exchange))
+        }
+        "TXT" | "SPF" => {
+            // tinydns-data splits the text into strings of its own accord.
+            format!("'{fqdn}:{}:{ttl}", escape(&decode_txt(rdata).concat()))
+        }
+        "AAAA" => {
+            let address: Ipv6Addr = rdata.parse().ok()?;
+            format!(":{fqdn}:{AAAA}:{}:{ttl}", escape(&address.octets()))
+        }
+        "SRV" => {
+            let [priority, weight, port, target] = fields[..] else {
+                return None;
+            };
+
+            let mut wire = Vec::new();
+            for number in [priority, weight, port] {
+                wire.extend(number.parse::<u16>().ok()?.to_be_bytes());
+            }
+            wire.extend(wire_name(&absolute(target, origin)));
+
+            format!(":{fqdn}:{SRV}:{}:{ttl}", escape(&wire))
+        }
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use kubizone_crds::v1alpha1::ZoneEntry;
+
+    use super::serialize;
+
+    fn entry(fqdn: &str, type_: &str, rdata: &str) -> ZoneEntry {
+        ZoneEntry {
+            fqdn: fqdn.to_string(),
+            type_: type_.to_string(),
+            class: String::from("IN"),
+            ttl: 300,
+            rdata: rdata.to_string(),
+        }
+    }
+
+    #[test]
+    fn data_lines() {
+        let data = serialize(
+            "example.org.",
+            &[
+                entry(
+                    "example.org.",
+                    "SOA",
+                    "ns.example.org. noc.example.org. (2023120100 86400 7200 3600000 360)",
+                ),
+                entry("@", "NS", "ns"),
+                entry("www", "A", "192.168.0.1"),
+                entry("example.org.", "MX", "10 mail.example.org."),
+                entry("example.org.", "TXT", "\"v=spf1 -all\" \"a:b\""),
+                entry("www", "AAAA", "2001:db8::1"),
+                entry("_sip._tcp", "SRV", "10 60 5060 sip"),
+                entry("example.org.", "CAA", "0 issue \"letsencrypt.org\""),
+            ],
+        );
+
+        assert_eq!(
+            data.lines().collect::<Vec<_>>(),
+            [
+                "Zexample.org:ns.example.org:noc.example.org:2023120100:86400:7200:3600000:360:300",
+                "&example.org::ns.example.org:300",
+                "+www.example.org:192.168.0.1:300",
+                "@example.org::mail.example.org:10:300",
+                "'example.org:v=spf1 -alla\\072b:300",
+                ":www.example.org:28: \\001\\015\\270\\000\\000\\000\\000\\000\\000\\000\\000\\000\\000\\000\\001:300",
+                ":_sip._tcp.example.org:33:\\000\\012\\000<\\023\\304\\003sip\\007example\\003org\\000:300",
+                "# unsupported CAA record for example.org.: 0 issue \"letsencrypt.org\"",
+            ]
+        );
+    }
+}